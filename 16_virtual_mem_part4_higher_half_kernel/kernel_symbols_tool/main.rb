@@ -0,0 +1,28 @@
+#!/usr/bin/env ruby
+# frozen_string_literal: true
+
+# SPDX-License-Identifier: MIT OR Apache-2.0
+#
+# Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+kernel_elf_path = ARGV[0]
+
+require 'rubygems'
+require 'bundler/setup'
+require 'colorize'
+require 'elftools'
+
+require_relative 'symbol_table'
+
+puts
+puts 'Generating kernel symbol table and patching kernel ELF'.cyan
+
+start = Time.now
+
+SYMBOL_TABLE = SymbolTable.new(kernel_elf_path)
+SYMBOL_TABLE.patch(kernel_elf_path)
+
+elapsed = Time.now - start
+
+print 'Finished'.rjust(12).green.bold
+puts " in #{elapsed.round(2)}s"
@@ -0,0 +1,106 @@
+# frozen_string_literal: true
+
+# SPDX-License-Identifier: MIT OR Apache-2.0
+#
+# Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+# Replacements for the escape sequences of legacy Rust symbol mangling.
+RUST_ESCAPES = {
+    '$SP$' => '@', '$BP$' => '*', '$RF$' => '&', '$LT$' => '<', '$GT$' => '>', '$LP$' => '(',
+    '$RP$' => ')', '$C$' => ',', '$u20$' => ' ', '$u22$' => '"', '$u27$' => "'", '$u2b$' => '+',
+    '$u3b$' => ';', '$u5b$' => '[', '$u5d$' => ']', '$u7b$' => '{', '$u7d$' => '}', '$u7e$' => '~'
+}.freeze
+
+# Demangle a legacy Rust symbol name, e.g. `_ZN9libkernel4time5sleep17h0123456789abcdefE` to
+# `libkernel::time::sleep`. Names that are not mangled are returned as is.
+def demangle(name)
+    return name unless name.start_with?('_ZN') && name.end_with?('E')
+
+    rest = name[3..-2]
+    parts = []
+    until rest.empty?
+        len = rest[/\A\d+/]
+        return name if len.nil?
+
+        rest = rest[len.size..]
+        parts << rest[0, len.to_i]
+        rest = rest[len.to_i..]
+    end
+
+    # Drop the hash.
+    parts.pop if parts.last&.match?(/\Ah[0-9a-f]{16}\z/)
+
+    parts.map do |part|
+        part = part.delete_prefix('_') if part.start_with?('_$')
+        part = part.gsub('..', '::')
+        RUST_ESCAPES.each { |k, v| part = part.gsub(k, v) }
+        part
+    end.join('::')
+end
+
+# The kernel's function symbols, in the binary format expected by `crate::backtrace::symbols`.
+class SymbolTable
+    MAGIC = 'KSYM'
+    HEADER_SIZE = 16
+    ENTRY_SIZE = 16
+    # Must match `MAX_NAME_LEN` in `src/backtrace/symbols.rs`.
+    MAX_NAME_LEN = 96
+    TABLE_SYMBOL = 'KERNEL_SYMBOLS'
+    STT_FUNC = 2
+
+    def initialize(kernel_elf_path)
+        @elf = ELFTools::ELFFile.new(File.open(kernel_elf_path, 'rb'))
+        symtab = @elf.section_by_name('.symtab')
+        @table = symtab.symbol_by_name(TABLE_SYMBOL).header
+
+        functions = symtab.symbols.select do |sym|
+            (sym.header.st_info & 0xf) == STT_FUNC && sym.header.st_value.positive?
+        end
+
+        @symbols = functions.map do |sym|
+            [sym.header.st_value, sym.header.st_size, demangle(sym.name).b[0, MAX_NAME_LEN]]
+        end.sort.uniq(&:first)
+    end
+
+    def table_offset_in_file
+        @elf.each_segments do |segment|
+            return segment.vma_to_offset(@table.st_value) if segment.vma_in?(@table.st_value)
+        end
+
+        raise "#{TABLE_SYMBOL} not found in a loadable segment"
+    end
+
+    # Pack as many symbols as fit into the reserved area.
+    def to_binary
+        capacity = @table.st_size
+        entries = []
+        names = String.new(encoding: Encoding::BINARY)
+
+        @symbols.each do |addr, size, name|
+            used = HEADER_SIZE + ((entries.size + 1) * ENTRY_SIZE) + names.bytesize + name.bytesize + 1
+            if used > capacity
+                puts "#{'Warning'.rjust(12).yellow.bold} Symbol table full, dropped " \
+                     "#{@symbols.size - entries.size} symbols"
+                break
+            end
+
+            entries << [addr, size, names.bytesize].pack('Q<L<L<')
+            names << [name.bytesize].pack('C') << name.b
+        end
+
+        names_offset = HEADER_SIZE + (entries.size * ENTRY_SIZE)
+        header = MAGIC.b + [entries.size, names_offset, 0].pack('L<L<L<')
+
+        header + entries.join + names
+    end
+
+    def patch(kernel_elf_path)
+        binary = to_binary
+
+        print 'Patching'.rjust(12).green.bold
+        print " Kernel symbol table (#{binary.bytesize} of #{@table.st_size} bytes) at ELF file offset "
+        puts "0x#{table_offset_in_file.to_s(16)}"
+
+        File.binwrite(kernel_elf_path, binary, table_offset_in_file)
+    end
+end
@@ -0,0 +1,62 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Architectural backtrace support.
+//!
+//! AAPCS64 frame records consist of two 64 bit words: The caller's frame pointer, followed by the
+//! return address. The frame pointer register, x29, points to the record of the current function.
+//!
+//! # Orientation
+//!
+//! Since arch modules are imported into generic modules using the path attribute, the path of this
+//! file is:
+//!
+//! crate::backtrace::arch_backtrace
+
+use core::arch::asm;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Size of a frame record.
+pub const FRAME_RECORD_SIZE: usize = 16;
+
+/// Required alignment of a frame record.
+pub const FRAME_RECORD_ALIGN: usize = 16;
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Return the current frame pointer.
+#[inline(always)]
+pub fn frame_pointer() -> usize {
+    let fp: usize;
+
+    unsafe { asm!("mov {}, x29", out(reg) fp, options(nomem, nostack, preserves_flags)) };
+
+    fp
+}
+
+/// Read the frame record at `fp`. Returns the caller's frame pointer and the return address.
+///
+/// # Safety
+///
+/// - `fp` must point to readable memory of at least `FRAME_RECORD_SIZE` bytes.
+pub unsafe fn read_frame_record(fp: usize) -> (usize, usize) {
+    let record = fp as *const usize;
+
+    (
+        core::ptr::read_volatile(record),
+        core::ptr::read_volatile(record.add(1)),
+    )
+}
+
+/// Turn a return address into the address of the call instruction.
+#[inline(always)]
+pub fn return_addr_to_call_site(return_addr: usize) -> usize {
+    // Instructions are always four bytes.
+    return_addr.wrapping_sub(4)
+}
@@ -27,6 +27,15 @@ pub fn wait_forever() -> ! {
     }
 }
 
+/// Idle the core until an interrupt is pending.
+///
+/// Returns even if IRQs are masked on the core. In that case, the interrupt will be taken once IRQs
+/// are unmasked again.
+#[inline(always)]
+pub fn wait_for_interrupt() {
+    asm::wfi()
+}
+
 //--------------------------------------------------------------------------------------------------
 // Testing
 //--------------------------------------------------------------------------------------------------
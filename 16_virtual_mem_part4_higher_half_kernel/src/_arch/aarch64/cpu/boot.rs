@@ -11,14 +11,25 @@
 //!
 //! crate::cpu::boot::arch_boot
 
-use crate::{memory, memory::Address};
-use core::arch::global_asm;
+use crate::{
+    memory,
+    memory::{Address, Physical},
+};
+use core::{arch::global_asm, ptr};
 use cortex_a::{asm, registers::*};
 use tock_registers::interfaces::Writeable;
 
 // Assembly counterpart to this file.
 global_asm!(include_str!("boot.s"));
 
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// The physical address of the device tree blob, as handed over by the firmware. Zero if there was
+/// none.
+static mut PHYS_DTB_ADDR: u64 = 0;
+
 //--------------------------------------------------------------------------------------------------
 // Private Code
 //--------------------------------------------------------------------------------------------------
@@ -86,7 +97,11 @@ pub unsafe extern "C" fn _start_rust(
     // 引数が仮想addressに変わった
     virt_boot_core_stack_end_exclusive_addr: u64,
     virt_kernel_init_addr: u64,
+    phys_dtb_addr: u64,
 ) -> ! {
+    // The MMU is still off, so this writes straight to DRAM.
+    ptr::write_volatile(ptr::addr_of_mut!(PHYS_DTB_ADDR), phys_dtb_addr);
+
     prepare_el2_to_el1_transition(
         // 引数が仮想addressに変わった
         virt_boot_core_stack_end_exclusive_addr,
@@ -102,3 +117,13 @@ pub unsafe extern "C" fn _start_rust(
     // EL1(OS)でkernel_init()を仮想addressで実行する．
     asm::eret()
 }
+
+/// The physical address of the device tree blob that the firmware handed over, if any.
+pub fn phys_dtb_addr() -> Option<Address<Physical>> {
+    let addr = unsafe { ptr::read_volatile(ptr::addr_of!(PHYS_DTB_ADDR)) };
+
+    match addr {
+        0 => None,
+        x => Some(Address::new(x as usize)),
+    }
+}
@@ -42,6 +42,10 @@
 // fn _start()
 //------------------------------------------------------------------------------
 _start:
+	// The firmware hands over the physical address of the device tree blob in x0. Keep it in x3,
+	// which is not touched until the jump to Rust code.
+	mov	x3, x0
+
 	// Only proceed if the core executes in EL2. Park it otherwise.
 	mrs	x0, CurrentEL
 	cmp	x0, _EL2
@@ -92,12 +96,13 @@ _start:
 	ADR_REL	x4, __boot_core_stack_end_exclusive	// stackの底のPC相対物理address
 	mov	sp, x4									// stack pointerを設定する
 
-	// Jump to Rust code. x0, x1 and x2 hold the function arguments provided to _start_rust().
+	// Jump to Rust code. x0, x1, x2 and x3 hold the function arguments provided to _start_rust().
 	// Rustで書かれた_start_rust関数に飛ぶ
 	// 引数は以下の通り
 	// kernelのtranslation tablesのbase addressが格納されたx0
 	// stackの底の絶対仮想addressが格納されたx1
 	// EL1(OS)で実行する開始地点が格納されたx2
+	// device tree blobの物理addressが格納されたx3
 	b	_start_rust
 
 	// Infinitely wait for events (aka "park the core").
@@ -11,12 +11,18 @@
 //!
 //! crate::exception::arch_exception
 
-use crate::{bsp, exception};
+use crate::{
+    backtrace::Backtrace,
+    bsp, exception,
+    memory::{self, Address, Virtual},
+};
 use core::{arch::global_asm, cell::UnsafeCell, fmt};
 use cortex_a::{asm::barrier, registers::*};
 use tock_registers::{
     interfaces::{Readable, Writeable},
+    register_bitfields,
     registers::InMemoryRegister,
+    LocalRegisterCopy,
 };
 
 // Assembly counterpart to this file.
@@ -31,6 +37,139 @@ global_asm!(include_str!("exception.s"));
 struct SpsrEL1(InMemoryRegister<u64, SPSR_EL1::Register>);
 struct EsrEL1(InMemoryRegister<u64, ESR_EL1::Register>);
 
+// Instruction Specific Syndrome encodings, per exception class.
+register_bitfields! {u64,
+    /// Data Aborts.
+    ISS_DATA_ABORT [
+        /// Instruction Syndrome Valid. If set, SAS, SSE, SRT, SF and AR are valid.
+        ISV   OFFSET(24) NUMBITS(1) [],
+
+        /// Syndrome Access Size.
+        SAS   OFFSET(22) NUMBITS(2) [
+            Byte = 0b00,
+            Halfword = 0b01,
+            Word = 0b10,
+            Doubleword = 0b11
+        ],
+
+        /// Syndrome Sign Extend.
+        SSE   OFFSET(21) NUMBITS(1) [],
+
+        /// Syndrome Register Transfer, the number of the register of the faulting access.
+        SRT   OFFSET(16) NUMBITS(5) [],
+
+        /// Sixty-Four bit register.
+        SF    OFFSET(15) NUMBITS(1) [],
+
+        /// Acquire/Release semantics.
+        AR    OFFSET(14) NUMBITS(1) [],
+
+        /// FAR not Valid.
+        FnV   OFFSET(10) NUMBITS(1) [],
+
+        /// External Abort type.
+        EA    OFFSET(9)  NUMBITS(1) [],
+
+        /// Cache Maintenance.
+        CM    OFFSET(8)  NUMBITS(1) [],
+
+        /// Stage 2 fault on a stage 1 translation table walk.
+        S1PTW OFFSET(7)  NUMBITS(1) [],
+
+        /// Write not Read.
+        WnR   OFFSET(6)  NUMBITS(1) [],
+
+        /// Data Fault Status Code.
+        DFSC  OFFSET(0)  NUMBITS(6) []
+    ],
+
+    /// Instruction Aborts.
+    ISS_INSTR_ABORT [
+        /// FAR not Valid.
+        FnV   OFFSET(10) NUMBITS(1) [],
+
+        /// External Abort type.
+        EA    OFFSET(9)  NUMBITS(1) [],
+
+        /// Stage 2 fault on a stage 1 translation table walk.
+        S1PTW OFFSET(7)  NUMBITS(1) [],
+
+        /// Instruction Fault Status Code.
+        IFSC  OFFSET(0)  NUMBITS(6) []
+    ],
+
+    /// Trapped MSR, MRS or System instructions.
+    ISS_MSR_MRS [
+        OP0       OFFSET(20) NUMBITS(2) [],
+        OP2       OFFSET(17) NUMBITS(3) [],
+        OP1       OFFSET(14) NUMBITS(3) [],
+        CRN       OFFSET(10) NUMBITS(4) [],
+        RT        OFFSET(5)  NUMBITS(5) [],
+        CRM       OFFSET(1)  NUMBITS(4) [],
+
+        /// Direction of the access.
+        DIRECTION OFFSET(0)  NUMBITS(1) [
+            Write = 0,
+            Read = 1
+        ]
+    ],
+
+    /// Trapped WFI or WFE instructions.
+    ISS_WFX [
+        TI OFFSET(0) NUMBITS(2) [
+            WFI = 0b00,
+            WFE = 0b01,
+            WFIT = 0b10,
+            WFET = 0b11
+        ]
+    ],
+
+    /// SVC, HVC, SMC and BRK instructions.
+    ISS_IMM16 [
+        /// The instruction's immediate value, or comment for BRK.
+        IMM16 OFFSET(0) NUMBITS(16) []
+    ],
+
+    /// SError interrupts.
+    ISS_SERROR [
+        /// IMPLEMENTATION DEFINED syndrome. If set, the other fields are not valid.
+        IDS  OFFSET(24) NUMBITS(1) [],
+
+        /// Implicit Error Synchronization event.
+        IESB OFFSET(13) NUMBITS(1) [],
+
+        /// Asynchronous Error Type.
+        AET  OFFSET(10) NUMBITS(3) [
+            Uncontainable = 0b000,
+            UnrecoverableState = 0b001,
+            RestartableState = 0b010,
+            RecoverableState = 0b011,
+            Corrected = 0b110
+        ],
+
+        /// External Abort type.
+        EA   OFFSET(9)  NUMBITS(1) [],
+
+        /// Data Fault Status Code.
+        DFSC OFFSET(0)  NUMBITS(6) [
+            Uncategorized = 0b00_0000,
+            AsyncSError = 0b01_0001
+        ]
+    ],
+
+    /// Watchpoints.
+    ISS_WATCHPOINT [
+        /// Cache Maintenance.
+        CM  OFFSET(8) NUMBITS(1) [],
+
+        /// Write not Read.
+        WnR OFFSET(6) NUMBITS(1) []
+    ]
+}
+
+/// A decoded data or instruction fault status code.
+struct FaultStatus(u64);
+
 /// The exception context as it is stored on the stack on exception entry.
 #[repr(C)]
 struct ExceptionContext {
@@ -67,9 +206,10 @@ fn default_exception_handler(exc: &ExceptionContext) {
 // Current, EL0
 //------------------------------------------------------------------------------
 
+/// A synchronous exception while handling a synchronous exception on the exception stack.
 #[no_mangle]
-unsafe extern "C" fn current_el0_synchronous(_e: &mut ExceptionContext) {
-    panic!("Should not be here. Use of SP_EL0 in EL1 is not supported.")
+unsafe extern "C" fn current_el0_synchronous(e: &mut ExceptionContext) {
+    current_elx_synchronous(e)
 }
 
 #[no_mangle]
@@ -88,6 +228,25 @@ unsafe extern "C" fn current_el0_serror(_e: &mut ExceptionContext) {
 
 #[no_mangle]
 unsafe extern "C" fn current_elx_synchronous(e: &mut ExceptionContext) {
+    // Data aborts of instructions with an exception table entry are resumed at their fixup code.
+    if e.exception_class() == Some(ESR_EL1::EC::Value::DataAbortCurrentEL) {
+        let insn = Address::<Virtual>::new(e.elr_el1 as usize);
+
+        if let Some(fixup) = exception::fixup::search_exception_table(insn) {
+            e.elr_el1 = fixup.as_usize() as u64;
+            return;
+        }
+    }
+
+    if let Some(stack) = e.overflowed_stack() {
+        panic!(
+            "\n\nKernel stack overflow! Stack: {}\n\
+            {}",
+            stack.name(),
+            e
+        );
+    }
+
     default_exception_handler(e);
 }
 
@@ -179,11 +338,268 @@ impl fmt::Display for SpsrEL1 {
     }
 }
 
+/// Return a description of a fault status code, and the translation table level it refers to, if
+/// any.
+fn fault_status(code: u64) -> (&'static str, Option<u64>) {
+    let level = code & 0b11;
+
+    match code {
+        0b00_0000..=0b00_0011 => ("Address size fault", Some(level)),
+        0b00_0100..=0b00_0111 => ("Translation fault", Some(level)),
+        0b00_1000..=0b00_1011 => ("Access flag fault", Some(level)),
+        0b00_1100..=0b00_1111 => ("Permission fault", Some(level)),
+        0b01_0000 => (
+            "Synchronous External abort, not on translation table walk",
+            None,
+        ),
+        0b01_0001 => ("Synchronous Tag Check Fault", None),
+        0b01_0100..=0b01_0111 => (
+            "Synchronous External abort on translation table walk",
+            Some(level),
+        ),
+        0b01_1000 => ("Synchronous parity or ECC error on memory access", None),
+        0b01_1100..=0b01_1111 => (
+            "Synchronous parity or ECC error on translation table walk",
+            Some(level),
+        ),
+        0b10_0001 => ("Alignment fault", None),
+        0b10_0010 => ("Debug exception", None),
+        0b11_0000 => ("TLB conflict abort", None),
+        0b11_0001 => ("Unsupported atomic hardware update fault", None),
+        0b11_0100 => ("IMPLEMENTATION DEFINED fault (Lockdown)", None),
+        0b11_0101 => (
+            "IMPLEMENTATION DEFINED fault (Unsupported Exclusive or Atomic access)",
+            None,
+        ),
+        _ => ("Reserved", None),
+    }
+}
+
+impl fmt::Display for FaultStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match fault_status(self.0) {
+            (name, Some(level)) => write!(f, "{:#04x} - {}, level {}", self.0, name, level),
+            (name, None) => write!(f, "{:#04x} - {}", self.0, name),
+        }
+    }
+}
+
 impl EsrEL1 {
     #[inline(always)]
     fn exception_class(&self) -> Option<ESR_EL1::EC::Value> {
         self.0.read_as_enum(ESR_EL1::EC)
     }
+
+    #[inline(always)]
+    fn iss(&self) -> u64 {
+        self.0.read(ESR_EL1::ISS)
+    }
+
+    /// Return true if the faulting address in FAR_EL1 is flagged as invalid by the syndrome.
+    fn far_not_valid(&self) -> bool {
+        use ESR_EL1::EC::Value::*;
+
+        match self.exception_class() {
+            Some(DataAbortLowerEL | DataAbortCurrentEL) => {
+                LocalRegisterCopy::<u64, ISS_DATA_ABORT::Register>::new(self.iss())
+                    .is_set(ISS_DATA_ABORT::FnV)
+            }
+            Some(InstrAbortLowerEL | InstrAbortCurrentEL) => {
+                LocalRegisterCopy::<u64, ISS_INSTR_ABORT::Register>::new(self.iss())
+                    .is_set(ISS_INSTR_ABORT::FnV)
+            }
+            _ => false,
+        }
+    }
+
+    fn exception_class_str(&self) -> &'static str {
+        use ESR_EL1::EC::Value::*;
+
+        match self.exception_class() {
+            None => "Reserved",
+            Some(ec) => match ec {
+                Unknown => "Unknown reason",
+                TrappedWFIorWFE => "Trapped WFI or WFE instruction",
+                TrappedMCRorMRC => "Trapped MCR or MRC access, coproc == 0b1111",
+                TrappedMCRRorMRRC => "Trapped MCRR or MRRC access, coproc == 0b1111",
+                TrappedMCRorMRC2 => "Trapped MCR or MRC access, coproc == 0b1110",
+                TrappedLDCorSTC => "Trapped LDC or STC access",
+                TrappedFP => "Trapped access to SVE, Advanced SIMD or floating-point",
+                TrappedMRRC => "Trapped MRRC access, coproc == 0b1110",
+                BranchTarget => "Branch Target Exception",
+                IllegalExecutionState => "Illegal Execution state",
+                SVC32 => "SVC instruction, AArch32",
+                SVC64 => "SVC instruction, AArch64",
+                HVC64 => "HVC instruction, AArch64",
+                SMC64 => "SMC instruction, AArch64",
+                TrappedMsrMrs => "Trapped MSR, MRS or System instruction",
+                TrappedSve => "Trapped access to SVE",
+                PointerAuth => "Pointer authentication failure",
+                InstrAbortLowerEL => "Instruction Abort, lower EL",
+                InstrAbortCurrentEL => "Instruction Abort, current EL",
+                PCAlignmentFault => "PC alignment fault",
+                DataAbortLowerEL => "Data Abort, lower EL",
+                DataAbortCurrentEL => "Data Abort, current EL",
+                SPAlignmentFault => "SP alignment fault",
+                TrappedFP32 => "Trapped floating-point exception, AArch32",
+                TrappedFP64 => "Trapped floating-point exception, AArch64",
+                SError => "SError interrupt",
+                BreakpointLowerEL => "Breakpoint, lower EL",
+                BreakpointCurrentEL => "Breakpoint, current EL",
+                SoftwareStepLowerEL => "Software Step, lower EL",
+                SoftwareStepCurrentEL => "Software Step, current EL",
+                WatchpointLowerEL => "Watchpoint, lower EL",
+                WatchpointCurrentEL => "Watchpoint, current EL",
+                Bkpt32 => "BKPT instruction, AArch32",
+                Brk64 => "BRK instruction, AArch64",
+            },
+        }
+    }
+
+    #[rustfmt::skip]
+    fn fmt_data_abort(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ISS_DATA_ABORT::*;
+
+        let iss = LocalRegisterCopy::<u64, Register>::new(self.iss());
+        let access = if iss.is_set(WnR) { "Write" } else { "Read" };
+
+        writeln!(f, "      Data Fault Status Code (DFSC): {}", FaultStatus(iss.read(DFSC)))?;
+        writeln!(f, "      Access                 (WnR) : {}", access)?;
+        writeln!(f, "      Cache Maintenance      (CM)  : {}", iss.is_set(CM))?;
+        writeln!(f, "      Stage 1 Table Walk    (S1PTW): {}", iss.is_set(S1PTW))?;
+        writeln!(f, "      External Abort Type    (EA)  : {:#x}", iss.read(EA))?;
+        writeln!(f, "      FAR not Valid          (FnV) : {}", iss.is_set(FnV))?;
+
+        if !iss.is_set(ISV) {
+            return write!(f, "      Instruction Syndrome   (ISV) : Not valid");
+        }
+
+        let size = match iss.read_as_enum(SAS) {
+            Some(SAS::Value::Byte) => "Byte",
+            Some(SAS::Value::Halfword) => "Halfword",
+            Some(SAS::Value::Word) => "Word",
+            Some(SAS::Value::Doubleword) => "Doubleword",
+            None => "N/A",
+        };
+        let reg_prefix = if iss.is_set(SF) { 'x' } else { 'w' };
+
+        writeln!(f, "      Access Size            (SAS) : {}", size)?;
+        writeln!(f, "      Sign Extended          (SSE) : {}", iss.is_set(SSE))?;
+        writeln!(f, "      Register               (SRT) : {}{}", reg_prefix, iss.read(SRT))?;
+        write!(f,   "      Acquire/Release        (AR)  : {}", iss.is_set(AR))
+    }
+
+    #[rustfmt::skip]
+    fn fmt_instr_abort(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ISS_INSTR_ABORT::*;
+
+        let iss = LocalRegisterCopy::<u64, Register>::new(self.iss());
+
+        writeln!(f, "      Instr Fault Status Code (IFSC): {}", FaultStatus(iss.read(IFSC)))?;
+        writeln!(f, "      Stage 1 Table Walk    (S1PTW): {}", iss.is_set(S1PTW))?;
+        writeln!(f, "      External Abort Type    (EA)  : {:#x}", iss.read(EA))?;
+        write!(f,   "      FAR not Valid          (FnV) : {}", iss.is_set(FnV))
+    }
+
+    #[rustfmt::skip]
+    fn fmt_msr_mrs(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ISS_MSR_MRS::*;
+
+        let iss = LocalRegisterCopy::<u64, Register>::new(self.iss());
+        let (op0, op1, crn, crm, op2) =
+            (iss.read(OP0), iss.read(OP1), iss.read(CRN), iss.read(CRM), iss.read(OP2));
+        let rt = iss.read(RT);
+
+        match iss.read_as_enum(DIRECTION) {
+            Some(DIRECTION::Value::Read) => write!(f,
+                "      Trapped instruction: MRS x{}, S{}_{}_C{}_C{}_{}", rt, op0, op1, crn, crm, op2),
+            _ => write!(f,
+                "      Trapped instruction: MSR S{}_{}_C{}_C{}_{}, x{}", op0, op1, crn, crm, op2, rt),
+        }
+    }
+
+    #[rustfmt::skip]
+    fn fmt_serror(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ISS_SERROR::*;
+
+        let iss = LocalRegisterCopy::<u64, Register>::new(self.iss());
+
+        if iss.is_set(IDS) {
+            return write!(f, "      IMPLEMENTATION DEFINED syndrome (IDS): {:#x}", self.iss());
+        }
+
+        let dfsc = match iss.read_as_enum(DFSC) {
+            Some(DFSC::Value::Uncategorized) => "Uncategorized error",
+            Some(DFSC::Value::AsyncSError) => "Asynchronous SError interrupt",
+            None => "Reserved",
+        };
+
+        let aet = match iss.read_as_enum(AET) {
+            Some(AET::Value::Uncontainable) => "Uncontainable",
+            Some(AET::Value::UnrecoverableState) => "Unrecoverable state",
+            Some(AET::Value::RestartableState) => "Restartable state",
+            Some(AET::Value::RecoverableState) => "Recoverable state",
+            Some(AET::Value::Corrected) => "Corrected",
+            None => "Reserved",
+        };
+
+        writeln!(f, "      Data Fault Status Code (DFSC): {:#04x} - {}", iss.read(DFSC), dfsc)?;
+        writeln!(f, "      Error Type             (AET) : {}", aet)?;
+        writeln!(f, "      External Abort Type    (EA)  : {:#x}", iss.read(EA))?;
+        write!(f,   "      Implicit Error Sync    (IESB): {}", iss.is_set(IESB))
+    }
+
+    /// Print the fields of the ISS that are specific to the exception class, if any.
+    fn fmt_iss(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ESR_EL1::EC::Value::*;
+
+        let imm16 =
+            LocalRegisterCopy::<u64, ISS_IMM16::Register>::new(self.iss()).read(ISS_IMM16::IMM16);
+
+        let ec = match self.exception_class() {
+            None => return Ok(()),
+            Some(ec) => ec,
+        };
+
+        writeln!(f)?;
+        match ec {
+            DataAbortLowerEL | DataAbortCurrentEL => self.fmt_data_abort(f),
+            InstrAbortLowerEL | InstrAbortCurrentEL => self.fmt_instr_abort(f),
+            TrappedMsrMrs => self.fmt_msr_mrs(f),
+            SError => self.fmt_serror(f),
+            SVC64 | HVC64 | SMC64 => write!(f, "      Immediate: {:#06x}", imm16),
+            Brk64 => write!(f, "      Comment: {:#06x}", imm16),
+            TrappedWFIorWFE => {
+                use ISS_WFX::TI;
+
+                let instr = match LocalRegisterCopy::<u64, ISS_WFX::Register>::new(self.iss())
+                    .read_as_enum(TI)
+                {
+                    Some(TI::Value::WFI) => "WFI",
+                    Some(TI::Value::WFE) => "WFE",
+                    Some(TI::Value::WFIT) => "WFIT",
+                    Some(TI::Value::WFET) => "WFET",
+                    None => "N/A",
+                };
+
+                write!(f, "      Trapped instruction: {}", instr)
+            }
+            WatchpointLowerEL | WatchpointCurrentEL => {
+                let iss = LocalRegisterCopy::<u64, ISS_WATCHPOINT::Register>::new(self.iss());
+                let access = if iss.is_set(ISS_WATCHPOINT::WnR) {
+                    "Write"
+                } else {
+                    "Read"
+                };
+
+                write!(f, "      Access: {}", access)?;
+                write!(f, ", Cache Maintenance: {}", iss.is_set(ISS_WATCHPOINT::CM))
+            }
+            PCAlignmentFault => write!(f, "      The misaligned PC is in FAR_EL1."),
+            SPAlignmentFault => write!(f, "      The stack pointer was not 16 byte aligned."),
+            _ => write!(f, "      No further decoding for this exception class."),
+        }
+    }
 }
 
 /// Human readable ESR_EL1.
@@ -194,17 +610,18 @@ impl fmt::Display for EsrEL1 {
         writeln!(f, "ESR_EL1: {:#010x}", self.0.get())?;
 
         // Raw print of exception class.
-        write!(f, "      Exception Class         (EC) : {:#x}", self.0.read(ESR_EL1::EC))?;
+        writeln!(f, "      Exception Class         (EC) : {:#x} - {}",
+            self.0.read(ESR_EL1::EC),
+            self.exception_class_str()
+        )?;
 
-        // Exception class.
-        let ec_translation = match self.exception_class() {
-            Some(ESR_EL1::EC::Value::DataAbortCurrentEL) => "Data Abort, current EL",
-            _ => "N/A",
-        };
-        writeln!(f, " - {}", ec_translation)?;
+        let il = if self.0.is_set(ESR_EL1::IL) { "32 bit" } else { "16 bit" };
+        writeln!(f, "      Instr Length            (IL) : {}", il)?;
 
         // Raw print of instruction specific syndrome.
-        write!(f, "      Instr Specific Syndrome (ISS): {:#x}", self.0.read(ESR_EL1::ISS))
+        write!(f, "      Instr Specific Syndrome (ISS): {:#x}", self.iss())?;
+
+        self.fmt_iss(f)
     }
 }
 
@@ -218,6 +635,10 @@ impl ExceptionContext {
     fn fault_address_valid(&self) -> bool {
         use ESR_EL1::EC::Value::*;
 
+        if self.esr_el1.far_not_valid() {
+            return false;
+        }
+
         match self.exception_class() {
             None => false,
             Some(ec) => matches!(
@@ -232,6 +653,17 @@ impl ExceptionContext {
             ),
         }
     }
+
+    /// Return the stack whose guard page was hit by a data abort, if any.
+    fn overflowed_stack(&self) -> Option<memory::stack::KernelStack> {
+        if self.exception_class() != Some(ESR_EL1::EC::Value::DataAbortCurrentEL)
+            || !self.fault_address_valid()
+        {
+            return None;
+        }
+
+        memory::stack::find_by_guard_page(Address::new(FAR_EL1.get() as usize))
+    }
 }
 
 /// Human readable print of the exception context.
@@ -257,7 +689,12 @@ impl fmt::Display for ExceptionContext {
         for (i, reg) in self.gpr.iter().enumerate() {
             write!(f, "      x{: <2}: {: >#018x}{}", i, reg, alternating(i))?;
         }
-        write!(f, "      lr : {:#018x}", self.lr)
+        writeln!(f, "      lr : {:#018x}", self.lr)?;
+        writeln!(f)?;
+
+        // x29 is the frame pointer.
+        let backtrace = Backtrace::from_frame(self.elr_el1 as usize, self.gpr[29] as usize);
+        write!(f, "{}", backtrace)
     }
 }
 
@@ -293,6 +730,34 @@ pub unsafe fn handling_init() {
 
     VBAR_EL1.set(__exception_vector_start.get() as u64);
 
+    // Synchronous exceptions are handled on the exception stack. See `exception.s`.
+    let exception_stack = bsp::memory::mmu::virt_exception_stack_region();
+    SP_EL0.set(
+        exception_stack
+            .end_exclusive_page_addr()
+            .into_inner()
+            .as_usize() as u64,
+    );
+
     // Force VBAR update to complete before next instruction.
     barrier::isb(barrier::SY);
 }
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Fault status codes are decoded including the translation table level.
+    #[kernel_test]
+    fn fault_status_decoding() {
+        assert_eq!(fault_status(0b00_0111), ("Translation fault", Some(3)));
+        assert_eq!(fault_status(0b00_1101), ("Permission fault", Some(1)));
+        assert_eq!(fault_status(0b10_0001), ("Alignment fault", None));
+        assert_eq!(fault_status(0b11_1111), ("Reserved", None));
+    }
+}
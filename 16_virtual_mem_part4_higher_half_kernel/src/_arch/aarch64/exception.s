@@ -49,6 +49,16 @@
 	b	__exception_restore_context
 .endm
 
+/// Same as `CALL_WITH_CONTEXT`, but on the exception stack, which `SP_EL0` points into.
+///
+/// Used for synchronous exceptions, whose cause might be an overflow of the interrupted code's
+/// stack. `eret` restores the interrupted code's stack pointer selection from SPSR_EL1.
+.macro CALL_WITH_CONTEXT_ON_EXCEPTION_STACK handler
+	msr	SPSel, #0
+
+	CALL_WITH_CONTEXT \handler
+.endm
+
 .macro FIQ_SUSPEND
 1:	wfe
 	b	1b
@@ -71,13 +81,15 @@ __exception_vector_start:
 
 // Current exception level with SP_EL0.
 //
+// SP_EL0 is only in use while a synchronous exception is handled on the exception stack.
+//
 // .org sets the offset relative to section start.
 //
 // # Safety
 //
-// - It must be ensured that `CALL_WITH_CONTEXT` <= 0x80 bytes.
+// - It must be ensured that `CALL_WITH_CONTEXT_ON_EXCEPTION_STACK` <= 0x80 bytes.
 .org 0x000
-	CALL_WITH_CONTEXT current_el0_synchronous
+	CALL_WITH_CONTEXT_ON_EXCEPTION_STACK current_el0_synchronous
 .org 0x080
 	CALL_WITH_CONTEXT current_el0_irq
 .org 0x100
@@ -87,7 +99,7 @@ __exception_vector_start:
 
 // Current exception level with SP_ELx, x > 0.
 .org 0x200
-	CALL_WITH_CONTEXT current_elx_synchronous
+	CALL_WITH_CONTEXT_ON_EXCEPTION_STACK current_elx_synchronous
 .org 0x280
 	CALL_WITH_CONTEXT current_elx_irq
 .org 0x300
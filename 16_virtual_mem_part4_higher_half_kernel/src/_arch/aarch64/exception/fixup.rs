@@ -0,0 +1,90 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Architectural memory accessors that recover from faults.
+//!
+//! Every instruction that might fault gets an entry in the `__ex_table` section, which points to
+//! the code that reports the fault to the caller.
+//!
+//! # Orientation
+//!
+//! Since arch modules are imported into generic modules using the path attribute, the path of this
+//! file is:
+//!
+//! crate::exception::fixup::arch_fixup
+
+use core::arch::asm;
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Read a 32 bit word. Returns `None` if the access faulted.
+///
+/// # Safety
+///
+/// - Reads from MMIO addresses can have side effects.
+pub unsafe fn read_u32(addr: usize) -> Option<u32> {
+    let value: u32;
+    let faulted: u64;
+
+    asm!(
+        "1: ldr {value:w}, [{addr}]",
+        "   mov {faulted}, #0",
+        "   b   3f",
+        "2: mov {faulted}, #1",
+        "   mov {value:w}, wzr",
+        "3:",
+        ".pushsection __ex_table, \"a\"",
+        ".balign 8",
+        ".quad 1b, 2b",
+        ".popsection",
+        addr = in(reg) addr,
+        value = out(reg) value,
+        faulted = out(reg) faulted,
+        options(nostack),
+    );
+
+    if faulted != 0 {
+        return None;
+    }
+
+    Some(value)
+}
+
+/// Copy `len` bytes from `src` to `dst`, one byte at a time. Returns false if an access faulted.
+///
+/// On a fault, the bytes before the faulting one have already been copied.
+///
+/// # Safety
+///
+/// - Writes to `dst` are not checked in any way, besides recovering from faults.
+pub unsafe fn copy_bytes(dst: usize, src: usize, len: usize) -> bool {
+    let completed: u64;
+
+    asm!(
+        "   cbz  {len}, 3f",
+        "1: ldrb {tmp:w}, [{src}], #1",
+        "2: strb {tmp:w}, [{dst}], #1",
+        "   subs {len}, {len}, #1",
+        "   b.ne 1b",
+        "3: mov  {completed}, #1",
+        "   b    5f",
+        "4: mov  {completed}, #0",
+        "5:",
+        ".pushsection __ex_table, \"a\"",
+        ".balign 8",
+        ".quad 1b, 4b",
+        ".quad 2b, 4b",
+        ".popsection",
+        src = inout(reg) src => _,
+        dst = inout(reg) dst => _,
+        len = inout(reg) len => _,
+        tmp = out(reg) _,
+        completed = out(reg) completed,
+        options(nostack),
+    );
+
+    completed != 0
+}
@@ -0,0 +1,116 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Architectural cache maintenance.
+//!
+//! Data cache maintenance is done by VA to the Point of Coherency, so that the results are visible
+//! to DMA masters as well. The line sizes are read from `CTR_EL0`.
+//!
+//! # Orientation
+//!
+//! Since arch modules are imported into generic modules using the path attribute, the path of this
+//! file is:
+//!
+//! crate::memory::cache::arch_cache
+
+use crate::memory::{Address, Virtual};
+use core::arch::asm;
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// The cache type register. Not provided by the register crate.
+#[inline(always)]
+fn ctr_el0() -> u64 {
+    let ctr: u64;
+    unsafe { asm!("mrs {}, CTR_EL0", out(reg) ctr, options(nomem, nostack, preserves_flags)) };
+
+    ctr
+}
+
+/// Calls `f` with the address of every cache line of `line_size` that overlaps the given range.
+#[inline(always)]
+fn for_each_line(start_addr: Address<Virtual>, size: usize, line_size: usize, f: impl Fn(usize)) {
+    if size == 0 {
+        return;
+    }
+
+    let mut addr = start_addr.as_usize() & !(line_size - 1);
+    let end_exclusive = start_addr.as_usize() + size;
+
+    while addr < end_exclusive {
+        f(addr);
+        addr += line_size;
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Smallest data cache line size in byte.
+pub fn data_cache_line_size() -> usize {
+    // DminLine, log2 of the number of words.
+    4 << ((ctr_el0() >> 16) & 0xf)
+}
+
+/// Write dirty data in the range back to memory.
+pub fn clean_range(start_addr: Address<Virtual>, size: usize) {
+    for_each_line(start_addr, size, data_cache_line_size(), |addr| unsafe {
+        asm!("dc cvac, {}", in(reg) addr, options(nostack, preserves_flags))
+    });
+
+    unsafe { asm!("dsb sy", options(nostack, preserves_flags)) };
+}
+
+/// Discard the cached data in the range, so that the next read is served from memory.
+///
+/// Partially covered lines at the edges are cleaned as well, so that neighbouring data is not lost.
+///
+/// # Safety
+///
+/// - Writes in the range that have not reached memory yet are lost.
+pub unsafe fn invalidate_range(start_addr: Address<Virtual>, size: usize) {
+    let line_size = data_cache_line_size();
+    let start = start_addr.as_usize();
+    let end_exclusive = start + size;
+
+    for_each_line(start_addr, size, line_size, |addr| {
+        if addr < start || addr + line_size > end_exclusive {
+            asm!("dc civac, {}", in(reg) addr, options(nostack, preserves_flags))
+        } else {
+            asm!("dc ivac, {}", in(reg) addr, options(nostack, preserves_flags))
+        }
+    });
+
+    asm!("dsb sy", options(nostack, preserves_flags));
+}
+
+/// Write dirty data in the range back to memory and discard the cached data afterwards.
+pub fn clean_and_invalidate_range(start_addr: Address<Virtual>, size: usize) {
+    for_each_line(start_addr, size, data_cache_line_size(), |addr| unsafe {
+        asm!("dc civac, {}", in(reg) addr, options(nostack, preserves_flags))
+    });
+
+    unsafe { asm!("dsb sy", options(nostack, preserves_flags)) };
+}
+
+/// Make instructions that were written to the range visible to instruction fetches.
+pub fn sync_instruction_cache(start_addr: Address<Virtual>, size: usize) {
+    // Instructions are fetched from the Point of Unification, which is sufficient here.
+    for_each_line(start_addr, size, data_cache_line_size(), |addr| unsafe {
+        asm!("dc cvau, {}", in(reg) addr, options(nostack, preserves_flags))
+    });
+
+    unsafe {
+        asm!(
+            "dsb ish",
+            "ic iallu",
+            "dsb ish",
+            "isb",
+            options(nostack, preserves_flags)
+        )
+    };
+}
@@ -4,7 +4,8 @@
 
 //! Memory Management Unit Driver.
 //!
-//! Only 64 KiB granule is supported.
+//! The 4 KiB, 16 KiB and 64 KiB granules are supported. The BSP selects one through
+//! `bsp::memory::mmu::KernelGranule`.
 //!
 //! # Orientation
 //!
@@ -32,14 +33,29 @@ struct MemoryManagementUnit;
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
-pub type Granule512MiB = TranslationGranule<{ 512 * 1024 * 1024 }>;
+pub type Granule4KiB = TranslationGranule<{ 4 * 1024 }>;
+pub type Granule16KiB = TranslationGranule<{ 16 * 1024 }>;
 pub type Granule64KiB = TranslationGranule<{ 64 * 1024 }>;
 
+/// Number of descriptors in a translation table. Every table occupies exactly one granule.
+pub const NUM_TABLE_ENTRIES: usize =
+    bsp::memory::mmu::KernelGranule::SIZE / core::mem::size_of::<u64>();
+
+/// The window covered by a level 2 descriptor, which is also the size of a level 2 block.
+///
+/// 2 MiB, 32 MiB or 512 MiB for the 4 KiB, 16 KiB or 64 KiB granule, respectively.
+pub type Lvl2Granule =
+    TranslationGranule<{ bsp::memory::mmu::KernelGranule::SIZE * NUM_TABLE_ENTRIES }>;
+
+/// The window covered by a level 1 descriptor.
+pub type Lvl1Granule = TranslationGranule<{ Lvl2Granule::SIZE * NUM_TABLE_ENTRIES }>;
+
 /// Constants for indexing the MAIR_EL1.
 #[allow(dead_code)]
 pub mod mair {
     pub const DEVICE: u64 = 0;
     pub const NORMAL: u64 = 1;
+    pub const NORMAL_NON_CACHEABLE: u64 = 2;
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -55,8 +71,11 @@ static MMU: MemoryManagementUnit = MemoryManagementUnit;
 impl<const AS_SIZE: usize> memory::mmu::AddressSpace<AS_SIZE> {
     /// Checks for architectural restrictions.
     pub const fn arch_address_space_size_sanity_checks() {
-        // Size must be at least one full 512 MiB table.
-        assert!((AS_SIZE % Granule512MiB::SIZE) == 0);
+        // Size must be at least one full level 3 table.
+        assert!((AS_SIZE % Lvl2Granule::SIZE) == 0);
+
+        // The translation tables provide at most three lookup levels, starting at level 1.
+        assert!(AS_SIZE <= Lvl1Granule::SIZE * NUM_TABLE_ENTRIES);
 
         // Check for 48 bit virtual address size as maximum, which is supported by any ARMv8
         // version.
@@ -70,6 +89,10 @@ impl MemoryManagementUnit {
     fn set_up_mair(&self) {
         // Define the memory types being mapped.
         MAIR_EL1.write(
+            // Attribute 2 - Non-cacheable normal DRAM.
+            MAIR_EL1::Attr2_Normal_Outer::NonCacheable +
+        MAIR_EL1::Attr2_Normal_Inner::NonCacheable +
+
             // Attribute 1 - Cacheable normal DRAM.
             MAIR_EL1::Attr1_Normal_Outer::WriteBack_NonTransient_ReadWriteAlloc +
         MAIR_EL1::Attr1_Normal_Inner::WriteBack_NonTransient_ReadWriteAlloc +
@@ -80,8 +103,17 @@ impl MemoryManagementUnit {
     }
 
     /// Configure various settings of stage 1 of the EL1 translation regime.
+    ///
+    /// The number of lookup levels follows from the granule and the size of the address space
+    /// (T1SZ), and matches the layout of the kernel's translation tables.
     #[inline(always)]
     fn configure_translation_control(&self) {
+        let tg1 = match bsp::memory::mmu::KernelGranule::SIZE {
+            Granule4KiB::SIZE => TCR_EL1::TG1::KiB_4,
+            Granule16KiB::SIZE => TCR_EL1::TG1::KiB_16,
+            _ => TCR_EL1::TG1::KiB_64,
+        };
+
         // t0szからt1szに変更された
         let t1sz = (64 - bsp::memory::mmu::KernelVirtAddrSpace::SIZE_SHIFT) as u64;
 
@@ -97,7 +129,7 @@ impl MemoryManagementUnit {
             TCR_EL1::TBI1::Used
                 + TCR_EL1::IPS::Bits_40
                 // TG0をTG1に変更
-                + TCR_EL1::TG1::KiB_64
+                + tg1
                 // SH0をSH1に変更
                 + TCR_EL1::SH1::Inner
                 // ORGN0をORGN1に変更
@@ -125,6 +157,22 @@ pub fn mmu() -> &'static impl memory::mmu::interface::MMU {
     &MMU
 }
 
+/// Invalidate all cached EL1 translations of the executing core.
+///
+/// Preceding writes to the translation tables are made visible to the table walker first.
+#[inline(always)]
+pub fn invalidate_tlb() {
+    unsafe {
+        core::arch::asm!(
+            "dsb ishst",
+            "tlbi vmalle1",
+            "dsb ish",
+            "isb",
+            options(nostack)
+        )
+    };
+}
+
 //------------------------------------------------------------------------------
 // OS Interface Code
 //------------------------------------------------------------------------------
@@ -140,7 +188,14 @@ impl memory::mmu::interface::MMU for MemoryManagementUnit {
         }
 
         // Fail early if translation granule is not supported.
-        if unlikely(!ID_AA64MMFR0_EL1.matches_all(ID_AA64MMFR0_EL1::TGran64::Supported)) {
+        let granule_supported = match bsp::memory::mmu::KernelGranule::SIZE {
+            Granule4KiB::SIZE => ID_AA64MMFR0_EL1.matches_all(ID_AA64MMFR0_EL1::TGran4::Supported),
+            Granule16KiB::SIZE => {
+                ID_AA64MMFR0_EL1.matches_all(ID_AA64MMFR0_EL1::TGran16::Supported)
+            }
+            _ => ID_AA64MMFR0_EL1.matches_all(ID_AA64MMFR0_EL1::TGran64::Supported),
+        };
+        if unlikely(!granule_supported) {
             return Err(MMUEnableError::Other(
                 "Translation granule not supported in HW",
             ));
@@ -161,8 +216,14 @@ impl memory::mmu::interface::MMU for MemoryManagementUnit {
         // First, force all previous changes to be seen before the MMU is enabled.
         barrier::isb(barrier::SY);
 
-        // Enable the MMU and turn on data and instruction caching.
-        SCTLR_EL1.modify(SCTLR_EL1::M::Enable + SCTLR_EL1::C::Cacheable + SCTLR_EL1::I::Cacheable);
+        // Enable the MMU and turn on data and instruction caching. Additionally, let the hardware
+        // treat all writable memory as execute-never (W^X).
+        SCTLR_EL1.modify(
+            SCTLR_EL1::M::Enable
+                + SCTLR_EL1::C::Cacheable
+                + SCTLR_EL1::I::Cacheable
+                + SCTLR_EL1::WXN::Enable,
+        );
 
         // Force MMU init to complete before next instruction.
         barrier::isb(barrier::SY);
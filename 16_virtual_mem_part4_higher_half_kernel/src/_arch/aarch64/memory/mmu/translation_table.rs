@@ -4,7 +4,20 @@
 
 //! Architectural translation table.
 //!
-//! Only 64 KiB granule is supported.
+//! The layout follows the kernel granule. Level 3 tables hold the page descriptors. Level 2 entries
+//! either point to a level 3 table, or directly map a block of `Lvl2Granule` size. Address spaces
+//! that are larger than what a single level 2 table covers get a level 1 table on top.
+//!
+//! Every table occupies one physical frame. The tables are allocated from the kernel's translation
+//! table frames when they are needed. The kernel's tables start out with the tables that the
+//! `translation table tool` precomputes for the kernel binary.
+//!
+//! There is no separate `FixedSizeTranslationTable` for the boot tables anymore. The tool writes
+//! the precomputed tables into translation table frames, in exactly the layout of
+//! `DynamicTranslationTable`, and `init_precomputed()` adopts them. A second type would only
+//! duplicate the table walk, and handing the kernel over from one type to the other at runtime
+//! would need a copy of every table. The static `.data` tables that scaled with the kernel address
+//! space are gone either way.
 //!
 //! # Orientation
 //!
@@ -14,19 +27,25 @@
 //! crate::memory::mmu::translation_table::arch_translation_table
 
 use crate::{
-    bsp,
+    bsp::{self, memory::mmu::KernelGranule},
+    common,
     memory::{
         self,
         mmu::{
-            arch_mmu::{Granule512MiB, Granule64KiB},
+            alloc,
+            arch_mmu::{
+                self, Granule16KiB, Granule4KiB, Granule64KiB, Lvl2Granule, NUM_TABLE_ENTRIES,
+            },
             AccessPermissions, AttributeFields, MemAttributes, MemoryRegion, PageAddress,
         },
         Address, Physical, Virtual,
     },
+    synchronization::interface::Mutex,
+    warn,
 };
-use core::convert;
+use core::{convert, num::NonZeroUsize};
 use tock_registers::{
-    interfaces::{Readable, Writeable},
+    interfaces::{ReadWriteable, Readable, Writeable},
     register_bitfields,
     registers::InMemoryRegister,
 };
@@ -35,11 +54,15 @@ use tock_registers::{
 // Private Definitions
 //--------------------------------------------------------------------------------------------------
 
+/// Descriptors store addresses in bits [47:12], regardless of the granule. For the larger granules,
+/// the lowest bits of the field are zero due to alignment.
+const ADDR_FIELD_SHIFT: usize = Granule4KiB::SHIFT;
+
 // A table descriptor, as per ARMv8-A Architecture Reference Manual Figure D5-15.
 register_bitfields! {u64,
     STAGE1_TABLE_DESCRIPTOR [
         /// Physical address of the next descriptor.
-        NEXT_LEVEL_TABLE_ADDR_64KiB OFFSET(16) NUMBITS(32) [], // [47:16]
+        NEXT_LEVEL_TABLE_ADDR OFFSET(12) NUMBITS(36) [], // [47:12]
 
         TYPE  OFFSET(1) NUMBITS(1) [
             Block = 0,
@@ -69,7 +92,7 @@ register_bitfields! {u64,
         ],
 
         /// Physical address of the next table descriptor (lvl2) or the page descriptor (lvl3).
-        OUTPUT_ADDR_64KiB OFFSET(16) NUMBITS(32) [], // [47:16]
+        OUTPUT_ADDR OFFSET(12) NUMBITS(36) [], // [47:12]
 
         /// Access flag.
         AF       OFFSET(10) NUMBITS(1) [
@@ -106,16 +129,17 @@ register_bitfields! {u64,
     ]
 }
 
-/// A table descriptor for 64 KiB aperture.
+/// A level 1 or level 2 descriptor.
 ///
-/// The output points to the next table.
+/// As a table descriptor, the output points to the next table. As a block descriptor, which is only
+/// used on level 2, the output points to `Lvl2Granule` of physical memory.
 #[derive(Copy, Clone)]
 #[repr(C)]
 struct TableDescriptor {
     value: u64,
 }
 
-/// A page descriptor with 64 KiB aperture.
+/// A page descriptor with an aperture of the kernel granule.
 ///
 /// The output points to physical memory.
 #[derive(Copy, Clone)]
@@ -124,40 +148,37 @@ struct PageDescriptor {
     value: u64,
 }
 
-trait StartAddr {
-    fn virt_start_addr(&self) -> Address<Virtual>;
+/// A physical frame that holds a translation table, and the kernel virtual address it is accessible
+/// at.
+#[derive(Copy, Clone)]
+struct TableFrame {
+    phys_page_addr: PageAddress<Physical>,
+    virt_page_addr: PageAddress<Virtual>,
 }
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
-/// Big monolithic struct for storing the translation tables. Individual levels must be 64 KiB
-/// aligned, so the lvl3 is put first.
+/// Translation tables whose tables are allocated from the kernel's translation table frames on
+/// demand.
+///
+/// The root table is allocated by `init()`, or taken over from the `translation table tool` with
+/// `init_precomputed()`. Lower level tables are allocated when `map_at()` touches a new range, and
+/// released when `unmap_at()` leaves them empty.
+///
+/// `NUM_TABLES` is the number of `Lvl2Granule` windows of the address space, which is the number
+/// of lvl3 tables that can exist at most.
 /// kernel空間とuser空間を区別するためのSTART_FROM_TOPが追加された
-#[repr(C)]
-#[repr(align(65536))]
-pub struct FixedSizeTranslationTable<const NUM_TABLES: usize, const START_FROM_TOP: bool> {
-    /// Page descriptors, covering 64 KiB windows per entry.
-    lvl3: [[PageDescriptor; 8192]; NUM_TABLES],
-
-    /// Table descriptors, covering 512 MiB windows.
-    lvl2: [TableDescriptor; NUM_TABLES],
-
-    /// Have the tables been initialized?
-    initialized: bool,
+pub struct DynamicTranslationTable<const NUM_TABLES: usize, const START_FROM_TOP: bool> {
+    /// The lvl1 table if the lookup starts at level 1, the lvl2 table otherwise.
+    root: Option<TableFrame>,
 }
 
 //--------------------------------------------------------------------------------------------------
 // Private Code
 //--------------------------------------------------------------------------------------------------
 
-impl<T, const N: usize> StartAddr for [T; N] {
-    fn virt_start_addr(&self) -> Address<Virtual> {
-        Address::new(self as *const _ as usize)
-    }
-}
-
 impl TableDescriptor {
     /// Create an instance.
     ///
@@ -170,15 +191,74 @@ impl TableDescriptor {
     pub fn from_next_lvl_table_addr(phys_next_lvl_table_addr: Address<Physical>) -> Self {
         let val = InMemoryRegister::<u64, STAGE1_TABLE_DESCRIPTOR::Register>::new(0);
 
-        let shifted = phys_next_lvl_table_addr.as_usize() >> Granule64KiB::SHIFT;
+        let shifted = phys_next_lvl_table_addr.as_usize() >> ADDR_FIELD_SHIFT;
         val.write(
-            STAGE1_TABLE_DESCRIPTOR::NEXT_LEVEL_TABLE_ADDR_64KiB.val(shifted as u64)
+            STAGE1_TABLE_DESCRIPTOR::NEXT_LEVEL_TABLE_ADDR.val(shifted as u64)
                 + STAGE1_TABLE_DESCRIPTOR::TYPE::Table
                 + STAGE1_TABLE_DESCRIPTOR::VALID::True,
         );
 
         TableDescriptor { value: val.get() }
     }
+
+    /// Create a block descriptor for the supplied `Lvl2Granule` aligned output address.
+    ///
+    /// Block descriptors share the layout of page descriptors, except for the type bit.
+    pub fn from_output_block_addr(
+        phys_output_block_addr: PageAddress<Physical>,
+        attribute_fields: &AttributeFields,
+    ) -> Self {
+        let page_desc =
+            PageDescriptor::from_output_page_addr(phys_output_block_addr, attribute_fields);
+
+        let val = InMemoryRegister::<u64, STAGE1_TABLE_DESCRIPTOR::Register>::new(page_desc.value);
+        val.modify(STAGE1_TABLE_DESCRIPTOR::TYPE::Block);
+
+        TableDescriptor { value: val.get() }
+    }
+
+    /// Returns the valid bit.
+    fn is_valid(&self) -> bool {
+        InMemoryRegister::<u64, STAGE1_TABLE_DESCRIPTOR::Register>::new(self.value)
+            .is_set(STAGE1_TABLE_DESCRIPTOR::VALID)
+    }
+
+    /// Returns true if this is a valid block descriptor.
+    fn is_block(&self) -> bool {
+        let val = InMemoryRegister::<u64, STAGE1_TABLE_DESCRIPTOR::Register>::new(self.value);
+
+        val.is_set(STAGE1_TABLE_DESCRIPTOR::VALID)
+            && val.matches_all(STAGE1_TABLE_DESCRIPTOR::TYPE::Block)
+    }
+
+    /// Returns the address of the next level table, if this is a valid table descriptor.
+    fn next_lvl_table_addr(&self) -> Option<Address<Physical>> {
+        let val = InMemoryRegister::<u64, STAGE1_TABLE_DESCRIPTOR::Register>::new(self.value);
+
+        if !val.is_set(STAGE1_TABLE_DESCRIPTOR::VALID)
+            || !val.matches_all(STAGE1_TABLE_DESCRIPTOR::TYPE::Table)
+        {
+            return None;
+        }
+
+        let shifted = val.read(STAGE1_TABLE_DESCRIPTOR::NEXT_LEVEL_TABLE_ADDR) as usize;
+        Some(Address::new(shifted << ADDR_FIELD_SHIFT))
+    }
+
+    /// Returns the page descriptor that maps the same memory as the block descriptor's page
+    /// `lvl3_index`.
+    fn block_page_descriptor(&self, lvl3_index: usize) -> PageDescriptor {
+        let val = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(self.value);
+        let page_offset = (lvl3_index << KernelGranule::SHIFT) >> ADDR_FIELD_SHIFT;
+        let output_page = val.read(STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR) + page_offset as u64;
+
+        val.modify(
+            STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR.val(output_page)
+                + STAGE1_PAGE_DESCRIPTOR::TYPE::Page,
+        );
+
+        PageDescriptor { value: val.get() }
+    }
 }
 
 /// Convert the kernel's generic memory attributes to HW-specific attributes of the MMU.
@@ -192,6 +272,11 @@ impl convert::From<AttributeFields>
                 STAGE1_PAGE_DESCRIPTOR::SH::InnerShareable
                     + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(memory::mmu::arch_mmu::mair::NORMAL)
             }
+            MemAttributes::NonCacheable => {
+                STAGE1_PAGE_DESCRIPTOR::SH::OuterShareable
+                    + STAGE1_PAGE_DESCRIPTOR::AttrIndx
+                        .val(memory::mmu::arch_mmu::mair::NORMAL_NON_CACHEABLE)
+            }
             MemAttributes::Device => {
                 STAGE1_PAGE_DESCRIPTOR::SH::OuterShareable
                     + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(memory::mmu::arch_mmu::mair::DEVICE)
@@ -227,6 +312,7 @@ impl convert::TryFrom<InMemoryRegister<u64, STAGE1_PAGE_DESCRIPTOR::Register>> f
     ) -> Result<AttributeFields, Self::Error> {
         let mem_attributes = match desc.read(STAGE1_PAGE_DESCRIPTOR::AttrIndx) {
             memory::mmu::arch_mmu::mair::NORMAL => MemAttributes::CacheableDRAM,
+            memory::mmu::arch_mmu::mair::NORMAL_NON_CACHEABLE => MemAttributes::NonCacheable,
             memory::mmu::arch_mmu::mair::DEVICE => MemAttributes::Device,
             _ => return Err("Unexpected memory attribute"),
         };
@@ -262,9 +348,9 @@ impl PageDescriptor {
     ) -> Self {
         let val = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(0);
 
-        let shifted = phys_output_page_addr.into_inner().as_usize() >> Granule64KiB::SHIFT;
+        let shifted = phys_output_page_addr.into_inner().as_usize() >> ADDR_FIELD_SHIFT;
         val.write(
-            STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR_64KiB.val(shifted as u64)
+            STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR.val(shifted as u64)
                 + STAGE1_PAGE_DESCRIPTOR::AF::True
                 + STAGE1_PAGE_DESCRIPTOR::TYPE::Page
                 + STAGE1_PAGE_DESCRIPTOR::VALID::True
@@ -283,9 +369,9 @@ impl PageDescriptor {
     /// Returns the output page.
     fn output_page_addr(&self) -> PageAddress<Physical> {
         let shifted = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(self.value)
-            .read(STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR_64KiB) as usize;
+            .read(STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR) as usize;
 
-        PageAddress::from(shifted << Granule64KiB::SHIFT)
+        PageAddress::from(shifted << ADDR_FIELD_SHIFT)
     }
 
     /// Returns the attributes.
@@ -294,6 +380,90 @@ impl PageDescriptor {
     }
 }
 
+impl TableFrame {
+    /// Allocate a zeroed frame from the kernel's translation table frames.
+    fn alloc() -> Result<Self, &'static str> {
+        let phys_region = alloc::kernel_table_frame_allocator()
+            .lock(|allocator| allocator.alloc(NonZeroUsize::new(1).unwrap()))?;
+
+        let frame = match Self::from_phys_addr(phys_region.start_addr()) {
+            Ok(x) => x,
+            Err(x) => {
+                alloc::kernel_table_frame_allocator()
+                    .lock(|allocator| allocator.free(phys_region))?;
+                return Err(x);
+            }
+        };
+
+        unsafe {
+            core::ptr::write_bytes(
+                frame.virt_page_addr.into_inner().as_usize() as *mut u8,
+                0,
+                KernelGranule::SIZE,
+            )
+        };
+
+        Ok(frame)
+    }
+
+    /// Access an already allocated frame.
+    fn from_phys_addr(phys_addr: Address<Physical>) -> Result<Self, &'static str> {
+        let virt_addr = bsp::memory::mmu::kernel_table_frame_virt_addr(phys_addr)?;
+
+        Ok(Self {
+            phys_page_addr: PageAddress::from(phys_addr),
+            virt_page_addr: PageAddress::from(virt_addr),
+        })
+    }
+
+    /// Return the frame to the kernel's translation table frames.
+    fn free(self) -> Result<(), &'static str> {
+        let phys_region = MemoryRegion::new(
+            self.phys_page_addr,
+            self.phys_page_addr.checked_offset(1).unwrap(),
+        );
+
+        alloc::kernel_table_frame_allocator().lock(|allocator| allocator.free(phys_region))
+    }
+
+    fn descriptor_ptr(&self, index: usize) -> *mut u64 {
+        assert!(index < NUM_TABLE_ENTRIES);
+
+        (self.virt_page_addr.into_inner().as_usize() as *mut u64).wrapping_add(index)
+    }
+
+    /// Returns the table descriptor at `index`. The frame must hold a lvl1 or lvl2 table.
+    fn table_descriptor(&self, index: usize) -> TableDescriptor {
+        TableDescriptor {
+            value: unsafe { core::ptr::read_volatile(self.descriptor_ptr(index)) },
+        }
+    }
+
+    /// Sets the table descriptor at `index`. The frame must hold a lvl1 or lvl2 table.
+    fn set_table_descriptor(&self, index: usize, desc: TableDescriptor) {
+        unsafe { core::ptr::write_volatile(self.descriptor_ptr(index), desc.value) }
+    }
+
+    /// Returns the page descriptor at `index`. The frame must hold a lvl3 table.
+    fn page_descriptor(&self, index: usize) -> PageDescriptor {
+        PageDescriptor {
+            value: unsafe { core::ptr::read_volatile(self.descriptor_ptr(index)) },
+        }
+    }
+
+    /// Sets the page descriptor at `index`. The frame must hold a lvl3 table.
+    fn set_page_descriptor(&self, index: usize, desc: PageDescriptor) {
+        unsafe { core::ptr::write_volatile(self.descriptor_ptr(index), desc.value) }
+    }
+
+    /// Returns true if the table holds no valid descriptor.
+    ///
+    /// The valid bit is at the same position for all kinds of descriptors.
+    fn is_empty(&self) -> bool {
+        (0..NUM_TABLE_ENTRIES).all(|i| !self.page_descriptor(i).is_valid())
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
@@ -301,53 +471,72 @@ impl PageDescriptor {
 impl<const AS_SIZE: usize> memory::mmu::AssociatedTranslationTable
     for memory::mmu::AddressSpace<AS_SIZE>
 where
-    [u8; Self::SIZE >> Granule512MiB::SHIFT]: Sized,
+    [u8; Self::SIZE >> Lvl2Granule::SHIFT]: Sized,
 {
     // kernel領域のTable
-    type TableStartFromTop =
-        FixedSizeTranslationTable<{ Self::SIZE >> Granule512MiB::SHIFT }, true>;
+    type TableStartFromTop = DynamicTranslationTable<{ Self::SIZE >> Lvl2Granule::SHIFT }, true>;
 
     // user領域のTable
     type TableStartFromBottom =
-        FixedSizeTranslationTable<{ Self::SIZE >> Granule512MiB::SHIFT }, false>;
+        DynamicTranslationTable<{ Self::SIZE >> Lvl2Granule::SHIFT }, false>;
 }
 
 impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
-    FixedSizeTranslationTable<NUM_TABLES, START_FROM_TOP>
+    DynamicTranslationTable<NUM_TABLES, START_FROM_TOP>
 {
     // kernel領域の仮想始点address
     const START_FROM_TOP_OFFSET: Address<Virtual> =
-        Address::new((usize::MAX - (Granule512MiB::SIZE * NUM_TABLES)) + 1);
+        Address::new((usize::MAX - (Lvl2Granule::SIZE * NUM_TABLES)) + 1);
+
+    /// Address spaces larger than a single lvl2 table start the lookup at level 1.
+    const USES_LVL1: bool = NUM_TABLES > NUM_TABLE_ENTRIES;
 
     /// Create an instance.
-    #[allow(clippy::assertions_on_constants)]
-    const fn _new(for_precompute: bool) -> Self {
-        assert!(bsp::memory::mmu::KernelGranule::SIZE == Granule64KiB::SIZE);
+    #[allow(clippy::assertions_on_constants, clippy::new_without_default)]
+    pub const fn new() -> Self {
+        assert!(matches!(
+            KernelGranule::SIZE,
+            Granule4KiB::SIZE | Granule16KiB::SIZE | Granule64KiB::SIZE
+        ));
 
         // Can't have a zero-sized address space.
         assert!(NUM_TABLES > 0);
 
-        Self {
-            lvl3: [[PageDescriptor::new_zeroed(); 8192]; NUM_TABLES],
-            lvl2: [TableDescriptor::new_zeroed(); NUM_TABLES],
-            initialized: for_precompute,
-        }
-    }
+        // The lvl1 can only point to complete lvl2 tables. Also, the tables provide at most three
+        // levels.
+        assert!(!Self::USES_LVL1 || (NUM_TABLES % NUM_TABLE_ENTRIES) == 0);
+        assert!(NUM_TABLES <= NUM_TABLE_ENTRIES * NUM_TABLE_ENTRIES);
 
-    pub const fn new_for_precompute() -> Self {
-        Self::_new(true)
+        Self { root: None }
     }
 
-    #[cfg(test)]
-    pub fn new_for_runtime() -> Self {
-        Self::_new(false)
+    /// Take over tables that were precomputed by the `translation table tool`, instead of
+    /// allocating a new root table.
+    ///
+    /// # Safety
+    ///
+    /// - The root table and all tables below it must have the layout of this type, and must be
+    ///   accessible through `bsp::memory::mmu::kernel_table_frame_virt_addr()`.
+    pub unsafe fn init_precomputed(
+        &mut self,
+        phys_root_table_addr: Address<Physical>,
+    ) -> Result<(), &'static str> {
+        if self.root.is_some() {
+            return Err("Translation tables already initialized");
+        }
+
+        self.root = Some(TableFrame::from_phys_addr(phys_root_table_addr)?);
+
+        Ok(())
     }
 
     /// Helper to calculate the lvl2 and lvl3 indices from an address.
+    ///
+    /// The lvl2 index counts the `Lvl2Granule` windows of the whole address space. If the lookup
+    /// starts at level 1, it spans several lvl2 tables.
     /// 仮想addressからlvl2とlvl3のtranslation table内における要素番号を計算
     #[inline(always)]
     fn lvl2_lvl3_index_from_page_addr(
-        &self,
         virt_page_addr: PageAddress<Virtual>,
     ) -> Result<(usize, usize), &'static str> {
         let mut addr = virt_page_addr.into_inner();
@@ -359,9 +548,9 @@ impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
         }
 
         // lvl2 translation tableにおける要素番号
-        let lvl2_index = addr.as_usize() >> Granule512MiB::SHIFT;
+        let lvl2_index = addr.as_usize() >> Lvl2Granule::SHIFT;
         // lvl3 translation tableにおける要素番号
-        let lvl3_index = (addr.as_usize() & Granule512MiB::MASK) >> Granule64KiB::SHIFT;
+        let lvl3_index = (addr.as_usize() & Lvl2Granule::MASK) >> KernelGranule::SHIFT;
 
         if lvl2_index > (NUM_TABLES - 1) {
             return Err("Virtual page is out of bounds of translation table");
@@ -370,37 +559,240 @@ impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
         Ok((lvl2_index, lvl3_index))
     }
 
+    /// Returns the table that descriptor `index` of `table` points to. If there is none and
+    /// `alloc` is set, a new table is allocated and linked.
+    ///
+    /// Must not be called with `alloc` set for a block descriptor.
+    fn next_lvl_table(
+        table: TableFrame,
+        index: usize,
+        alloc: bool,
+    ) -> Result<Option<TableFrame>, &'static str> {
+        if let Some(phys_table_addr) = table.table_descriptor(index).next_lvl_table_addr() {
+            return TableFrame::from_phys_addr(phys_table_addr).map(Some);
+        }
+
+        if !alloc {
+            return Ok(None);
+        }
+
+        let new_table = TableFrame::alloc()?;
+        table.set_table_descriptor(
+            index,
+            TableDescriptor::from_next_lvl_table_addr(new_table.phys_page_addr.into_inner()),
+        );
+
+        Ok(Some(new_table))
+    }
+
+    /// Returns the lvl2 table and the index into it for the supplied global lvl2 index.
+    fn lvl2_table(
+        &self,
+        lvl2_index: usize,
+        alloc: bool,
+    ) -> Result<Option<(TableFrame, usize)>, &'static str> {
+        let root = self.root.ok_or("Translation tables not initialized")?;
+
+        if !Self::USES_LVL1 {
+            return Ok(Some((root, lvl2_index)));
+        }
+
+        let lvl2_table = Self::next_lvl_table(root, lvl2_index / NUM_TABLE_ENTRIES, alloc)?;
+
+        Ok(lvl2_table.map(|x| (x, lvl2_index % NUM_TABLE_ENTRIES)))
+    }
+
+    /// Returns the lvl2 descriptor for the supplied global lvl2 index. It is invalid if there is no
+    /// lvl2 table.
+    fn lvl2_descriptor(&self, lvl2_index: usize) -> Result<TableDescriptor, &'static str> {
+        Ok(match self.lvl2_table(lvl2_index, false)? {
+            None => TableDescriptor::new_zeroed(),
+            Some((lvl2_table, lvl2_table_index)) => lvl2_table.table_descriptor(lvl2_table_index),
+        })
+    }
+
+    /// Returns the lvl3 table and the index into it for the supplied page address. A missing lvl3
+    /// table is allocated, unless the page is part of a block.
+    fn lvl3_table(
+        &mut self,
+        virt_page_addr: PageAddress<Virtual>,
+    ) -> Result<(TableFrame, usize), &'static str> {
+        let (lvl2_index, lvl3_index) = Self::lvl2_lvl3_index_from_page_addr(virt_page_addr)?;
+
+        let (lvl2_table, lvl2_table_index) = self.lvl2_table(lvl2_index, true)?.unwrap();
+        if lvl2_table.table_descriptor(lvl2_table_index).is_block() {
+            return Err("Virtual page is part of a block");
+        }
+        let lvl3_table = Self::next_lvl_table(lvl2_table, lvl2_table_index, true)?.unwrap();
+
+        Ok((lvl3_table, lvl3_index))
+    }
+
     /// Returns the PageDescriptor corresponding to the supplied page address.
-    #[inline(always)]
+    ///
+    /// For pages that are part of a block, an equivalent PageDescriptor is returned. Pages without
+    /// a lvl3 table get an invalid descriptor.
     fn page_descriptor_from_page_addr(
         &self,
         virt_page_addr: PageAddress<Virtual>,
-    ) -> Result<&PageDescriptor, &'static str> {
-        let (lvl2_index, lvl3_index) = self.lvl2_lvl3_index_from_page_addr(virt_page_addr)?;
-        let desc = &self.lvl3[lvl2_index][lvl3_index];
+    ) -> Result<PageDescriptor, &'static str> {
+        let (lvl2_index, lvl3_index) = Self::lvl2_lvl3_index_from_page_addr(virt_page_addr)?;
+
+        let lvl2_desc = self.lvl2_descriptor(lvl2_index)?;
+        if lvl2_desc.is_block() {
+            return Ok(lvl2_desc.block_page_descriptor(lvl3_index));
+        }
 
-        Ok(desc)
+        Ok(match lvl2_desc.next_lvl_table_addr() {
+            None => PageDescriptor::new_zeroed(),
+            Some(x) => TableFrame::from_phys_addr(x)?.page_descriptor(lvl3_index),
+        })
     }
 
-    /// Sets the PageDescriptor corresponding to the supplied page address.
+    /// Returns an error if any page of the region is not mapped.
+    fn check_mapped(&self, virt_region: &MemoryRegion<Virtual>) -> Result<(), &'static str> {
+        for virt_page_addr in virt_region.into_iter() {
+            if !self
+                .page_descriptor_from_page_addr(virt_page_addr)?
+                .is_valid()
+            {
+                return Err("Virtual page is not mapped");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replace a lvl2 descriptor.
     ///
-    /// Doesn't allow overriding an already valid page.
-    #[inline(always)]
-    fn set_page_descriptor_from_page_addr(
+    /// Changing between a block and a table requires the break-before-make sequence, in case the
+    /// tables are live. Hence, the memory covered by the descriptor must not be accessed meanwhile.
+    fn replace_lvl2_descriptor(lvl2_table: TableFrame, index: usize, new_desc: TableDescriptor) {
+        lvl2_table.set_table_descriptor(index, TableDescriptor::new_zeroed());
+        arch_mmu::invalidate_tlb();
+
+        lvl2_table.set_table_descriptor(index, new_desc);
+        arch_mmu::invalidate_tlb();
+    }
+
+    /// Returns the lvl2 table and index if the start of the supplied regions can be mapped with a
+    /// block. Allocates the lvl2 table if needed.
+    fn block_lvl2_table(
         &mut self,
-        virt_page_addr: PageAddress<Virtual>,
-        new_desc: &PageDescriptor,
-    ) -> Result<(), &'static str> {
-        let (lvl2_index, lvl3_index) = self.lvl2_lvl3_index_from_page_addr(virt_page_addr)?;
-        let desc = &mut self.lvl3[lvl2_index][lvl3_index];
+        virt_region: &MemoryRegion<Virtual>,
+        phys_region: &MemoryRegion<Physical>,
+    ) -> Result<Option<(TableFrame, usize)>, &'static str> {
+        if virt_region.size() < Lvl2Granule::SIZE
+            || !common::is_aligned(virt_region.start_addr().as_usize(), Lvl2Granule::SIZE)
+            || !common::is_aligned(phys_region.start_addr().as_usize(), Lvl2Granule::SIZE)
+        {
+            return Ok(None);
+        }
+
+        let (lvl2_index, _) = Self::lvl2_lvl3_index_from_page_addr(virt_region.start_page_addr())?;
+
+        // A lvl3 table that is in use is kept.
+        let (lvl2_table, lvl2_table_index) = self.lvl2_table(lvl2_index, true)?.unwrap();
+        if lvl2_table.table_descriptor(lvl2_table_index).is_valid() {
+            return Ok(None);
+        }
+
+        Ok(Some((lvl2_table, lvl2_table_index)))
+    }
+
+    /// Replace the block of the supplied lvl2 index by a lvl3 table with the equivalent page
+    /// descriptors.
+    fn split_block(&mut self, lvl2_index: usize) -> Result<(), &'static str> {
+        let (lvl2_table, lvl2_table_index) = self.lvl2_table(lvl2_index, false)?.unwrap();
+        let block_desc = lvl2_table.table_descriptor(lvl2_table_index);
+
+        let lvl3_table = TableFrame::alloc()?;
+        for lvl3_index in 0..NUM_TABLE_ENTRIES {
+            lvl3_table
+                .set_page_descriptor(lvl3_index, block_desc.block_page_descriptor(lvl3_index));
+        }
+
+        let table_desc =
+            TableDescriptor::from_next_lvl_table_addr(lvl3_table.phys_page_addr.into_inner());
+        Self::replace_lvl2_descriptor(lvl2_table, lvl2_table_index, table_desc);
+
+        Ok(())
+    }
+
+    /// Release the lvl3 table of the supplied lvl2 index if it is empty, and the lvl2 table above
+    /// it if that becomes empty as well.
+    fn release_empty_tables(&mut self, lvl2_index: usize) -> Result<(), &'static str> {
+        let (lvl2_table, lvl2_table_index) = match self.lvl2_table(lvl2_index, false)? {
+            None => return Ok(()),
+            Some(x) => x,
+        };
+
+        if let Some(lvl3_table) = Self::next_lvl_table(lvl2_table, lvl2_table_index, false)? {
+            if !lvl3_table.is_empty() {
+                return Ok(());
+            }
+
+            // The table walker must not see a table anymore before its frame is freed.
+            lvl2_table.set_table_descriptor(lvl2_table_index, TableDescriptor::new_zeroed());
+            arch_mmu::invalidate_tlb();
+            lvl3_table.free()?;
+        }
+
+        if !Self::USES_LVL1 || !lvl2_table.is_empty() {
+            return Ok(());
+        }
 
-        if desc.is_valid() {
-            return Err("Virtual page is already mapped");
+        self.root.unwrap().set_table_descriptor(
+            lvl2_index / NUM_TABLE_ENTRIES,
+            TableDescriptor::new_zeroed(),
+        );
+        arch_mmu::invalidate_tlb();
+        lvl2_table.free()
+    }
+
+    /// Free all lvl3 tables that are linked from the supplied lvl2 table.
+    fn free_lvl3_tables(lvl2_table: TableFrame) -> Result<(), &'static str> {
+        for i in 0..NUM_TABLE_ENTRIES {
+            if let Some(x) = Self::next_lvl_table(lvl2_table, i, false)? {
+                x.free()?;
+            }
         }
 
-        *desc = *new_desc;
         Ok(())
     }
+
+    /// Free all tables, including the root.
+    fn free_tables(&mut self) -> Result<(), &'static str> {
+        let root = match self.root.take() {
+            None => return Ok(()),
+            Some(x) => x,
+        };
+
+        if Self::USES_LVL1 {
+            for i in 0..(NUM_TABLES / NUM_TABLE_ENTRIES) {
+                if let Some(lvl2_table) = Self::next_lvl_table(root, i, false)? {
+                    Self::free_lvl3_tables(lvl2_table)?;
+                    lvl2_table.free()?;
+                }
+            }
+        } else {
+            Self::free_lvl3_tables(root)?;
+        }
+
+        root.free()
+    }
+}
+
+/// Returns all tables to the kernel's translation table frames. The tables must not be in use by
+/// the MMU anymore.
+impl<const NUM_TABLES: usize, const START_FROM_TOP: bool> Drop
+    for DynamicTranslationTable<NUM_TABLES, START_FROM_TOP>
+{
+    fn drop(&mut self) {
+        if let Err(x) = self.free_tables() {
+            warn!("Failed to free translation tables: {}", x);
+        }
+    }
 }
 
 //------------------------------------------------------------------------------
@@ -409,23 +801,14 @@ impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
 
 impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
     memory::mmu::translation_table::interface::TranslationTable
-    for FixedSizeTranslationTable<NUM_TABLES, START_FROM_TOP>
+    for DynamicTranslationTable<NUM_TABLES, START_FROM_TOP>
 {
     fn init(&mut self) -> Result<(), &'static str> {
-        if self.initialized {
+        if self.root.is_some() {
             return Ok(());
         }
 
-        // Populate the l2 entries.
-        for (lvl2_nr, lvl2_entry) in self.lvl2.iter_mut().enumerate() {
-            let virt_table_addr = self.lvl3[lvl2_nr].virt_start_addr();
-            let phys_table_addr = memory::mmu::try_kernel_virt_addr_to_phys_addr(virt_table_addr)?;
-
-            let new_desc = TableDescriptor::from_next_lvl_table_addr(phys_table_addr);
-            *lvl2_entry = new_desc;
-        }
-
-        self.initialized = true;
+        self.root = Some(TableFrame::alloc()?);
 
         Ok(())
     }
@@ -436,7 +819,11 @@ impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
         phys_region: &MemoryRegion<Physical>,
         attr: &AttributeFields,
     ) -> Result<(), &'static str> {
-        assert!(self.initialized, "Translation tables not initialized");
+        assert!(self.root.is_some(), "Translation tables not initialized");
+
+        if attr.is_writable_and_executable() {
+            return Err("Tried to map memory as both writable and executable");
+        }
 
         if virt_region.size() != phys_region.size() {
             return Err("Tried to map memory regions with unequal sizes");
@@ -447,12 +834,142 @@ impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
             return Err("Tried to map outside of physical address space");
         }
 
-        let iter = phys_region.into_iter().zip(virt_region.into_iter());
-        for (phys_page_addr, virt_page_addr) in iter {
-            let new_desc = PageDescriptor::from_output_page_addr(phys_page_addr, attr);
-            let virt_page = virt_page_addr;
+        for virt_page_addr in virt_region.into_iter() {
+            if self
+                .page_descriptor_from_page_addr(virt_page_addr)?
+                .is_valid()
+            {
+                return Err("Virtual page is already mapped");
+            }
+        }
+
+        // Use blocks wherever the alignment allows it, and pages for the rest.
+        let (mut virt_region, mut phys_region) = (*virt_region, *phys_region);
+        while virt_region.num_pages() > 0 {
+            if let Some((lvl2_table, lvl2_table_index)) =
+                self.block_lvl2_table(&virt_region, &phys_region)?
+            {
+                let num_pages = NonZeroUsize::new(NUM_TABLE_ENTRIES).unwrap();
+                virt_region.take_first_n_pages(num_pages)?;
+                let phys_block = phys_region.take_first_n_pages(num_pages)?;
+
+                lvl2_table.set_table_descriptor(
+                    lvl2_table_index,
+                    TableDescriptor::from_output_block_addr(phys_block.start_page_addr(), attr),
+                );
+
+                continue;
+            }
+
+            let one_page = NonZeroUsize::new(1).unwrap();
+            let virt_page = virt_region.take_first_n_pages(one_page)?;
+            let phys_page = phys_region.take_first_n_pages(one_page)?;
+
+            let (lvl3_table, lvl3_index) = self.lvl3_table(virt_page.start_page_addr())?;
+            lvl3_table.set_page_descriptor(
+                lvl3_index,
+                PageDescriptor::from_output_page_addr(phys_page.start_page_addr(), attr),
+            );
+        }
+
+        Ok(())
+    }
+
+    unsafe fn protect_at(
+        &mut self,
+        virt_region: &MemoryRegion<Virtual>,
+        attr: &AttributeFields,
+    ) -> Result<(), &'static str> {
+        assert!(self.root.is_some(), "Translation tables not initialized");
+
+        if attr.is_writable_and_executable() {
+            return Err("Tried to map memory as both writable and executable");
+        }
+
+        // Check everything first, so that nothing is changed on error.
+        self.check_mapped(virt_region)?;
+
+        let mut virt_region = *virt_region;
+        while virt_region.num_pages() > 0 {
+            let (lvl2_index, lvl3_index) =
+                Self::lvl2_lvl3_index_from_page_addr(virt_region.start_page_addr())?;
+
+            let lvl2_desc = self.lvl2_descriptor(lvl2_index)?;
+            if lvl2_desc.is_block() {
+                // A block that is covered completely keeps being a block.
+                if lvl3_index == 0 && virt_region.num_pages() >= NUM_TABLE_ENTRIES {
+                    let (lvl2_table, lvl2_table_index) =
+                        self.lvl2_table(lvl2_index, false)?.unwrap();
+                    let new_desc = TableDescriptor::from_output_block_addr(
+                        lvl2_desc.block_page_descriptor(0).output_page_addr(),
+                        attr,
+                    );
+                    Self::replace_lvl2_descriptor(lvl2_table, lvl2_table_index, new_desc);
+
+                    virt_region
+                        .take_first_n_pages(NonZeroUsize::new(NUM_TABLE_ENTRIES).unwrap())?;
+                    continue;
+                }
+
+                self.split_block(lvl2_index)?;
+            }
+
+            let (lvl3_table, lvl3_index) = self.lvl3_table(virt_region.start_page_addr())?;
+            let desc = lvl3_table.page_descriptor(lvl3_index);
+            lvl3_table.set_page_descriptor(
+                lvl3_index,
+                PageDescriptor::from_output_page_addr(desc.output_page_addr(), attr),
+            );
 
-            self.set_page_descriptor_from_page_addr(virt_page, &new_desc)?;
+            virt_region.take_first_n_pages(NonZeroUsize::new(1).unwrap())?;
+        }
+        arch_mmu::invalidate_tlb();
+
+        Ok(())
+    }
+
+    unsafe fn unmap_at(&mut self, virt_region: &MemoryRegion<Virtual>) -> Result<(), &'static str> {
+        assert!(self.root.is_some(), "Translation tables not initialized");
+
+        // Check everything first, so that nothing is changed on error.
+        self.check_mapped(virt_region)?;
+
+        let (first_lvl2_index, _) =
+            Self::lvl2_lvl3_index_from_page_addr(virt_region.start_page_addr())?;
+
+        let mut remaining_region = *virt_region;
+        let mut last_lvl2_index = first_lvl2_index;
+        while remaining_region.num_pages() > 0 {
+            let (lvl2_index, lvl3_index) =
+                Self::lvl2_lvl3_index_from_page_addr(remaining_region.start_page_addr())?;
+            last_lvl2_index = lvl2_index;
+
+            if self.lvl2_descriptor(lvl2_index)?.is_block() {
+                // A block that is covered completely is removed as a whole.
+                if lvl3_index == 0 && remaining_region.num_pages() >= NUM_TABLE_ENTRIES {
+                    let (lvl2_table, lvl2_table_index) =
+                        self.lvl2_table(lvl2_index, false)?.unwrap();
+                    lvl2_table
+                        .set_table_descriptor(lvl2_table_index, TableDescriptor::new_zeroed());
+
+                    remaining_region
+                        .take_first_n_pages(NonZeroUsize::new(NUM_TABLE_ENTRIES).unwrap())?;
+                    continue;
+                }
+
+                self.split_block(lvl2_index)?;
+            }
+
+            let (lvl3_table, lvl3_index) = self.lvl3_table(remaining_region.start_page_addr())?;
+            lvl3_table.set_page_descriptor(lvl3_index, PageDescriptor::new_zeroed());
+
+            remaining_region.take_first_n_pages(NonZeroUsize::new(1).unwrap())?;
+        }
+        arch_mmu::invalidate_tlb();
+
+        // Every table that was touched might be empty now.
+        for lvl2_index in first_lvl2_index..=last_lvl2_index {
+            self.release_empty_tables(lvl2_index)?;
         }
 
         Ok(())
@@ -484,6 +1001,47 @@ impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
         page_desc.try_attributes()
     }
 
+    fn try_descriptor_size(
+        &self,
+        virt_page_addr: PageAddress<Virtual>,
+    ) -> Result<usize, &'static str> {
+        self.try_virt_page_addr_to_phys_page_addr(virt_page_addr)?;
+
+        let (lvl2_index, _) = Self::lvl2_lvl3_index_from_page_addr(virt_page_addr)?;
+        if self.lvl2_descriptor(lvl2_index)?.is_block() {
+            return Ok(Lvl2Granule::SIZE);
+        }
+
+        Ok(KernelGranule::SIZE)
+    }
+
+    fn try_unmapped_size(
+        &self,
+        virt_page_addr: PageAddress<Virtual>,
+    ) -> Result<usize, &'static str> {
+        if self
+            .page_descriptor_from_page_addr(virt_page_addr)?
+            .is_valid()
+        {
+            return Err("Virtual page is mapped");
+        }
+
+        let (lvl2_index, lvl3_index) = Self::lvl2_lvl3_index_from_page_addr(virt_page_addr)?;
+        let offset_into_lvl2_window = lvl3_index * KernelGranule::SIZE;
+
+        if self.lvl2_table(lvl2_index, false)?.is_none() {
+            let num_lvl2_windows = NUM_TABLE_ENTRIES - (lvl2_index % NUM_TABLE_ENTRIES);
+
+            return Ok((num_lvl2_windows * Lvl2Granule::SIZE) - offset_into_lvl2_window);
+        }
+
+        if !self.lvl2_descriptor(lvl2_index)?.is_valid() {
+            return Ok(Lvl2Granule::SIZE - offset_into_lvl2_window);
+        }
+
+        Ok(KernelGranule::SIZE)
+    }
+
     /// Try to translate a virtual address to a physical address.
     ///
     /// Will only succeed if there exists a valid mapping for the input address.
@@ -502,12 +1060,14 @@ impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
 // Testing
 //--------------------------------------------------------------------------------------------------
 
+/// The smallest possible translation table.
 #[cfg(test)]
-pub type MinSizeTranslationTable = FixedSizeTranslationTable<1, true>;
+pub type MinSizeTranslationTable = DynamicTranslationTable<1, true>;
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use memory::mmu::translation_table::interface::TranslationTable;
     use test_macros::kernel_test;
 
     /// Check if the size of `struct TableDescriptor` is as expected.
@@ -527,4 +1087,162 @@ mod tests {
             core::mem::size_of::<u64>()
         );
     }
+
+    /// Check if a translation table occupies exactly one granule.
+    #[kernel_test]
+    fn size_of_table_equals_granule() {
+        assert_eq!(
+            core::mem::size_of::<[PageDescriptor; NUM_TABLE_ENTRIES]>(),
+            KernelGranule::SIZE
+        );
+    }
+
+    /// Large, aligned regions are mapped with blocks, which are split up again if only a part of
+    /// them is protected. Unmapping the region releases the lvl3 table of the split block.
+    #[kernel_test]
+    fn translationtable_block_mappings() {
+        let free_frames =
+            || alloc::kernel_table_frame_allocator().lock(|allocator| allocator.stats().free_pages);
+
+        // Two lvl2 windows, so that a complete window can be described by a MemoryRegion.
+        let mut tables = DynamicTranslationTable::<2, true>::new();
+
+        assert!(tables.init().is_ok());
+
+        let virt_start_page_addr: PageAddress<Virtual> =
+            PageAddress::from(usize::MAX - 2 * Lvl2Granule::SIZE + 1);
+        let phys_start_page_addr: PageAddress<Physical> = PageAddress::from(0);
+        let num_pages = NUM_TABLE_ENTRIES as isize;
+
+        let virt_region = MemoryRegion::new(
+            virt_start_page_addr,
+            virt_start_page_addr.checked_offset(num_pages).unwrap(),
+        );
+        let phys_region = MemoryRegion::new(
+            phys_start_page_addr,
+            phys_start_page_addr.checked_offset(num_pages).unwrap(),
+        );
+
+        let attr = AttributeFields {
+            mem_attributes: MemAttributes::CacheableDRAM,
+            acc_perms: AccessPermissions::ReadWrite,
+            execute_never: true,
+        };
+
+        unsafe { assert_eq!(tables.map_at(&virt_region, &phys_region, &attr), Ok(())) };
+
+        let virt_page_addr = virt_start_page_addr.checked_offset(3).unwrap();
+        let phys_page_addr = phys_start_page_addr.checked_offset(3).unwrap();
+        assert_eq!(
+            tables.try_descriptor_size(virt_page_addr),
+            Ok(Lvl2Granule::SIZE)
+        );
+        assert_eq!(
+            tables.try_virt_page_addr_to_phys_page_addr(virt_page_addr),
+            Ok(phys_page_addr)
+        );
+        assert_eq!(tables.try_page_attributes(virt_page_addr), Ok(attr));
+
+        // Protect a single page. The block must be split up.
+        let ro_attr = AttributeFields {
+            acc_perms: AccessPermissions::ReadOnly,
+            ..attr
+        };
+        let protect_region =
+            MemoryRegion::new(virt_page_addr, virt_page_addr.checked_offset(1).unwrap());
+        unsafe { assert_eq!(tables.protect_at(&protect_region, &ro_attr), Ok(())) };
+
+        assert_eq!(
+            tables.try_descriptor_size(virt_page_addr),
+            Ok(KernelGranule::SIZE)
+        );
+        assert_eq!(tables.try_page_attributes(virt_page_addr), Ok(ro_attr));
+        assert_eq!(
+            tables.try_virt_page_addr_to_phys_page_addr(virt_page_addr),
+            Ok(phys_page_addr)
+        );
+
+        let next_page_addr = virt_page_addr.checked_offset(1).unwrap();
+        assert_eq!(tables.try_page_attributes(next_page_addr), Ok(attr));
+        assert_eq!(
+            tables.try_virt_page_addr_to_phys_page_addr(next_page_addr),
+            Ok(phys_page_addr.checked_offset(1).unwrap())
+        );
+
+        // Only the root is left.
+        let frames_with_lvl3 = free_frames();
+        unsafe { assert_eq!(tables.unmap_at(&virt_region), Ok(())) };
+        assert_eq!(free_frames(), frames_with_lvl3 + 1);
+        assert!(tables.try_page_attributes(next_page_addr).is_err());
+    }
+
+    /// Dynamic tables take frames when a new range is mapped, and return them when the range is
+    /// unmapped again or the tables are dropped.
+    #[kernel_test]
+    fn dynamic_translationtable_allocates_and_releases_tables() {
+        let free_frames =
+            || alloc::kernel_table_frame_allocator().lock(|allocator| allocator.stats().free_pages);
+        let initial_free_frames = free_frames();
+
+        {
+            let mut tables = DynamicTranslationTable::<1, true>::new();
+
+            assert!(tables.init().is_ok());
+            assert_eq!(free_frames(), initial_free_frames - 1);
+
+            let virt_start_page_addr: PageAddress<Virtual> =
+                PageAddress::from(usize::MAX - Lvl2Granule::SIZE + 1);
+            let phys_start_page_addr: PageAddress<Physical> = PageAddress::from(0);
+
+            let virt_region = MemoryRegion::new(
+                virt_start_page_addr,
+                virt_start_page_addr.checked_offset(2).unwrap(),
+            );
+            let phys_region = MemoryRegion::new(
+                phys_start_page_addr,
+                phys_start_page_addr.checked_offset(2).unwrap(),
+            );
+
+            let attr = AttributeFields {
+                mem_attributes: MemAttributes::CacheableDRAM,
+                acc_perms: AccessPermissions::ReadWrite,
+                execute_never: true,
+            };
+
+            unsafe { assert_eq!(tables.map_at(&virt_region, &phys_region, &attr), Ok(())) };
+            assert_eq!(free_frames(), initial_free_frames - 2);
+
+            let virt_page_addr = virt_start_page_addr.checked_offset(1).unwrap();
+            assert_eq!(
+                tables.try_virt_page_addr_to_phys_page_addr(virt_page_addr),
+                Ok(phys_start_page_addr.checked_offset(1).unwrap())
+            );
+            assert_eq!(tables.try_page_attributes(virt_page_addr), Ok(attr));
+            assert!(unsafe { tables.map_at(&virt_region, &phys_region, &attr) }.is_err());
+
+            // Unmapped pages next to mapped ones are skipped one by one.
+            let unmapped_page_addr = virt_start_page_addr.checked_offset(2).unwrap();
+            assert!(tables.try_unmapped_size(virt_page_addr).is_err());
+            assert_eq!(
+                tables.try_unmapped_size(unmapped_page_addr),
+                Ok(KernelGranule::SIZE)
+            );
+
+            unsafe { assert_eq!(tables.unmap_at(&virt_region), Ok(())) };
+            assert_eq!(free_frames(), initial_free_frames - 1);
+            assert!(tables
+                .try_virt_page_addr_to_phys_page_addr(virt_page_addr)
+                .is_err());
+
+            // Without the lvl3 table, the rest of its window is skipped at once.
+            assert_eq!(
+                tables.try_unmapped_size(virt_page_addr),
+                Ok(Lvl2Granule::SIZE - KernelGranule::SIZE)
+            );
+
+            unsafe { assert_eq!(tables.map_at(&virt_region, &phys_region, &attr), Ok(())) };
+        }
+
+        assert_eq!(free_frames(), initial_free_frames);
+    }
 }
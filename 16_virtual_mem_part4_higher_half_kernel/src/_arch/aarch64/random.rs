@@ -0,0 +1,27 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Architectural entropy.
+//!
+//! # Orientation
+//!
+//! Since arch modules are imported into generic modules using the path attribute, the path of this
+//! file is:
+//!
+//! crate::random::arch_random
+
+use cortex_a::{asm::barrier, registers::*};
+use tock_registers::interfaces::Readable;
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Read the physical counter. The time between two reads jitters with caches, pipelines and the
+/// bus, which makes the low bits somewhat unpredictable.
+#[inline(always)]
+pub fn jitter_sample() -> u64 {
+    unsafe { barrier::isb(barrier::SY) };
+    CNTPCT_EL0.get()
+}
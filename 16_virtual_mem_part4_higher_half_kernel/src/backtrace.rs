@@ -0,0 +1,207 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Stack backtraces.
+//!
+//! The kernel is compiled with frame pointers, so the call chain can be recovered by following the
+//! linked list of frame records on the stack. Every record is checked to lie within a registered
+//! kernel stack before it is read, so a corrupted chain ends the backtrace instead of causing a
+//! fault.
+//!
+//! Addresses are symbolized using a symbol table that is patched into the kernel ELF after linking,
+//! see [`symbols`].
+
+#[cfg(target_arch = "aarch64")]
+#[path = "_arch/aarch64/backtrace.rs"]
+mod arch_backtrace;
+
+pub mod symbols;
+
+use crate::memory::{self, Address, Virtual};
+use core::fmt;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Upper limit for the number of printed frames.
+const MAX_FRAMES: usize = 32;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A backtrace that is walked lazily, for example when it is printed.
+#[derive(Copy, Clone)]
+pub struct Backtrace {
+    /// Address of the innermost frame, if it is not covered by a frame record.
+    pc: Option<usize>,
+
+    /// Frame pointer of the innermost frame.
+    fp: usize,
+}
+
+/// Iterator over the call sites of a [`Backtrace`], innermost first.
+pub struct Frames {
+    pc: Option<usize>,
+    fp: usize,
+    num_frames: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Return true if a frame record at `fp` lies completely within a kernel stack.
+fn is_valid_frame_pointer(fp: usize) -> bool {
+    if fp % arch_backtrace::FRAME_RECORD_ALIGN != 0 {
+        return false;
+    }
+
+    let stack = match memory::stack::find(Address::new(fp)) {
+        None => return false,
+        Some(stack) => stack,
+    };
+    let end_exclusive = stack
+        .region()
+        .end_exclusive_page_addr()
+        .into_inner()
+        .as_usize();
+
+    fp <= end_exclusive - arch_backtrace::FRAME_RECORD_SIZE
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Backtrace {
+    /// Capture the backtrace of the caller.
+    ///
+    /// The frame records are only read when the backtrace is walked, which must happen before the
+    /// caller returns.
+    #[inline(always)]
+    pub fn current() -> Self {
+        Self {
+            pc: None,
+            fp: arch_backtrace::frame_pointer(),
+        }
+    }
+
+    /// Create a backtrace from a saved program counter and frame pointer, for example from an
+    /// exception context.
+    pub fn from_frame(pc: usize, fp: usize) -> Self {
+        Self { pc: Some(pc), fp }
+    }
+
+    /// Iterate over the call sites.
+    pub fn frames(&self) -> Frames {
+        Frames {
+            pc: self.pc,
+            fp: self.fp,
+            num_frames: 0,
+        }
+    }
+}
+
+impl Iterator for Frames {
+    type Item = Address<Virtual>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.num_frames == MAX_FRAMES {
+            return None;
+        }
+
+        let addr = match self.pc.take() {
+            Some(pc) => pc,
+            None => {
+                if !is_valid_frame_pointer(self.fp) {
+                    return None;
+                }
+
+                let (caller_fp, return_addr) =
+                    unsafe { arch_backtrace::read_frame_record(self.fp) };
+                if return_addr == 0 {
+                    return None;
+                }
+
+                // The stack grows downwards, so callers' records must be at higher addresses. This
+                // also guarantees that a corrupted chain cannot loop.
+                self.fp = if caller_fp > self.fp { caller_fp } else { 0 };
+
+                arch_backtrace::return_addr_to_call_site(return_addr)
+            }
+        };
+        self.num_frames += 1;
+
+        Some(Address::new(addr))
+    }
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Backtrace:")?;
+
+        for (i, addr) in self.frames().enumerate() {
+            write!(f, "      {:>2}: {}", i, addr)?;
+
+            match symbols::lookup(addr) {
+                Some((symbol, offset)) => writeln!(f, " - {} + {:#x}", symbol, offset)?,
+                None => writeln!(f)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    #[inline(never)]
+    fn num_frames() -> usize {
+        Backtrace::current().frames().count()
+    }
+
+    /// Return true if one of the caller's frames resolves to a symbol ending in `name`.
+    #[inline(never)]
+    fn frames_resolve_to(name: &str) -> bool {
+        Backtrace::current()
+            .frames()
+            .any(|addr| match symbols::lookup(addr) {
+                None => false,
+                Some((symbol, _)) => symbol.name().ends_with(name),
+            })
+    }
+
+    /// Not inlined, so that its return address identifies it. The check after the call keeps it
+    /// from becoming a tail call.
+    #[inline(never)]
+    fn symbolized_caller() {
+        assert!(frames_resolve_to("::symbolized_caller"));
+    }
+
+    /// The frame record chain can be walked, and the starting frame is reported first.
+    #[kernel_test]
+    fn backtrace_walks_frames() {
+        assert!(num_frames() >= 2);
+
+        let bt = Backtrace::from_frame(0x1234, 0);
+        let mut frames = bt.frames();
+        assert_eq!(frames.next(), Some(Address::new(0x1234)));
+        assert_eq!(frames.next(), None);
+    }
+
+    /// The frames of a backtrace resolve to the names of the functions they are in.
+    #[kernel_test]
+    fn backtrace_frames_are_symbolized() {
+        symbolized_caller();
+    }
+}
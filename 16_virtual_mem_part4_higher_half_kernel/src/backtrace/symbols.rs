@@ -0,0 +1,156 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Kernel symbol table.
+//!
+//! The table is generated from the kernel ELF by the `kernel symbols tool` after linking, and
+//! patched into a reserved area of the binary. Layout, all values little endian:
+//!
+//! | Offset | Content                                                                    |
+//! |--------|----------------------------------------------------------------------------|
+//! | 0      | Magic `b"KSYM"`                                                            |
+//! | 4      | Number of symbols, `u32`                                                   |
+//! | 8      | Offset of the names area, `u32`                                            |
+//! | 12     | Reserved                                                                   |
+//! | 16     | Entries sorted by address: start address `u64`, size `u32`, name offset `u32` |
+//! | names  | Names, each prefixed with its length as a `u8`                             |
+//!
+//! If the tool did not run, the table is empty and no symbols are found.
+
+use crate::memory::{Address, Virtual};
+use core::fmt;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const KERNEL_SYMBOLS_SIZE: usize = 64 * 1024;
+
+const MAGIC: u32 = u32::from_le_bytes(*b"KSYM");
+const HEADER_SIZE: usize = 16;
+const ENTRY_SIZE: usize = 16;
+
+/// Longest name in the table. The `kernel symbols tool` cuts longer names to the same length.
+const MAX_NAME_LEN: usize = 96;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A symbol's name.
+pub struct Symbol {
+    name: [u8; MAX_NAME_LEN],
+    len: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// The symbol table.
+///
+/// This will be patched by the "kernel symbols tool" after linking. Since the compiler only knows
+/// the zeroed dummy value, it must only be accessed with volatile reads.
+#[link_section = ".kernel_symbols"]
+#[no_mangle]
+static KERNEL_SYMBOLS: [u8; KERNEL_SYMBOLS_SIZE] = [0; KERNEL_SYMBOLS_SIZE];
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn read_u8(offset: usize) -> u8 {
+    assert!(offset < KERNEL_SYMBOLS_SIZE);
+
+    unsafe { core::ptr::read_volatile(KERNEL_SYMBOLS.as_ptr().add(offset)) }
+}
+
+fn read_u32(offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    for (i, b) in bytes.iter_mut().enumerate() {
+        *b = read_u8(offset + i);
+    }
+
+    u32::from_le_bytes(bytes)
+}
+
+fn read_u64(offset: usize) -> u64 {
+    (read_u32(offset) as u64) | ((read_u32(offset + 4) as u64) << 32)
+}
+
+/// Return the start address and size of entry `i`.
+fn entry_range(i: usize) -> (usize, usize) {
+    let offset = HEADER_SIZE + i * ENTRY_SIZE;
+
+    (read_u64(offset) as usize, read_u32(offset + 8) as usize)
+}
+
+fn read_symbol(i: usize, names_offset: usize) -> Symbol {
+    let name_offset = names_offset + read_u32(HEADER_SIZE + i * ENTRY_SIZE + 12) as usize;
+    let len = (read_u8(name_offset) as usize).min(MAX_NAME_LEN);
+
+    let mut name = [0; MAX_NAME_LEN];
+    for (k, c) in name[..len].iter_mut().enumerate() {
+        *c = read_u8(name_offset + 1 + k);
+    }
+
+    Symbol { name, len }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Find the symbol containing `addr`. Returns the symbol and the offset of `addr` into it.
+pub fn lookup(addr: Address<Virtual>) -> Option<(Symbol, usize)> {
+    if read_u32(0) != MAGIC {
+        return None;
+    }
+
+    let num_symbols = read_u32(4) as usize;
+    let names_offset = read_u32(8) as usize;
+    let addr = addr.as_usize();
+
+    if num_symbols > (KERNEL_SYMBOLS_SIZE - HEADER_SIZE) / ENTRY_SIZE {
+        return None;
+    }
+
+    // Binary search for the number of symbols starting at or before `addr`.
+    let (mut low, mut high) = (0, num_symbols);
+    while low < high {
+        let mid = low + (high - low) / 2;
+
+        if entry_range(mid).0 <= addr {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+
+    let i = low.checked_sub(1)?;
+    let (start, size) = entry_range(i);
+    let offset = addr - start;
+    if offset >= size {
+        return None;
+    }
+
+    Some((read_symbol(i, names_offset), offset))
+}
+
+impl Symbol {
+    /// The demangled name.
+    pub fn name(&self) -> &str {
+        // The tool only emits ASCII, but be defensive about a cut name.
+        match core::str::from_utf8(&self.name[..self.len]) {
+            Ok(name) => name,
+            Err(e) => core::str::from_utf8(&self.name[..e.valid_up_to()]).unwrap_or_default(),
+        }
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
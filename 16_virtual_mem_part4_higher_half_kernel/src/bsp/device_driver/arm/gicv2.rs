@@ -97,8 +97,8 @@ pub type IRQNumber = exception::asynchronous::IRQNumber<{ GICv2::MAX_IRQ_NUMBER
 
 /// Representation of the GIC.
 pub struct GICv2 {
-    gicd_mmio_descriptor: memory::mmu::MMIODescriptor,
-    gicc_mmio_descriptor: memory::mmu::MMIODescriptor,
+    gicd_mmio_descriptor: InitStateLock<memory::mmu::MMIODescriptor>,
+    gicc_mmio_descriptor: InitStateLock<memory::mmu::MMIODescriptor>,
 
     /// The Distributor.
     gicd: gicd::GICD,
@@ -118,6 +118,9 @@ pub struct GICv2 {
 //--------------------------------------------------------------------------------------------------
 
 impl GICv2 {
+    /// The compatible string.
+    pub const COMPATIBLE: &'static str = "GICv2 (ARM Generic Interrupt Controller v2)";
+
     const MAX_IRQ_NUMBER: usize = 300; // Normally 1019, but keep it lower to save some space.
     const NUM_IRQS: usize = Self::MAX_IRQ_NUMBER + 1;
 
@@ -131,14 +134,28 @@ impl GICv2 {
         gicc_mmio_descriptor: memory::mmu::MMIODescriptor,
     ) -> Self {
         Self {
-            gicd_mmio_descriptor,
-            gicc_mmio_descriptor,
+            gicd_mmio_descriptor: InitStateLock::new(gicd_mmio_descriptor),
+            gicc_mmio_descriptor: InitStateLock::new(gicc_mmio_descriptor),
             gicd: gicd::GICD::new(gicd_mmio_descriptor.start_addr().as_usize()),
             gicc: gicc::GICC::new(gicc_mmio_descriptor.start_addr().as_usize()),
             is_mmio_remapped: AtomicBool::new(false),
             handler_table: InitStateLock::new([None; Self::NUM_IRQS]),
         }
     }
+
+    /// Replace the MMIO descriptors that were given to `new()`.
+    ///
+    /// Only has an effect if called before `init()`, which maps the MMIO regions.
+    pub fn set_mmio_descriptors(
+        &self,
+        gicd_mmio_descriptor: memory::mmu::MMIODescriptor,
+        gicc_mmio_descriptor: memory::mmu::MMIODescriptor,
+    ) {
+        self.gicd_mmio_descriptor
+            .write(|x| *x = gicd_mmio_descriptor);
+        self.gicc_mmio_descriptor
+            .write(|x| *x = gicc_mmio_descriptor);
+    }
 }
 
 //------------------------------------------------------------------------------
@@ -148,18 +165,20 @@ use synchronization::interface::ReadWriteEx;
 
 impl driver::interface::DeviceDriver for GICv2 {
     fn compatible(&self) -> &'static str {
-        "GICv2 (ARM Generic Interrupt Controller v2)"
+        Self::COMPATIBLE
     }
 
     unsafe fn init(&self) -> Result<(), &'static str> {
         let remapped = self.is_mmio_remapped.load(Ordering::Relaxed);
         if !remapped {
             // GICD
-            let mut virt_addr = memory::mmu::kernel_map_mmio("GICD", &self.gicd_mmio_descriptor)?;
+            let mmio_descriptor = self.gicd_mmio_descriptor.read(|x| *x);
+            let mut virt_addr = memory::mmu::kernel_map_mmio("GICD", &mmio_descriptor)?;
             self.gicd.set_mmio(virt_addr.as_usize());
 
             // GICC
-            virt_addr = memory::mmu::kernel_map_mmio("GICC", &self.gicc_mmio_descriptor)?;
+            let mmio_descriptor = self.gicc_mmio_descriptor.read(|x| *x);
+            virt_addr = memory::mmu::kernel_map_mmio("GICC", &mmio_descriptor)?;
             self.gicc.set_mmio(virt_addr.as_usize());
 
             // Conclude remapping.
@@ -175,6 +194,30 @@ impl driver::interface::DeviceDriver for GICv2 {
 
         Ok(())
     }
+
+    unsafe fn shutdown(&self) -> Result<(), &'static str> {
+        self.gicc.disable();
+
+        if bsp::cpu::BOOT_CORE_ID == cpu::smp::core_id() {
+            self.gicd.disable();
+        }
+
+        Ok(())
+    }
+
+    unsafe fn suspend(&self) -> Result<(), &'static str> {
+        // The distributor keeps the enabled and pending IRQs. They are signaled once the CPU
+        // interface is enabled again.
+        self.gicc.disable();
+
+        Ok(())
+    }
+
+    unsafe fn resume(&self) -> Result<(), &'static str> {
+        self.gicc.enable();
+
+        Ok(())
+    }
 }
 
 impl exception::asynchronous::interface::IRQManager for GICv2 {
@@ -233,6 +276,16 @@ impl exception::asynchronous::interface::IRQManager for GICv2 {
     fn print_handler(&self) {
         use crate::info;
 
+        info!("      Private handler:");
+
+        self.handler_table.read(|table| {
+            for (i, opt) in table.iter().take(32).enumerate() {
+                if let Some(handler) = opt {
+                    info!("            {: >3}. {}", i, handler.name);
+                }
+            }
+        });
+
         info!("      Peripheral handler:");
 
         self.handler_table.read(|table| {
@@ -116,6 +116,18 @@ impl GICC {
         });
     }
 
+    /// Disable the interface - stop accepting IRQs.
+    ///
+    /// # Safety
+    ///
+    /// - GICC MMIO registers are banked per CPU core. It is therefore safe to have `&self` instead
+    ///   of `&mut self`.
+    pub fn disable(&self) {
+        self.registers.read(|regs| {
+            regs.CTLR.write(CTLR::Enable::CLEAR);
+        });
+    }
+
     /// Extract the number of the highest-priority pending IRQ.
     ///
     /// Can only be called from IRQ context, which is ensured by taking an `IRQContext` token.
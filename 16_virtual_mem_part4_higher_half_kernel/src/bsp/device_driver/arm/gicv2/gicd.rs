@@ -179,6 +179,13 @@ impl GICD {
         });
     }
 
+    /// Disable the distributor. No IRQs are forwarded to any core afterwards.
+    pub fn disable(&self) {
+        self.shared_registers.lock(|regs| {
+            regs.CTLR.write(CTLR::Enable::CLEAR);
+        });
+    }
+
     /// Enable an interrupt.
     pub fn enable(&self, irq_num: super::IRQNumber) {
         let irq_num = irq_num.get();
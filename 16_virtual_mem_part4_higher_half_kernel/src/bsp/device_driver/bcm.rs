@@ -4,12 +4,30 @@
 
 //! BCM driver top level.
 
+mod bcm2xxx_dma;
 mod bcm2xxx_gpio;
+mod bcm2xxx_i2c;
 #[cfg(feature = "bsp_rpi3")]
 mod bcm2xxx_interrupt_controller;
 mod bcm2xxx_pl011_uart;
+mod bcm2xxx_pm_watchdog;
+#[cfg(feature = "bsp_rpi3")]
+mod bcm2xxx_rng;
+mod bcm2xxx_spi;
+mod bcm2xxx_system_timer;
+#[cfg(feature = "bsp_rpi3")]
+mod bcm2xxx_usb;
 
+pub use bcm2xxx_dma::*;
 pub use bcm2xxx_gpio::*;
+pub use bcm2xxx_i2c::*;
 #[cfg(feature = "bsp_rpi3")]
 pub use bcm2xxx_interrupt_controller::*;
 pub use bcm2xxx_pl011_uart::*;
+pub use bcm2xxx_pm_watchdog::*;
+#[cfg(feature = "bsp_rpi3")]
+pub use bcm2xxx_rng::*;
+pub use bcm2xxx_spi::*;
+pub use bcm2xxx_system_timer::*;
+#[cfg(feature = "bsp_rpi3")]
+pub use bcm2xxx_usb::*;
@@ -0,0 +1,776 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! DMA Controller Driver.
+//!
+//! Each DMA channel executes a chain of control blocks, which it fetches from memory. A control
+//! block describes one contiguous transfer and links to the next one. Transfers to or from a
+//! peripheral can be paced by the peripheral's DREQ signal, so that its FIFO is neither overrun
+//! nor underrun.
+//!
+//! The DMA engines address memory and peripherals through the VideoCore bus. RAM is reached
+//! through its uncached alias at bus address `0xC000_0000`, which covers the first GiB only.
+//! Buffers are handed over with [`DmaBuffer::give_to_device()`], which does the cache maintenance.
+//! The control blocks live in non-cacheable memory, so they need none.
+//!
+//! Only channels 0 to 10 are used, which have a dedicated IRQ and the same register layout on both
+//! the BCM2837 and the BCM2711. Channels 7 to 10 of them are DMA lite channels, which have less
+//! bandwidth and transfer at most 64 KiB per control block. Chains are built for the lite limit,
+//! so that they run on any channel. Channels that the GPU firmware claims are left alone.
+//!
+//! The engine accesses the chain's buffers until the transfer has finished. Dropping a
+//! [`DMATransfer`] aborts it, but a leaked one keeps running, which is why starting a transfer is
+//! `unsafe`.
+//!
+//! # Resources
+//!
+//! - <https://github.com/raspberrypi/documentation/files/1888662/BCM2837-ARM-Peripherals.-.Revised.-.V2-1.pdf>
+//! - <https://datasheets.raspberrypi.org/bcm2711/bcm2711-peripherals.pdf>
+//! - Linux, `drivers/dma/bcm2835-dma.c`
+
+use crate::{
+    bsp,
+    bsp::device_driver::common::MMIODerefWrapper,
+    driver, exception, executor,
+    memory::{
+        self,
+        dma::{DeviceOwnedDmaBuffer, DmaBuffer, DmaDirection},
+        Address, Physical,
+    },
+    synchronization,
+    synchronization::{IRQSafeNullLock, InitStateLock},
+};
+use core::{
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    sync::atomic::{AtomicU16, AtomicU8, AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_bitfields, register_structs,
+    registers::ReadWrite,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+register_bitfields! {
+    u32,
+
+    /// Control and Status Register.
+    CS [
+        /// Writing a 1 resets the channel.
+        RESET OFFSET(31) NUMBITS(1) [],
+
+        /// The channel waits for the AXI write responses of a control block before it signals
+        /// the end of the control block.
+        WAIT_FOR_OUTSTANDING_WRITES OFFSET(28) NUMBITS(1) [],
+
+        /// Set while the channel waits for write responses.
+        WAITING_FOR_OUTSTANDING_WRITES OFFSET(6) NUMBITS(1) [],
+
+        /// Set if an error was flagged in the `DEBUG` register.
+        ERROR OFFSET(8) NUMBITS(1) [],
+
+        /// Set when a control block with `TI::INTEN` has finished. Writing a 1 clears it.
+        INT OFFSET(2) NUMBITS(1) [],
+
+        /// Set when a control block has finished. Writing a 1 clears it.
+        END OFFSET(1) NUMBITS(1) [],
+
+        /// Starts the channel, and is cleared once the chain has finished.
+        ACTIVE OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Transfer Information of a control block.
+    TI [
+        /// The peripheral whose DREQ paces the transfer.
+        PERMAP OFFSET(16) NUMBITS(5) [],
+
+        /// The source is paced by DREQ.
+        SRC_DREQ OFFSET(10) NUMBITS(1) [],
+
+        /// The source address is incremented.
+        SRC_INC OFFSET(8) NUMBITS(1) [],
+
+        /// The destination is paced by DREQ.
+        DEST_DREQ OFFSET(6) NUMBITS(1) [],
+
+        /// The destination address is incremented.
+        DEST_INC OFFSET(4) NUMBITS(1) [],
+
+        /// Wait for the write response of each write.
+        WAIT_RESP OFFSET(3) NUMBITS(1) [],
+
+        /// Raise an IRQ when the control block has finished.
+        INTEN OFFSET(0) NUMBITS(1) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    ChannelRegisterBlock {
+        (0x00 => CS: ReadWrite<u32, CS::Register>),
+        (0x04 => CONBLK_AD: ReadWrite<u32>),
+        (0x08 => _reserved1),
+        (0x20 => DEBUG: ReadWrite<u32>),
+        (0x24 => @END),
+    }
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x000 => _reserved1),
+        (0xFE0 => INT_STATUS: ReadWrite<u32>),
+        (0xFE4 => _reserved2),
+        (0xFF0 => ENABLE: ReadWrite<u32>),
+        (0xFF4 => @END),
+    }
+}
+
+/// Abstraction for the associated MMIO registers.
+type Registers = MMIODerefWrapper<RegisterBlock>;
+type ChannelRegisters = MMIODerefWrapper<ChannelRegisterBlock>;
+
+/// A control block, as fetched by the DMA engine. It must be 32 byte aligned.
+#[repr(C, align(32))]
+#[derive(Copy, Clone)]
+struct ControlBlock {
+    ti: u32,
+    source_ad: u32,
+    dest_ad: u32,
+    txfr_len: u32,
+    stride: u32,
+    nextconbk: u32,
+    reserved: [u32; 2],
+}
+
+/// Progress of a channel's transfer, shared with the IRQ handler.
+#[derive(Copy, Clone, PartialEq)]
+#[repr(u8)]
+enum TransferStatus {
+    Idle,
+    Running,
+    Done,
+    Error,
+}
+
+/// The per-channel state.
+struct ChannelState {
+    registers: IRQSafeNullLock<ChannelRegisters>,
+    status: AtomicU8,
+    waker: executor::WakerCell,
+}
+
+/// Channels 0 to 10 have a dedicated IRQ. Channels 1, 3, 6 and 7 are claimed by the GPU firmware.
+const USABLE_CHANNELS: u16 = 0b111_0011_0101;
+
+/// Channels 0 to 10.
+const NUM_CHANNELS: usize = 11;
+
+/// Distance between the register blocks of two channels.
+const CHANNEL_STRIDE: usize = 0x100;
+
+/// The bus address of RAM's uncached alias.
+const RAM_BUS_ALIAS: usize = 0xC000_0000;
+
+/// The size of RAM's bus alias.
+const RAM_BUS_ALIAS_SIZE: usize = 0x4000_0000;
+
+/// The bus address of the controller, which is used to translate peripheral addresses.
+const BUS_MMIO_START_ADDR: usize = 0x7E00_7000;
+
+/// The lite channels cannot transfer more than 64 KiB per control block. Longer segments are
+/// split into multiple control blocks.
+const MAX_CONTROL_BLOCK_LEN: usize = 0xFFFC;
+
+/// `DEBUG` error flags, which are cleared by writing a 1.
+const DEBUG_ERRORS: u32 = 0b111;
+
+/// How often to poll for outstanding writes when a channel is aborted.
+const ABORT_POLL_ITERATIONS: usize = 10_000;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Peripherals that can pace a transfer with their DREQ signal.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DMAPeripheral {
+    /// The EMMC controller.
+    Emmc,
+
+    /// The PL011 UART's transmit FIFO.
+    UartTx,
+
+    /// The PL011 UART's receive FIFO.
+    UartRx,
+}
+
+/// A chain of control blocks, built before it is handed to a channel.
+///
+/// The buffers of the chain are borrowed until the transfer has finished.
+pub struct DMAChain<'a> {
+    control_blocks: DmaBuffer,
+    len: usize,
+    capacity: usize,
+    phys_mmio_start_addr: usize,
+    buffers: PhantomData<&'a DeviceOwnedDmaBuffer>,
+}
+
+/// An allocated DMA channel. It is returned to the controller when dropped.
+pub struct DMAChannel<'a> {
+    controller: &'a DMAController,
+    index: usize,
+}
+
+/// Future returned by [`DMAChannel::start()`]. Resolves when the chain has finished.
+///
+/// Dropping it before then aborts the transfer.
+pub struct DMATransfer<'a> {
+    channel: &'a ChannelState,
+    control_blocks: Option<DeviceOwnedDmaBuffer>,
+    buffers: PhantomData<&'a DeviceOwnedDmaBuffer>,
+}
+
+/// Representation of the DMA controller.
+pub struct DMAController {
+    mmio_descriptor: InitStateLock<memory::mmu::MMIODescriptor>,
+    virt_mmio_start_addr: AtomicUsize,
+    registers: IRQSafeNullLock<Registers>,
+    irq_numbers: [bsp::device_driver::IRQNumber; NUM_CHANNELS],
+    channels: [ChannelState; NUM_CHANNELS],
+
+    /// One bit per channel that is usable and not allocated.
+    free_channels: AtomicU16,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// The bus address of `len` bytes at `offset` into `buffer`.
+fn buffer_bus_addr(
+    buffer: &DeviceOwnedDmaBuffer,
+    offset: usize,
+    len: usize,
+    is_read: bool,
+) -> Result<usize, &'static str> {
+    let is_direction_ok = match buffer.direction() {
+        DmaDirection::Bidirectional => true,
+        DmaDirection::ToDevice => is_read,
+        DmaDirection::FromDevice => !is_read,
+    };
+    if !is_direction_ok {
+        return Err("DMA buffer was given to the device for the other direction");
+    }
+
+    match offset.checked_add(len) {
+        Some(end) if end <= buffer.size() => (),
+        _ => return Err("DMA segment exceeds the buffer"),
+    }
+
+    ram_bus_addr(buffer.phys_start_addr() + offset)
+}
+
+fn ram_bus_addr(phys_addr: Address<Physical>) -> Result<usize, &'static str> {
+    if phys_addr.as_usize() >= RAM_BUS_ALIAS_SIZE {
+        return Err("Memory is out of reach of the DMA controller");
+    }
+
+    Ok(RAM_BUS_ALIAS + phys_addr.as_usize())
+}
+
+/// Stop a channel and reset it.
+fn reset_channel(regs: &ChannelRegisters) {
+    // Pause the channel and let it finish its outstanding writes first.
+    regs.CS.write(CS::ACTIVE::CLEAR);
+    for _ in 0..ABORT_POLL_ITERATIONS {
+        if !regs.CS.is_set(CS::WAITING_FOR_OUTSTANDING_WRITES) {
+            break;
+        }
+    }
+
+    regs.CS.write(CS::RESET::SET);
+    regs.DEBUG.set(DEBUG_ERRORS);
+}
+
+impl DMAPeripheral {
+    fn dreq(self) -> u32 {
+        match self {
+            DMAPeripheral::Emmc => 11,
+            DMAPeripheral::UartTx => 12,
+            DMAPeripheral::UartRx => 14,
+        }
+    }
+}
+
+impl ChannelState {
+    const fn new() -> Self {
+        Self {
+            registers: IRQSafeNullLock::new(unsafe { ChannelRegisters::new(0) }),
+            status: AtomicU8::new(TransferStatus::Idle as u8),
+            waker: executor::WakerCell::new(),
+        }
+    }
+
+    fn status(&self) -> TransferStatus {
+        match self.status.load(Ordering::Acquire) {
+            x if x == TransferStatus::Running as u8 => TransferStatus::Running,
+            x if x == TransferStatus::Done as u8 => TransferStatus::Done,
+            x if x == TransferStatus::Error as u8 => TransferStatus::Error,
+            _ => TransferStatus::Idle,
+        }
+    }
+
+    fn set_status(&self, status: TransferStatus) {
+        self.status.store(status as u8, Ordering::Release);
+    }
+
+    /// Abort a running transfer.
+    fn abort(&self) {
+        self.registers.lock(|regs| reset_channel(regs));
+        self.set_status(TransferStatus::Idle);
+    }
+}
+
+impl<'a> DMAChain<'a> {
+    fn control_block_mut(&mut self, index: usize) -> &mut ControlBlock {
+        assert!(index < self.capacity);
+
+        unsafe {
+            &mut *(self.control_blocks.as_mut_slice().as_mut_ptr() as *mut ControlBlock).add(index)
+        }
+    }
+
+    /// Append control blocks for a segment. Sides with an increment flag in `ti` advance.
+    fn push(
+        &mut self,
+        ti: u32,
+        source_ad: usize,
+        dest_ad: usize,
+        len: usize,
+    ) -> Result<(), &'static str> {
+        if len == 0 {
+            return Err("Empty DMA segment");
+        }
+
+        let num_blocks = (len + MAX_CONTROL_BLOCK_LEN - 1) / MAX_CONTROL_BLOCK_LEN;
+        if self.capacity - self.len < num_blocks {
+            return Err("DMA chain is full");
+        }
+
+        let cb_bus_start_addr = ram_bus_addr(self.control_blocks.phys_start_addr())?;
+        let source_inc = ti & TI::SRC_INC::SET.value != 0;
+        let dest_inc = ti & TI::DEST_INC::SET.value != 0;
+
+        for block in 0..num_blocks {
+            let offset = block * MAX_CONTROL_BLOCK_LEN;
+            let index = self.len;
+
+            *self.control_block_mut(index) = ControlBlock {
+                ti,
+                source_ad: (source_ad + if source_inc { offset } else { 0 }) as u32,
+                dest_ad: (dest_ad + if dest_inc { offset } else { 0 }) as u32,
+                txfr_len: (len - offset).min(MAX_CONTROL_BLOCK_LEN) as u32,
+                stride: 0,
+                nextconbk: 0,
+                reserved: [0; 2],
+            };
+
+            if index > 0 {
+                let bus_addr = cb_bus_start_addr + index * core::mem::size_of::<ControlBlock>();
+                self.control_block_mut(index - 1).nextconbk = bus_addr as u32;
+            }
+
+            self.len += 1;
+        }
+
+        Ok(())
+    }
+
+    /// The bus address of a peripheral register.
+    fn peripheral_bus_addr(&self, phys_addr: Address<Physical>) -> Result<usize, &'static str> {
+        (phys_addr.as_usize() + BUS_MMIO_START_ADDR)
+            .checked_sub(self.phys_mmio_start_addr)
+            .ok_or("Peripheral is out of reach of the DMA controller")
+    }
+}
+
+impl Future for DMATransfer<'_> {
+    type Output = Result<(), &'static str>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Register first, so that a completion in between is not missed.
+        self.channel.waker.register(cx.waker());
+
+        let result = match self.channel.status() {
+            TransferStatus::Running => return Poll::Pending,
+            TransferStatus::Done => Ok(()),
+            _ => Err("DMA transfer failed"),
+        };
+
+        self.channel.set_status(TransferStatus::Idle);
+        self.control_blocks = None;
+
+        Poll::Ready(result)
+    }
+}
+
+impl Drop for DMATransfer<'_> {
+    fn drop(&mut self) {
+        // The control blocks and buffers must not be freed while the channel still uses them.
+        if self.channel.status() == TransferStatus::Running {
+            self.channel.abort();
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl<'a> DMAChain<'a> {
+    /// The number of control blocks in the chain.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the chain has no control blocks.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Append a memory to memory copy of `len` bytes.
+    pub fn push_copy(
+        &mut self,
+        dest: &'a DeviceOwnedDmaBuffer,
+        dest_offset: usize,
+        src: &'a DeviceOwnedDmaBuffer,
+        src_offset: usize,
+        len: usize,
+    ) -> Result<(), &'static str> {
+        let source_ad = buffer_bus_addr(src, src_offset, len, true)?;
+        let dest_ad = buffer_bus_addr(dest, dest_offset, len, false)?;
+        let ti = TI::SRC_INC::SET + TI::DEST_INC::SET + TI::WAIT_RESP::SET;
+
+        self.push(ti.value, source_ad, dest_ad, len)
+    }
+
+    /// Append a transfer of `len` bytes from memory to a peripheral's FIFO register, paced by the
+    /// peripheral's DREQ. The peripheral must have its DMA requests enabled.
+    pub fn push_to_peripheral(
+        &mut self,
+        peripheral: DMAPeripheral,
+        fifo_addr: Address<Physical>,
+        src: &'a DeviceOwnedDmaBuffer,
+        src_offset: usize,
+        len: usize,
+    ) -> Result<(), &'static str> {
+        let source_ad = buffer_bus_addr(src, src_offset, len, true)?;
+        let dest_ad = self.peripheral_bus_addr(fifo_addr)?;
+        let ti = TI::SRC_INC::SET
+            + TI::DEST_DREQ::SET
+            + TI::PERMAP.val(peripheral.dreq())
+            + TI::WAIT_RESP::SET;
+
+        self.push(ti.value, source_ad, dest_ad, len)
+    }
+
+    /// Append a transfer of `len` bytes from a peripheral's FIFO register to memory, paced by the
+    /// peripheral's DREQ. The peripheral must have its DMA requests enabled.
+    pub fn push_from_peripheral(
+        &mut self,
+        dest: &'a DeviceOwnedDmaBuffer,
+        dest_offset: usize,
+        peripheral: DMAPeripheral,
+        fifo_addr: Address<Physical>,
+        len: usize,
+    ) -> Result<(), &'static str> {
+        let source_ad = self.peripheral_bus_addr(fifo_addr)?;
+        let dest_ad = buffer_bus_addr(dest, dest_offset, len, false)?;
+        let ti = TI::DEST_INC::SET
+            + TI::SRC_DREQ::SET
+            + TI::PERMAP.val(peripheral.dreq())
+            + TI::WAIT_RESP::SET;
+
+        self.push(ti.value, source_ad, dest_ad, len)
+    }
+}
+
+impl<'a> DMAChannel<'a> {
+    /// The channel number.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Execute a chain. The returned future resolves once the last control block has finished.
+    ///
+    /// # Safety
+    ///
+    /// - The returned transfer must not be leaked, e.g. with `core::mem::forget()`. It must be
+    ///   polled to completion or dropped, which aborts the transfer, before the chain's buffers can
+    ///   be used otherwise. A leaked transfer keeps the engine writing to them.
+    pub unsafe fn start<'b>(
+        &'b mut self,
+        mut chain: DMAChain<'b>,
+    ) -> Result<DMATransfer<'b>, &'static str> {
+        if chain.is_empty() {
+            return Err("Empty DMA chain");
+        }
+
+        let last = chain.len - 1;
+        chain.control_block_mut(last).ti |= TI::INTEN::SET.value;
+
+        let cb_bus_start_addr = ram_bus_addr(chain.control_blocks.phys_start_addr())?;
+        let control_blocks = chain.control_blocks.give_to_device(DmaDirection::ToDevice);
+        let channel = &self.controller.channels[self.index];
+
+        channel.set_status(TransferStatus::Running);
+        channel.registers.lock(|regs| {
+            regs.CONBLK_AD.set(cb_bus_start_addr as u32);
+            regs.CS
+                .write(CS::WAIT_FOR_OUTSTANDING_WRITES::SET + CS::ACTIVE::SET);
+        });
+
+        Ok(DMATransfer {
+            channel,
+            control_blocks: Some(control_blocks),
+            buffers: PhantomData,
+        })
+    }
+}
+
+impl Drop for DMAChannel<'_> {
+    fn drop(&mut self) {
+        self.controller
+            .free_channels
+            .fetch_or(1 << self.index, Ordering::AcqRel);
+    }
+}
+
+impl DMAController {
+    /// The compatible string.
+    pub const COMPATIBLE: &'static str = "BCM DMA Controller";
+
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide correct MMIO descriptors.
+    /// - The user must ensure to provide correct IRQ numbers, one per channel from 0 to 10.
+    pub const unsafe fn new(
+        mmio_descriptor: memory::mmu::MMIODescriptor,
+        irq_numbers: [bsp::device_driver::IRQNumber; NUM_CHANNELS],
+    ) -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const IDLE_CHANNEL: ChannelState = ChannelState::new();
+
+        Self {
+            mmio_descriptor: InitStateLock::new(mmio_descriptor),
+            virt_mmio_start_addr: AtomicUsize::new(0),
+            registers: IRQSafeNullLock::new(Registers::new(
+                mmio_descriptor.start_addr().as_usize(),
+            )),
+            irq_numbers,
+            channels: [IDLE_CHANNEL; NUM_CHANNELS],
+            free_channels: AtomicU16::new(USABLE_CHANNELS),
+        }
+    }
+
+    /// Replace the MMIO descriptor that was given to `new()`, e.g. with one from the device tree.
+    ///
+    /// Only has an effect if called before `init()`, which maps the MMIO region.
+    pub fn set_mmio_descriptor(&self, mmio_descriptor: memory::mmu::MMIODescriptor) {
+        self.mmio_descriptor.write(|x| *x = mmio_descriptor);
+    }
+
+    /// Allocate a free channel.
+    pub fn allocate_channel(&self) -> Result<DMAChannel<'_>, &'static str> {
+        if self.virt_mmio_start_addr.load(Ordering::Relaxed) == 0 {
+            return Err("DMA controller is not initialized");
+        }
+
+        let free = self
+            .free_channels
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |x| {
+                (x != 0).then(|| x & (x - 1))
+            })
+            .map_err(|_| "No free DMA channel")?;
+
+        Ok(DMAChannel {
+            controller: self,
+            index: free.trailing_zeros() as usize,
+        })
+    }
+
+    /// Create an empty chain with room for `capacity` control blocks.
+    ///
+    /// A segment takes one control block per started 64 KiB.
+    pub fn new_chain<'a>(&self, capacity: usize) -> Result<DMAChain<'a>, &'static str> {
+        let size = capacity
+            .checked_mul(core::mem::size_of::<ControlBlock>())
+            .ok_or("DMA chain too long")?;
+        let control_blocks = DmaBuffer::new_coherent(size, core::mem::align_of::<ControlBlock>())?;
+
+        Ok(DMAChain {
+            control_blocks,
+            len: 0,
+            capacity,
+            phys_mmio_start_addr: self.mmio_descriptor.read(|x| x.start_addr().as_usize()),
+            buffers: PhantomData,
+        })
+    }
+
+    /// Copy the contents of `src` to `dest`, using a channel of its own.
+    ///
+    /// # Safety
+    ///
+    /// - Same as [`DMAChannel::start()`], for the returned future.
+    pub async unsafe fn copy(
+        &self,
+        dest: &DeviceOwnedDmaBuffer,
+        src: &DeviceOwnedDmaBuffer,
+    ) -> Result<(), &'static str> {
+        let len = src.size();
+        if dest.size() < len {
+            return Err("DMA destination is smaller than the source");
+        }
+
+        let mut channel = self.allocate_channel()?;
+        let mut chain =
+            self.new_chain((len + MAX_CONTROL_BLOCK_LEN - 1) / MAX_CONTROL_BLOCK_LEN)?;
+        chain.push_copy(dest, 0, src, 0, len)?;
+
+        let transfer = channel.start(chain)?;
+
+        transfer.await
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+use synchronization::interface::{Mutex, ReadWriteEx};
+
+impl driver::interface::DeviceDriver for DMAController {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        let mmio_descriptor = self.mmio_descriptor.read(|x| *x);
+        let virt_addr = memory::mmu::kernel_map_mmio(self.compatible(), &mmio_descriptor)?;
+
+        for index in 0..NUM_CHANNELS {
+            if USABLE_CHANNELS & (1 << index) == 0 {
+                continue;
+            }
+
+            self.channels[index].registers.lock(|regs| {
+                *regs = ChannelRegisters::new(virt_addr.as_usize() + index * CHANNEL_STRIDE);
+
+                reset_channel(regs);
+            });
+        }
+
+        self.registers.lock(|regs| {
+            *regs = Registers::new(virt_addr.as_usize());
+
+            regs.ENABLE
+                .set(regs.ENABLE.get() | u32::from(USABLE_CHANNELS));
+        });
+
+        self.virt_mmio_start_addr
+            .store(virt_addr.as_usize(), Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    unsafe fn shutdown(&self) -> Result<(), &'static str> {
+        for channel in self.channels.iter() {
+            if channel.status() == TransferStatus::Running {
+                channel.abort();
+            }
+        }
+
+        Ok(())
+    }
+
+    unsafe fn suspend(&self) -> Result<(), &'static str> {
+        if self
+            .channels
+            .iter()
+            .any(|x| x.status() == TransferStatus::Running)
+        {
+            return Err("DMA transfer in progress");
+        }
+
+        Ok(())
+    }
+
+    fn register_and_enable_irq_handler(&'static self) -> Result<(), &'static str> {
+        use bsp::exception::asynchronous::irq_manager;
+        use exception::asynchronous::{interface::IRQManager, IRQDescriptor};
+
+        for (index, channel) in self.channels.iter().enumerate() {
+            if USABLE_CHANNELS & (1 << index) == 0 {
+                continue;
+            }
+
+            let descriptor = IRQDescriptor {
+                name: Self::COMPATIBLE,
+                handler: channel,
+            };
+
+            irq_manager().register_handler(self.irq_numbers[index], descriptor)?;
+            irq_manager().enable(self.irq_numbers[index]);
+        }
+
+        Ok(())
+    }
+
+    fn virt_mmio_start_addr(&self) -> Option<usize> {
+        let addr = self.virt_mmio_start_addr.load(Ordering::Relaxed);
+
+        if addr == 0 {
+            return None;
+        }
+
+        Some(addr)
+    }
+}
+
+impl exception::asynchronous::interface::IRQHandler for ChannelState {
+    fn handle(&self) -> Result<(), &'static str> {
+        let status = self.registers.lock(|regs| {
+            if regs.CS.is_set(CS::ERROR) {
+                reset_channel(regs);
+
+                return Some(TransferStatus::Error);
+            }
+
+            if !regs.CS.is_set(CS::INT) {
+                return None;
+            }
+
+            regs.CS.write(CS::INT::SET + CS::END::SET);
+
+            Some(TransferStatus::Done)
+        });
+
+        if let Some(status) = status {
+            if self.status() == TransferStatus::Running {
+                self.set_status(status);
+                self.waker.wake();
+            }
+        }
+
+        Ok(())
+    }
+}
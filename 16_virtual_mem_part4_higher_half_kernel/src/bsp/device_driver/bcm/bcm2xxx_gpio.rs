@@ -5,12 +5,19 @@
 //! GPIO Driver.
 
 use crate::{
-    bsp::device_driver::common::MMIODerefWrapper, driver, memory, synchronization,
-    synchronization::IRQSafeNullLock,
+    bsp,
+    bsp::device_driver::common::MMIODerefWrapper,
+    driver, exception, executor, memory, synchronization,
+    synchronization::{IRQSafeNullLock, InitStateLock},
+};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering},
+    task::{Context, Poll},
 };
-use core::sync::atomic::{AtomicUsize, Ordering};
 use tock_registers::{
-    interfaces::{ReadWriteable, Writeable},
+    interfaces::{ReadWriteable, Readable, Writeable},
     register_bitfields, register_structs,
     registers::ReadWrite,
 };
@@ -27,6 +34,44 @@ use tock_registers::{
 register_bitfields! {
     u32,
 
+    /// GPIO Function Select 0
+    GPFSEL0 [
+        /// Pin 9
+        FSEL9 OFFSET(27) NUMBITS(3) [
+            Input = 0b000,
+            Output = 0b001,
+            AltFunc0 = 0b100  // SPI0 MISO
+        ],
+
+        /// Pin 8
+        FSEL8 OFFSET(24) NUMBITS(3) [
+            Input = 0b000,
+            Output = 0b001,
+            AltFunc0 = 0b100  // SPI0 CE0
+        ],
+
+        /// Pin 7
+        FSEL7 OFFSET(21) NUMBITS(3) [
+            Input = 0b000,
+            Output = 0b001,
+            AltFunc0 = 0b100  // SPI0 CE1
+        ],
+
+        /// Pin 3
+        FSEL3 OFFSET(9) NUMBITS(3) [
+            Input = 0b000,
+            Output = 0b001,
+            AltFunc0 = 0b100  // I2C1 SCL
+        ],
+
+        /// Pin 2
+        FSEL2 OFFSET(6) NUMBITS(3) [
+            Input = 0b000,
+            Output = 0b001,
+            AltFunc0 = 0b100  // I2C1 SDA
+        ]
+    ],
+
     /// GPIO Function Select 1
     GPFSEL1 [
         /// Pin 15
@@ -42,6 +87,20 @@ register_bitfields! {
             Input = 0b000,
             Output = 0b001,
             AltFunc0 = 0b100  // PL011 UART TX
+        ],
+
+        /// Pin 11
+        FSEL11 OFFSET(3) NUMBITS(3) [
+            Input = 0b000,
+            Output = 0b001,
+            AltFunc0 = 0b100  // SPI0 SCLK
+        ],
+
+        /// Pin 10
+        FSEL10 OFFSET(0) NUMBITS(3) [
+            Input = 0b000,
+            Output = 0b001,
+            AltFunc0 = 0b100  // SPI0 MOSI
         ]
     ],
 
@@ -95,12 +154,18 @@ register_bitfields! {
 register_structs! {
     #[allow(non_snake_case)]
     RegisterBlock {
-        (0x00 => _reserved1),
+        (0x00 => GPFSEL0: ReadWrite<u32, GPFSEL0::Register>),
         (0x04 => GPFSEL1: ReadWrite<u32, GPFSEL1::Register>),
         (0x08 => _reserved2),
+        (0x40 => GPEDS0: ReadWrite<u32>),
+        (0x44 => _reserved3),
+        (0x4C => GPREN0: ReadWrite<u32>),
+        (0x50 => _reserved4),
+        (0x58 => GPFEN0: ReadWrite<u32>),
+        (0x5C => _reserved5),
         (0x94 => GPPUD: ReadWrite<u32, GPPUD::Register>),
         (0x98 => GPPUDCLK0: ReadWrite<u32, GPPUDCLK0::Register>),
-        (0x9C => _reserved3),
+        (0x9C => _reserved6),
         (0xE4 => GPIO_PUP_PDN_CNTRL_REG0: ReadWrite<u32, GPIO_PUP_PDN_CNTRL_REG0::Register>),
         (0xE8 => @END),
     }
@@ -109,12 +174,18 @@ register_structs! {
 /// Abstraction for the associated MMIO registers.
 type Registers = MMIODerefWrapper<RegisterBlock>;
 
+/// Number of pins in bank 0, which is the only bank with edge detection support in this driver.
+const NUM_EDGE_DETECT_PINS: usize = 32;
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
 pub struct GPIOInner {
     registers: Registers,
+
+    /// The rising and falling edge detect enables from before `suspend()`.
+    suspended_edge_detect: Option<(u32, u32)>,
 }
 
 // Export the inner struct so that BSPs can use it for the panic handler.
@@ -122,9 +193,30 @@ pub use GPIOInner as PanicGPIO;
 
 /// Representation of the GPIO HW.
 pub struct GPIO {
-    mmio_descriptor: memory::mmu::MMIODescriptor,
+    mmio_descriptor: InitStateLock<memory::mmu::MMIODescriptor>,
     virt_mmio_start_addr: AtomicUsize,
     inner: IRQSafeNullLock<GPIOInner>,
+    irq_number: bsp::device_driver::IRQNumber,
+
+    /// One bit per pin. Set by the IRQ handler when an edge was detected.
+    detected_edges: AtomicU32,
+    edge_wakers: [executor::WakerCell; NUM_EDGE_DETECT_PINS],
+}
+
+/// Signal edges that can be detected on a pin.
+#[derive(Copy, Clone)]
+pub enum GPIOEdge {
+    /// Low to high.
+    Rising,
+    /// High to low.
+    Falling,
+}
+
+/// Future returned by [`GPIO::wait_for_edge()`].
+pub struct GPIOEdgeFuture<'a> {
+    gpio: &'a GPIO,
+    pin: usize,
+    edge: GPIOEdge,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -140,6 +232,7 @@ impl GPIOInner {
     pub const unsafe fn new(mmio_start_addr: usize) -> Self {
         Self {
             registers: Registers::new(mmio_start_addr),
+            suspended_edge_detect: None,
         }
     }
 
@@ -156,6 +249,64 @@ impl GPIOInner {
         Ok(())
     }
 
+    /// Enable asynchronous detection of the given edge on a pin.
+    fn enable_edge_detect(&mut self, pin: usize, edge: GPIOEdge) {
+        let reg = match edge {
+            GPIOEdge::Rising => &self.registers.GPREN0,
+            GPIOEdge::Falling => &self.registers.GPFEN0,
+        };
+
+        reg.set(reg.get() | (1 << pin));
+    }
+
+    /// Disable edge detection on a pin.
+    fn disable_edge_detect(&mut self, pin: usize) {
+        for reg in [&self.registers.GPREN0, &self.registers.GPFEN0] {
+            reg.set(reg.get() & !(1 << pin));
+        }
+    }
+
+    /// Disable edge detection on all pins and discard the edges detected so far.
+    fn shutdown(&mut self) {
+        self.registers.GPREN0.set(0);
+        self.registers.GPFEN0.set(0);
+        self.take_detected_edges();
+    }
+
+    /// Disable edge detection on all pins, remembering where it was enabled.
+    ///
+    /// Edges detected so far are kept, so that their waiters are woken after `resume()`.
+    fn suspend(&mut self) {
+        self.suspended_edge_detect =
+            Some((self.registers.GPREN0.get(), self.registers.GPFEN0.get()));
+
+        self.registers.GPREN0.set(0);
+        self.registers.GPFEN0.set(0);
+    }
+
+    /// Enable edge detection again where it was enabled before `suspend()`.
+    fn resume(&mut self) -> Result<(), &'static str> {
+        let (rising, falling) = self
+            .suspended_edge_detect
+            .take()
+            .ok_or("GPIO is not suspended")?;
+
+        self.registers.GPREN0.set(rising);
+        self.registers.GPFEN0.set(falling);
+
+        Ok(())
+    }
+
+    /// Return and clear the pins on which an edge was detected.
+    fn take_detected_edges(&mut self) -> u32 {
+        let pending = self.registers.GPEDS0.get();
+
+        // Writing a 1 clears the respective event.
+        self.registers.GPEDS0.set(pending);
+
+        pending
+    }
+
     /// Disable pull-up/down on pins 14 and 15.
     #[cfg(feature = "bsp_rpi3")]
     fn disable_pud_14_15_bcm2837(&mut self) {
@@ -203,40 +354,139 @@ impl GPIOInner {
         #[cfg(feature = "bsp_rpi4")]
         self.disable_pud_14_15_bcm2711();
     }
+
+    /// Map the I2C1 controller.
+    ///
+    /// SDA to pin 2
+    /// SCL to pin 3
+    ///
+    /// Both pins have pull-ups on the board.
+    pub fn map_i2c1(&mut self) {
+        self.registers
+            .GPFSEL0
+            .modify(GPFSEL0::FSEL3::AltFunc0 + GPFSEL0::FSEL2::AltFunc0);
+    }
+
+    /// Map the SPI0 controller.
+    ///
+    /// CE1 to pin 7
+    /// CE0 to pin 8
+    /// MISO to pin 9
+    /// MOSI to pin 10
+    /// SCLK to pin 11
+    pub fn map_spi0(&mut self) {
+        self.registers
+            .GPFSEL0
+            .modify(GPFSEL0::FSEL9::AltFunc0 + GPFSEL0::FSEL8::AltFunc0 + GPFSEL0::FSEL7::AltFunc0);
+        self.registers
+            .GPFSEL1
+            .modify(GPFSEL1::FSEL11::AltFunc0 + GPFSEL1::FSEL10::AltFunc0);
+    }
 }
 
 impl GPIO {
+    /// The compatible string.
+    pub const COMPATIBLE: &'static str = "BCM GPIO";
+
     /// Create an instance.
     ///
     /// # Safety
     ///
     /// - The user must ensure to provide correct MMIO descriptors.
-    pub const unsafe fn new(mmio_descriptor: memory::mmu::MMIODescriptor) -> Self {
+    /// - The user must ensure to provide correct IRQ numbers.
+    pub const unsafe fn new(
+        mmio_descriptor: memory::mmu::MMIODescriptor,
+        irq_number: bsp::device_driver::IRQNumber,
+    ) -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const NO_WAKER: executor::WakerCell = executor::WakerCell::new();
+
         Self {
-            mmio_descriptor,
+            mmio_descriptor: InitStateLock::new(mmio_descriptor),
             virt_mmio_start_addr: AtomicUsize::new(0),
             inner: IRQSafeNullLock::new(GPIOInner::new(mmio_descriptor.start_addr().as_usize())),
+            irq_number,
+            detected_edges: AtomicU32::new(0),
+            edge_wakers: [NO_WAKER; NUM_EDGE_DETECT_PINS],
         }
     }
 
+    /// Replace the MMIO descriptor that was given to `new()`, e.g. with one from the device tree.
+    ///
+    /// Only has an effect if called before `init()`, which maps the MMIO region.
+    pub fn set_mmio_descriptor(&self, mmio_descriptor: memory::mmu::MMIODescriptor) {
+        self.mmio_descriptor.write(|x| *x = mmio_descriptor);
+    }
+
     /// Concurrency safe version of `GPIOInner.map_pl011_uart()`
     pub fn map_pl011_uart(&self) {
         self.inner.lock(|inner| inner.map_pl011_uart())
     }
+
+    /// Concurrency safe version of `GPIOInner.map_i2c1()`
+    pub fn map_i2c1(&self) {
+        self.inner.lock(|inner| inner.map_i2c1())
+    }
+
+    /// Concurrency safe version of `GPIOInner.map_spi0()`
+    pub fn map_spi0(&self) {
+        self.inner.lock(|inner| inner.map_spi0())
+    }
+
+    /// Asynchronously wait for an edge on an input pin.
+    ///
+    /// Only edges that occur after the first poll of the returned future are detected.
+    pub fn wait_for_edge(&self, pin: usize, edge: GPIOEdge) -> GPIOEdgeFuture<'_> {
+        assert!(pin < NUM_EDGE_DETECT_PINS);
+
+        GPIOEdgeFuture {
+            gpio: self,
+            pin,
+            edge,
+        }
+    }
+}
+
+impl Future for GPIOEdgeFuture<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let pin_mask = 1 << self.pin;
+        let detected_edges = self
+            .gpio
+            .detected_edges
+            .fetch_and(!pin_mask, Ordering::AcqRel);
+
+        if detected_edges & pin_mask != 0 {
+            self.gpio
+                .inner
+                .lock(|inner| inner.disable_edge_detect(self.pin));
+
+            return Poll::Ready(());
+        }
+
+        self.gpio.edge_wakers[self.pin].register(cx.waker());
+        self.gpio
+            .inner
+            .lock(|inner| inner.enable_edge_detect(self.pin, self.edge));
+
+        Poll::Pending
+    }
 }
 
 //------------------------------------------------------------------------------
 // OS Interface Code
 //------------------------------------------------------------------------------
-use synchronization::interface::Mutex;
+use synchronization::interface::{Mutex, ReadWriteEx};
 
 impl driver::interface::DeviceDriver for GPIO {
     fn compatible(&self) -> &'static str {
-        "BCM GPIO"
+        Self::COMPATIBLE
     }
 
     unsafe fn init(&self) -> Result<(), &'static str> {
-        let virt_addr = memory::mmu::kernel_map_mmio(self.compatible(), &self.mmio_descriptor)?;
+        let mmio_descriptor = self.mmio_descriptor.read(|x| *x);
+        let virt_addr = memory::mmu::kernel_map_mmio(self.compatible(), &mmio_descriptor)?;
 
         self.inner
             .lock(|inner| inner.init(Some(virt_addr.as_usize())))?;
@@ -247,6 +497,37 @@ impl driver::interface::DeviceDriver for GPIO {
         Ok(())
     }
 
+    unsafe fn shutdown(&self) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.shutdown());
+
+        Ok(())
+    }
+
+    unsafe fn suspend(&self) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.suspend());
+
+        Ok(())
+    }
+
+    unsafe fn resume(&self) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.resume())
+    }
+
+    fn register_and_enable_irq_handler(&'static self) -> Result<(), &'static str> {
+        use bsp::exception::asynchronous::irq_manager;
+        use exception::asynchronous::{interface::IRQManager, IRQDescriptor};
+
+        let descriptor = IRQDescriptor {
+            name: Self::COMPATIBLE,
+            handler: self,
+        };
+
+        irq_manager().register_handler(self.irq_number, descriptor)?;
+        irq_manager().enable(self.irq_number);
+
+        Ok(())
+    }
+
     fn virt_mmio_start_addr(&self) -> Option<usize> {
         let addr = self.virt_mmio_start_addr.load(Ordering::Relaxed);
 
@@ -257,3 +538,18 @@ impl driver::interface::DeviceDriver for GPIO {
         Some(addr)
     }
 }
+
+impl exception::asynchronous::interface::IRQHandler for GPIO {
+    fn handle(&self) -> Result<(), &'static str> {
+        let pending = self.inner.lock(|inner| inner.take_detected_edges());
+        self.detected_edges.fetch_or(pending, Ordering::AcqRel);
+
+        for (pin, waker) in self.edge_wakers.iter().enumerate() {
+            if pending & (1 << pin) != 0 {
+                waker.wake();
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,559 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! I2C Driver.
+//!
+//! Driver for the Broadcom Serial Controller (BSC), which is an I2C master. A transfer is started
+//! with the address and the number of bytes, and data is exchanged through a 16 byte FIFO. The
+//! controller signals a missing acknowledge and a slave that stretches the clock for too long in
+//! its status register.
+//!
+//! The controller does not issue a repeated start on its own. It does so if a read is started
+//! while a write is still active, which is why the write part of a write-read must fit into the
+//! FIFO.
+//!
+//! # Resources
+//!
+//! - <https://github.com/raspberrypi/documentation/files/1888662/BCM2837-ARM-Peripherals.-.Revised.-.V2-1.pdf>
+//! - <https://datasheets.raspberrypi.org/bcm2711/bcm2711-peripherals.pdf>
+//! - Linux, `drivers/i2c/busses/i2c-bcm2835.c`
+
+use crate::{
+    bsp::device_driver::common::{MMIORegisters, RegisterAccess},
+    driver, i2c, memory, synchronization,
+    synchronization::{IRQSafeNullLock, InitStateLock},
+    time,
+};
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use tock_registers::register_bitfields;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+register_bitfields! {
+    u32,
+
+    /// Control Register.
+    C [
+        /// Enables the controller.
+        I2CEN OFFSET(15) NUMBITS(1) [],
+
+        /// Starts a transfer. Self clearing.
+        ST OFFSET(7) NUMBITS(1) [],
+
+        /// Clears the FIFO. Self clearing.
+        CLEAR OFFSET(4) NUMBITS(2) [
+            ClearFifo = 0b01
+        ],
+
+        /// The transfer reads from the slave.
+        READ OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Status Register.
+    S [
+        /// The slave stretched the clock for too long. Writing a 1 clears it.
+        CLKT OFFSET(9) NUMBITS(1) [],
+
+        /// The slave did not acknowledge its address or data. Writing a 1 clears it.
+        ERR OFFSET(8) NUMBITS(1) [],
+
+        /// The FIFO holds data.
+        RXD OFFSET(5) NUMBITS(1) [],
+
+        /// The FIFO can accept data.
+        TXD OFFSET(4) NUMBITS(1) [],
+
+        /// The transfer has finished. Writing a 1 clears it.
+        DONE OFFSET(1) NUMBITS(1) [],
+
+        /// A transfer is active.
+        TA OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Data Delay Register.
+    DEL [
+        /// Core clock cycles between the falling edge of SCL and driving SDA.
+        FEDL OFFSET(16) NUMBITS(16) [],
+
+        /// Core clock cycles between the rising edge of SCL and sampling SDA.
+        REDL OFFSET(0) NUMBITS(16) []
+    ]
+}
+
+// Register offsets.
+const C: usize = 0x00;
+const S: usize = 0x04;
+const DLEN: usize = 0x08;
+const A: usize = 0x0C;
+const FIFO: usize = 0x10;
+const DIV: usize = 0x14;
+const DEL: usize = 0x18;
+
+const FIFO_SIZE: usize = 16;
+
+/// The clock divider is even and 16 bits wide.
+const MIN_DIVIDER: u32 = 2;
+const MAX_DIVIDER: u32 = 0xFFFE;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(100);
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The bus logic, independent of how the registers are accessed.
+pub struct I2CInner<R> {
+    registers: R,
+    core_clock_hz: u32,
+    timeout: Duration,
+}
+
+/// Representation of the I2C controller.
+pub struct I2C {
+    mmio_descriptor: InitStateLock<memory::mmu::MMIODescriptor>,
+    virt_mmio_start_addr: AtomicUsize,
+    inner: IRQSafeNullLock<I2CInner<MMIORegisters>>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl<R: RegisterAccess> I2CInner<R> {
+    fn deadline(&self) -> Duration {
+        use time::interface::TimeManager;
+
+        time::time_manager().uptime() + self.timeout
+    }
+
+    fn is_expired(deadline: Duration) -> bool {
+        use time::interface::TimeManager;
+
+        time::time_manager().uptime() >= deadline
+    }
+
+    /// Clear the FIFO and the status, and set up a transfer.
+    fn prepare(&mut self, addr: u8, len: usize) -> Result<(), &'static str> {
+        if addr > i2c::MAX_ADDR {
+            return Err("Invalid I2C address");
+        }
+        let len = u16::try_from(len).map_err(|_| "I2C transfer too long")?;
+
+        self.registers
+            .write(C, (C::I2CEN::SET + C::CLEAR::ClearFifo).value);
+        self.registers
+            .write(S, (S::CLKT::SET + S::ERR::SET + S::DONE::SET).value);
+        self.registers.write(A, addr.into());
+        self.registers.write(DLEN, len.into());
+
+        Ok(())
+    }
+
+    /// Exchange data through the FIFO until the transfer has finished.
+    fn transfer(&mut self, tx: &[u8], rx: &mut [u8]) -> Result<(), &'static str> {
+        let deadline = self.deadline();
+        let (mut num_sent, mut num_received) = (0, 0);
+
+        loop {
+            let status = self.registers.read(S);
+
+            if S::ERR.is_set(status) {
+                return Err(i2c::NACK_ERROR);
+            }
+            if S::CLKT.is_set(status) {
+                return Err("I2C clock stretch timeout");
+            }
+
+            if num_sent < tx.len() && S::TXD.is_set(status) {
+                self.registers.write(FIFO, tx[num_sent].into());
+                num_sent += 1;
+                continue;
+            }
+
+            if num_received < rx.len() && S::RXD.is_set(status) {
+                rx[num_received] = self.registers.read(FIFO) as u8;
+                num_received += 1;
+                continue;
+            }
+
+            if S::DONE.is_set(status) {
+                if num_sent < tx.len() || num_received < rx.len() {
+                    return Err("I2C transfer ended early");
+                }
+
+                return Ok(());
+            }
+
+            if Self::is_expired(deadline) {
+                return Err(i2c::TIMEOUT_ERROR);
+            }
+        }
+    }
+
+    /// Wait until the current transfer is active.
+    fn wait_for_active(&mut self) -> Result<(), &'static str> {
+        let deadline = self.deadline();
+
+        loop {
+            let status = self.registers.read(S);
+
+            if S::ERR.is_set(status) {
+                return Err(i2c::NACK_ERROR);
+            }
+            if S::TA.is_set(status) || S::DONE.is_set(status) {
+                return Ok(());
+            }
+            if Self::is_expired(deadline) {
+                return Err(i2c::TIMEOUT_ERROR);
+            }
+        }
+    }
+
+    /// Disable the controller, which aborts a transfer that is still active, and clear the FIFO
+    /// and the status.
+    fn finish(&mut self, result: Result<(), &'static str>) -> Result<(), &'static str> {
+        self.registers.write(C, C::CLEAR::ClearFifo.value);
+        self.registers
+            .write(S, (S::CLKT::SET + S::ERR::SET + S::DONE::SET).value);
+
+        result
+    }
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), &'static str> {
+        self.prepare(addr, bytes.len())?;
+        self.registers.write(C, (C::I2CEN::SET + C::ST::SET).value);
+
+        let result = self.transfer(bytes, &mut []);
+        self.finish(result)
+    }
+
+    fn read(&mut self, addr: u8, buf: &mut [u8]) -> Result<(), &'static str> {
+        self.prepare(addr, buf.len())?;
+        self.registers
+            .write(C, (C::I2CEN::SET + C::ST::SET + C::READ::SET).value);
+
+        let result = self.transfer(&[], buf);
+        self.finish(result)
+    }
+
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buf: &mut [u8]) -> Result<(), &'static str> {
+        if bytes.len() > FIFO_SIZE {
+            return Err("I2C write before a repeated start must fit into the FIFO");
+        }
+        let read_len = u16::try_from(buf.len()).map_err(|_| "I2C transfer too long")?;
+
+        self.prepare(addr, bytes.len())?;
+        for byte in bytes {
+            self.registers.write(FIFO, (*byte).into());
+        }
+        self.registers.write(C, (C::I2CEN::SET + C::ST::SET).value);
+
+        // Starting the read while the write is active makes the controller issue a repeated start.
+        let result = self.wait_for_active().and_then(|_| {
+            self.registers.write(DLEN, read_len.into());
+            self.registers
+                .write(C, (C::I2CEN::SET + C::ST::SET + C::READ::SET).value);
+
+            self.transfer(&[], buf)
+        });
+        self.finish(result)
+    }
+
+    fn set_clock(&mut self, hz: u32) -> Result<(), &'static str> {
+        if hz == 0 {
+            return Err("Invalid I2C clock");
+        }
+
+        // Round up, so that the bus is never clocked faster than requested. Computed in u64, since
+        // the sum overflows for large clocks.
+        let divider = ((u64::from(self.core_clock_hz) + u64::from(hz) - 1) / u64::from(hz)) as u32;
+        let divider = divider + (divider & 1);
+        if !(MIN_DIVIDER..=MAX_DIVIDER).contains(&divider) {
+            return Err("I2C clock out of range");
+        }
+
+        self.registers.write(DIV, divider);
+
+        // The data delays must stay below half a clock period.
+        let del = DEL::FEDL.val((divider / 16).max(1)) + DEL::REDL.val((divider / 4).max(1));
+        self.registers.write(DEL, del.value);
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl<R> I2CInner<R> {
+    /// Create an instance.
+    pub const fn new(registers: R, core_clock_hz: u32) -> Self {
+        Self {
+            registers,
+            core_clock_hz,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl I2C {
+    /// The compatible string.
+    pub const COMPATIBLE: &'static str = "BCM I2C";
+
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide correct MMIO descriptors.
+    /// - The user must ensure to provide the frequency of the core clock, which drives SCL.
+    pub const unsafe fn new(
+        mmio_descriptor: memory::mmu::MMIODescriptor,
+        core_clock_hz: u32,
+    ) -> Self {
+        Self {
+            mmio_descriptor: InitStateLock::new(mmio_descriptor),
+            virt_mmio_start_addr: AtomicUsize::new(0),
+            inner: IRQSafeNullLock::new(I2CInner::new(
+                MMIORegisters::new(mmio_descriptor.start_addr().as_usize()),
+                core_clock_hz,
+            )),
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+use synchronization::interface::{Mutex, ReadWriteEx};
+
+impl driver::interface::DeviceDriver for I2C {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        let mmio_descriptor = self.mmio_descriptor.read(|x| *x);
+        let virt_addr = memory::mmu::kernel_map_mmio(self.compatible(), &mmio_descriptor)?;
+
+        self.inner.lock(|inner| {
+            inner.registers = MMIORegisters::new(virt_addr.as_usize());
+
+            inner.finish(Ok(()))?;
+            inner.set_clock(i2c::STANDARD_MODE_HZ)
+        })?;
+
+        self.virt_mmio_start_addr
+            .store(virt_addr.as_usize(), Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    unsafe fn shutdown(&self) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.finish(Ok(())))
+    }
+
+    fn virt_mmio_start_addr(&self) -> Option<usize> {
+        let addr = self.virt_mmio_start_addr.load(Ordering::Relaxed);
+
+        if addr == 0 {
+            return None;
+        }
+
+        Some(addr)
+    }
+}
+
+impl i2c::interface::Bus for I2C {
+    fn set_clock(&self, hz: u32) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.set_clock(hz))
+    }
+
+    fn set_timeout(&self, timeout: Duration) {
+        self.inner.lock(|inner| inner.timeout = timeout)
+    }
+
+    fn write(&self, addr: u8, bytes: &[u8]) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.write(addr, bytes))
+    }
+
+    fn read(&self, addr: u8, buf: &mut [u8]) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.read(addr, buf))
+    }
+
+    fn write_read(&self, addr: u8, bytes: &[u8], buf: &mut [u8]) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.write_read(addr, bytes, buf))
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    const CORE_CLOCK_HZ: u32 = 250_000_000;
+    const DEVICE_ADDR: u8 = 0x42;
+
+    /// A simulated controller with a single device on the bus. Transfers finish instantly, unless
+    /// the bus is stalled.
+    #[derive(Default)]
+    struct MockBSC {
+        c: u32,
+        s: u32,
+        dlen: u32,
+        a: u32,
+        div: u32,
+        del: u32,
+        is_stalled: bool,
+
+        written: [u8; FIFO_SIZE],
+        num_written: usize,
+        response: [u8; FIFO_SIZE],
+        num_read: usize,
+    }
+
+    impl MockBSC {
+        fn is_active(&self) -> bool {
+            S::TA.is_set(self.s) && !self.is_stalled
+        }
+    }
+
+    impl RegisterAccess for MockBSC {
+        fn read(&mut self, offset: usize) -> u32 {
+            match offset {
+                C => self.c,
+                S => {
+                    let is_reading = C::READ.is_set(self.c);
+                    let mut s = self.s;
+
+                    if self.is_active() && !is_reading && self.num_written < FIFO_SIZE {
+                        s |= S::TXD::SET.value;
+                    }
+                    if self.is_active() && is_reading && self.num_read < self.dlen as usize {
+                        s |= S::RXD::SET.value;
+                    }
+
+                    s
+                }
+                DLEN => self.dlen,
+                A => self.a,
+                FIFO => {
+                    self.num_read += 1;
+                    self.response[self.num_read - 1].into()
+                }
+                DIV => self.div,
+                DEL => self.del,
+                _ => 0,
+            }
+        }
+
+        fn write(&mut self, offset: usize, value: u32) {
+            match offset {
+                C => {
+                    self.c = value;
+
+                    if !C::I2CEN.is_set(value) {
+                        self.s &= !S::TA::SET.value;
+                    } else if C::ST.is_set(value) {
+                        self.s |= S::TA::SET.value;
+                        if !self.is_stalled {
+                            self.s |= S::DONE::SET.value;
+                        }
+                        if self.a != DEVICE_ADDR.into() {
+                            self.s |= S::ERR::SET.value;
+                        }
+                    }
+                }
+                S => self.s &= !(value & (S::CLKT::SET + S::ERR::SET + S::DONE::SET).value),
+                DLEN => self.dlen = value,
+                A => self.a = value,
+                FIFO => {
+                    self.written[self.num_written] = value as u8;
+                    self.num_written += 1;
+                }
+                DIV => self.div = value,
+                DEL => self.del = value,
+                _ => (),
+            }
+        }
+    }
+
+    fn mock_i2c() -> I2CInner<MockBSC> {
+        I2CInner::new(MockBSC::default(), CORE_CLOCK_HZ)
+    }
+
+    /// Writes, reads and write-reads move the right bytes to the right address.
+    #[kernel_test]
+    fn i2c_transactions() {
+        let mut bus = mock_i2c();
+        assert_eq!(bus.write(DEVICE_ADDR, &[1, 2, 3]), Ok(()));
+        assert_eq!(bus.registers.written[..3], [1, 2, 3]);
+        assert_eq!(bus.registers.a, DEVICE_ADDR.into());
+        assert_eq!(bus.registers.dlen, 3);
+        assert!(!C::I2CEN.is_set(bus.registers.c));
+
+        let mut bus = mock_i2c();
+        bus.registers.response[..2].copy_from_slice(&[0xAB, 0xCD]);
+        let mut buf = [0; 2];
+        assert_eq!(bus.read(DEVICE_ADDR, &mut buf), Ok(()));
+        assert_eq!(buf, [0xAB, 0xCD]);
+
+        let mut bus = mock_i2c();
+        bus.registers.response[..2].copy_from_slice(&[0xAB, 0xCD]);
+        let mut buf = [0; 2];
+        assert_eq!(bus.write_read(DEVICE_ADDR, &[0x10], &mut buf), Ok(()));
+        assert_eq!(bus.registers.written[..1], [0x10]);
+        assert_eq!(buf, [0xAB, 0xCD]);
+        assert_eq!(bus.registers.dlen, 2);
+
+        let mut bus = mock_i2c();
+        assert!(bus
+            .write_read(DEVICE_ADDR, &[0; FIFO_SIZE + 1], &mut buf)
+            .is_err());
+        assert!(bus.write(i2c::MAX_ADDR + 1, &[1]).is_err());
+    }
+
+    /// A missing device and a stalled bus are reported, and leave the controller disabled.
+    #[kernel_test]
+    fn i2c_nack_and_timeout() {
+        let mut bus = mock_i2c();
+        assert_eq!(bus.write(DEVICE_ADDR + 1, &[1]), Err(i2c::NACK_ERROR));
+        assert_eq!(bus.registers.s & S::ERR::SET.value, 0);
+
+        let mut bus = mock_i2c();
+        bus.registers.is_stalled = true;
+        bus.timeout = Duration::from_millis(1);
+        assert_eq!(bus.read(DEVICE_ADDR, &mut [0; 1]), Err(i2c::TIMEOUT_ERROR));
+        assert!(!C::I2CEN.is_set(bus.registers.c));
+    }
+
+    /// The clock divider never clocks the bus faster than requested.
+    #[kernel_test]
+    fn i2c_clock_divider() {
+        let mut bus = mock_i2c();
+
+        assert_eq!(bus.set_clock(i2c::STANDARD_MODE_HZ), Ok(()));
+        assert_eq!(bus.registers.div, 2500);
+
+        assert_eq!(bus.set_clock(333_333), Ok(()));
+        assert_eq!(bus.registers.div, 752);
+
+        assert!(bus.set_clock(0).is_err());
+        assert!(bus.set_clock(1_000).is_err());
+        assert!(bus.set_clock(CORE_CLOCK_HZ).is_ok());
+
+        // The fastest clock the core clock allows.
+        assert_eq!(bus.set_clock(u32::MAX), Ok(()));
+        assert_eq!(bus.registers.div, MIN_DIVIDER);
+    }
+}
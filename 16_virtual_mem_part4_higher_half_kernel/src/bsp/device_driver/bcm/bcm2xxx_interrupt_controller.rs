@@ -4,6 +4,7 @@
 
 //! Interrupt Controller Driver.
 
+mod local_ic;
 mod peripheral_ic;
 
 use crate::{driver, exception, memory};
@@ -35,6 +36,7 @@ pub enum IRQNumber {
 
 /// Representation of the Interrupt Controller.
 pub struct InterruptController {
+    local: local_ic::LocalIC,
     periph: peripheral_ic::PeripheralIC,
 }
 
@@ -70,7 +72,11 @@ impl Iterator for PendingIRQs {
 //--------------------------------------------------------------------------------------------------
 
 impl InterruptController {
+    /// The compatible string.
+    pub const COMPATIBLE: &'static str = "BCM Interrupt Controller";
+
     const MAX_LOCAL_IRQ_NUMBER: usize = 11;
+    const NUM_LOCAL_IRQS: usize = Self::MAX_LOCAL_IRQ_NUMBER + 1;
     const MAX_PERIPHERAL_IRQ_NUMBER: usize = 63;
     const NUM_PERIPHERAL_IRQS: usize = Self::MAX_PERIPHERAL_IRQ_NUMBER + 1;
 
@@ -80,13 +86,26 @@ impl InterruptController {
     ///
     /// - The user must ensure to provide correct MMIO descriptors.
     pub const unsafe fn new(
-        _local_mmio_descriptor: memory::mmu::MMIODescriptor,
+        local_mmio_descriptor: memory::mmu::MMIODescriptor,
         periph_mmio_descriptor: memory::mmu::MMIODescriptor,
     ) -> Self {
         Self {
+            local: local_ic::LocalIC::new(local_mmio_descriptor),
             periph: peripheral_ic::PeripheralIC::new(periph_mmio_descriptor),
         }
     }
+
+    /// Replace the MMIO descriptors that were given to `new()`.
+    ///
+    /// Only has an effect if called before `init()`, which maps the MMIO regions.
+    pub fn set_mmio_descriptors(
+        &self,
+        local_mmio_descriptor: memory::mmu::MMIODescriptor,
+        periph_mmio_descriptor: memory::mmu::MMIODescriptor,
+    ) {
+        self.local.set_mmio_descriptor(local_mmio_descriptor);
+        self.periph.set_mmio_descriptor(periph_mmio_descriptor);
+    }
 }
 
 //------------------------------------------------------------------------------
@@ -95,12 +114,28 @@ impl InterruptController {
 
 impl driver::interface::DeviceDriver for InterruptController {
     fn compatible(&self) -> &'static str {
-        "BCM Interrupt Controller"
+        Self::COMPATIBLE
     }
 
     unsafe fn init(&self) -> Result<(), &'static str> {
+        self.local.init()?;
         self.periph.init()
     }
+
+    unsafe fn shutdown(&self) -> Result<(), &'static str> {
+        self.periph.shutdown()?;
+        self.local.shutdown()
+    }
+
+    unsafe fn suspend(&self) -> Result<(), &'static str> {
+        self.periph.suspend()?;
+        self.local.suspend()
+    }
+
+    unsafe fn resume(&self) -> Result<(), &'static str> {
+        self.local.resume()?;
+        self.periph.resume()
+    }
 }
 
 impl exception::asynchronous::interface::IRQManager for InterruptController {
@@ -112,14 +147,14 @@ impl exception::asynchronous::interface::IRQManager for InterruptController {
         descriptor: exception::asynchronous::IRQDescriptor,
     ) -> Result<(), &'static str> {
         match irq {
-            IRQNumber::Local(_) => unimplemented!("Local IRQ controller not implemented."),
+            IRQNumber::Local(lirq) => self.local.register_handler(lirq, descriptor),
             IRQNumber::Peripheral(pirq) => self.periph.register_handler(pirq, descriptor),
         }
     }
 
     fn enable(&self, irq: Self::IRQNumberType) {
         match irq {
-            IRQNumber::Local(_) => unimplemented!("Local IRQ controller not implemented."),
+            IRQNumber::Local(lirq) => self.local.enable(lirq),
             IRQNumber::Peripheral(pirq) => self.periph.enable(pirq),
         }
     }
@@ -128,11 +163,14 @@ impl exception::asynchronous::interface::IRQManager for InterruptController {
         &'irq_context self,
         ic: &exception::asynchronous::IRQContext<'irq_context>,
     ) {
-        // It can only be a peripheral IRQ pending because enable() does not support local IRQs yet.
+        // Pending peripheral IRQs show up as a single local IRQ. The peripheral controller has its
+        // own pending registers, so it can just always be asked as well.
+        self.local.handle_pending_irqs(ic);
         self.periph.handle_pending_irqs(ic)
     }
 
     fn print_handler(&self) {
+        self.local.print_handler();
         self.periph.print_handler();
     }
 }
@@ -0,0 +1,246 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Local Interrupt Controller Driver.
+//!
+//! The "ARM local" block of the BCM2836 and successors routes interrupts that are private to a
+//! core, for example the ARM generic timers. Only the core timer interrupts of the boot core are
+//! supported.
+//!
+//! # Resources
+//!
+//! - <https://datasheets.raspberrypi.com/bcm2836/bcm2836-peripherals.pdf>
+
+use super::{InterruptController, LocalIRQ, PendingIRQs};
+use crate::{
+    bsp::device_driver::common::MMIODerefWrapper,
+    driver, exception, memory, synchronization,
+    synchronization::{IRQSafeNullLock, InitStateLock},
+};
+use core::sync::atomic::{AtomicU32, Ordering};
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_structs,
+    registers::{ReadOnly, ReadWrite},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RWRegisterBlock {
+        (0x00 => _reserved1),
+        (0x40 => CORE0_TIMER_INTERRUPT_CONTROL: ReadWrite<u32>),
+        (0x44 => @END),
+    }
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RORegisterBlock {
+        (0x00 => _reserved1),
+        (0x60 => CORE0_INTERRUPT_SOURCE: ReadOnly<u32>),
+        (0x64 => @END),
+    }
+}
+
+/// Abstraction for the ReadWrite parts of the associated MMIO registers.
+type ReadWriteRegisters = MMIODerefWrapper<RWRegisterBlock>;
+
+/// Abstraction for the ReadOnly parts of the associated MMIO registers.
+type ReadOnlyRegisters = MMIODerefWrapper<RORegisterBlock>;
+
+type HandlerTable =
+    [Option<exception::asynchronous::IRQDescriptor>; InterruptController::NUM_LOCAL_IRQS];
+
+/// The local IRQs 0..=3 are the core timer IRQs. Their numbers equal their enable bit positions.
+const MAX_TIMER_IRQ_NUMBER: usize = 3;
+
+/// Local IRQ number that signals a pending peripheral IRQ.
+const GPU_IRQ_NUMBER: usize = 8;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Representation of the local interrupt controller.
+pub struct LocalIC {
+    mmio_descriptor: InitStateLock<memory::mmu::MMIODescriptor>,
+
+    /// Access to read-modify-write registers is guarded with a lock.
+    rw_registers: IRQSafeNullLock<ReadWriteRegisters>,
+
+    /// Register read access is unguarded.
+    ro_registers: InitStateLock<ReadOnlyRegisters>,
+
+    /// The timer IRQ enables from before `suspend()`.
+    suspended_timer_irqs: AtomicU32,
+
+    /// Stores registered IRQ handlers. Writable only during kernel init. RO afterwards.
+    handler_table: InitStateLock<HandlerTable>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl LocalIC {
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide correct MMIO descriptors.
+    pub const unsafe fn new(mmio_descriptor: memory::mmu::MMIODescriptor) -> Self {
+        let addr = mmio_descriptor.start_addr().as_usize();
+
+        Self {
+            mmio_descriptor: InitStateLock::new(mmio_descriptor),
+            rw_registers: IRQSafeNullLock::new(ReadWriteRegisters::new(addr)),
+            ro_registers: InitStateLock::new(ReadOnlyRegisters::new(addr)),
+            suspended_timer_irqs: AtomicU32::new(0),
+            handler_table: InitStateLock::new([None; InterruptController::NUM_LOCAL_IRQS]),
+        }
+    }
+
+    /// Replace the MMIO descriptor that was given to `new()`.
+    ///
+    /// Only has an effect if called before `init()`, which maps the MMIO region.
+    pub fn set_mmio_descriptor(&self, mmio_descriptor: memory::mmu::MMIODescriptor) {
+        self.mmio_descriptor.write(|x| *x = mmio_descriptor);
+    }
+
+    /// Query the list of pending IRQs.
+    ///
+    /// Pending peripheral IRQs are left out. They are taken care of by the peripheral controller.
+    fn pending_irqs(&self) -> PendingIRQs {
+        self.ro_registers.read(|regs| {
+            let pending_mask =
+                u64::from(regs.CORE0_INTERRUPT_SOURCE.get()) & !(1 << GPU_IRQ_NUMBER);
+
+            PendingIRQs::new(pending_mask)
+        })
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+use synchronization::interface::{Mutex, ReadWriteEx};
+
+impl driver::interface::DeviceDriver for LocalIC {
+    fn compatible(&self) -> &'static str {
+        "BCM Local Interrupt Controller"
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        let mmio_descriptor = self.mmio_descriptor.read(|x| *x);
+        let virt_addr =
+            memory::mmu::kernel_map_mmio(self.compatible(), &mmio_descriptor)?.as_usize();
+
+        self.rw_registers
+            .lock(|regs| *regs = ReadWriteRegisters::new(virt_addr));
+        self.ro_registers
+            .write(|regs| *regs = ReadOnlyRegisters::new(virt_addr));
+
+        Ok(())
+    }
+
+    unsafe fn shutdown(&self) -> Result<(), &'static str> {
+        self.rw_registers
+            .lock(|regs| regs.CORE0_TIMER_INTERRUPT_CONTROL.set(0));
+
+        Ok(())
+    }
+
+    unsafe fn suspend(&self) -> Result<(), &'static str> {
+        self.rw_registers.lock(|regs| {
+            let timer_irqs = regs.CORE0_TIMER_INTERRUPT_CONTROL.get();
+
+            self.suspended_timer_irqs
+                .store(timer_irqs, Ordering::Relaxed);
+            regs.CORE0_TIMER_INTERRUPT_CONTROL.set(0);
+        });
+
+        Ok(())
+    }
+
+    unsafe fn resume(&self) -> Result<(), &'static str> {
+        let timer_irqs = self.suspended_timer_irqs.load(Ordering::Relaxed);
+
+        self.rw_registers
+            .lock(|regs| regs.CORE0_TIMER_INTERRUPT_CONTROL.set(timer_irqs));
+
+        Ok(())
+    }
+}
+
+impl exception::asynchronous::interface::IRQManager for LocalIC {
+    type IRQNumberType = LocalIRQ;
+
+    fn register_handler(
+        &self,
+        irq: Self::IRQNumberType,
+        descriptor: exception::asynchronous::IRQDescriptor,
+    ) -> Result<(), &'static str> {
+        if irq.get() > MAX_TIMER_IRQ_NUMBER {
+            return Err("Only core timer IRQs are supported by the local IRQ controller");
+        }
+
+        self.handler_table.write(|table| {
+            let irq_number = irq.get();
+
+            if table[irq_number].is_some() {
+                return Err("IRQ handler already registered");
+            }
+
+            table[irq_number] = Some(descriptor);
+
+            Ok(())
+        })
+    }
+
+    fn enable(&self, irq: Self::IRQNumberType) {
+        assert!(irq.get() <= MAX_TIMER_IRQ_NUMBER);
+
+        self.rw_registers.lock(|regs| {
+            let enable_bit: u32 = 1 << irq.get();
+
+            regs.CORE0_TIMER_INTERRUPT_CONTROL
+                .set(regs.CORE0_TIMER_INTERRUPT_CONTROL.get() | enable_bit);
+        });
+    }
+
+    fn handle_pending_irqs<'irq_context>(
+        &'irq_context self,
+        _ic: &exception::asynchronous::IRQContext<'irq_context>,
+    ) {
+        self.handler_table.read(|table| {
+            for irq_number in self.pending_irqs() {
+                match table[irq_number] {
+                    None => panic!("No handler registered for local IRQ {}", irq_number),
+                    Some(descriptor) => {
+                        // Call the IRQ handler. Panics on failure.
+                        descriptor.handler.handle().expect("Error handling IRQ");
+                    }
+                }
+            }
+        })
+    }
+
+    fn print_handler(&self) {
+        use crate::info;
+
+        info!("      Local handler:");
+
+        self.handler_table.read(|table| {
+            for (i, opt) in table.iter().enumerate() {
+                if let Some(handler) = opt {
+                    info!("            {: >3}. {}", i, handler.name);
+                }
+            }
+        });
+    }
+}
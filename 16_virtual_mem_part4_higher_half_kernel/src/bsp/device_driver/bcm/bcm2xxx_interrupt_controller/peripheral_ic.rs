@@ -10,6 +10,7 @@ use crate::{
     driver, exception, memory, synchronization,
     synchronization::{IRQSafeNullLock, InitStateLock},
 };
+use core::sync::atomic::{AtomicU64, Ordering};
 use tock_registers::{
     interfaces::{Readable, Writeable},
     register_structs,
@@ -26,6 +27,9 @@ register_structs! {
         (0x00 => _reserved1),
         (0x10 => ENABLE_1: WriteOnly<u32>),
         (0x14 => ENABLE_2: WriteOnly<u32>),
+        (0x18 => _reserved2),
+        (0x1c => DISABLE_1: WriteOnly<u32>),
+        (0x20 => DISABLE_2: WriteOnly<u32>),
         (0x24 => @END),
     }
 }
@@ -55,7 +59,7 @@ type HandlerTable =
 
 /// Representation of the peripheral interrupt controller.
 pub struct PeripheralIC {
-    mmio_descriptor: memory::mmu::MMIODescriptor,
+    mmio_descriptor: InitStateLock<memory::mmu::MMIODescriptor>,
 
     /// Access to write registers is guarded with a lock.
     wo_registers: IRQSafeNullLock<WriteOnlyRegisters>,
@@ -63,6 +67,9 @@ pub struct PeripheralIC {
     /// Register read access is unguarded.
     ro_registers: InitStateLock<ReadOnlyRegisters>,
 
+    /// One bit per IRQ. The enable registers are write-only, so the enabled IRQs are tracked here.
+    enabled_irqs: AtomicU64,
+
     /// Stores registered IRQ handlers. Writable only during kernel init. RO afterwards.
     handler_table: InitStateLock<HandlerTable>,
 }
@@ -81,13 +88,21 @@ impl PeripheralIC {
         let addr = mmio_descriptor.start_addr().as_usize();
 
         Self {
-            mmio_descriptor,
+            mmio_descriptor: InitStateLock::new(mmio_descriptor),
             wo_registers: IRQSafeNullLock::new(WriteOnlyRegisters::new(addr)),
             ro_registers: InitStateLock::new(ReadOnlyRegisters::new(addr)),
+            enabled_irqs: AtomicU64::new(0),
             handler_table: InitStateLock::new([None; InterruptController::NUM_PERIPHERAL_IRQS]),
         }
     }
 
+    /// Replace the MMIO descriptor that was given to `new()`.
+    ///
+    /// Only has an effect if called before `init()`, which maps the MMIO region.
+    pub fn set_mmio_descriptor(&self, mmio_descriptor: memory::mmu::MMIODescriptor) {
+        self.mmio_descriptor.write(|x| *x = mmio_descriptor);
+    }
+
     /// Query the list of pending IRQs.
     fn pending_irqs(&self) -> PendingIRQs {
         self.ro_registers.read(|regs| {
@@ -97,6 +112,14 @@ impl PeripheralIC {
             PendingIRQs::new(pending_mask)
         })
     }
+
+    /// Disable all IRQs in the hardware. The record of enabled IRQs is kept.
+    fn disable_all(&self) {
+        self.wo_registers.lock(|regs| {
+            regs.DISABLE_1.set(u32::MAX);
+            regs.DISABLE_2.set(u32::MAX);
+        });
+    }
 }
 
 //------------------------------------------------------------------------------
@@ -110,8 +133,9 @@ impl driver::interface::DeviceDriver for PeripheralIC {
     }
 
     unsafe fn init(&self) -> Result<(), &'static str> {
+        let mmio_descriptor = self.mmio_descriptor.read(|x| *x);
         let virt_addr =
-            memory::mmu::kernel_map_mmio(self.compatible(), &self.mmio_descriptor)?.as_usize();
+            memory::mmu::kernel_map_mmio(self.compatible(), &mmio_descriptor)?.as_usize();
 
         self.wo_registers
             .lock(|regs| *regs = WriteOnlyRegisters::new(virt_addr));
@@ -120,6 +144,29 @@ impl driver::interface::DeviceDriver for PeripheralIC {
 
         Ok(())
     }
+
+    unsafe fn shutdown(&self) -> Result<(), &'static str> {
+        self.disable_all();
+
+        Ok(())
+    }
+
+    unsafe fn suspend(&self) -> Result<(), &'static str> {
+        self.disable_all();
+
+        Ok(())
+    }
+
+    unsafe fn resume(&self) -> Result<(), &'static str> {
+        let enabled_irqs = self.enabled_irqs.load(Ordering::Relaxed);
+
+        self.wo_registers.lock(|regs| {
+            regs.ENABLE_1.set(enabled_irqs as u32);
+            regs.ENABLE_2.set((enabled_irqs >> 32) as u32);
+        });
+
+        Ok(())
+    }
 }
 
 impl exception::asynchronous::interface::IRQManager for PeripheralIC {
@@ -157,6 +204,9 @@ impl exception::asynchronous::interface::IRQManager for PeripheralIC {
             // bits are unaffected. So we don't need read and OR'ing here.
             enable_reg.set(enable_bit);
         });
+
+        self.enabled_irqs
+            .fetch_or(1 << irq.get(), Ordering::Relaxed);
     }
 
     fn handle_pending_irqs<'irq_context>(
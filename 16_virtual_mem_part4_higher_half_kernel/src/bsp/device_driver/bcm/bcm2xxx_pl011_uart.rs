@@ -10,12 +10,15 @@
 //! - <https://developer.arm.com/documentation/ddi0183/latest>
 
 use crate::{
-    bsp, bsp::device_driver::common::MMIODerefWrapper, console, cpu, driver, exception, memory,
-    synchronization, synchronization::IRQSafeNullLock,
+    bsp,
+    bsp::device_driver::common::MMIODerefWrapper,
+    console, cpu, driver, exception, executor, memory, synchronization,
+    synchronization::{IRQSafeNullLock, InitStateLock},
 };
 use core::{
     fmt,
     sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll},
 };
 use tock_registers::{
     interfaces::{Readable, Writeable},
@@ -234,10 +237,11 @@ pub use PL011UartInner as PanicUart;
 
 /// Representation of the UART.
 pub struct PL011Uart {
-    mmio_descriptor: memory::mmu::MMIODescriptor,
+    mmio_descriptor: InitStateLock<memory::mmu::MMIODescriptor>,
     virt_mmio_start_addr: AtomicUsize,
     inner: IRQSafeNullLock<PL011UartInner>,
     irq_number: bsp::device_driver::IRQNumber,
+    rx_waker: executor::WakerCell,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -317,9 +321,7 @@ impl PL011UartInner {
         self.registers.IFLS.write(IFLS::RXIFLSEL::OneEigth);
 
         // Enable RX IRQ + RX timeout IRQ.
-        self.registers
-            .IMSC
-            .write(IMSC::RXIM::Enabled + IMSC::RTIM::Enabled);
+        self.enable_rx_irqs();
 
         // Turn the UART on.
         self.registers
@@ -329,6 +331,41 @@ impl PL011UartInner {
         Ok(())
     }
 
+    /// Enable RX IRQ + RX timeout IRQ.
+    fn enable_rx_irqs(&mut self) {
+        self.registers
+            .IMSC
+            .write(IMSC::RXIM::Enabled + IMSC::RTIM::Enabled);
+    }
+
+    /// Disable RX IRQ + RX timeout IRQ.
+    fn disable_rx_irqs(&mut self) {
+        self.registers
+            .IMSC
+            .write(IMSC::RXIM::Disabled + IMSC::RTIM::Disabled);
+    }
+
+    /// Transmit what is still queued, then stop receiving.
+    ///
+    /// The transmitter stays enabled, so that output that follows is not lost.
+    fn shutdown(&mut self) {
+        self.flush();
+
+        self.disable_rx_irqs();
+        self.registers.ICR.write(ICR::ALL::CLEAR);
+        self.registers
+            .CR
+            .write(CR::UARTEN::Enabled + CR::TXE::Enabled);
+    }
+
+    /// Transmit what is still queued and stop interrupting.
+    fn suspend(&mut self) {
+        self.flush();
+
+        self.disable_rx_irqs();
+        self.registers.ICR.write(ICR::ALL::CLEAR);
+    }
+
     /// Send a character.
     fn write_char(&mut self, c: char) {
         // Spin while TX FIFO full is set, waiting for an empty slot.
@@ -400,6 +437,9 @@ impl fmt::Write for PL011UartInner {
 }
 
 impl PL011Uart {
+    /// The compatible string.
+    pub const COMPATIBLE: &'static str = "BCM PL011 UART";
+
     /// Create an instance.
     ///
     /// # Safety
@@ -411,28 +451,37 @@ impl PL011Uart {
         irq_number: bsp::device_driver::IRQNumber,
     ) -> Self {
         Self {
-            mmio_descriptor,
+            mmio_descriptor: InitStateLock::new(mmio_descriptor),
             virt_mmio_start_addr: AtomicUsize::new(0),
             inner: IRQSafeNullLock::new(PL011UartInner::new(
                 mmio_descriptor.start_addr().as_usize(),
             )),
             irq_number,
+            rx_waker: executor::WakerCell::new(),
         }
     }
+
+    /// Replace the MMIO descriptor that was given to `new()`, e.g. with one from the device tree.
+    ///
+    /// Only has an effect if called before `init()`, which maps the MMIO region.
+    pub fn set_mmio_descriptor(&self, mmio_descriptor: memory::mmu::MMIODescriptor) {
+        self.mmio_descriptor.write(|x| *x = mmio_descriptor);
+    }
 }
 
 //------------------------------------------------------------------------------
 // OS Interface Code
 //------------------------------------------------------------------------------
-use synchronization::interface::Mutex;
+use synchronization::interface::{Mutex, ReadWriteEx};
 
 impl driver::interface::DeviceDriver for PL011Uart {
     fn compatible(&self) -> &'static str {
-        "BCM PL011 UART"
+        Self::COMPATIBLE
     }
 
     unsafe fn init(&self) -> Result<(), &'static str> {
-        let virt_addr = memory::mmu::kernel_map_mmio(self.compatible(), &self.mmio_descriptor)?;
+        let mmio_descriptor = self.mmio_descriptor.read(|x| *x);
+        let virt_addr = memory::mmu::kernel_map_mmio(self.compatible(), &mmio_descriptor)?;
 
         self.inner
             .lock(|inner| inner.init(Some(virt_addr.as_usize())))?;
@@ -443,12 +492,32 @@ impl driver::interface::DeviceDriver for PL011Uart {
         Ok(())
     }
 
+    unsafe fn shutdown(&self) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.shutdown());
+
+        Ok(())
+    }
+
+    unsafe fn suspend(&self) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.suspend());
+
+        Ok(())
+    }
+
+    unsafe fn resume(&self) -> Result<(), &'static str> {
+        // Characters that arrived in the meantime are waiting in the RX FIFO, and raise an IRQ
+        // right away.
+        self.inner.lock(|inner| inner.enable_rx_irqs());
+
+        Ok(())
+    }
+
     fn register_and_enable_irq_handler(&'static self) -> Result<(), &'static str> {
         use bsp::exception::asynchronous::irq_manager;
         use exception::asynchronous::{interface::IRQManager, IRQDescriptor};
 
         let descriptor = IRQDescriptor {
-            name: "BCM PL011 UART",
+            name: Self::COMPATIBLE,
             handler: self,
         };
 
@@ -494,6 +563,22 @@ impl console::interface::Read for PL011Uart {
             .lock(|inner| inner.read_char_converting(BlockingMode::Blocking).unwrap())
     }
 
+    fn poll_read_char(&self, cx: &mut Context) -> Poll<char> {
+        // Register before checking the FIFO, so that a character arriving right afterwards will
+        // wake the reader.
+        self.rx_waker.register(cx.waker());
+
+        self.inner.lock(
+            |inner| match inner.read_char_converting(BlockingMode::NonBlocking) {
+                Some(c) => Poll::Ready(c),
+                None => {
+                    inner.enable_rx_irqs();
+                    Poll::Pending
+                }
+            },
+        )
+    }
+
     fn clear_rx(&self) {
         // Read from the RX FIFO until it is indicating empty.
         while self
@@ -516,21 +601,28 @@ impl console::interface::Statistics for PL011Uart {
 
 impl exception::asynchronous::interface::IRQHandler for PL011Uart {
     fn handle(&self) -> Result<(), &'static str> {
-        self.inner.lock(|inner| {
+        let rx_pending = self.inner.lock(|inner| {
             let pending = inner.registers.MIS.extract();
 
             // Clear all pending IRQs.
             inner.registers.ICR.write(ICR::ALL::CLEAR);
 
             // Check for any kind of RX interrupt.
-            if pending.matches_any(MIS::RXMIS::SET + MIS::RTMIS::SET) {
-                // Echo any received characters.
-                while let Some(c) = inner.read_char_converting(BlockingMode::NonBlocking) {
-                    inner.write_char(c)
-                }
+            let rx_pending = pending.matches_any(MIS::RXMIS::SET + MIS::RTMIS::SET);
+            if rx_pending {
+                // Leave the received characters in the RX FIFO for the reader. The RX IRQs would
+                // fire again right away while the FIFO is filled, so mask them until the reader
+                // asks for more.
+                inner.disable_rx_irqs();
             }
+
+            rx_pending
         });
 
+        if rx_pending {
+            self.rx_waker.wake();
+        }
+
         Ok(())
     }
 }
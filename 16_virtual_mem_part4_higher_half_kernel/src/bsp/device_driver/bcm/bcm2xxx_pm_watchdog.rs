@@ -0,0 +1,284 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Power Management Watchdog Driver.
+//!
+//! The watchdog of the power management block counts down in ticks of 1/65536 s. When it expires,
+//! the board is reset as configured in `RSTC`. The boot partition in `RSTS` survives the reset and
+//! tells the firmware what to do: partition 63 makes it halt instead of booting.
+//!
+//! Every register write must carry a password in its upper byte, or it is ignored.
+//!
+//! # Resources
+//!
+//! - Linux, `drivers/watchdog/bcm2835_wdt.c`
+
+use crate::{
+    bsp::device_driver::common::MMIODerefWrapper,
+    cpu, driver, memory, synchronization,
+    synchronization::{IRQSafeNullLock, InitStateLock},
+    watchdog,
+};
+use core::{
+    sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+    time::Duration,
+};
+use tock_registers::{
+    interfaces::{ReadWriteable, Readable, Writeable},
+    register_bitfields, register_structs,
+    registers::ReadWrite,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+register_bitfields! {
+    u32,
+
+    /// Reset Control Register.
+    RSTC [
+        /// Must be written with every write.
+        PASSWD OFFSET(24) NUMBITS(8) [
+            Password = 0x5A
+        ],
+
+        /// What happens when the watchdog expires.
+        WRCFG OFFSET(4) NUMBITS(2) [
+            Nothing = 0b00,
+            FullReset = 0b10
+        ]
+    ],
+
+    /// Watchdog Register.
+    WDOG [
+        /// Must be written with every write.
+        PASSWD OFFSET(24) NUMBITS(8) [
+            Password = 0x5A
+        ],
+
+        /// Ticks until the watchdog expires.
+        TIME OFFSET(0) NUMBITS(20) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => _reserved1),
+        (0x1C => RSTC: ReadWrite<u32, RSTC::Register>),
+        (0x20 => RSTS: ReadWrite<u32>),
+        (0x24 => WDOG: ReadWrite<u32, WDOG::Register>),
+        (0x28 => @END),
+    }
+}
+
+/// Abstraction for the associated MMIO registers.
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+/// The password in the upper byte, for registers without bitfields.
+const PASSWORD: u32 = 0x5A00_0000;
+
+/// Boot partition 63, spread over every other bit of `RSTS`.
+const RSTS_PARTITION_HALT: u32 = 0x555;
+
+const TICKS_PER_S: u128 = 65536;
+const MAX_TICKS: u32 = (1 << 20) - 1;
+
+/// Ticks before a requested reset, about 150 µs.
+const RESET_TICKS: u32 = 10;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Representation of the power management watchdog.
+pub struct PMWatchdog {
+    mmio_descriptor: InitStateLock<memory::mmu::MMIODescriptor>,
+    virt_mmio_start_addr: AtomicUsize,
+    registers: IRQSafeNullLock<Registers>,
+
+    /// The timeout given to `start()`, in ticks.
+    timeout_ticks: AtomicU32,
+
+    /// Whether the watchdog was armed when it was suspended.
+    is_suspended_armed: AtomicBool,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn duration_to_ticks(duration: Duration) -> Option<u32> {
+    u32::try_from(duration.as_micros() * TICKS_PER_S / 1_000_000)
+        .ok()
+        .filter(|x| *x <= MAX_TICKS)
+}
+
+fn ticks_to_duration(ticks: u32) -> Duration {
+    Duration::from_micros((u128::from(ticks) * 1_000_000 / TICKS_PER_S) as u64)
+}
+
+impl PMWatchdog {
+    /// Let the watchdog expire after `ticks` and reset the board then.
+    fn arm(&self, ticks: u32) {
+        self.registers.lock(|regs| {
+            regs.WDOG
+                .write(WDOG::PASSWD::Password + WDOG::TIME.val(ticks));
+            regs.RSTC
+                .modify(RSTC::PASSWD::Password + RSTC::WRCFG::FullReset);
+        });
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl PMWatchdog {
+    /// The compatible string.
+    pub const COMPATIBLE: &'static str = "BCM PM Watchdog";
+
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide correct MMIO descriptors.
+    pub const unsafe fn new(mmio_descriptor: memory::mmu::MMIODescriptor) -> Self {
+        Self {
+            mmio_descriptor: InitStateLock::new(mmio_descriptor),
+            virt_mmio_start_addr: AtomicUsize::new(0),
+            registers: IRQSafeNullLock::new(Registers::new(
+                mmio_descriptor.start_addr().as_usize(),
+            )),
+            timeout_ticks: AtomicU32::new(0),
+            is_suspended_armed: AtomicBool::new(false),
+        }
+    }
+
+    /// Replace the MMIO descriptor that was given to `new()`, e.g. with one from the device tree.
+    ///
+    /// Only has an effect if called before `init()`, which maps the MMIO region.
+    pub fn set_mmio_descriptor(&self, mmio_descriptor: memory::mmu::MMIODescriptor) {
+        self.mmio_descriptor.write(|x| *x = mmio_descriptor);
+    }
+
+    /// Reset the board right away.
+    ///
+    /// # Safety
+    ///
+    /// - Must only be called after `init()`.
+    pub unsafe fn reset(&self) -> ! {
+        self.arm(RESET_TICKS);
+
+        cpu::wait_forever()
+    }
+
+    /// Reset the board and let the firmware halt instead of booting.
+    ///
+    /// # Safety
+    ///
+    /// - Must only be called after `init()`.
+    pub unsafe fn power_off(&self) -> ! {
+        self.registers.lock(|regs| {
+            regs.RSTS
+                .set(regs.RSTS.get() | PASSWORD | RSTS_PARTITION_HALT)
+        });
+
+        self.reset()
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+use synchronization::interface::{Mutex, ReadWriteEx};
+
+impl driver::interface::DeviceDriver for PMWatchdog {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        let mmio_descriptor = self.mmio_descriptor.read(|x| *x);
+        let virt_addr = memory::mmu::kernel_map_mmio(self.compatible(), &mmio_descriptor)?;
+
+        self.registers
+            .lock(|regs| *regs = Registers::new(virt_addr.as_usize()));
+
+        self.virt_mmio_start_addr
+            .store(virt_addr.as_usize(), Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    unsafe fn suspend(&self) -> Result<(), &'static str> {
+        use watchdog::interface::Watchdog;
+
+        // Nobody pets the watchdog while the system idles.
+        let is_armed = self.time_left().is_some();
+        if is_armed {
+            self.stop();
+        }
+        self.is_suspended_armed.store(is_armed, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    unsafe fn resume(&self) -> Result<(), &'static str> {
+        if self.is_suspended_armed.swap(false, Ordering::Relaxed) {
+            self.arm(self.timeout_ticks.load(Ordering::Relaxed));
+        }
+
+        Ok(())
+    }
+
+    fn virt_mmio_start_addr(&self) -> Option<usize> {
+        let addr = self.virt_mmio_start_addr.load(Ordering::Relaxed);
+
+        if addr == 0 {
+            return None;
+        }
+
+        Some(addr)
+    }
+}
+
+impl watchdog::interface::Watchdog for PMWatchdog {
+    fn start(&self, timeout: Duration) -> Result<(), &'static str> {
+        let ticks = match duration_to_ticks(timeout) {
+            None => return Err("Watchdog timeout too long"),
+            Some(0) => return Err("Watchdog timeout too short"),
+            Some(x) => x,
+        };
+
+        self.timeout_ticks.store(ticks, Ordering::Relaxed);
+        self.arm(ticks);
+
+        Ok(())
+    }
+
+    fn pet(&self) {
+        if self.time_left().is_some() {
+            self.arm(self.timeout_ticks.load(Ordering::Relaxed));
+        }
+    }
+
+    fn stop(&self) {
+        self.registers.lock(|regs| {
+            regs.RSTC
+                .modify(RSTC::PASSWD::Password + RSTC::WRCFG::Nothing)
+        });
+    }
+
+    fn time_left(&self) -> Option<Duration> {
+        self.registers.lock(|regs| {
+            if !regs.RSTC.matches_all(RSTC::WRCFG::FullReset) {
+                return None;
+            }
+
+            Some(ticks_to_duration(regs.WDOG.read(WDOG::TIME)))
+        })
+    }
+}
@@ -0,0 +1,186 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Random Number Generator Driver.
+//!
+//! The RNG fills a FIFO with 32 bit words of entropy. Its first output after enabling it is of
+//! poor quality, so a number of words is discarded first, as configured in `STATUS`.
+//!
+//! # Resources
+//!
+//! - Linux, `drivers/char/hw_random/bcm2835-rng.c`
+
+use crate::{
+    bsp::device_driver::common::MMIODerefWrapper,
+    driver, memory, random, synchronization,
+    synchronization::{IRQSafeNullLock, InitStateLock},
+    time,
+};
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use tock_registers::{
+    interfaces::{ReadWriteable, Readable, Writeable},
+    register_bitfields, register_structs,
+    registers::{ReadOnly, ReadWrite},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+register_bitfields! {
+    u32,
+
+    /// Control Register.
+    CTRL [
+        /// Random bit generator enable.
+        RBGEN OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Status Register.
+    STATUS [
+        /// Number of words in the FIFO.
+        WORDS_AVAILABLE OFFSET(24) NUMBITS(8) [],
+
+        /// Number of words to discard after enabling. Only meaningful when written.
+        WARMUP_COUNT OFFSET(0) NUMBITS(20) []
+    ],
+
+    /// Interrupt Mask Register.
+    INT_MASK [
+        /// Masks the IRQ that signals available words.
+        INT_OFF OFFSET(0) NUMBITS(1) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => CTRL: ReadWrite<u32, CTRL::Register>),
+        (0x04 => STATUS: ReadWrite<u32, STATUS::Register>),
+        (0x08 => DATA: ReadOnly<u32>),
+        (0x0C => FF_THRES: ReadWrite<u32>),
+        (0x10 => INT_MASK: ReadWrite<u32, INT_MASK::Register>),
+        (0x14 => @END),
+    }
+}
+
+/// Abstraction for the associated MMIO registers.
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+/// Words discarded after enabling the generator.
+const WARMUP_COUNT: u32 = 0x40000;
+
+/// How long to wait for a word, which includes the warmup on the first read.
+const READ_TIMEOUT: Duration = Duration::from_millis(50);
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Representation of the RNG.
+pub struct RNG {
+    mmio_descriptor: InitStateLock<memory::mmu::MMIODescriptor>,
+    virt_mmio_start_addr: AtomicUsize,
+    registers: IRQSafeNullLock<Registers>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl RNG {
+    /// The compatible string.
+    pub const COMPATIBLE: &'static str = "BCM RNG";
+
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide correct MMIO descriptors.
+    pub const unsafe fn new(mmio_descriptor: memory::mmu::MMIODescriptor) -> Self {
+        Self {
+            mmio_descriptor: InitStateLock::new(mmio_descriptor),
+            virt_mmio_start_addr: AtomicUsize::new(0),
+            registers: IRQSafeNullLock::new(Registers::new(
+                mmio_descriptor.start_addr().as_usize(),
+            )),
+        }
+    }
+
+    /// Replace the MMIO descriptor that was given to `new()`, e.g. with one from the device tree.
+    ///
+    /// Only has an effect if called before `init()`, which maps the MMIO region.
+    pub fn set_mmio_descriptor(&self, mmio_descriptor: memory::mmu::MMIODescriptor) {
+        self.mmio_descriptor.write(|x| *x = mmio_descriptor);
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+use synchronization::interface::{Mutex, ReadWriteEx};
+
+impl driver::interface::DeviceDriver for RNG {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        let mmio_descriptor = self.mmio_descriptor.read(|x| *x);
+        let virt_addr = memory::mmu::kernel_map_mmio(self.compatible(), &mmio_descriptor)?;
+
+        self.registers.lock(|regs| {
+            *regs = Registers::new(virt_addr.as_usize());
+
+            // The FIFO is polled.
+            regs.INT_MASK.modify(INT_MASK::INT_OFF::SET);
+
+            regs.STATUS.write(STATUS::WARMUP_COUNT.val(WARMUP_COUNT));
+            regs.CTRL.modify(CTRL::RBGEN::SET);
+        });
+
+        self.virt_mmio_start_addr
+            .store(virt_addr.as_usize(), Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    unsafe fn shutdown(&self) -> Result<(), &'static str> {
+        self.registers
+            .lock(|regs| regs.CTRL.modify(CTRL::RBGEN::CLEAR));
+
+        Ok(())
+    }
+
+    fn virt_mmio_start_addr(&self) -> Option<usize> {
+        let addr = self.virt_mmio_start_addr.load(Ordering::Relaxed);
+
+        if addr == 0 {
+            return None;
+        }
+
+        Some(addr)
+    }
+}
+
+impl random::interface::EntropySource for RNG {
+    fn read_entropy(&self) -> Option<u32> {
+        use time::interface::TimeManager;
+
+        let deadline = time::time_manager().uptime() + READ_TIMEOUT;
+
+        self.registers.lock(|regs| loop {
+            if regs.STATUS.read(STATUS::WORDS_AVAILABLE) > 0 {
+                return Some(regs.DATA.get());
+            }
+
+            if time::time_manager().uptime() >= deadline {
+                return None;
+            }
+        })
+    }
+}
@@ -0,0 +1,464 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! SPI Driver.
+//!
+//! Driver for SPI0, an SPI master with two chip select lines. While a transfer is active, every
+//! byte written to the FIFO is shifted out, and the byte shifted in at the same time is queued in
+//! the receive FIFO. The chip select line is asserted for the whole transfer.
+//!
+//! # Resources
+//!
+//! - <https://github.com/raspberrypi/documentation/files/1888662/BCM2837-ARM-Peripherals.-.Revised.-.V2-1.pdf>
+//! - <https://datasheets.raspberrypi.org/bcm2711/bcm2711-peripherals.pdf>
+//! - Linux, `drivers/spi/spi-bcm2835.c`
+
+use crate::{
+    bsp::device_driver::common::{MMIORegisters, RegisterAccess},
+    driver, memory, spi, synchronization,
+    synchronization::{IRQSafeNullLock, InitStateLock},
+    time,
+};
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use tock_registers::register_bitfields;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+register_bitfields! {
+    u32,
+
+    /// Control and Status Register.
+    CS [
+        /// The transmit FIFO can accept data.
+        TXD OFFSET(18) NUMBITS(1) [],
+
+        /// The receive FIFO holds data.
+        RXD OFFSET(17) NUMBITS(1) [],
+
+        /// The transfer has finished: the transmit FIFO is empty and the last byte was shifted.
+        DONE OFFSET(16) NUMBITS(1) [],
+
+        /// A transfer is active. The chip select line is asserted.
+        TA OFFSET(7) NUMBITS(1) [],
+
+        /// Clears the FIFOs. Self clearing.
+        CLEAR OFFSET(4) NUMBITS(2) [
+            ClearBoth = 0b11
+        ],
+
+        /// Clock polarity.
+        CPOL OFFSET(3) NUMBITS(1) [],
+
+        /// Clock phase.
+        CPHA OFFSET(2) NUMBITS(1) [],
+
+        /// The chip select line to assert.
+        CS OFFSET(0) NUMBITS(2) []
+    ]
+}
+
+// Register offsets.
+const CS: usize = 0x00;
+const FIFO: usize = 0x04;
+const CLK: usize = 0x08;
+
+/// The receive FIFO holds 64 bytes. Never have more in flight, so that it cannot overflow.
+const FIFO_SIZE: usize = 64;
+
+/// Chip select lines 0 and 1 are routed to pins.
+const NUM_CHIP_SELECTS: usize = 2;
+
+/// The clock divider is even and 16 bits wide.
+const MIN_DIVIDER: u32 = 2;
+const MAX_DIVIDER: u32 = 0xFFFE;
+
+const DEFAULT_CLOCK_HZ: u32 = 1_000_000;
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(100);
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The bus logic, independent of how the registers are accessed.
+pub struct SPIInner<R> {
+    registers: R,
+    core_clock_hz: u32,
+    mode: spi::Mode,
+    timeout: Duration,
+}
+
+/// Representation of the SPI controller.
+pub struct SPI {
+    mmio_descriptor: InitStateLock<memory::mmu::MMIODescriptor>,
+    virt_mmio_start_addr: AtomicUsize,
+    inner: IRQSafeNullLock<SPIInner<MMIORegisters>>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl<R: RegisterAccess> SPIInner<R> {
+    /// The `CS` bits that stay the same for the whole transfer.
+    fn config(&self, chip_select: usize) -> u32 {
+        let mut config = CS::CS.val(chip_select as u32).value;
+
+        if self.mode.cpol() {
+            config |= CS::CPOL::SET.value;
+        }
+        if self.mode.cpha() {
+            config |= CS::CPHA::SET.value;
+        }
+
+        config
+    }
+
+    /// Shift `len` bytes. The bytes of `tx` are sent, followed by zeros. Received bytes are
+    /// stored in `rx`, starting with the one received at `rx_offset`.
+    fn transaction(
+        &mut self,
+        chip_select: usize,
+        tx: &[u8],
+        rx: &mut [u8],
+        rx_offset: usize,
+    ) -> Result<(), &'static str> {
+        use time::interface::TimeManager;
+
+        if chip_select >= NUM_CHIP_SELECTS {
+            return Err("Invalid SPI chip select");
+        }
+
+        let config = self.config(chip_select);
+        let len = tx.len().max(rx_offset + rx.len());
+        let deadline = time::time_manager().uptime() + self.timeout;
+
+        self.registers
+            .write(CS, config | CS::CLEAR::ClearBoth.value);
+        self.registers.write(CS, config | CS::TA::SET.value);
+
+        let (mut num_sent, mut num_received) = (0, 0);
+        let result = loop {
+            let status = self.registers.read(CS);
+
+            if num_received == len && CS::DONE.is_set(status) {
+                break Ok(());
+            }
+
+            if num_sent < len && num_sent - num_received < FIFO_SIZE && CS::TXD.is_set(status) {
+                self.registers
+                    .write(FIFO, tx.get(num_sent).copied().unwrap_or(0).into());
+                num_sent += 1;
+                continue;
+            }
+
+            if num_received < len && CS::RXD.is_set(status) {
+                let byte = self.registers.read(FIFO) as u8;
+                if let Some(x) = num_received
+                    .checked_sub(rx_offset)
+                    .and_then(|i| rx.get_mut(i))
+                {
+                    *x = byte;
+                }
+                num_received += 1;
+                continue;
+            }
+
+            if time::time_manager().uptime() >= deadline {
+                break Err(spi::TIMEOUT_ERROR);
+            }
+        };
+
+        // Deassert the chip select line and discard what is left in the FIFOs.
+        self.registers
+            .write(CS, config | CS::CLEAR::ClearBoth.value);
+
+        result
+    }
+
+    fn set_clock(&mut self, hz: u32) -> Result<(), &'static str> {
+        if hz == 0 {
+            return Err("Invalid SPI clock");
+        }
+
+        // Round up, so that the bus is never clocked faster than requested. Computed in u64, since
+        // the sum overflows for large clocks.
+        let divider = ((u64::from(self.core_clock_hz) + u64::from(hz) - 1) / u64::from(hz)) as u32;
+        let divider = divider + (divider & 1);
+        if !(MIN_DIVIDER..=MAX_DIVIDER).contains(&divider) {
+            return Err("SPI clock out of range");
+        }
+
+        self.registers.write(CLK, divider);
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl<R> SPIInner<R> {
+    /// Create an instance.
+    pub const fn new(registers: R, core_clock_hz: u32) -> Self {
+        Self {
+            registers,
+            core_clock_hz,
+            mode: spi::Mode::Mode0,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl SPI {
+    /// The compatible string.
+    pub const COMPATIBLE: &'static str = "BCM SPI";
+
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide correct MMIO descriptors.
+    /// - The user must ensure to provide the frequency of the core clock, which drives SCLK.
+    pub const unsafe fn new(
+        mmio_descriptor: memory::mmu::MMIODescriptor,
+        core_clock_hz: u32,
+    ) -> Self {
+        Self {
+            mmio_descriptor: InitStateLock::new(mmio_descriptor),
+            virt_mmio_start_addr: AtomicUsize::new(0),
+            inner: IRQSafeNullLock::new(SPIInner::new(
+                MMIORegisters::new(mmio_descriptor.start_addr().as_usize()),
+                core_clock_hz,
+            )),
+        }
+    }
+
+    /// Replace the MMIO descriptor that was given to `new()`, e.g. with one from the device tree.
+    ///
+    /// Only has an effect if called before `init()`, which maps the MMIO region.
+    pub fn set_mmio_descriptor(&self, mmio_descriptor: memory::mmu::MMIODescriptor) {
+        self.mmio_descriptor.write(|x| *x = mmio_descriptor);
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+use synchronization::interface::{Mutex, ReadWriteEx};
+
+impl driver::interface::DeviceDriver for SPI {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        let mmio_descriptor = self.mmio_descriptor.read(|x| *x);
+        let virt_addr = memory::mmu::kernel_map_mmio(self.compatible(), &mmio_descriptor)?;
+
+        self.inner.lock(|inner| {
+            inner.registers = MMIORegisters::new(virt_addr.as_usize());
+
+            inner.registers.write(CS, CS::CLEAR::ClearBoth.value);
+            inner.set_clock(DEFAULT_CLOCK_HZ)
+        })?;
+
+        self.virt_mmio_start_addr
+            .store(virt_addr.as_usize(), Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    fn virt_mmio_start_addr(&self) -> Option<usize> {
+        let addr = self.virt_mmio_start_addr.load(Ordering::Relaxed);
+
+        if addr == 0 {
+            return None;
+        }
+
+        Some(addr)
+    }
+}
+
+impl spi::interface::Bus for SPI {
+    fn set_clock(&self, hz: u32) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.set_clock(hz))
+    }
+
+    fn set_mode(&self, mode: spi::Mode) {
+        self.inner.lock(|inner| inner.mode = mode)
+    }
+
+    fn set_timeout(&self, timeout: Duration) {
+        self.inner.lock(|inner| inner.timeout = timeout)
+    }
+
+    fn write(&self, chip_select: usize, bytes: &[u8]) -> Result<(), &'static str> {
+        self.inner
+            .lock(|inner| inner.transaction(chip_select, bytes, &mut [], 0))
+    }
+
+    fn read(&self, chip_select: usize, buf: &mut [u8]) -> Result<(), &'static str> {
+        self.inner
+            .lock(|inner| inner.transaction(chip_select, &[], buf, 0))
+    }
+
+    fn write_read(
+        &self,
+        chip_select: usize,
+        bytes: &[u8],
+        buf: &mut [u8],
+    ) -> Result<(), &'static str> {
+        self.inner
+            .lock(|inner| inner.transaction(chip_select, bytes, buf, bytes.len()))
+    }
+
+    fn transfer(
+        &self,
+        chip_select: usize,
+        bytes: &[u8],
+        buf: &mut [u8],
+    ) -> Result<(), &'static str> {
+        self.inner
+            .lock(|inner| inner.transaction(chip_select, bytes, buf, 0))
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    const CORE_CLOCK_HZ: u32 = 250_000_000;
+
+    /// A simulated controller with a device that answers with a scripted response. Bytes are
+    /// shifted instantly, unless the bus is stalled.
+    #[derive(Default)]
+    struct MockSPI {
+        cs: u32,
+        clk: u32,
+        is_stalled: bool,
+
+        sent: [u8; 8],
+        num_sent: usize,
+        response: [u8; 8],
+        num_received: usize,
+    }
+
+    impl RegisterAccess for MockSPI {
+        fn read(&mut self, offset: usize) -> u32 {
+            match offset {
+                CS => {
+                    let mut cs = self.cs;
+
+                    if CS::TA.is_set(cs) && !self.is_stalled {
+                        cs |= (CS::TXD::SET + CS::DONE::SET).value;
+
+                        if self.num_received < self.num_sent {
+                            cs |= CS::RXD::SET.value;
+                        }
+                    }
+
+                    cs
+                }
+                FIFO => {
+                    self.num_received += 1;
+                    self.response[self.num_received - 1].into()
+                }
+                CLK => self.clk,
+                _ => 0,
+            }
+        }
+
+        fn write(&mut self, offset: usize, value: u32) {
+            match offset {
+                CS => {
+                    self.cs = value & !CS::CLEAR::ClearBoth.value;
+
+                    if value & CS::CLEAR::ClearBoth.value != 0 {
+                        self.num_received = self.num_sent;
+                    }
+                }
+                FIFO => {
+                    self.sent[self.num_sent] = value as u8;
+                    self.num_sent += 1;
+                }
+                CLK => self.clk = value,
+                _ => (),
+            }
+        }
+    }
+
+    fn mock_spi() -> SPIInner<MockSPI> {
+        SPIInner::new(MockSPI::default(), CORE_CLOCK_HZ)
+    }
+
+    /// Bytes are sent and received in the right order, and the chip select line is released.
+    #[kernel_test]
+    fn spi_transactions() {
+        let mut bus = mock_spi();
+        bus.registers.response[..4].copy_from_slice(&[0xFF, 1, 2, 3]);
+        let mut buf = [0; 3];
+        assert_eq!(bus.transaction(0, &[0x9F], &mut buf, 1), Ok(()));
+        assert_eq!(bus.registers.sent[..4], [0x9F, 0, 0, 0]);
+        assert_eq!(buf, [1, 2, 3]);
+        assert!(!CS::TA.is_set(bus.registers.cs));
+
+        let mut bus = mock_spi();
+        bus.mode = spi::Mode::Mode3;
+        bus.registers.response[..2].copy_from_slice(&[0xAB, 0xCD]);
+        let mut buf = [0; 2];
+        assert_eq!(bus.transaction(1, &[1, 2], &mut buf, 0), Ok(()));
+        assert_eq!(bus.registers.sent[..2], [1, 2]);
+        assert_eq!(buf, [0xAB, 0xCD]);
+        assert_eq!(CS::CS.read(bus.registers.cs), 1);
+        assert!(CS::CPOL.is_set(bus.registers.cs) && CS::CPHA.is_set(bus.registers.cs));
+
+        let mut bus = mock_spi();
+        assert!(bus.transaction(NUM_CHIP_SELECTS, &[1], &mut [], 0).is_err());
+        assert_eq!(bus.registers.num_sent, 0);
+    }
+
+    /// A stalled bus is reported, and the chip select line is released.
+    #[kernel_test]
+    fn spi_timeout() {
+        let mut bus = mock_spi();
+        bus.registers.is_stalled = true;
+        bus.timeout = Duration::from_millis(1);
+
+        assert_eq!(
+            bus.transaction(0, &[1], &mut [], 0),
+            Err(spi::TIMEOUT_ERROR)
+        );
+        assert!(!CS::TA.is_set(bus.registers.cs));
+    }
+
+    /// The clock divider never clocks the bus faster than requested.
+    #[kernel_test]
+    fn spi_clock_divider() {
+        let mut bus = mock_spi();
+
+        assert_eq!(bus.set_clock(1_000_000), Ok(()));
+        assert_eq!(bus.registers.clk, 250);
+
+        assert_eq!(bus.set_clock(3_000_000), Ok(()));
+        assert_eq!(bus.registers.clk, 84);
+
+        assert!(bus.set_clock(0).is_err());
+        assert!(bus.set_clock(1_000).is_err());
+
+        // The fastest clock the core clock allows.
+        assert_eq!(bus.set_clock(u32::MAX), Ok(()));
+        assert_eq!(bus.registers.clk, MIN_DIVIDER);
+    }
+}
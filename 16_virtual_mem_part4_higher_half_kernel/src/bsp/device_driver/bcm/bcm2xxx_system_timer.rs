@@ -0,0 +1,285 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! System Timer Driver.
+//!
+//! A free-running 64 bit counter at 1 MHz, with four 32 bit compare channels that raise an IRQ when
+//! they match the lower half of the counter. Channels 0 and 2 are used by the GPU firmware, so this
+//! driver uses channel 1.
+//!
+//! # Resources
+//!
+//! - <https://github.com/raspberrypi/documentation/files/1888662/BCM2837-ARM-Peripherals.-.Revised.-.V2-1.pdf>
+//! - <https://datasheets.raspberrypi.org/bcm2711/bcm2711-peripherals.pdf>
+
+use crate::{
+    bsp,
+    bsp::device_driver::common::MMIODerefWrapper,
+    driver, exception, memory, synchronization,
+    synchronization::{IRQSafeNullLock, InitStateLock},
+    time, warn,
+};
+use core::{
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_bitfields, register_structs,
+    registers::{ReadOnly, ReadWrite},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+register_bitfields! {
+    u32,
+
+    /// Control/Status Register.
+    CS [
+        /// Channel 1 match. Set when the lower half of the counter matches `C1`. Writing a 1
+        /// clears the bit and the IRQ.
+        M1 OFFSET(1) NUMBITS(1) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => CS: ReadWrite<u32, CS::Register>),
+        (0x04 => CLO: ReadOnly<u32>),
+        (0x08 => CHI: ReadOnly<u32>),
+        (0x0C => C0: ReadWrite<u32>),
+        (0x10 => C1: ReadWrite<u32>),
+        (0x14 => C2: ReadWrite<u32>),
+        (0x18 => C3: ReadWrite<u32>),
+        (0x1C => @END),
+    }
+}
+
+/// Abstraction for the associated MMIO registers.
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+/// The counter's frequency is 1 MHz.
+const RESOLUTION: Duration = Duration::from_micros(1);
+
+/// Compare values closer than this to the counter might be missed while they are programmed.
+const MIN_EVENT_DELTA_US: u32 = 2;
+
+/// Compare values more than this ahead of the counter could not be told apart from missed ones.
+/// Longer deadlines raise an early IRQ, on which the event is set again.
+const MAX_EVENT_DELTA_US: u32 = u32::MAX / 2;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Representation of the system timer.
+pub struct SystemTimer {
+    mmio_descriptor: InitStateLock<memory::mmu::MMIODescriptor>,
+    virt_mmio_start_addr: AtomicUsize,
+
+    /// Access to the compare channel is guarded with a lock.
+    rw_registers: IRQSafeNullLock<Registers>,
+
+    /// Reading the counter is unguarded.
+    ro_registers: InitStateLock<Registers>,
+
+    irq_number: bsp::device_driver::IRQNumber,
+
+    /// Whether the compare channel holds a deadline of the event source.
+    is_event_set: AtomicBool,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl SystemTimer {
+    /// The counter value.
+    fn counter(&self) -> u64 {
+        self.ro_registers.read(|regs| loop {
+            // The halves are read separately. Retry if the lower half overflowed in between.
+            let hi = regs.CHI.get();
+            let lo = regs.CLO.get();
+
+            if regs.CHI.get() == hi {
+                return (u64::from(hi) << 32) | u64::from(lo);
+            }
+        })
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl SystemTimer {
+    /// The compatible string.
+    pub const COMPATIBLE: &'static str = "BCM System Timer";
+
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide correct MMIO descriptors.
+    /// - The user must ensure to provide correct IRQ numbers.
+    pub const unsafe fn new(
+        mmio_descriptor: memory::mmu::MMIODescriptor,
+        irq_number: bsp::device_driver::IRQNumber,
+    ) -> Self {
+        let addr = mmio_descriptor.start_addr().as_usize();
+
+        Self {
+            mmio_descriptor: InitStateLock::new(mmio_descriptor),
+            virt_mmio_start_addr: AtomicUsize::new(0),
+            rw_registers: IRQSafeNullLock::new(Registers::new(addr)),
+            ro_registers: InitStateLock::new(Registers::new(addr)),
+            irq_number,
+            is_event_set: AtomicBool::new(false),
+        }
+    }
+
+    /// Replace the MMIO descriptor that was given to `new()`, e.g. with one from the device tree.
+    ///
+    /// Only has an effect if called before `init()`, which maps the MMIO region.
+    pub fn set_mmio_descriptor(&self, mmio_descriptor: memory::mmu::MMIODescriptor) {
+        self.mmio_descriptor.write(|x| *x = mmio_descriptor);
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+use synchronization::interface::{Mutex, ReadWriteEx};
+
+impl driver::interface::DeviceDriver for SystemTimer {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        let mmio_descriptor = self.mmio_descriptor.read(|x| *x);
+        let virt_addr = memory::mmu::kernel_map_mmio(self.compatible(), &mmio_descriptor)?;
+
+        self.rw_registers.lock(|regs| {
+            *regs = Registers::new(virt_addr.as_usize());
+
+            // Discard a match left behind by the firmware or a previous kernel.
+            regs.CS.write(CS::M1::SET);
+        });
+        self.ro_registers
+            .write(|regs| *regs = Registers::new(virt_addr.as_usize()));
+
+        self.virt_mmio_start_addr
+            .store(virt_addr.as_usize(), Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    unsafe fn shutdown(&self) -> Result<(), &'static str> {
+        time::interface::EventSource::clear_event(self);
+
+        Ok(())
+    }
+
+    fn register_and_enable_irq_handler(&'static self) -> Result<(), &'static str> {
+        use bsp::exception::asynchronous::irq_manager;
+        use exception::asynchronous::{interface::IRQManager, IRQDescriptor};
+
+        let descriptor = IRQDescriptor {
+            name: Self::COMPATIBLE,
+            handler: self,
+        };
+
+        irq_manager().register_handler(self.irq_number, descriptor)?;
+        irq_manager().enable(self.irq_number);
+
+        Ok(())
+    }
+
+    fn virt_mmio_start_addr(&self) -> Option<usize> {
+        let addr = self.virt_mmio_start_addr.load(Ordering::Relaxed);
+
+        if addr == 0 {
+            return None;
+        }
+
+        Some(addr)
+    }
+}
+
+impl time::interface::TimeManager for SystemTimer {
+    fn resolution(&self) -> Duration {
+        RESOLUTION
+    }
+
+    fn uptime(&self) -> Duration {
+        Duration::from_micros(self.counter())
+    }
+
+    fn spin_for(&self, duration: Duration) {
+        // Instantly return on zero.
+        if duration.as_nanos() == 0 {
+            return;
+        }
+
+        let target = match u64::try_from(duration.as_micros())
+            .ok()
+            .and_then(|micros| self.counter().checked_add(micros))
+        {
+            None => {
+                warn!("Spin duration too long, skipping");
+                return;
+            }
+            Some(val) => val,
+        };
+
+        while self.counter() < target {}
+    }
+}
+
+impl time::interface::EventSource for SystemTimer {
+    fn set_event(&self, deadline: Duration) {
+        use time::interface::TimeManager;
+
+        let delta = deadline.saturating_sub(self.uptime()).as_micros();
+        let delta = u32::try_from(delta)
+            .unwrap_or(u32::MAX)
+            .clamp(MIN_EVENT_DELTA_US, MAX_EVENT_DELTA_US);
+
+        self.is_event_set.store(true, Ordering::Relaxed);
+
+        self.rw_registers.lock(|regs| loop {
+            let compare = regs.CLO.get().wrapping_add(delta);
+            regs.C1.set(compare);
+
+            // If the counter passed the compare value already, the match was missed. Try again.
+            if (compare.wrapping_sub(regs.CLO.get()) as i32) > 0 {
+                break;
+            }
+        });
+    }
+
+    fn clear_event(&self) {
+        // The compare channel cannot be disabled. The IRQ handler ignores its matches instead.
+        self.is_event_set.store(false, Ordering::Relaxed);
+
+        self.rw_registers.lock(|regs| regs.CS.write(CS::M1::SET));
+    }
+}
+
+impl exception::asynchronous::interface::IRQHandler for SystemTimer {
+    fn handle(&self) -> Result<(), &'static str> {
+        self.rw_registers.lock(|regs| regs.CS.write(CS::M1::SET));
+
+        // Sleepers are woken and the event is set again, if any are left.
+        if self.is_event_set.swap(false, Ordering::Relaxed) {
+            time::wake_expired_sleepers();
+        }
+
+        Ok(())
+    }
+}
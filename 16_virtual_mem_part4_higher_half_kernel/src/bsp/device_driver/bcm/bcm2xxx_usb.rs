@@ -0,0 +1,933 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! USB Host Controller Driver.
+//!
+//! The BCM2837 has a Synopsys DesignWare USB 2.0 OTG controller (DWC2), which this driver runs in
+//! host mode. Its internal DMA engine moves the data of a transaction between RAM and the USB
+//! FIFOs, so transfers are described by a host channel's registers alone.
+//!
+//! A single host channel executes one packet at a time, and waits for the channel to halt by
+//! polling. That is slow, but simple, and sufficient for enumeration and keyboards. Low and full
+//! speed devices behind a high speed hub are reached with split transactions: a start split hands
+//! the packet to the hub's transaction translator, and complete splits are retried until the
+//! translator has the device's response.
+//!
+//! The controller must have been powered on by the firmware.
+//!
+//! # Resources
+//!
+//! - Linux, `drivers/usb/dwc2/`
+//! - Circle, `lib/usb/dwhcidevice.cpp`
+//! - <https://github.com/raspberrypi/documentation/files/1888662/BCM2837-ARM-Peripherals.-.Revised.-.V2-1.pdf>
+
+use crate::{
+    bsp::device_driver::common::MMIODerefWrapper,
+    driver,
+    memory::{
+        self,
+        dma::{DmaBuffer, DmaDirection},
+    },
+    synchronization,
+    synchronization::{IRQSafeNullLock, InitStateLock},
+    time,
+    usb::{self, Device, Direction, Endpoint, SetupPacket, Speed, TransactionTranslator},
+};
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use tock_registers::{
+    interfaces::{ReadWriteable, Readable, Writeable},
+    register_bitfields, register_structs,
+    registers::{ReadOnly, ReadWrite},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+register_bitfields! {
+    u32,
+
+    /// AHB Configuration Register.
+    GAHBCFG [
+        /// The core fetches and stores transaction data through its DMA engine.
+        DMAEN OFFSET(5) NUMBITS(1) [],
+
+        /// BCM specific: wait for AXI write responses before a transfer is signaled as done.
+        WAIT_AXI_WRITES OFFSET(4) NUMBITS(1) [],
+
+        /// Global interrupt enable.
+        GLBLINTRMSK OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// USB Configuration Register.
+    GUSBCFG [
+        FORCEDEVMODE OFFSET(30) NUMBITS(1) [],
+        FORCEHOSTMODE OFFSET(29) NUMBITS(1) [],
+
+        /// Selects the ULPI PHY instead of the UTMI+ PHY.
+        ULPI_UTMI_SEL OFFSET(4) NUMBITS(1) [],
+
+        /// Selects a 16 bit wide PHY interface.
+        PHYIF OFFSET(3) NUMBITS(1) []
+    ],
+
+    /// Reset Register.
+    GRSTCTL [
+        /// Set when the AHB master is idle.
+        AHBIDLE OFFSET(31) NUMBITS(1) [],
+
+        /// The TX FIFO to flush. 0x10 flushes all of them.
+        TXFNUM OFFSET(6) NUMBITS(5) [],
+
+        TXFFLSH OFFSET(5) NUMBITS(1) [],
+        RXFFLSH OFFSET(4) NUMBITS(1) [],
+
+        /// Core soft reset. Cleared by the core when done.
+        CSFTRST OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// FIFO Size Registers.
+    FIFOSIZ [
+        /// Depth in words.
+        DEPTH OFFSET(16) NUMBITS(16) [],
+
+        /// Start address in words.
+        START OFFSET(0) NUMBITS(16) []
+    ],
+
+    /// Host Configuration Register.
+    HCFG [
+        /// Clock of the full and low speed PHY.
+        FSLSPCLKSEL OFFSET(0) NUMBITS(2) [
+            Clock30_60MHz = 0
+        ]
+    ],
+
+    /// Host Frame Number Register.
+    HFNUM [
+        FRNUM OFFSET(0) NUMBITS(16) []
+    ],
+
+    /// Host Port Control and Status Register.
+    HPRT [
+        /// Speed of the attached device.
+        PRTSPD OFFSET(17) NUMBITS(2) [
+            High = 0,
+            Full = 1,
+            Low = 2
+        ],
+
+        PRTPWR OFFSET(12) NUMBITS(1) [],
+        PRTRST OFFSET(8) NUMBITS(1) [],
+
+        /// Overcurrent change. Writing a 1 clears it.
+        PRTOVRCURRCHNG OFFSET(5) NUMBITS(1) [],
+
+        /// Port enable change. Writing a 1 clears it.
+        PRTENCHNG OFFSET(3) NUMBITS(1) [],
+
+        /// Port enabled. Writing a 1 disables the port.
+        PRTENA OFFSET(2) NUMBITS(1) [],
+
+        /// Connect detected. Writing a 1 clears it.
+        PRTCONNDET OFFSET(1) NUMBITS(1) [],
+
+        PRTCONNSTS OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Host Channel Characteristics Register.
+    HCCHAR [
+        /// Enable the channel. Cleared by the core when the channel halts.
+        CHENA OFFSET(31) NUMBITS(1) [],
+
+        /// Halt the channel.
+        CHDIS OFFSET(30) NUMBITS(1) [],
+
+        /// Execute a periodic transaction in an odd frame.
+        ODDFRM OFFSET(29) NUMBITS(1) [],
+
+        DEVADDR OFFSET(22) NUMBITS(7) [],
+
+        /// Transactions per frame for periodic endpoints.
+        EC OFFSET(20) NUMBITS(2) [],
+
+        EPTYPE OFFSET(18) NUMBITS(2) [
+            Control = 0,
+            Interrupt = 3
+        ],
+
+        LSPDDEV OFFSET(17) NUMBITS(1) [],
+
+        EPDIR OFFSET(15) NUMBITS(1) [
+            Out = 0,
+            In = 1
+        ],
+
+        EPNUM OFFSET(11) NUMBITS(4) [],
+        MPS OFFSET(0) NUMBITS(11) []
+    ],
+
+    /// Host Channel Split Control Register.
+    HCSPLT [
+        SPLTENA OFFSET(31) NUMBITS(1) [],
+
+        /// Set for a complete split, cleared for a start split.
+        COMPSPLT OFFSET(16) NUMBITS(1) [],
+
+        /// The part of the payload that a split transaction carries.
+        XACTPOS OFFSET(14) NUMBITS(2) [
+            All = 3
+        ],
+
+        HUBADDR OFFSET(7) NUMBITS(7) [],
+        PRTADDR OFFSET(0) NUMBITS(7) []
+    ],
+
+    /// Host Channel Interrupt Register. Writing a 1 clears a bit.
+    HCINT [
+        DATATGLERR OFFSET(10) NUMBITS(1) [],
+        FRMOVRUN OFFSET(9) NUMBITS(1) [],
+        BBLERR OFFSET(8) NUMBITS(1) [],
+        XACTERR OFFSET(7) NUMBITS(1) [],
+        NYET OFFSET(6) NUMBITS(1) [],
+        ACK OFFSET(5) NUMBITS(1) [],
+        NAK OFFSET(4) NUMBITS(1) [],
+        STALL OFFSET(3) NUMBITS(1) [],
+        AHBERR OFFSET(2) NUMBITS(1) [],
+        CHHLTD OFFSET(1) NUMBITS(1) [],
+        XFERCOMPL OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Host Channel Transfer Size Register.
+    HCTSIZ [
+        PID OFFSET(29) NUMBITS(2) [
+            Data0 = 0,
+            Data1 = 2,
+            Setup = 3
+        ],
+
+        PKTCNT OFFSET(19) NUMBITS(10) [],
+
+        /// Bytes to transfer. Counts down as data is received.
+        XFERSIZE OFFSET(0) NUMBITS(19) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x000 => _reserved1),
+        (0x008 => GAHBCFG: ReadWrite<u32, GAHBCFG::Register>),
+        (0x00C => GUSBCFG: ReadWrite<u32, GUSBCFG::Register>),
+        (0x010 => GRSTCTL: ReadWrite<u32, GRSTCTL::Register>),
+        (0x014 => _reserved2),
+        (0x024 => GRXFSIZ: ReadWrite<u32>),
+        (0x028 => GNPTXFSIZ: ReadWrite<u32, FIFOSIZ::Register>),
+        (0x02C => _reserved3),
+        (0x040 => GSNPSID: ReadOnly<u32>),
+        (0x044 => _reserved4),
+        (0x100 => HPTXFSIZ: ReadWrite<u32, FIFOSIZ::Register>),
+        (0x104 => _reserved5),
+        (0x400 => HCFG: ReadWrite<u32, HCFG::Register>),
+        (0x404 => _reserved6),
+        (0x408 => HFNUM: ReadOnly<u32, HFNUM::Register>),
+        (0x40C => _reserved7),
+        (0x440 => HPRT: ReadWrite<u32, HPRT::Register>),
+        (0x444 => _reserved8),
+        (0x500 => HCCHAR0: ReadWrite<u32, HCCHAR::Register>),
+        (0x504 => HCSPLT0: ReadWrite<u32, HCSPLT::Register>),
+        (0x508 => HCINT0: ReadWrite<u32, HCINT::Register>),
+        (0x50C => HCINTMSK0: ReadWrite<u32, HCINT::Register>),
+        (0x510 => HCTSIZ0: ReadWrite<u32, HCTSIZ::Register>),
+        (0x514 => HCDMA0: ReadWrite<u32>),
+        (0x518 => _reserved9),
+        (0xE00 => PCGCCTL: ReadWrite<u32>),
+        (0xE04 => @END),
+    }
+}
+
+/// Abstraction for the associated MMIO registers.
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+/// The upper half of `GSNPSID` of all DWC2 cores, "OT".
+const SNPSID_DWC2: u32 = 0x4F54;
+
+/// `HPRT` bits that are cleared by writing a 1. They must be written as 0 when changing other bits.
+const HPRT_WRITE_TO_CLEAR: u32 = HPRT::PRTCONNDET::SET.value
+    | HPRT::PRTENA::SET.value
+    | HPRT::PRTENCHNG::SET.value
+    | HPRT::PRTOVRCURRCHNG::SET.value;
+
+/// FIFO sizes in words, the same that Circle uses.
+const RX_FIFO_SIZE: u32 = 1024;
+const NON_PERIODIC_TX_FIFO_SIZE: u32 = 1024;
+const PERIODIC_TX_FIFO_SIZE: u32 = 1024;
+
+/// All TX FIFOs, for `GRSTCTL::TXFNUM`.
+const ALL_TX_FIFOS: u32 = 0x10;
+
+/// The bus address of RAM's uncached alias, through which the core's DMA engine reaches RAM.
+const RAM_BUS_ALIAS: usize = 0xC000_0000;
+
+/// The size of RAM's bus alias.
+const RAM_BUS_ALIAS_SIZE: usize = 0x4000_0000;
+
+/// The setup packet is at the start of the DMA buffer, the data follows at this offset.
+const DATA_OFFSET: usize = 64;
+
+/// The largest data stage of a transfer.
+const MAX_TRANSFER_SIZE: usize = 1024;
+
+/// The largest packet of any endpoint.
+const MAX_PACKET_SIZE: usize = 1024;
+
+/// IN packets are received in full, so the buffer has room for one beyond the largest transfer.
+const BUFFER_SIZE: usize = DATA_OFFSET + MAX_TRANSFER_SIZE + MAX_PACKET_SIZE;
+
+const REGISTER_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The PHY needs time to settle after a core reset.
+const CORE_RESET_SETTLE_TIME: Duration = Duration::from_millis(100);
+
+/// Switching between host and device mode takes up to 25 ms.
+const FORCE_HOST_MODE_TIME: Duration = Duration::from_millis(25);
+
+/// How long to wait for a device on the root port after it was powered.
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// USB 2.0, section 7.1.7.5.
+const PORT_RESET_TIME: Duration = Duration::from_millis(50);
+
+/// USB 2.0, section 7.1.7.3.
+const PORT_RESET_RECOVERY: Duration = Duration::from_millis(10);
+
+/// A single transaction finishes within a few frames, unless the device is gone.
+const TRANSACTION_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How long a non-periodic transfer is retried while the device answers with NAK.
+const NAK_TIMEOUT: Duration = Duration::from_millis(1000);
+
+const TIMEOUT_ERROR: &str = "USB transaction timed out";
+
+/// The packet ID of a transaction.
+#[derive(Copy, Clone, PartialEq)]
+enum Pid {
+    Setup,
+    Data0,
+    Data1,
+}
+
+/// The way a transaction ended.
+enum Response {
+    /// The data was transferred. Holds the number of bytes.
+    Complete(usize),
+
+    /// A start split was accepted by the transaction translator.
+    Ack,
+
+    /// The device had no data, or could not take it.
+    Nak,
+
+    /// The transaction translator does not have the device's response yet.
+    Nyet,
+}
+
+/// The endpoint a transaction is addressed to.
+struct Pipe {
+    device: Device,
+    number: u8,
+    direction: Direction,
+    is_interrupt: bool,
+    max_packet_size: u16,
+}
+
+struct USBInner {
+    registers: Registers,
+
+    /// Holds the setup packet and the data of the current transfer. `None` until `init()`.
+    buffer: Option<DmaBuffer>,
+
+    /// The bus address of `buffer`.
+    buffer_bus_addr: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Representation of the USB host controller.
+pub struct USB {
+    mmio_descriptor: InitStateLock<memory::mmu::MMIODescriptor>,
+    virt_mmio_start_addr: AtomicUsize,
+    inner: IRQSafeNullLock<USBInner>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Poll `condition` until it holds or `timeout` expires.
+fn wait_until(mut condition: impl FnMut() -> bool, timeout: Duration) -> Result<(), &'static str> {
+    use time::interface::TimeManager;
+
+    let deadline = time::time_manager().uptime() + timeout;
+
+    while !condition() {
+        if time::time_manager().uptime() >= deadline {
+            return Err("Timeout waiting for the USB controller");
+        }
+    }
+
+    Ok(())
+}
+
+fn spin_for(duration: Duration) {
+    use time::interface::TimeManager;
+
+    time::time_manager().spin_for(duration);
+}
+
+impl Pid {
+    fn toggled(self) -> Self {
+        match self {
+            Pid::Data0 => Pid::Data1,
+            Pid::Setup | Pid::Data1 => Pid::Data0,
+        }
+    }
+}
+
+impl USBInner {
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct MMIO start address.
+    const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            buffer: None,
+            buffer_bus_addr: 0,
+        }
+    }
+
+    /// Reset the core and switch it to host mode.
+    fn init(&mut self) -> Result<(), &'static str> {
+        if self.registers.GSNPSID.get() >> 16 != SNPSID_DWC2 {
+            return Err("No DWC2 USB controller found");
+        }
+
+        // Channels are polled.
+        self.registers.GAHBCFG.modify(GAHBCFG::GLBLINTRMSK::CLEAR);
+
+        // The internal UTMI+ PHY, with an 8 bit interface.
+        self.registers
+            .GUSBCFG
+            .modify(GUSBCFG::ULPI_UTMI_SEL::CLEAR + GUSBCFG::PHYIF::CLEAR);
+        self.reset_core()?;
+
+        self.registers
+            .GAHBCFG
+            .modify(GAHBCFG::DMAEN::SET + GAHBCFG::WAIT_AXI_WRITES::SET);
+
+        self.registers
+            .GUSBCFG
+            .modify(GUSBCFG::FORCEDEVMODE::CLEAR + GUSBCFG::FORCEHOSTMODE::SET);
+        spin_for(FORCE_HOST_MODE_TIME);
+
+        // Ungate the clocks.
+        self.registers.PCGCCTL.set(0);
+
+        self.registers.HCFG.modify(HCFG::FSLSPCLKSEL::Clock30_60MHz);
+
+        self.registers.GRXFSIZ.set(RX_FIFO_SIZE);
+        self.registers.GNPTXFSIZ.write(
+            FIFOSIZ::START.val(RX_FIFO_SIZE) + FIFOSIZ::DEPTH.val(NON_PERIODIC_TX_FIFO_SIZE),
+        );
+        self.registers.HPTXFSIZ.write(
+            FIFOSIZ::START.val(RX_FIFO_SIZE + NON_PERIODIC_TX_FIFO_SIZE)
+                + FIFOSIZ::DEPTH.val(PERIODIC_TX_FIFO_SIZE),
+        );
+        self.flush_fifos()?;
+
+        self.registers.HCINTMSK0.set(0);
+        self.write_hprt(HPRT::PRTPWR::SET.value, 0);
+
+        Ok(())
+    }
+
+    fn reset_core(&mut self) -> Result<(), &'static str> {
+        let regs = &self.registers;
+
+        wait_until(|| regs.GRSTCTL.is_set(GRSTCTL::AHBIDLE), REGISTER_TIMEOUT)?;
+        regs.GRSTCTL.write(GRSTCTL::CSFTRST::SET);
+        wait_until(|| !regs.GRSTCTL.is_set(GRSTCTL::CSFTRST), REGISTER_TIMEOUT)?;
+        spin_for(CORE_RESET_SETTLE_TIME);
+
+        Ok(())
+    }
+
+    fn flush_fifos(&mut self) -> Result<(), &'static str> {
+        let regs = &self.registers;
+
+        regs.GRSTCTL
+            .write(GRSTCTL::TXFFLSH::SET + GRSTCTL::TXFNUM.val(ALL_TX_FIFOS));
+        wait_until(|| !regs.GRSTCTL.is_set(GRSTCTL::TXFFLSH), REGISTER_TIMEOUT)?;
+
+        regs.GRSTCTL.write(GRSTCTL::RXFFLSH::SET);
+        wait_until(|| !regs.GRSTCTL.is_set(GRSTCTL::RXFFLSH), REGISTER_TIMEOUT)
+    }
+
+    /// Set and clear bits of `HPRT` without clearing its write-to-clear bits by accident.
+    fn write_hprt(&mut self, set: u32, clear: u32) {
+        let value = self.registers.HPRT.get() & !HPRT_WRITE_TO_CLEAR;
+
+        self.registers.HPRT.set((value | set) & !clear);
+    }
+
+    fn reset_root_port(&mut self) -> Result<Speed, &'static str> {
+        if self.buffer.is_none() {
+            return Err("USB controller not initialized");
+        }
+
+        let regs = &self.registers;
+        if wait_until(|| regs.HPRT.is_set(HPRT::PRTCONNSTS), CONNECT_TIMEOUT).is_err() {
+            return Err("No device on the USB root port");
+        }
+
+        self.write_hprt(HPRT::PRTRST::SET.value, 0);
+        spin_for(PORT_RESET_TIME);
+        self.write_hprt(0, HPRT::PRTRST::SET.value);
+        spin_for(PORT_RESET_RECOVERY);
+
+        // Acknowledge the changes that the connect and the reset caused.
+        let changes = self.registers.HPRT.get()
+            & (HPRT::PRTCONNDET::SET.value
+                | HPRT::PRTENCHNG::SET.value
+                | HPRT::PRTOVRCURRCHNG::SET.value);
+        self.write_hprt(changes, 0);
+
+        if !self.registers.HPRT.is_set(HPRT::PRTENA) {
+            return Err("USB root port was not enabled by the reset");
+        }
+
+        match self.registers.HPRT.read_as_enum(HPRT::PRTSPD) {
+            Some(HPRT::PRTSPD::Value::High) => Ok(Speed::High),
+            Some(HPRT::PRTSPD::Value::Full) => Ok(Speed::Full),
+            Some(HPRT::PRTSPD::Value::Low) => Ok(Speed::Low),
+            None => Err("Unknown USB root port speed"),
+        }
+    }
+
+    /// Execute a single transaction of up to one packet on channel 0, and wait for it to end.
+    fn transaction(
+        &mut self,
+        pipe: &Pipe,
+        pid: Pid,
+        bus_addr: usize,
+        len: usize,
+        split: Option<(TransactionTranslator, bool)>,
+    ) -> Result<Response, &'static str> {
+        let regs = &self.registers;
+
+        regs.HCINT0.set(u32::MAX);
+
+        regs.HCSPLT0.write(match split {
+            None => HCSPLT::SPLTENA::CLEAR,
+            Some((tt, is_complete_split)) => {
+                HCSPLT::SPLTENA::SET
+                    + HCSPLT::COMPSPLT.val(is_complete_split as u32)
+                    + HCSPLT::XACTPOS::All
+                    + HCSPLT::HUBADDR.val(tt.hub_address as u32)
+                    + HCSPLT::PRTADDR.val(tt.port as u32)
+            }
+        });
+
+        regs.HCTSIZ0.write(
+            HCTSIZ::XFERSIZE.val(len as u32)
+                + HCTSIZ::PKTCNT.val(1)
+                + match pid {
+                    Pid::Setup => HCTSIZ::PID::Setup,
+                    Pid::Data0 => HCTSIZ::PID::Data0,
+                    Pid::Data1 => HCTSIZ::PID::Data1,
+                },
+        );
+        regs.HCDMA0.set(bus_addr as u32);
+
+        // Periodic transactions are scheduled for the next frame.
+        let is_next_frame_odd = regs.HFNUM.read(HFNUM::FRNUM) & 1 == 0;
+
+        regs.HCCHAR0.write(
+            HCCHAR::MPS.val(pipe.max_packet_size as u32)
+                + HCCHAR::EPNUM.val(pipe.number as u32)
+                + match pipe.direction {
+                    Direction::Out => HCCHAR::EPDIR::Out,
+                    Direction::In => HCCHAR::EPDIR::In,
+                }
+                + HCCHAR::LSPDDEV.val((pipe.device.speed == Speed::Low) as u32)
+                + match pipe.is_interrupt {
+                    false => HCCHAR::EPTYPE::Control,
+                    true => HCCHAR::EPTYPE::Interrupt,
+                }
+                + HCCHAR::EC.val(1)
+                + HCCHAR::DEVADDR.val(pipe.device.address as u32)
+                + HCCHAR::ODDFRM.val((pipe.is_interrupt && is_next_frame_odd) as u32)
+                + HCCHAR::CHENA::SET,
+        );
+
+        if wait_until(|| regs.HCINT0.is_set(HCINT::CHHLTD), TRANSACTION_TIMEOUT).is_err() {
+            regs.HCCHAR0.modify(HCCHAR::CHDIS::SET + HCCHAR::CHENA::SET);
+            wait_until(|| regs.HCINT0.is_set(HCINT::CHHLTD), REGISTER_TIMEOUT)?;
+
+            return Err(TIMEOUT_ERROR);
+        }
+
+        let hcint = regs.HCINT0.get();
+
+        if HCINT::AHBERR.is_set(hcint) {
+            return Err("USB DMA error");
+        }
+
+        if HCINT::STALL.is_set(hcint) {
+            return Err(usb::STALL_ERROR);
+        }
+
+        if HCINT::XACTERR.is_set(hcint)
+            || HCINT::BBLERR.is_set(hcint)
+            || HCINT::FRMOVRUN.is_set(hcint)
+            || HCINT::DATATGLERR.is_set(hcint)
+        {
+            return Err("USB transaction error");
+        }
+
+        if HCINT::XFERCOMPL.is_set(hcint) {
+            let len = match pipe.direction {
+                Direction::Out => len,
+                Direction::In => len - regs.HCTSIZ0.read(HCTSIZ::XFERSIZE) as usize,
+            };
+
+            return Ok(Response::Complete(len));
+        }
+
+        if HCINT::NAK.is_set(hcint) {
+            return Ok(Response::Nak);
+        }
+
+        if HCINT::NYET.is_set(hcint) {
+            return Ok(Response::Nyet);
+        }
+
+        if HCINT::ACK.is_set(hcint) {
+            return Ok(Response::Ack);
+        }
+
+        Err("USB channel halted without a reason")
+    }
+
+    /// Transfer a single packet. Returns `None` if the device answered with NAK.
+    fn packet(
+        &mut self,
+        pipe: &Pipe,
+        pid: Pid,
+        bus_addr: usize,
+        len: usize,
+    ) -> Result<Option<usize>, &'static str> {
+        use time::interface::TimeManager;
+
+        let tt = match pipe.device.transaction_translator {
+            None => {
+                return match self.transaction(pipe, pid, bus_addr, len, None)? {
+                    Response::Complete(x) => Ok(Some(x)),
+                    Response::Nak => Ok(None),
+                    Response::Ack | Response::Nyet => Err("Unexpected USB handshake"),
+                }
+            }
+            Some(x) => x,
+        };
+
+        match self.transaction(pipe, pid, bus_addr, len, Some((tt, false)))? {
+            Response::Ack => (),
+            Response::Complete(x) => return Ok(Some(x)),
+            Response::Nak => return Ok(None),
+            Response::Nyet => return Err("Unexpected USB handshake"),
+        }
+
+        let deadline = time::time_manager().uptime() + TRANSACTION_TIMEOUT;
+        loop {
+            match self.transaction(pipe, pid, bus_addr, len, Some((tt, true)))? {
+                Response::Complete(x) => return Ok(Some(x)),
+                Response::Nak => return Ok(None),
+                // An OUT transaction is done once the translator acknowledged the complete split.
+                Response::Ack if pipe.direction == Direction::Out => return Ok(Some(len)),
+                Response::Ack | Response::Nyet => (),
+            }
+
+            if time::time_manager().uptime() >= deadline {
+                return Err(TIMEOUT_ERROR);
+            }
+        }
+    }
+}
+
+impl USB {
+    /// Transfer `len` bytes at `bus_addr`, packet by packet.
+    ///
+    /// Returns early on a short packet. `pid` is updated for the next transfer. Interrupt
+    /// transfers return [`usb::NAK_ERROR`] on NAK, others are retried. The lock is only taken for
+    /// single packets, so that IRQs are not masked while a device keeps answering with NAK.
+    fn transfer(
+        &self,
+        pipe: &Pipe,
+        pid: &mut Pid,
+        bus_addr: usize,
+        len: usize,
+    ) -> Result<usize, &'static str> {
+        use time::interface::TimeManager;
+
+        let max_packet_size = pipe.max_packet_size as usize;
+        let deadline = time::time_manager().uptime() + NAK_TIMEOUT;
+        let mut done = 0;
+
+        loop {
+            let packet_len = (len - done).min(max_packet_size);
+
+            // A device that sends more than was asked for must not make the core flag babble.
+            let channel_len = match pipe.direction {
+                Direction::Out => packet_len,
+                Direction::In => max_packet_size,
+            };
+
+            let response = self
+                .inner
+                .lock(|inner| inner.packet(pipe, *pid, bus_addr + done, channel_len))?;
+
+            match response {
+                None if pipe.is_interrupt => return Err(usb::NAK_ERROR),
+                None if time::time_manager().uptime() >= deadline => return Err(TIMEOUT_ERROR),
+                None => (),
+                Some(x) => {
+                    let x = x.min(packet_len);
+                    *pid = pid.toggled();
+                    done += x;
+
+                    if x < packet_len || done == len {
+                        return Ok(done);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Run the setup, data and status stages of a control transfer on the DMA buffer at
+    /// `bus_addr`.
+    fn control_stages(
+        &self,
+        device: &Device,
+        setup: &SetupPacket,
+        bus_addr: usize,
+        len: usize,
+    ) -> Result<usize, &'static str> {
+        let mut pipe = Pipe {
+            device: *device,
+            number: 0,
+            direction: Direction::Out,
+            is_interrupt: false,
+            max_packet_size: device.max_packet_size_0,
+        };
+
+        self.transfer(&pipe, &mut Pid::Setup, bus_addr, SetupPacket::SIZE)?;
+
+        let mut len = len;
+        if len > 0 {
+            pipe.direction = setup.direction();
+            len = self.transfer(&pipe, &mut Pid::Data1, bus_addr + DATA_OFFSET, len)?;
+        }
+
+        // The status stage goes the other way, or IN if there was no data stage.
+        pipe.direction = match (setup.length, setup.direction()) {
+            (1.., Direction::In) => Direction::Out,
+            _ => Direction::In,
+        };
+        self.transfer(&pipe, &mut Pid::Data1, bus_addr + DATA_OFFSET, 0)?;
+
+        Ok(len)
+    }
+
+    /// Hand the DMA buffer to the core for `f`, with `data` copied in before and out afterwards.
+    ///
+    /// `f` is given the bus address of the buffer. The buffer is taken out of the inner state
+    /// for the duration, so that `f` can take the lock packet by packet.
+    fn with_buffer(
+        &self,
+        setup: Option<&SetupPacket>,
+        direction: Direction,
+        data: &mut [u8],
+        f: impl FnOnce(usize) -> Result<usize, &'static str>,
+    ) -> Result<usize, &'static str> {
+        if data.len() > MAX_TRANSFER_SIZE {
+            return Err("USB transfer too large");
+        }
+
+        let (buffer, bus_addr) = self
+            .inner
+            .lock(|inner| (inner.buffer.take(), inner.buffer_bus_addr));
+        let mut buffer = match buffer {
+            None => return Err("USB controller not initialized or busy"),
+            Some(x) => x,
+        };
+
+        if let Some(setup) = setup {
+            buffer.as_mut_slice()[..SetupPacket::SIZE].copy_from_slice(&setup.to_bytes());
+        }
+        if direction == Direction::Out {
+            buffer.as_mut_slice()[DATA_OFFSET..][..data.len()].copy_from_slice(data);
+        }
+
+        let buffer = buffer.give_to_device(DmaDirection::Bidirectional);
+        let result = f(bus_addr);
+        let buffer = buffer.take_from_device();
+
+        if let (Ok(len), Direction::In) = (result, direction) {
+            data[..len].copy_from_slice(&buffer.as_slice()[DATA_OFFSET..][..len]);
+        }
+
+        self.inner.lock(|inner| inner.buffer = Some(buffer));
+
+        result
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl USB {
+    /// The compatible string.
+    pub const COMPATIBLE: &'static str = "BCM USB";
+
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide correct MMIO descriptors.
+    pub const unsafe fn new(mmio_descriptor: memory::mmu::MMIODescriptor) -> Self {
+        Self {
+            mmio_descriptor: InitStateLock::new(mmio_descriptor),
+            virt_mmio_start_addr: AtomicUsize::new(0),
+            inner: IRQSafeNullLock::new(USBInner::new(mmio_descriptor.start_addr().as_usize())),
+        }
+    }
+
+    /// Replace the MMIO descriptor that was given to `new()`, e.g. with one from the device tree.
+    ///
+    /// Only has an effect if called before `init()`, which maps the MMIO region.
+    pub fn set_mmio_descriptor(&self, mmio_descriptor: memory::mmu::MMIODescriptor) {
+        self.mmio_descriptor.write(|x| *x = mmio_descriptor);
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+use synchronization::interface::{Mutex, ReadWriteEx};
+
+impl driver::interface::DeviceDriver for USB {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        let mmio_descriptor = self.mmio_descriptor.read(|x| *x);
+        let virt_addr = memory::mmu::kernel_map_mmio(self.compatible(), &mmio_descriptor)?;
+        let buffer = DmaBuffer::new(BUFFER_SIZE, 4)?;
+
+        let phys_addr = buffer.phys_start_addr().as_usize();
+        if phys_addr >= RAM_BUS_ALIAS_SIZE {
+            return Err("Memory is out of reach of the USB controller");
+        }
+
+        self.inner.lock(|inner| {
+            inner.registers = Registers::new(virt_addr.as_usize());
+            inner.init()?;
+            inner.buffer = Some(buffer);
+            inner.buffer_bus_addr = RAM_BUS_ALIAS + phys_addr;
+
+            Ok::<(), &'static str>(())
+        })?;
+
+        self.virt_mmio_start_addr
+            .store(virt_addr.as_usize(), Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    unsafe fn shutdown(&self) -> Result<(), &'static str> {
+        // Unpower the root port.
+        self.inner
+            .lock(|inner| inner.write_hprt(0, HPRT::PRTPWR::SET.value));
+
+        Ok(())
+    }
+
+    fn virt_mmio_start_addr(&self) -> Option<usize> {
+        let addr = self.virt_mmio_start_addr.load(Ordering::Relaxed);
+
+        if addr == 0 {
+            return None;
+        }
+
+        Some(addr)
+    }
+}
+
+impl usb::interface::HostController for USB {
+    fn reset_root_port(&self) -> Result<Speed, &'static str> {
+        self.inner.lock(|inner| inner.reset_root_port())
+    }
+
+    fn control_transfer(
+        &self,
+        device: &Device,
+        setup: &SetupPacket,
+        data: &mut [u8],
+    ) -> Result<usize, &'static str> {
+        let len = setup.length as usize;
+        let data = match data.get_mut(..len) {
+            None => return Err("Buffer too small for the USB control transfer"),
+            Some(x) => x,
+        };
+
+        self.with_buffer(Some(setup), setup.direction(), data, |bus_addr| {
+            self.control_stages(device, setup, bus_addr, len)
+        })
+    }
+
+    fn interrupt_transfer(
+        &self,
+        endpoint: &mut Endpoint,
+        data: &mut [u8],
+    ) -> Result<usize, &'static str> {
+        let pipe = Pipe {
+            device: endpoint.device,
+            number: endpoint.number,
+            direction: endpoint.direction,
+            is_interrupt: true,
+            max_packet_size: endpoint.max_packet_size,
+        };
+        let len = data.len().min(endpoint.max_packet_size as usize);
+
+        let mut pid = match endpoint.data_toggle {
+            false => Pid::Data0,
+            true => Pid::Data1,
+        };
+
+        let result = self.with_buffer(None, endpoint.direction, &mut data[..len], |bus_addr| {
+            self.transfer(&pipe, &mut pid, bus_addr + DATA_OFFSET, len)
+        });
+
+        endpoint.data_toggle = pid == Pid::Data1;
+
+        result
+    }
+}
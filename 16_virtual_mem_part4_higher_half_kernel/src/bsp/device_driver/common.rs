@@ -15,6 +15,23 @@ pub struct MMIODerefWrapper<T> {
     phantom: PhantomData<fn() -> T>,
 }
 
+/// Access to 32 bit registers by their offset.
+///
+/// Drivers that are written against this instead of a register block can be unit tested with a
+/// simulated device.
+pub trait RegisterAccess {
+    /// Read the register at `offset`.
+    fn read(&mut self, offset: usize) -> u32;
+
+    /// Write the register at `offset`.
+    fn write(&mut self, offset: usize, value: u32);
+}
+
+/// Registers that are accessed through MMIO.
+pub struct MMIORegisters {
+    start_addr: usize,
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
@@ -29,6 +46,23 @@ impl<T> MMIODerefWrapper<T> {
     }
 }
 
+impl MMIORegisters {
+    /// Create an instance.
+    pub const unsafe fn new(start_addr: usize) -> Self {
+        Self { start_addr }
+    }
+}
+
+impl RegisterAccess for MMIORegisters {
+    fn read(&mut self, offset: usize) -> u32 {
+        unsafe { core::ptr::read_volatile((self.start_addr + offset) as *const u32) }
+    }
+
+    fn write(&mut self, offset: usize, value: u32) {
+        unsafe { core::ptr::write_volatile((self.start_addr + offset) as *mut u32, value) }
+    }
+}
+
 impl<T> ops::Deref for MMIODerefWrapper<T> {
     type Target = T;
 
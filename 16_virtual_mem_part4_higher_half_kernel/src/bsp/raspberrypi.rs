@@ -6,6 +6,7 @@
 
 pub mod console;
 pub mod cpu;
+pub mod device_tree;
 pub mod driver;
 pub mod exception;
 pub mod memory;
@@ -14,12 +15,28 @@ use super::device_driver;
 use crate::memory::mmu::MMIODescriptor;
 use memory::map::mmio;
 
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The frequency of the core clock, which drives the I2C and SPI controllers. This is the
+/// firmware's default for `core_freq`.
+#[cfg(feature = "bsp_rpi3")]
+const CORE_CLOCK_HZ: u32 = 400_000_000;
+
+#[cfg(feature = "bsp_rpi4")]
+const CORE_CLOCK_HZ: u32 = 500_000_000;
+
 //--------------------------------------------------------------------------------------------------
 // Global instances
 //--------------------------------------------------------------------------------------------------
 
-static GPIO: device_driver::GPIO =
-    unsafe { device_driver::GPIO::new(MMIODescriptor::new(mmio::GPIO_START, mmio::GPIO_SIZE)) };
+static GPIO: device_driver::GPIO = unsafe {
+    device_driver::GPIO::new(
+        MMIODescriptor::new(mmio::GPIO_START, mmio::GPIO_SIZE),
+        exception::asynchronous::irq_map::GPIO,
+    )
+};
 
 static PL011_UART: device_driver::PL011Uart = unsafe {
     device_driver::PL011Uart::new(
@@ -28,6 +45,49 @@ static PL011_UART: device_driver::PL011Uart = unsafe {
     )
 };
 
+static SYSTEM_TIMER: device_driver::SystemTimer = unsafe {
+    device_driver::SystemTimer::new(
+        MMIODescriptor::new(mmio::SYSTEM_TIMER_START, mmio::SYSTEM_TIMER_SIZE),
+        exception::asynchronous::irq_map::SYSTEM_TIMER,
+    )
+};
+
+static I2C: device_driver::I2C = unsafe {
+    device_driver::I2C::new(
+        MMIODescriptor::new(mmio::I2C_START, mmio::I2C_SIZE),
+        CORE_CLOCK_HZ,
+    )
+};
+
+static SPI: device_driver::SPI = unsafe {
+    device_driver::SPI::new(
+        MMIODescriptor::new(mmio::SPI_START, mmio::SPI_SIZE),
+        CORE_CLOCK_HZ,
+    )
+};
+
+static DMA: device_driver::DMAController = unsafe {
+    device_driver::DMAController::new(
+        MMIODescriptor::new(mmio::DMA_START, mmio::DMA_SIZE),
+        exception::asynchronous::irq_map::DMA,
+    )
+};
+
+static PM_WATCHDOG: device_driver::PMWatchdog = unsafe {
+    device_driver::PMWatchdog::new(MMIODescriptor::new(
+        mmio::PM_WATCHDOG_START,
+        mmio::PM_WATCHDOG_SIZE,
+    ))
+};
+
+#[cfg(feature = "bsp_rpi3")]
+static RNG: device_driver::RNG =
+    unsafe { device_driver::RNG::new(MMIODescriptor::new(mmio::RNG_START, mmio::RNG_SIZE)) };
+
+#[cfg(feature = "bsp_rpi3")]
+static USB: device_driver::USB =
+    unsafe { device_driver::USB::new(MMIODescriptor::new(mmio::USB_START, mmio::USB_SIZE)) };
+
 #[cfg(feature = "bsp_rpi3")]
 static INTERRUPT_CONTROLLER: device_driver::InterruptController = unsafe {
     device_driver::InterruptController::new(
@@ -12,3 +12,33 @@
 #[no_mangle]
 #[link_section = ".text._start_arguments"]
 pub static BOOT_CORE_ID: u64 = 0;
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Reset the board with the watchdog.
+///
+/// Halts the core if the watchdog is not initialized.
+pub fn reset() -> ! {
+    use crate::driver::interface::DeviceDriver;
+
+    if super::PM_WATCHDOG.virt_mmio_start_addr().is_none() {
+        crate::cpu::wait_forever()
+    }
+
+    unsafe { super::PM_WATCHDOG.reset() }
+}
+
+/// Reset the board with the watchdog and let the firmware halt instead of booting.
+///
+/// Halts the core if the watchdog is not initialized.
+pub fn power_off() -> ! {
+    use crate::driver::interface::DeviceDriver;
+
+    if super::PM_WATCHDOG.virt_mmio_start_addr().is_none() {
+        crate::cpu::wait_forever()
+    }
+
+    unsafe { super::PM_WATCHDOG.power_off() }
+}
@@ -0,0 +1,225 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! BSP device tree.
+//!
+//! The firmware hands over a device tree blob (DTB) that describes the board it booted on. If there
+//! is one, the MMIO regions of the drivers and the size of DRAM are taken from it. The constants in
+//! [`super::memory::map`] are used otherwise, e.g. when QEMU is not given a DTB.
+//!
+//! The blob is copied into the kernel before the direct map is set up, so that the memory the
+//! firmware put it in needs no reservation.
+
+use crate::{
+    cpu,
+    fdt::{self, Fdt},
+    memory::{
+        mmu::{self, MMIODescriptor},
+        Address, Physical,
+    },
+    synchronization::{interface::ReadWriteEx, InitStateLock},
+};
+use core::cell::UnsafeCell;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(feature = "bsp_rpi3")]
+const GPIO_COMPATIBLE: &str = "brcm,bcm2835-gpio";
+
+#[cfg(feature = "bsp_rpi4")]
+const GPIO_COMPATIBLE: &str = "brcm,bcm2711-gpio";
+
+const PL011_UART_COMPATIBLE: &str = "arm,pl011";
+
+const SYSTEM_TIMER_COMPATIBLE: &str = "brcm,bcm2835-system-timer";
+
+/// SPI0 is the first node. The I2C controllers cannot be told apart by their compatible string, so
+/// the I2C driver keeps its built-in descriptor.
+const SPI_COMPATIBLE: &str = "brcm,bcm2835-spi";
+
+const DMA_COMPATIBLE: &str = "brcm,bcm2835-dma";
+
+const PM_WATCHDOG_COMPATIBLE: &str = "brcm,bcm2835-pm-wdt";
+
+#[cfg(feature = "bsp_rpi3")]
+const RNG_COMPATIBLE: &str = "brcm,bcm2835-rng";
+
+#[cfg(feature = "bsp_rpi3")]
+const USB_COMPATIBLE: &str = "brcm,bcm2835-usb";
+
+#[cfg(feature = "bsp_rpi3")]
+const LOCAL_IC_COMPATIBLE: &str = "brcm,bcm2836-l1-intc";
+
+#[cfg(feature = "bsp_rpi3")]
+const PERIPHERAL_IC_COMPATIBLE: &str = "brcm,bcm2836-armctrl-ic";
+
+#[cfg(feature = "bsp_rpi4")]
+const GIC_COMPATIBLE: &str = "arm,gic-400";
+
+/// Larger blobs are rejected.
+const MAX_BLOB_SIZE: usize = 128 * 1024;
+
+/// The kernel's copy of the blob.
+#[repr(align(8))]
+struct BlobBuffer(UnsafeCell<[u8; MAX_BLOB_SIZE]>);
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static BLOB: BlobBuffer = BlobBuffer(UnsafeCell::new([0; MAX_BLOB_SIZE]));
+
+static DEVICE_TREE: InitStateLock<Option<Fdt<'static>>> = InitStateLock::new(None);
+
+/// The start address and size of the first DRAM region. Looked up once, because the direct map
+/// asks for it on every translation.
+static DRAM_RANGE: InitStateLock<Option<(usize, usize)>> = InitStateLock::new(None);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// The blob is only written in `init()`, before it is shared.
+unsafe impl Sync for BlobBuffer {}
+
+/// Copy the blob that the firmware handed over into the kernel.
+///
+/// # Safety
+///
+/// - Must be called before the direct map is in place.
+/// - Must only be called once.
+unsafe fn copy_blob(phys_addr: Address<Physical>) -> Result<&'static [u8], &'static str> {
+    let buf = &mut *BLOB.0.get();
+
+    // The header tells how much to copy.
+    mmu::kernel_copy_from_phys_early(phys_addr, &mut buf[..fdt::HEADER_SIZE])?;
+    let total_size = Fdt::total_size_from_header(&buf[..fdt::HEADER_SIZE])?;
+
+    let blob = buf
+        .get_mut(..total_size)
+        .ok_or("Device tree is larger than the kernel's copy")?;
+    mmu::kernel_copy_from_phys_early(phys_addr, blob)?;
+
+    Ok(blob)
+}
+
+/// The start address and size of the first DRAM region that the device tree describes.
+fn fdt_dram_range(fdt: &Fdt<'_>) -> Option<(usize, usize)> {
+    let (start, size) = fdt.memory_nodes().next()?.reg()?.next()?;
+
+    Some((usize::try_from(start).ok()?, usize::try_from(size).ok()?))
+}
+
+/// The `index`th `reg` entry of the first `/soc` child that is compatible with `compatible`,
+/// translated from the bus address to the physical address.
+fn soc_mmio_descriptor(fdt: &Fdt<'_>, compatible: &str, index: usize) -> Option<MMIODescriptor> {
+    let soc = fdt.find_node("/soc")?;
+    let node = soc.children().find(|x| x.is_compatible(compatible))?;
+
+    let (bus_addr, size) = node.reg()?.nth(index)?;
+    let phys_addr = soc.translate_child_address(bus_addr)?;
+
+    Some(MMIODescriptor::new(
+        Address::new(usize::try_from(phys_addr).ok()?),
+        usize::try_from(size).ok()?,
+    ))
+}
+
+/// Replace the drivers' built-in MMIO descriptors with the ones from the device tree. Drivers whose
+/// nodes are missing keep the built-in ones.
+fn set_mmio_descriptors(fdt: &Fdt<'_>) {
+    if let Some(x) = soc_mmio_descriptor(fdt, GPIO_COMPATIBLE, 0) {
+        super::GPIO.set_mmio_descriptor(x);
+    }
+
+    if let Some(x) = soc_mmio_descriptor(fdt, PL011_UART_COMPATIBLE, 0) {
+        super::PL011_UART.set_mmio_descriptor(x);
+    }
+
+    if let Some(x) = soc_mmio_descriptor(fdt, SYSTEM_TIMER_COMPATIBLE, 0) {
+        super::SYSTEM_TIMER.set_mmio_descriptor(x);
+    }
+
+    if let Some(x) = soc_mmio_descriptor(fdt, SPI_COMPATIBLE, 0) {
+        super::SPI.set_mmio_descriptor(x);
+    }
+
+    if let Some(x) = soc_mmio_descriptor(fdt, DMA_COMPATIBLE, 0) {
+        super::DMA.set_mmio_descriptor(x);
+    }
+
+    if let Some(x) = soc_mmio_descriptor(fdt, PM_WATCHDOG_COMPATIBLE, 0) {
+        super::PM_WATCHDOG.set_mmio_descriptor(x);
+    }
+
+    #[cfg(feature = "bsp_rpi3")]
+    if let Some(x) = soc_mmio_descriptor(fdt, RNG_COMPATIBLE, 0) {
+        super::RNG.set_mmio_descriptor(x);
+    }
+
+    #[cfg(feature = "bsp_rpi3")]
+    if let Some(x) = soc_mmio_descriptor(fdt, USB_COMPATIBLE, 0) {
+        super::USB.set_mmio_descriptor(x);
+    }
+
+    #[cfg(feature = "bsp_rpi3")]
+    if let (Some(local), Some(periph)) = (
+        soc_mmio_descriptor(fdt, LOCAL_IC_COMPATIBLE, 0),
+        soc_mmio_descriptor(fdt, PERIPHERAL_IC_COMPATIBLE, 0),
+    ) {
+        super::INTERRUPT_CONTROLLER.set_mmio_descriptors(local, periph);
+    }
+
+    #[cfg(feature = "bsp_rpi4")]
+    if let (Some(gicd), Some(gicc)) = (
+        soc_mmio_descriptor(fdt, GIC_COMPATIBLE, 0),
+        soc_mmio_descriptor(fdt, GIC_COMPATIBLE, 1),
+    ) {
+        super::INTERRUPT_CONTROLLER.set_mmio_descriptors(gicd, gicc);
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Parse the device tree that the firmware handed over, if any, and configure the drivers with it.
+///
+/// # Safety
+///
+/// - Must be called before the direct map is in place, which is bounded by the DRAM the device tree
+///   describes.
+/// - Must be called before the drivers are initialized.
+/// - Must only be called once.
+pub unsafe fn init() -> Result<(), &'static str> {
+    let phys_addr = match cpu::boot::phys_dtb_addr() {
+        None => return Ok(()),
+        Some(x) => x,
+    };
+
+    let fdt = Fdt::new(copy_blob(phys_addr)?)?;
+
+    DEVICE_TREE.write(|x| *x = Some(fdt));
+    DRAM_RANGE.write(|x| *x = fdt_dram_range(&fdt));
+    set_mmio_descriptors(&fdt);
+
+    Ok(())
+}
+
+/// The device tree, if the firmware handed over a valid one.
+pub fn device_tree() -> Option<Fdt<'static>> {
+    DEVICE_TREE.read(|x| *x)
+}
+
+/// The start address and size of the first DRAM region that the device tree describes.
+pub fn dram_range() -> Option<(usize, usize)> {
+    DRAM_RANGE.read(|x| *x)
+}
+
+/// The kernel command line that the firmware put into the device tree.
+pub fn bootargs() -> Option<&'static str> {
+    device_tree()?.chosen()?.property_str("bootargs")
+}
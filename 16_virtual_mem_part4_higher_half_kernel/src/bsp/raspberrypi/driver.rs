@@ -4,58 +4,172 @@
 
 //! BSP driver support.
 
-use crate::driver;
+use super::device_driver;
+use crate::{driver, driver::DeviceDriverDescriptor, i2c, log, spi, time, watchdog};
+use core::future::Future;
+
+pub use device_driver::{DMAChain, DMAChannel, DMAPeripheral, DMATransfer, GPIOEdge};
 
 //--------------------------------------------------------------------------------------------------
 // Private Definitions
 //--------------------------------------------------------------------------------------------------
 
-/// Device Driver Manager type.
-struct BSPDriverManager {
-    device_drivers: [&'static (dyn DeviceDriver + Sync); 3],
-}
+#[cfg(feature = "bsp_rpi3")]
+const INTERRUPT_CONTROLLER_COMPATIBLE: &str = device_driver::InterruptController::COMPATIBLE;
+
+#[cfg(feature = "bsp_rpi4")]
+const INTERRUPT_CONTROLLER_COMPATIBLE: &str = device_driver::GICv2::COMPATIBLE;
 
 //--------------------------------------------------------------------------------------------------
-// Global instances
+// Private Code
 //--------------------------------------------------------------------------------------------------
 
-static BSP_DRIVER_MANAGER: BSPDriverManager = BSPDriverManager {
-    device_drivers: [
-        &super::GPIO,
-        &super::PL011_UART,
-        &super::INTERRUPT_CONTROLLER,
-    ],
-};
+/// Configure the UART's pins, which the UART depends on.
+unsafe fn post_init_gpio() -> Result<(), &'static str> {
+    super::GPIO.map_pl011_uart();
+
+    Ok(())
+}
+
+/// Printing is available from here on. Replay what was logged so far.
+unsafe fn post_init_uart() -> Result<(), &'static str> {
+    log::console_ready();
+
+    Ok(())
+}
+
+/// Route the I2C controller to its pins.
+unsafe fn post_init_i2c() -> Result<(), &'static str> {
+    super::GPIO.map_i2c1();
+
+    Ok(())
+}
+
+/// Route the SPI controller to its pins.
+unsafe fn post_init_spi() -> Result<(), &'static str> {
+    super::GPIO.map_spi0();
+
+    Ok(())
+}
+
+/// Offer the system timer as an alternative to the architectural timer.
+unsafe fn post_init_system_timer() -> Result<(), &'static str> {
+    time::register_board_timer(&super::SYSTEM_TIMER, &super::SYSTEM_TIMER);
+
+    Ok(())
+}
+
+/// Feed the RNG's output into the entropy pool.
+#[cfg(feature = "bsp_rpi3")]
+unsafe fn post_init_rng() -> Result<(), &'static str> {
+    crate::random::register_hw_entropy_source(&super::RNG);
+
+    Ok(())
+}
+
+/// Offer the USB controller for enumeration.
+#[cfg(feature = "bsp_rpi3")]
+unsafe fn post_init_usb() -> Result<(), &'static str> {
+    crate::usb::register_host_controller(&super::USB);
+
+    Ok(())
+}
 
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
-/// Return a reference to the driver manager.
-pub fn driver_manager() -> &'static impl driver::interface::DriverManager {
-    &BSP_DRIVER_MANAGER
+/// Register the BSP's drivers with the kernel's driver manager.
+///
+/// The interrupt controller is registered first, because most drivers depend on it. The UART, I2C
+/// and SPI drivers depend on the GPIO, which muxes their pins. The USB controller is optional, so
+/// that a board without it still boots cleanly.
+pub fn init() -> Result<(), &'static str> {
+    let driver_manager = driver::driver_manager();
+
+    driver_manager.register_driver(DeviceDriverDescriptor::new(
+        &super::INTERRUPT_CONTROLLER,
+        &[],
+        None,
+    ))?;
+
+    driver_manager.register_driver(DeviceDriverDescriptor::new(
+        &super::GPIO,
+        &[INTERRUPT_CONTROLLER_COMPATIBLE],
+        Some(post_init_gpio),
+    ))?;
+
+    driver_manager.register_driver(DeviceDriverDescriptor::new(
+        &super::PL011_UART,
+        &[
+            device_driver::GPIO::COMPATIBLE,
+            INTERRUPT_CONTROLLER_COMPATIBLE,
+        ],
+        Some(post_init_uart),
+    ))?;
+
+    driver_manager.register_driver(DeviceDriverDescriptor::new(
+        &super::I2C,
+        &[device_driver::GPIO::COMPATIBLE],
+        Some(post_init_i2c),
+    ))?;
+
+    driver_manager.register_driver(DeviceDriverDescriptor::new(
+        &super::SPI,
+        &[device_driver::GPIO::COMPATIBLE],
+        Some(post_init_spi),
+    ))?;
+
+    driver_manager.register_driver(DeviceDriverDescriptor::new(
+        &super::SYSTEM_TIMER,
+        &[INTERRUPT_CONTROLLER_COMPATIBLE],
+        Some(post_init_system_timer),
+    ))?;
+
+    driver_manager.register_driver(DeviceDriverDescriptor::new(
+        &super::DMA,
+        &[INTERRUPT_CONTROLLER_COMPATIBLE],
+        None,
+    ))?;
+
+    driver_manager.register_driver(DeviceDriverDescriptor::new(&super::PM_WATCHDOG, &[], None))?;
+
+    #[cfg(feature = "bsp_rpi3")]
+    driver_manager.register_driver(DeviceDriverDescriptor::new(
+        &super::RNG,
+        &[],
+        Some(post_init_rng),
+    ))?;
+
+    #[cfg(feature = "bsp_rpi3")]
+    driver_manager.register_driver(
+        DeviceDriverDescriptor::new(&super::USB, &[], Some(post_init_usb)).optional(),
+    )?;
+
+    Ok(())
 }
 
-//------------------------------------------------------------------------------
-// OS Interface Code
-//------------------------------------------------------------------------------
-use driver::interface::DeviceDriver;
+/// Return a reference to the I2C bus.
+pub fn i2c() -> &'static impl i2c::interface::Bus {
+    &super::I2C
+}
 
-impl driver::interface::DriverManager for BSPDriverManager {
-    fn all_device_drivers(&self) -> &[&'static (dyn DeviceDriver + Sync)] {
-        &self.device_drivers[..]
-    }
+/// Return a reference to the SPI bus.
+pub fn spi() -> &'static impl spi::interface::Bus {
+    &super::SPI
+}
 
-    fn early_print_device_drivers(&self) -> &[&'static (dyn DeviceDriver + Sync)] {
-        &self.device_drivers[0..=1]
-    }
+/// Return a reference to the DMA controller.
+pub fn dma() -> &'static device_driver::DMAController {
+    &super::DMA
+}
 
-    fn non_early_print_device_drivers(&self) -> &[&'static (dyn DeviceDriver + Sync)] {
-        &self.device_drivers[2..]
-    }
+/// Return a reference to the watchdog.
+pub fn watchdog() -> &'static impl watchdog::interface::Watchdog {
+    &super::PM_WATCHDOG
+}
 
-    fn post_early_print_device_driver_init(&self) {
-        // Configure PL011Uart's output pins.
-        super::GPIO.map_pl011_uart();
-    }
+/// Asynchronously wait for an edge on a GPIO input pin.
+pub fn gpio_wait_for_edge(pin: usize, edge: GPIOEdge) -> impl Future<Output = ()> {
+    super::GPIO.wait_for_edge(pin, edge)
 }
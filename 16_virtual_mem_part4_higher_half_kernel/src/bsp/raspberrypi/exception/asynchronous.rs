@@ -11,17 +11,53 @@ use crate::{bsp, exception};
 //--------------------------------------------------------------------------------------------------
 
 #[cfg(feature = "bsp_rpi3")]
-pub(in crate::bsp) mod irq_map {
-    use super::bsp::device_driver::{IRQNumber, PeripheralIRQ};
+pub(crate) mod irq_map {
+    use super::bsp::device_driver::{IRQNumber, LocalIRQ, PeripheralIRQ};
 
+    pub const ARCH_TIMER: IRQNumber = IRQNumber::Local(LocalIRQ::new(1));
+    pub const GPIO: IRQNumber = IRQNumber::Peripheral(PeripheralIRQ::new(49));
     pub const PL011_UART: IRQNumber = IRQNumber::Peripheral(PeripheralIRQ::new(57));
+    pub const SYSTEM_TIMER: IRQNumber = IRQNumber::Peripheral(PeripheralIRQ::new(1));
+
+    /// DMA channels 0 to 10.
+    pub const DMA: [IRQNumber; 11] = [
+        IRQNumber::Peripheral(PeripheralIRQ::new(16)),
+        IRQNumber::Peripheral(PeripheralIRQ::new(17)),
+        IRQNumber::Peripheral(PeripheralIRQ::new(18)),
+        IRQNumber::Peripheral(PeripheralIRQ::new(19)),
+        IRQNumber::Peripheral(PeripheralIRQ::new(20)),
+        IRQNumber::Peripheral(PeripheralIRQ::new(21)),
+        IRQNumber::Peripheral(PeripheralIRQ::new(22)),
+        IRQNumber::Peripheral(PeripheralIRQ::new(23)),
+        IRQNumber::Peripheral(PeripheralIRQ::new(24)),
+        IRQNumber::Peripheral(PeripheralIRQ::new(25)),
+        IRQNumber::Peripheral(PeripheralIRQ::new(26)),
+    ];
 }
 
 #[cfg(feature = "bsp_rpi4")]
-pub(in crate::bsp) mod irq_map {
+pub(crate) mod irq_map {
     use super::bsp::device_driver::IRQNumber;
 
+    pub const ARCH_TIMER: IRQNumber = IRQNumber::new(30);
+    pub const GPIO: IRQNumber = IRQNumber::new(145);
     pub const PL011_UART: IRQNumber = IRQNumber::new(153);
+    pub const SYSTEM_TIMER: IRQNumber = IRQNumber::new(97);
+
+    /// DMA channels 0 to 10.
+    pub const DMA: [IRQNumber; 11] = [
+        IRQNumber::new(112),
+        IRQNumber::new(113),
+        IRQNumber::new(114),
+        IRQNumber::new(115),
+        IRQNumber::new(116),
+        IRQNumber::new(117),
+        IRQNumber::new(118),
+        IRQNumber::new(119),
+        IRQNumber::new(120),
+        IRQNumber::new(121),
+        IRQNumber::new(122),
+    ];
 }
 
 //--------------------------------------------------------------------------------------------------
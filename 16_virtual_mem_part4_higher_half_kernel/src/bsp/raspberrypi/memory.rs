@@ -7,14 +7,18 @@
 //! The physical memory layout.
 //!
 //! The Raspberry's firmware copies the kernel binary to 0x8_0000. The preceding region will be used
-//! as the boot core's stack.
+//! as the exception stack and the boot core's stack.
 //! 
 //! 物理memory layout
 //! Raspberry Piのfirmwareはkernel bynaryを0x8_0000に読み込む．
 //! PCはaddressの大きい方に進んでいき，boot core stackはaddressの小さい方に伸びて行く
 //!
 //! +---------------------------------------+
-//! |                                       | boot_core_stack_start @ 0x0
+//! |                                       | exception_stack_start @ 0x0
+//! | Exception Stack                       |
+//! |                                       |
+//! +---------------------------------------+
+//! |                                       | boot_core_stack_start @ 0x1_0000
 //! |                                       |                                ^
 //! | Boot-core Stack                       |                                | stack
 //! |                                       |                                | growth
@@ -22,13 +26,19 @@
 //! +---------------------------------------+
 //! |                                       | code_start @ 0x8_0000 == boot_core_stack_end_exclusive
 //! | .text                                 |
+//! |                                       |
+//! +---------------------------------------+
+//! |                                       | rodata_start == code_end_exclusive
 //! | .rodata                               |
 //! | .got                                  |
 //! |                                       |
 //! +---------------------------------------+
-//! |                                       | data_start == code_end_exclusive
+//! |                                       | data_start == rodata_end_exclusive
 //! | .data                                 |
 //! | .bss                                  |
+//! | .table_frames                         |
+//! | .dma_frames                           |
+//! | .dma_coherent_frames                  |
 //! |                                       |
 //! +---------------------------------------+
 //! |                                       | data_end_exclusive
@@ -46,13 +56,19 @@
 //! +---------------------------------------+
 //! |                                       | code_start @ __kernel_virt_start_addr
 //! | .text                                 |
+//! |                                       |
+//! +---------------------------------------+
+//! |                                       | rodata_start == code_end_exclusive
 //! | .rodata                               |
 //! | .got                                  |
 //! |                                       |
 //! +---------------------------------------+
-//! |                                       | data_start == code_end_exclusive
+//! |                                       | data_start == rodata_end_exclusive
 //! | .data                                 |
 //! | .bss                                  |
+//! | .table_frames                         |
+//! | .dma_frames                           |
+//! | .dma_coherent_frames                  |
 //! |                                       |
 //! +---------------------------------------+
 //! |                                       |  mmio_remap_start == data_end_exclusive
@@ -61,6 +77,14 @@
 //! +---------------------------------------+
 //! |                                       |  mmio_remap_end_exclusive
 //! | Unmapped guard page                   |
+//! |                                       |
+//! +---------------------------------------+
+//! |                                       | exception_stack_start
+//! | Exception Stack                       |
+//! |                                       |
+//! +---------------------------------------+
+//! |                                       | exception_stack_end_exclusive
+//! | Unmapped guard page                   |
 //! |                                       |  stack overflowを検出するための領域
 //! +---------------------------------------+
 //! |                                       | boot_core_stack_start
@@ -71,6 +95,13 @@
 //! +---------------------------------------+
 //! |                                       | boot_core_stack_end_exclusive
 //! |                                       |
+//! +---------------------------------------+
+//! |                                       | direct_map_start
+//! | Direct map of DRAM                    |
+//! |                                       |
+//! +---------------------------------------+
+//! |                                       |
+//! |                                       |
 pub mod mmu;
 
 use crate::memory::{mmu::PageAddress, Address, Physical, Virtual};
@@ -85,14 +116,31 @@ extern "Rust" {
     static __code_start: UnsafeCell<()>;
     static __code_end_exclusive: UnsafeCell<()>;
 
+    static __rodata_start: UnsafeCell<()>;
+    static __rodata_end_exclusive: UnsafeCell<()>;
+
     static __data_start: UnsafeCell<()>;
     static __data_end_exclusive: UnsafeCell<()>;
 
+    static __table_frames_start: UnsafeCell<()>;
+    static __table_frames_end_exclusive: UnsafeCell<()>;
+
+    static __dma_frames_start: UnsafeCell<()>;
+    static __dma_frames_end_exclusive: UnsafeCell<()>;
+
+    static __dma_coherent_frames_start: UnsafeCell<()>;
+    static __dma_coherent_frames_end_exclusive: UnsafeCell<()>;
+
     static __mmio_remap_start: UnsafeCell<()>;
     static __mmio_remap_end_exclusive: UnsafeCell<()>;
 
+    static __exception_stack_start: UnsafeCell<()>;
+    static __exception_stack_end_exclusive: UnsafeCell<()>;
+
     static __boot_core_stack_start: UnsafeCell<()>;
     static __boot_core_stack_end_exclusive: UnsafeCell<()>;
+
+    static __direct_map_start: UnsafeCell<()>;
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -109,15 +157,38 @@ pub(super) mod map {
     pub mod mmio {
         use super::*;
 
+        pub const START:               Address<Physical> = Address::new(0x3F00_0000);
+
+        pub const SYSTEM_TIMER_START:  Address<Physical> = Address::new(0x3F00_3000);
+        pub const SYSTEM_TIMER_SIZE:   usize             =              0x1C;
+
+        pub const DMA_START:           Address<Physical> = Address::new(0x3F00_7000);
+        pub const DMA_SIZE:            usize             =              0xFF4;
+
         pub const PERIPHERAL_IC_START: Address<Physical> = Address::new(0x3F00_B200);
         pub const PERIPHERAL_IC_SIZE:  usize             =              0x24;
 
+        pub const PM_WATCHDOG_START:   Address<Physical> = Address::new(0x3F10_0000);
+        pub const PM_WATCHDOG_SIZE:    usize             =              0x28;
+
+        pub const RNG_START:           Address<Physical> = Address::new(0x3F10_4000);
+        pub const RNG_SIZE:            usize             =              0x14;
+
         pub const GPIO_START:          Address<Physical> = Address::new(0x3F20_0000);
         pub const GPIO_SIZE:           usize             =              0xA0;
 
         pub const PL011_UART_START:    Address<Physical> = Address::new(0x3F20_1000);
         pub const PL011_UART_SIZE:     usize             =              0x48;
 
+        pub const SPI_START:           Address<Physical> = Address::new(0x3F20_4000);
+        pub const SPI_SIZE:            usize             =              0x18;
+
+        pub const I2C_START:           Address<Physical> = Address::new(0x3F80_4000);
+        pub const I2C_SIZE:            usize             =              0x20;
+
+        pub const USB_START:           Address<Physical> = Address::new(0x3F98_0000);
+        pub const USB_SIZE:            usize             =              0xE04;
+
         pub const LOCAL_IC_START:      Address<Physical> = Address::new(0x4000_0000);
         pub const LOCAL_IC_SIZE:       usize             =              0x100;
 
@@ -129,22 +200,44 @@ pub(super) mod map {
     pub mod mmio {
         use super::*;
 
-        pub const GPIO_START:       Address<Physical> = Address::new(0xFE20_0000);
-        pub const GPIO_SIZE:        usize             =              0xA0;
+        pub const START:              Address<Physical> = Address::new(0xFC00_0000);
+
+        pub const SYSTEM_TIMER_START: Address<Physical> = Address::new(0xFE00_3000);
+        pub const SYSTEM_TIMER_SIZE:  usize             =              0x1C;
+
+        pub const DMA_START:          Address<Physical> = Address::new(0xFE00_7000);
+        pub const DMA_SIZE:           usize             =              0xFF4;
+
+        pub const PM_WATCHDOG_START:  Address<Physical> = Address::new(0xFE10_0000);
+        pub const PM_WATCHDOG_SIZE:   usize             =              0x28;
 
-        pub const PL011_UART_START: Address<Physical> = Address::new(0xFE20_1000);
-        pub const PL011_UART_SIZE:  usize             =              0x48;
+        pub const GPIO_START:         Address<Physical> = Address::new(0xFE20_0000);
+        pub const GPIO_SIZE:          usize             =              0xA0;
 
-        pub const GICD_START:       Address<Physical> = Address::new(0xFF84_1000);
-        pub const GICD_SIZE:        usize             =              0x824;
+        pub const PL011_UART_START:   Address<Physical> = Address::new(0xFE20_1000);
+        pub const PL011_UART_SIZE:    usize             =              0x48;
 
-        pub const GICC_START:       Address<Physical> = Address::new(0xFF84_2000);
-        pub const GICC_SIZE:        usize             =              0x14;
+        pub const SPI_START:          Address<Physical> = Address::new(0xFE20_4000);
+        pub const SPI_SIZE:           usize             =              0x18;
 
-        pub const END:              Address<Physical> = Address::new(0xFF85_0000);
+        pub const I2C_START:          Address<Physical> = Address::new(0xFE80_4000);
+        pub const I2C_SIZE:           usize             =              0x20;
+
+        pub const GICD_START:         Address<Physical> = Address::new(0xFF84_1000);
+        pub const GICD_SIZE:          usize             =              0x824;
+
+        pub const GICC_START:         Address<Physical> = Address::new(0xFF84_2000);
+        pub const GICC_SIZE:          usize             =              0x14;
+
+        pub const END:                Address<Physical> = Address::new(0xFF85_0000);
     }
 
     pub const END: Address<Physical> = mmio::END;
+
+    /// Physical DRAM starts at the bottom and reaches up to the peripherals at most. How much of it
+    /// is actually there depends on the board.
+    pub const DRAM_START: Address<Physical> = Address::new(0);
+    pub const DRAM_END:   Address<Physical> = mmio::START;
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -171,6 +264,18 @@ fn code_size() -> usize {
     unsafe { (__code_end_exclusive.get() as usize) - (__code_start.get() as usize) }
 }
 
+/// Start page address of the read-only data segment.
+#[inline(always)]
+fn virt_rodata_start() -> PageAddress<Virtual> {
+    PageAddress::from(unsafe { __rodata_start.get() as usize })
+}
+
+/// Size of the read-only data segment.
+#[inline(always)]
+fn rodata_size() -> usize {
+    unsafe { (__rodata_end_exclusive.get() as usize) - (__rodata_start.get() as usize) }
+}
+
 /// Start page address of the data segment.
 #[inline(always)]
 fn virt_data_start() -> PageAddress<Virtual> {
@@ -187,6 +292,45 @@ fn data_size() -> usize {
     unsafe { (__data_end_exclusive.get() as usize) - (__data_start.get() as usize) }
 }
 
+/// Start page address of the translation table frames.
+#[inline(always)]
+fn virt_table_frames_start() -> PageAddress<Virtual> {
+    PageAddress::from(unsafe { __table_frames_start.get() as usize })
+}
+
+/// Size of the translation table frames.
+#[inline(always)]
+fn table_frames_size() -> usize {
+    unsafe { (__table_frames_end_exclusive.get() as usize) - (__table_frames_start.get() as usize) }
+}
+
+/// Start page address of the DMA frames.
+#[inline(always)]
+fn virt_dma_frames_start() -> PageAddress<Virtual> {
+    PageAddress::from(unsafe { __dma_frames_start.get() as usize })
+}
+
+/// Size of the DMA frames.
+#[inline(always)]
+fn dma_frames_size() -> usize {
+    unsafe { (__dma_frames_end_exclusive.get() as usize) - (__dma_frames_start.get() as usize) }
+}
+
+/// Start page address of the non-cacheable DMA frames.
+#[inline(always)]
+fn virt_dma_coherent_frames_start() -> PageAddress<Virtual> {
+    PageAddress::from(unsafe { __dma_coherent_frames_start.get() as usize })
+}
+
+/// Size of the non-cacheable DMA frames.
+#[inline(always)]
+fn dma_coherent_frames_size() -> usize {
+    unsafe {
+        (__dma_coherent_frames_end_exclusive.get() as usize)
+            - (__dma_coherent_frames_start.get() as usize)
+    }
+}
+
 /// Start page address of the MMIO remap reservation.
 ///
 /// # Safety
@@ -207,6 +351,20 @@ fn mmio_remap_size() -> usize {
     unsafe { (__mmio_remap_end_exclusive.get() as usize) - (__mmio_remap_start.get() as usize) }
 }
 
+/// Start page address of the exception stack.
+#[inline(always)]
+fn virt_exception_stack_start() -> PageAddress<Virtual> {
+    PageAddress::from(unsafe { __exception_stack_start.get() as usize })
+}
+
+/// Size of the exception stack.
+#[inline(always)]
+fn exception_stack_size() -> usize {
+    unsafe {
+        (__exception_stack_end_exclusive.get() as usize) - (__exception_stack_start.get() as usize)
+    }
+}
+
 /// Start page address of the boot core's stack.
 #[inline(always)]
 fn virt_boot_core_stack_start() -> PageAddress<Virtual> {
@@ -221,6 +379,12 @@ fn boot_core_stack_size() -> usize {
     }
 }
 
+/// Start page address of the direct map.
+#[inline(always)]
+fn virt_direct_map_start() -> PageAddress<Virtual> {
+    PageAddress::from(unsafe { __direct_map_start.get() as usize })
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
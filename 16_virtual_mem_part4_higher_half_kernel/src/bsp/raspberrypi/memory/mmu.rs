@@ -5,15 +5,17 @@
 //! BSP Memory Management Unit.
 
 use crate::{
+    bsp, common,
     memory::{
         mmu::{
             self as generic_mmu, AddressSpace, AssociatedTranslationTable, AttributeFields,
-            MemoryRegion, PageAddress, TranslationGranule,
+            Lvl2Granule, MemoryRegion, PageAddress, TranslationGranule,
         },
-        Physical, Virtual,
+        Address, Physical, Virtual,
     },
-    synchronization::InitStateLock,
+    synchronization::{interface::ReadWriteEx, InitStateLock},
 };
+use core::cell::UnsafeCell;
 
 //--------------------------------------------------------------------------------------------------
 // Private Definitions
@@ -23,13 +25,31 @@ use crate::{
 type KernelTranslationTable =
     <KernelVirtAddrSpace as AssociatedTranslationTable>::TableStartFromTop;
 
+/// The part of the kernel binary's virtual range that the precomputed tables can cover at most. The
+/// `translation table tool` fails if the binary outgrows it.
+const MAX_KERNEL_BINARY_SIZE: usize = 16 * 1024 * 1024;
+
+/// The root table, a lvl2 table in case the lookup starts at level 1, and the lvl3 tables that map
+/// the kernel binary. Each lvl3 table covers the window of one lvl2 descriptor.
+const NUM_PRECOMPUTED_TABLE_FRAMES: usize =
+    2 + (MAX_KERNEL_BINARY_SIZE + Lvl2Granule::SIZE - 1) / Lvl2Granule::SIZE;
+
+/// Frames for the translation tables that are needed to boot. The alignment suffices for the
+/// largest granule.
+#[repr(C)]
+#[repr(align(65536))]
+struct PrecomputedTableFrames(UnsafeCell<[u8; NUM_PRECOMPUTED_TABLE_FRAMES * KernelGranule::SIZE]>);
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
 /// The translation granule chosen by this BSP. This will be used everywhere else in the kernel to
 /// derive respective data structures and their sizes. For example, the `crate::memory::mmu::Page`.
-pub type KernelGranule = TranslationGranule<{ 64 * 1024 }>;
+///
+/// Set in `kernel_granule_size.ld`, which is shared with the linker script. The architecture
+/// supports 4 KiB, 16 KiB and 64 KiB.
+pub type KernelGranule = TranslationGranule<{ kernel_granule_size() }>;
 
 /// The kernel's virtual address space defined by this BSP.
 pub type KernelVirtAddrSpace = AddressSpace<{ kernel_virt_addr_space_size() }>;
@@ -40,14 +60,20 @@ pub type KernelVirtAddrSpace = AddressSpace<{ kernel_virt_addr_space_size() }>;
 
 /// The kernel translation tables.
 ///
-/// It is mandatory that InitStateLock is transparent.
+/// They take over the precomputed tables in `post_enable_init()`. All other tables are allocated
+/// from the kernel's translation table frames.
+static KERNEL_TABLES: InitStateLock<KernelTranslationTable> =
+    InitStateLock::new(KernelTranslationTable::new());
+
+/// The tables that map the kernel binary, with the root table in the first frame.
 ///
-/// That is, `size_of(InitStateLock<KernelTranslationTable>) == size_of(KernelTranslationTable)`.
-/// There is a unit tests that checks this porperty.
+/// The tables will be precomputed and patched in by the "translation table tool" after linking.
+/// Frames that the tool does not need stay unused.
 #[link_section = ".data"]
 #[no_mangle]
-static KERNEL_TABLES: InitStateLock<KernelTranslationTable> =
-    InitStateLock::new(KernelTranslationTable::new_for_precompute());
+static PRECOMPUTED_TABLE_FRAMES: PrecomputedTableFrames = PrecomputedTableFrames(UnsafeCell::new(
+    [0; NUM_PRECOMPUTED_TABLE_FRAMES * KernelGranule::SIZE],
+));
 
 /// This value is needed during early boot for MMU setup.
 ///
@@ -61,6 +87,9 @@ static PHYS_KERNEL_TABLES_BASE_ADDR: u64 = 0xCCCCAAAAFFFFEEEE;
 // Private Code
 //--------------------------------------------------------------------------------------------------
 
+/// The frames are only accessed through raw pointers, by the translation table code.
+unsafe impl Sync for PrecomputedTableFrames {}
+
 /// This is a hack for retrieving the value for the kernel's virtual address space size as a
 /// constant from a common place, since it is needed as a compile-time/link-time constant in both,
 /// the linker script and the Rust sources.
@@ -73,6 +102,16 @@ const fn kernel_virt_addr_space_size() -> usize {
     __kernel_virt_addr_space_size
 }
 
+/// Same hack as above, for the size of the translation granule.
+#[allow(clippy::needless_late_init)]
+const fn kernel_granule_size() -> usize {
+    let __kernel_granule_size;
+
+    include!("../kernel_granule_size.ld");
+
+    __kernel_granule_size
+}
+
 /// Helper function for calculating the number of pages the given parameter spans.
 const fn size_to_num_pages(size: usize) -> usize {
     assert!(size > 0);
@@ -91,21 +130,21 @@ fn virt_code_region() -> MemoryRegion<Virtual> {
     MemoryRegion::new(start_page_addr, end_exclusive_page_addr)
 }
 
-/// The data pages of the kernel binary.
-fn virt_data_region() -> MemoryRegion<Virtual> {
-    let num_pages = size_to_num_pages(super::data_size());
+/// The read-only data pages of the kernel binary.
+fn virt_rodata_region() -> MemoryRegion<Virtual> {
+    let num_pages = size_to_num_pages(super::rodata_size());
 
-    let start_page_addr = super::virt_data_start();
+    let start_page_addr = super::virt_rodata_start();
     let end_exclusive_page_addr = start_page_addr.checked_offset(num_pages as isize).unwrap();
 
     MemoryRegion::new(start_page_addr, end_exclusive_page_addr)
 }
 
-/// The boot core stack pages.
-fn virt_boot_core_stack_region() -> MemoryRegion<Virtual> {
-    let num_pages = size_to_num_pages(super::boot_core_stack_size());
+/// The data pages of the kernel binary.
+fn virt_data_region() -> MemoryRegion<Virtual> {
+    let num_pages = size_to_num_pages(super::data_size());
 
-    let start_page_addr = super::virt_boot_core_stack_start();
+    let start_page_addr = super::virt_data_start();
     let end_exclusive_page_addr = start_page_addr.checked_offset(num_pages as isize).unwrap();
 
     MemoryRegion::new(start_page_addr, end_exclusive_page_addr)
@@ -129,15 +168,178 @@ fn kernel_page_attributes(virt_page_addr: PageAddress<Virtual>) -> AttributeFiel
     generic_mmu::try_kernel_page_attributes(virt_page_addr).unwrap()
 }
 
+/// The physical address of the precomputed root table.
+fn phys_kernel_tables_base_addr() -> Address<Physical> {
+    // The value is patched after compilation, so the compiler must not assume the dummy.
+    let addr = unsafe { core::ptr::read_volatile(&PHYS_KERNEL_TABLES_BASE_ADDR) };
+
+    Address::new(addr as usize)
+}
+
+/// The difference between the virtual and the physical addresses of the kernel's data segment.
+///
+/// Derived from the precomputed root table, whose physical address is known without a table walk.
+fn data_virt_phys_offset() -> usize {
+    let virt_root_table_addr = PRECOMPUTED_TABLE_FRAMES.0.get() as usize;
+
+    virt_root_table_addr.wrapping_sub(phys_kernel_tables_base_addr().as_usize())
+}
+
+/// All of DRAM that the board can have below the peripherals.
+fn phys_dram_window() -> MemoryRegion<Physical> {
+    MemoryRegion::new(
+        PageAddress::from(super::map::DRAM_START),
+        PageAddress::from(super::map::DRAM_END),
+    )
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
+/// The boot core stack pages.
+pub fn virt_boot_core_stack_region() -> MemoryRegion<Virtual> {
+    let num_pages = size_to_num_pages(super::boot_core_stack_size());
+
+    let start_page_addr = super::virt_boot_core_stack_start();
+    let end_exclusive_page_addr = start_page_addr.checked_offset(num_pages as isize).unwrap();
+
+    MemoryRegion::new(start_page_addr, end_exclusive_page_addr)
+}
+
+/// The exception stack pages.
+pub fn virt_exception_stack_region() -> MemoryRegion<Virtual> {
+    let num_pages = size_to_num_pages(super::exception_stack_size());
+
+    let start_page_addr = super::virt_exception_stack_start();
+    let end_exclusive_page_addr = start_page_addr.checked_offset(num_pages as isize).unwrap();
+
+    MemoryRegion::new(start_page_addr, end_exclusive_page_addr)
+}
+
+/// The physical frames that are reserved for translation tables allocated at runtime.
+pub fn phys_table_frames_region() -> MemoryRegion<Physical> {
+    let num_pages = size_to_num_pages(super::table_frames_size());
+
+    let start_page_addr = super::virt_table_frames_start();
+    let end_exclusive_page_addr = start_page_addr.checked_offset(num_pages as isize).unwrap();
+
+    kernel_virt_to_phys_region(MemoryRegion::new(start_page_addr, end_exclusive_page_addr))
+}
+
+/// The physical frames that are reserved for DMA buffers.
+pub fn phys_dma_frames_region() -> MemoryRegion<Physical> {
+    let num_pages = size_to_num_pages(super::dma_frames_size());
+
+    let start_page_addr = super::virt_dma_frames_start();
+    let end_exclusive_page_addr = start_page_addr.checked_offset(num_pages as isize).unwrap();
+
+    kernel_virt_to_phys_region(MemoryRegion::new(start_page_addr, end_exclusive_page_addr))
+}
+
+/// The frames that are reserved for non-cacheable DMA memory, at their address in the kernel
+/// binary.
+pub fn virt_dma_coherent_frames_region() -> MemoryRegion<Virtual> {
+    let num_pages = size_to_num_pages(super::dma_coherent_frames_size());
+
+    let start_page_addr = super::virt_dma_coherent_frames_start();
+    let end_exclusive_page_addr = start_page_addr.checked_offset(num_pages as isize).unwrap();
+
+    MemoryRegion::new(start_page_addr, end_exclusive_page_addr)
+}
+
+/// The physical frames that are reserved for non-cacheable DMA memory.
+pub fn phys_dma_coherent_frames_region() -> MemoryRegion<Physical> {
+    kernel_virt_to_phys_region(virt_dma_coherent_frames_region())
+}
+
+/// The physical frames of the kernel's code and read-only data.
+pub fn phys_kernel_read_only_region() -> MemoryRegion<Physical> {
+    let virt_code_region = virt_code_region();
+    let virt_rodata_region = virt_rodata_region();
+    assert!(virt_code_region.end_exclusive_page_addr() == virt_rodata_region.start_page_addr());
+
+    kernel_virt_to_phys_region(MemoryRegion::new(
+        virt_code_region.start_page_addr(),
+        virt_rodata_region.end_exclusive_page_addr(),
+    ))
+}
+
+/// The physical memory that is covered by the direct map.
+///
+/// That is the device tree's first memory region, if there is one. Falls back to all of DRAM that
+/// the board can have below the peripherals otherwise. Before the device tree is parsed, the
+/// fallback applies as well.
+pub fn phys_direct_map_region() -> MemoryRegion<Physical> {
+    let dram_window = phys_dram_window();
+
+    let (start, size) = match bsp::device_tree::dram_range() {
+        None => return dram_window,
+        Some(x) => x,
+    };
+
+    // Only whole pages inside the window are usable.
+    let window_end_exclusive = dram_window.end_exclusive_page_addr().into_inner();
+    let end_exclusive = common::align_down(start.saturating_add(size), KernelGranule::SIZE)
+        .min(window_end_exclusive.as_usize());
+    let start = common::align_up(start, KernelGranule::SIZE);
+
+    if start >= end_exclusive || start < dram_window.start_addr().as_usize() {
+        return dram_window;
+    }
+
+    MemoryRegion::new(PageAddress::from(start), PageAddress::from(end_exclusive))
+}
+
+/// The direct map pages. DRAM is mapped at the same offsets, starting at the top half of the
+/// kernel's address space.
+pub fn virt_direct_map_region() -> MemoryRegion<Virtual> {
+    let phys_region = phys_direct_map_region();
+    let size = phys_region.size();
+    assert!(size <= KernelVirtAddrSpace::SIZE / 2);
+
+    let offset_pages = (phys_region.start_addr().as_usize()
+        - phys_dram_window().start_addr().as_usize())
+        >> KernelGranule::SHIFT;
+    let num_pages = size_to_num_pages(size);
+
+    let start_page_addr = super::virt_direct_map_start()
+        .checked_offset(offset_pages as isize)
+        .unwrap();
+    let end_exclusive_page_addr = start_page_addr.checked_offset(num_pages as isize).unwrap();
+
+    MemoryRegion::new(start_page_addr, end_exclusive_page_addr)
+}
+
 /// Return a reference to the kernel's translation tables.
 pub fn kernel_translation_tables() -> &'static InitStateLock<KernelTranslationTable> {
     &KERNEL_TABLES
 }
 
+/// Let the kernel's translation tables take over the tables that were precomputed for the kernel
+/// binary.
+pub fn kernel_init_translation_tables() -> Result<(), &'static str> {
+    KERNEL_TABLES.write(|tables| unsafe { tables.init_precomputed(phys_kernel_tables_base_addr()) })
+}
+
+/// Translate the physical address of a translation table frame to the kernel virtual address it is
+/// accessible at.
+///
+/// All translation tables are in the kernel's data segment, either in the precomputed frames or in
+/// the `.table_frames`. The data segment is mapped at a constant offset from the start, so unlike
+/// the direct map, this works before `post_enable_init()`.
+pub fn kernel_table_frame_virt_addr(
+    phys_addr: Address<Physical>,
+) -> Result<Address<Virtual>, &'static str> {
+    let virt_addr = Address::new(phys_addr.as_usize().wrapping_add(data_virt_phys_offset()));
+
+    if !virt_data_region().contains(virt_addr) {
+        return Err("Translation table is outside of the kernel's data segment");
+    }
+
+    Ok(virt_addr)
+}
+
 /// The MMIO remap pages.
 pub fn virt_mmio_remap_region() -> MemoryRegion<Virtual> {
     let num_pages = size_to_num_pages(super::mmio_remap_size());
@@ -162,12 +364,20 @@ pub fn kernel_add_mapping_records_for_precomputed() {
     // 前回までboot core stackはここにあった．
     let virt_code_region = virt_code_region();
     generic_mmu::kernel_add_mapping_record(
-        "Kernel code and RO data",
+        "Kernel code",
         &virt_code_region,
         &kernel_virt_to_phys_region(virt_code_region),
         &kernel_page_attributes(virt_code_region.start_page_addr()),
     );
 
+    let virt_rodata_region = virt_rodata_region();
+    generic_mmu::kernel_add_mapping_record(
+        "Kernel RO data",
+        &virt_rodata_region,
+        &kernel_virt_to_phys_region(virt_rodata_region),
+        &kernel_page_attributes(virt_rodata_region.start_page_addr()),
+    );
+
     let virt_data_region = virt_data_region();
     generic_mmu::kernel_add_mapping_record(
         "Kernel data and bss",
@@ -176,6 +386,14 @@ pub fn kernel_add_mapping_records_for_precomputed() {
         &kernel_page_attributes(virt_data_region.start_page_addr()),
     );
 
+    let virt_exception_stack_region = virt_exception_stack_region();
+    generic_mmu::kernel_add_mapping_record(
+        "Kernel exception stack",
+        &virt_exception_stack_region,
+        &kernel_virt_to_phys_region(virt_exception_stack_region),
+        &kernel_page_attributes(virt_exception_stack_region.start_page_addr()),
+    );
+
     // boot core stackのmapping record entry
     // 場所が変わったのでそれに合わせてmapping record entryを作る順序も変えた．
     let virt_boot_core_stack_region = virt_boot_core_stack_region();
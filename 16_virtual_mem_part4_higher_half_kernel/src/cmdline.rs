@@ -0,0 +1,444 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Kernel command line.
+//!
+//! The command line is a whitespace separated list of parameters, each either `name=value` or just
+//! `name`. Values that contain whitespace are put in double quotes, e.g. `name="a b"`. In names,
+//! `-` and `_` are interchangeable. If a parameter is given more than once, the last one wins.
+//!
+//! The command line is taken from the device tree's `/chosen/bootargs`. If there is none, the one
+//! embedded at build time from the `KERNEL_CMDLINE` environment variable is used.
+//!
+//! Parameters are declared anywhere in the kernel with [`kernel_param!`](crate::kernel_param). The
+//! macro puts a reference to the parameter into the `.kernel_params` linker section, so no further
+//! registration is needed. Parameters that are given on the command line, but not declared, are
+//! warned about.
+
+use crate::{
+    synchronization::{interface::ReadWriteEx, InitStateLock},
+    warn,
+};
+use core::{cell::UnsafeCell, time::Duration};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Iterator over the `(name, value)` pairs of a command line.
+struct Tokens<'a> {
+    remaining: &'a str,
+}
+
+// Symbols from the linker script.
+extern "Rust" {
+    static __kernel_params_start: UnsafeCell<()>;
+    static __kernel_params_end_exclusive: UnsafeCell<()>;
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Kernel parameter interfaces.
+pub mod interface {
+    /// A parameter that can be set from the command line.
+    pub trait Param {
+        /// The name on the command line.
+        fn name(&self) -> &'static str;
+
+        /// Parse and store `value`, which is `None` for a parameter without `=`.
+        fn set(&self, value: Option<&str>) -> Result<(), &'static str>;
+    }
+}
+
+/// Types that parameters can have.
+pub trait ParamValue: Copy + Send {
+    /// Parse the value given on the command line, which is `None` for a parameter without `=`.
+    fn parse_param(value: Option<&str>) -> Result<Self, &'static str>;
+}
+
+/// A typed parameter. Declare instances with [`kernel_param!`](crate::kernel_param).
+pub struct Param<T> {
+    name: &'static str,
+    value: InitStateLock<T>,
+    on_set: Option<fn(T)>,
+}
+
+/// Declare a kernel parameter.
+///
+/// ```ignore
+/// kernel_param! {
+///     /// Maximum level of log records.
+///     static LOG_LEVEL: Level = Level::Info, name = "loglevel", on_set = set_max_level;
+/// }
+/// ```
+///
+/// `on_set` is optional. If given, it is called with the new value after the command line was
+/// parsed.
+#[macro_export]
+macro_rules! kernel_param {
+    (
+        $(#[$attr:meta])*
+        $vis:vis static $ident:ident: $ty:ty = $default:expr, name = $name:literal,
+            on_set = $on_set:expr $(,)?;
+    ) => {
+        $(#[$attr])*
+        $vis static $ident: $crate::cmdline::Param<$ty> =
+            $crate::cmdline::Param::new($name, $default, Some($on_set as fn($ty)));
+
+        $crate::kernel_param!(@register $ident);
+    };
+
+    (
+        $(#[$attr:meta])*
+        $vis:vis static $ident:ident: $ty:ty = $default:expr, name = $name:literal $(,)?;
+    ) => {
+        $(#[$attr])*
+        $vis static $ident: $crate::cmdline::Param<$ty> =
+            $crate::cmdline::Param::new($name, $default, None);
+
+        $crate::kernel_param!(@register $ident);
+    };
+
+    (@register $ident:ident) => {
+        const _: () = {
+            #[link_section = ".kernel_params"]
+            #[used]
+            static REGISTRATION: &(dyn $crate::cmdline::interface::Param + Sync) = &$ident;
+        };
+    };
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static CMDLINE: InitStateLock<&'static str> = InitStateLock::new("");
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// All declared parameters.
+fn params() -> &'static [&'static (dyn interface::Param + Sync)] {
+    unsafe {
+        let start = __kernel_params_start.get() as usize;
+        let end_exclusive = __kernel_params_end_exclusive.get() as usize;
+        let len = (end_exclusive - start)
+            / core::mem::size_of::<&'static (dyn interface::Param + Sync)>();
+
+        core::slice::from_raw_parts(start as *const &'static (dyn interface::Param + Sync), len)
+    }
+}
+
+/// Compare parameter names, treating `-` and `_` as equal.
+fn name_matches(a: &str, b: &str) -> bool {
+    let normalize = |c: u8| if c == b'-' { b'_' } else { c };
+
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .all(|(x, y)| normalize(x) == normalize(y))
+}
+
+/// Strip one pair of surrounding double quotes.
+fn unquote(value: &str) -> &str {
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        return &value[1..value.len() - 1];
+    }
+
+    value
+}
+
+impl<'a> Tokens<'a> {
+    fn new(cmdline: &'a str) -> Self {
+        Self { remaining: cmdline }
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = (&'a str, Option<&'a str>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let s = self.remaining.trim_start();
+        if s.is_empty() {
+            self.remaining = s;
+            return None;
+        }
+
+        // Whitespace ends the token, unless it is quoted.
+        let mut in_quotes = false;
+        let end = s
+            .char_indices()
+            .find(|&(_, c)| {
+                if c == '"' {
+                    in_quotes = !in_quotes;
+                }
+
+                c.is_whitespace() && !in_quotes
+            })
+            .map_or(s.len(), |(i, _)| i);
+
+        let token = &s[..end];
+        self.remaining = &s[end..];
+
+        Some(match token.split_once('=') {
+            None => (token, None),
+            Some((name, value)) => (name, Some(unquote(value))),
+        })
+    }
+}
+
+/// Parse an integer, in decimal or, with a `0x` prefix, in hexadecimal.
+fn parse_integer(value: Option<&str>) -> Result<(u64, bool), &'static str> {
+    let value = value.ok_or("Missing value")?;
+    let (negative, digits) = match value.strip_prefix('-') {
+        None => (false, value),
+        Some(x) => (true, x),
+    };
+
+    let magnitude = match digits.strip_prefix("0x") {
+        None => digits.parse::<u64>(),
+        Some(x) => u64::from_str_radix(x, 16),
+    }
+    .map_err(|_| "Invalid integer")?;
+
+    Ok((magnitude, negative))
+}
+
+/// Apply the parameters of `cmdline` to the declared ones.
+fn apply(cmdline: &str) {
+    for (name, value) in Tokens::new(cmdline) {
+        // Later parameters of the same name override earlier ones.
+        match params().iter().find(|x| name_matches(x.name(), name)) {
+            None => warn!("Unknown kernel parameter: {}", name),
+            Some(param) => {
+                if let Err(x) = param.set(value) {
+                    warn!("Invalid value for kernel parameter {}: {}", name, x);
+                }
+            }
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl<T: ParamValue> Param<T> {
+    /// Create an instance. Use [`kernel_param!`](crate::kernel_param) instead.
+    #[doc(hidden)]
+    pub const fn new(name: &'static str, default: T, on_set: Option<fn(T)>) -> Self {
+        Self {
+            name,
+            value: InitStateLock::new(default),
+            on_set,
+        }
+    }
+
+    /// The current value.
+    pub fn get(&self) -> T {
+        self.value.read(|x| *x)
+    }
+}
+
+impl<T: ParamValue> interface::Param for Param<T> {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn set(&self, value: Option<&str>) -> Result<(), &'static str> {
+        let value = T::parse_param(value)?;
+
+        self.value.write(|x| *x = value);
+        if let Some(f) = self.on_set {
+            f(value);
+        }
+
+        Ok(())
+    }
+}
+
+/// A parameter without a value means `true`.
+impl ParamValue for bool {
+    fn parse_param(value: Option<&str>) -> Result<Self, &'static str> {
+        match value {
+            None | Some("1" | "y" | "yes" | "on" | "true") => Ok(true),
+            Some("0" | "n" | "no" | "off" | "false") => Ok(false),
+            Some(_) => Err("Invalid boolean"),
+        }
+    }
+}
+
+macro_rules! impl_param_value_unsigned {
+    ($($ty:ty),*) => {$(
+        impl ParamValue for $ty {
+            fn parse_param(value: Option<&str>) -> Result<Self, &'static str> {
+                match parse_integer(value)? {
+                    (_, true) => Err("Negative value"),
+                    (x, false) => <$ty>::try_from(x).map_err(|_| "Integer out of range"),
+                }
+            }
+        }
+    )*};
+}
+
+macro_rules! impl_param_value_signed {
+    ($($ty:ty),*) => {$(
+        impl ParamValue for $ty {
+            fn parse_param(value: Option<&str>) -> Result<Self, &'static str> {
+                let (magnitude, negative) = parse_integer(value)?;
+                let x = i128::from(magnitude);
+
+                <$ty>::try_from(if negative { -x } else { x }).map_err(|_| "Integer out of range")
+            }
+        }
+    )*};
+}
+
+impl_param_value_unsigned!(u8, u16, u32, u64, usize);
+impl_param_value_signed!(i8, i16, i32, i64, isize);
+
+/// An integer with one of the units `ns`, `us`, `ms` or `s`, e.g. `250ms`.
+impl ParamValue for Duration {
+    fn parse_param(value: Option<&str>) -> Result<Self, &'static str> {
+        let value = value.ok_or("Missing value")?;
+        let unit_start = value
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or("Missing unit")?;
+        let (number, unit) = value.split_at(unit_start);
+        let number = number.parse::<u64>().map_err(|_| "Invalid integer")?;
+
+        match unit {
+            "ns" => Ok(Duration::from_nanos(number)),
+            "us" => Ok(Duration::from_micros(number)),
+            "ms" => Ok(Duration::from_millis(number)),
+            "s" => Ok(Duration::from_secs(number)),
+            _ => Err("Unknown unit"),
+        }
+    }
+}
+
+/// The command line embedded at build time. Empty if `KERNEL_CMDLINE` was not set.
+pub fn builtin_cmdline() -> &'static str {
+    option_env!("KERNEL_CMDLINE").unwrap_or("")
+}
+
+/// Store the command line and set the declared parameters from it.
+///
+/// Must be called during kernel init.
+pub fn init(cmdline: &'static str) {
+    CMDLINE.write(|x| *x = cmdline);
+    apply(cmdline);
+}
+
+/// The command line.
+pub fn cmdline() -> &'static str {
+    CMDLINE.read(|x| *x)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{kernel_param, log::Level};
+    use test_macros::kernel_test;
+
+    kernel_param! {
+        static TEST_BOOL: bool = false, name = "test_bool";
+    }
+
+    kernel_param! {
+        static TEST_U32: u32 = 7, name = "test-u32";
+    }
+
+    kernel_param! {
+        static TEST_DURATION: Duration = Duration::from_secs(1), name = "test_duration";
+    }
+
+    kernel_param! {
+        static TEST_LEVEL: Level = Level::Info, name = "test_level";
+    }
+
+    /// Splitting the command line into parameters.
+    #[kernel_test]
+    fn cmdline_tokens() {
+        let mut tokens = Tokens::new("  a  b=1 c= d=e=f g=\"h  i\" \"j k\" l=\"");
+
+        assert_eq!(tokens.next(), Some(("a", None)));
+        assert_eq!(tokens.next(), Some(("b", Some("1"))));
+        assert_eq!(tokens.next(), Some(("c", Some(""))));
+        assert_eq!(tokens.next(), Some(("d", Some("e=f"))));
+        assert_eq!(tokens.next(), Some(("g", Some("h  i"))));
+        assert_eq!(tokens.next(), Some(("\"j k\"", None)));
+        assert_eq!(tokens.next(), Some(("l", Some("\""))));
+        assert_eq!(tokens.next(), None);
+
+        assert_eq!(Tokens::new("").next(), None);
+        assert_eq!(Tokens::new(" \t\n").next(), None);
+
+        assert!(name_matches("test-u32", "test_u32"));
+        assert!(!name_matches("test_u3", "test_u32"));
+    }
+
+    /// Parsing of the value types.
+    #[kernel_test]
+    fn cmdline_param_values() {
+        assert_eq!(bool::parse_param(None), Ok(true));
+        assert_eq!(bool::parse_param(Some("off")), Ok(false));
+        assert!(bool::parse_param(Some("")).is_err());
+
+        assert_eq!(u8::parse_param(Some("255")), Ok(255));
+        assert_eq!(u32::parse_param(Some("0x1f")), Ok(0x1f));
+        assert!(u8::parse_param(Some("256")).is_err());
+        assert!(u32::parse_param(Some("-1")).is_err());
+        assert!(u32::parse_param(Some("")).is_err());
+        assert!(u32::parse_param(None).is_err());
+        assert_eq!(i8::parse_param(Some("-128")), Ok(-128));
+        assert_eq!(i64::parse_param(Some("-0x10")), Ok(-16));
+        assert!(i8::parse_param(Some("128")).is_err());
+
+        assert_eq!(
+            Duration::parse_param(Some("250ms")),
+            Ok(Duration::from_millis(250))
+        );
+        assert_eq!(
+            Duration::parse_param(Some("3s")),
+            Ok(Duration::from_secs(3))
+        );
+        assert_eq!(
+            Duration::parse_param(Some("7ns")),
+            Ok(Duration::from_nanos(7))
+        );
+        assert!(Duration::parse_param(Some("5")).is_err());
+        assert!(Duration::parse_param(Some("ms")).is_err());
+        assert!(Duration::parse_param(Some("5 min")).is_err());
+
+        assert_eq!(Level::parse_param(Some("debug")), Ok(Level::Debug));
+        assert!(Level::parse_param(Some("loud")).is_err());
+        assert!(Level::parse_param(None).is_err());
+    }
+
+    /// Declared parameters are found and set. Invalid values and unknown parameters are skipped.
+    #[kernel_test]
+    fn cmdline_apply() {
+        apply(
+            "test_bool test_u32=0x10 test-u32=oops unknown test_duration=\"5us\" test_level=trace",
+        );
+
+        assert!(TEST_BOOL.get());
+        assert_eq!(TEST_U32.get(), 0x10);
+        assert_eq!(TEST_DURATION.get(), Duration::from_micros(5));
+        assert_eq!(TEST_LEVEL.get(), Level::Trace);
+
+        apply("test_bool=0 test_u32=1 test_u32=2");
+
+        assert!(!TEST_BOOL.get());
+        assert_eq!(TEST_U32.get(), 2);
+    }
+}
@@ -3,6 +3,16 @@
 // Copyright (c) 2018-2022 Andre Richter <andre.o.richter@gmail.com>
 
 //! System console.
+//!
+//! Besides the BSP's console device, a further input source, e.g. a keyboard, can be registered.
+//! [`read_char()`] and [`read_line()`] take characters from both.
+
+use crate::synchronization::{interface::ReadWriteEx, InitStateLock};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -10,7 +20,10 @@
 
 /// Console interfaces.
 pub mod interface {
-    use core::fmt;
+    use core::{
+        fmt,
+        task::{Context, Poll},
+    };
 
     /// Console write functions.
     pub trait Write {
@@ -31,6 +44,13 @@ pub mod interface {
             ' '
         }
 
+        /// Try to read a single character without blocking.
+        ///
+        /// If no character is available, the waker of `cx` must be woken once one arrives.
+        fn poll_read_char(&self, _cx: &mut Context) -> Poll<char> {
+            Poll::Ready(self.read_char())
+        }
+
         /// Clear RX buffers, if any.
         fn clear_rx(&self);
     }
@@ -51,3 +71,87 @@ pub mod interface {
     /// Trait alias for a full-fledged console.
     pub trait All = Write + Read + Statistics;
 }
+
+/// Future returned by [`read_char()`].
+pub struct ReadChar<'a, T: ?Sized> {
+    console: &'a T,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static INPUT_SOURCE: InitStateLock<Option<&'static (dyn interface::Read + Sync)>> =
+    InitStateLock::new(None);
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl<T> Future for ReadChar<'_, T>
+where
+    T: interface::Read + ?Sized,
+{
+    type Output = char;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<char> {
+        // Both sources are polled, so that either one wakes the reader.
+        if let Some(source) = INPUT_SOURCE.read(|x| *x) {
+            if let Poll::Ready(c) = source.poll_read_char(cx) {
+                return Poll::Ready(c);
+            }
+        }
+
+        self.console.poll_read_char(cx)
+    }
+}
+
+/// Register an additional source of console input. Replaces a previously registered one.
+pub fn register_input_source(source: &'static (dyn interface::Read + Sync)) {
+    INPUT_SOURCE.write(|x| *x = Some(source));
+}
+
+/// Asynchronously read a single character from `console`, or from the registered input source.
+pub fn read_char<T>(console: &T) -> ReadChar<'_, T>
+where
+    T: interface::Read + ?Sized,
+{
+    ReadChar { console }
+}
+
+/// Asynchronously read a line and echo it back.
+///
+/// Returns once a newline was received, without the newline. Backspace removes the last character.
+/// Non-ASCII characters and characters that do not fit into `buf` anymore are dropped.
+pub async fn read_line<'a, T>(console: &T, buf: &'a mut [u8]) -> &'a str
+where
+    T: interface::All + ?Sized,
+{
+    let mut len = 0;
+
+    loop {
+        match read_char(console).await {
+            '\n' => {
+                console.write_char('\n');
+                break;
+            }
+            '\x08' | '\x7f' => {
+                if len > 0 {
+                    len -= 1;
+                    for c in ['\x08', ' ', '\x08'] {
+                        console.write_char(c);
+                    }
+                }
+            }
+            c if c.is_ascii() && !c.is_ascii_control() && len < buf.len() => {
+                buf[len] = c as u8;
+                len += 1;
+                console.write_char(c);
+            }
+            _ => (),
+        }
+    }
+
+    // Only printable ASCII was stored, so this cannot fail.
+    core::str::from_utf8(&buf[..len]).unwrap_or_default()
+}
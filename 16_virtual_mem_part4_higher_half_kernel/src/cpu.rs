@@ -8,14 +8,40 @@
 #[path = "_arch/aarch64/cpu.rs"]
 mod arch_cpu;
 
-mod boot;
+pub mod boot;
 
 pub mod smp;
 
+use crate::{bsp, driver, exception};
+
 //--------------------------------------------------------------------------------------------------
 // Architectural Public Reexports
 //--------------------------------------------------------------------------------------------------
-pub use arch_cpu::{nop, wait_forever};
+pub use arch_cpu::{nop, wait_for_interrupt, wait_forever};
 
 #[cfg(feature = "test_build")]
 pub use arch_cpu::{qemu_exit_failure, qemu_exit_success};
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Shut down the device drivers and reset the board.
+pub fn reboot() -> ! {
+    unsafe {
+        exception::asynchronous::local_irq_mask();
+        driver::driver_manager().shutdown_drivers();
+    }
+
+    bsp::cpu::reset()
+}
+
+/// Shut down the device drivers and power the board off.
+pub fn power_off() -> ! {
+    unsafe {
+        exception::asynchronous::local_irq_mask();
+        driver::driver_manager().shutdown_drivers();
+    }
+
+    bsp::cpu::power_off()
+}
@@ -3,6 +3,38 @@
 // Copyright (c) 2018-2022 Andre Richter <andre.o.richter@gmail.com>
 
 //! Driver support.
+//!
+//! The BSP registers its drivers with the [`DriverManager`], together with the compatible strings
+//! of the drivers they depend on. [`DriverManager::init_drivers()`] then initializes them in
+//! dependency order. A driver whose `init()` returns [`PROBE_DEFER`] is probed again once other
+//! drivers made progress. Drivers registered as optional, for example for hardware that might not
+//! be attached, are allowed to fail.
+//!
+//! Shutdown and suspend go through the ready drivers in reverse initialization order, so that no
+//! driver is stopped before the drivers that depend on it. Resume goes the other way around.
+
+use crate::{
+    info,
+    synchronization::{interface::ReadWriteEx, InitStateLock},
+    time::{self, interface::TimeManager},
+    warn,
+};
+use core::{fmt, time::Duration};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Maximum number of registered drivers.
+const MAX_DRIVERS: usize = 16;
+
+struct DriverManagerInner {
+    drivers: [Option<RegisteredDriver>; MAX_DRIVERS],
+
+    /// Indices into `drivers`, in the order in which the drivers became ready.
+    init_order: [usize; MAX_DRIVERS],
+    num_ready: usize,
+}
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -17,6 +49,8 @@ pub mod interface {
 
         /// Called by the kernel to bring up the device.
         ///
+        /// Return [`super::PROBE_DEFER`] if the device cannot be brought up yet.
+        ///
         /// # Safety
         ///
         /// - During init, drivers might do stuff with system-wide impact.
@@ -24,6 +58,33 @@ pub mod interface {
             Ok(())
         }
 
+        /// Called by the kernel to bring the device to a halt, e.g. before a reboot.
+        ///
+        /// # Safety
+        ///
+        /// - The device must not be used afterwards, except for output that is still pending.
+        unsafe fn shutdown(&self) -> Result<(), &'static str> {
+            Ok(())
+        }
+
+        /// Called by the kernel to quiesce the device before the system idles in a low-power state.
+        ///
+        /// # Safety
+        ///
+        /// - Must be followed by a call to `resume()` before the device is used again.
+        unsafe fn suspend(&self) -> Result<(), &'static str> {
+            Ok(())
+        }
+
+        /// Called by the kernel to undo `suspend()`.
+        ///
+        /// # Safety
+        ///
+        /// - Must only be called after a successful `suspend()`.
+        unsafe fn resume(&self) -> Result<(), &'static str> {
+            Ok(())
+        }
+
         /// Called by the kernel to register and enable the device's IRQ handlers, if any.
         ///
         /// Rust's type system will prevent a call to this function unless the calling instance
@@ -40,23 +101,577 @@ pub mod interface {
             None
         }
     }
+}
+
+/// Returned by [`interface::DeviceDriver::init()`] if the device cannot be brought up yet, for
+/// example because a resource it needs is not available. The driver is probed again later.
+pub const PROBE_DEFER: &str = "Probe deferred";
+
+/// Called after a driver's `init()` succeeded.
+pub type DeviceDriverPostInitCallback = unsafe fn() -> Result<(), &'static str>;
 
-    /// Device driver management functions.
+/// A driver and what it needs, for registration with the [`DriverManager`].
+#[derive(Copy, Clone)]
+pub struct DeviceDriverDescriptor {
+    device_driver: &'static (dyn interface::DeviceDriver + Sync),
+    dependencies: &'static [&'static str],
+    post_init_callback: Option<DeviceDriverPostInitCallback>,
+    optional: bool,
+}
+
+/// The state of a registered driver.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DriverState {
+    /// Not probed yet.
+    Registered,
+
+    /// Waiting for a dependency, or `init()` returned [`PROBE_DEFER`].
+    Deferred,
+
+    /// Initialized.
+    Ready,
+
+    /// Initialization failed.
+    Failed(&'static str),
+}
+
+/// A driver in the [`DriverManager`].
+#[derive(Copy, Clone)]
+pub struct RegisteredDriver {
+    descriptor: DeviceDriverDescriptor,
+    state: DriverState,
+    init_time: Duration,
+}
+
+/// Keeps track of the registered drivers and brings them up.
+pub struct DriverManager {
+    inner: InitStateLock<DriverManagerInner>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static DRIVER_MANAGER: DriverManager = DriverManager::new();
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl DriverManagerInner {
+    const fn new() -> Self {
+        Self {
+            drivers: [None; MAX_DRIVERS],
+            init_order: [0; MAX_DRIVERS],
+            num_ready: 0,
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &RegisteredDriver> {
+        self.drivers.iter().flatten()
+    }
+
+    /// The ready drivers, in initialization order.
+    fn ready_drivers(&self) -> impl DoubleEndedIterator<Item = &RegisteredDriver> {
+        self.init_order[..self.num_ready]
+            .iter()
+            .filter_map(|i| self.drivers[*i].as_ref())
+    }
+
+    /// The state that `dependencies` put a driver in, if they are not all ready.
+    fn blocking_state(&self, dependencies: &[&str]) -> Option<DriverState> {
+        let mut blocking_state = None;
+
+        for dependency in dependencies {
+            match self.iter().find(|x| x.compatible() == *dependency) {
+                Some(x) if x.state == DriverState::Ready => (),
+                Some(RegisteredDriver {
+                    state: DriverState::Failed(_),
+                    ..
+                }) => return Some(DriverState::Failed("Dependency failed")),
+                // Missing dependencies might still be registered by a later init step.
+                _ => blocking_state = Some(DriverState::Deferred),
+            }
+        }
+
+        blocking_state
+    }
+}
+
+impl DriverManager {
+    const fn new() -> Self {
+        Self {
+            inner: InitStateLock::new(DriverManagerInner::new()),
+        }
+    }
+}
+
+impl RegisteredDriver {
+    /// Initialize the driver and run its post-init callback.
+    unsafe fn probe(&self) -> DriverState {
+        match self.descriptor.device_driver.init() {
+            Err(x) if x == PROBE_DEFER => return DriverState::Deferred,
+            Err(x) => return DriverState::Failed(x),
+            Ok(()) => (),
+        }
+
+        if let Some(callback) = self.descriptor.post_init_callback {
+            if let Err(x) = callback() {
+                return DriverState::Failed(x);
+            }
+        }
+
+        DriverState::Ready
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl DeviceDriverDescriptor {
+    /// Create an instance.
     ///
-    /// The `BSP` is supposed to supply one global instance.
-    pub trait DriverManager {
-        /// Return a slice of references to all `BSP`-instantiated drivers.
-        fn all_device_drivers(&self) -> &[&'static (dyn DeviceDriver + Sync)];
+    /// `dependencies` are the compatible strings of the drivers that must be ready before this one
+    /// is initialized.
+    pub const fn new(
+        device_driver: &'static (dyn interface::DeviceDriver + Sync),
+        dependencies: &'static [&'static str],
+        post_init_callback: Option<DeviceDriverPostInitCallback>,
+    ) -> Self {
+        Self {
+            device_driver,
+            dependencies,
+            post_init_callback,
+            optional: false,
+        }
+    }
 
-        /// Return only those drivers needed for the BSP's early printing functionality.
-        ///
-        /// For example, the default UART.
-        fn early_print_device_drivers(&self) -> &[&'static (dyn DeviceDriver + Sync)];
+    /// Mark the driver as optional. Its failure to initialize is not an error.
+    #[must_use]
+    pub const fn optional(self) -> Self {
+        Self {
+            optional: true,
+            ..self
+        }
+    }
+}
 
-        /// Return all drivers minus early-print drivers.
-        fn non_early_print_device_drivers(&self) -> &[&'static (dyn DeviceDriver + Sync)];
+impl fmt::Display for DriverState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DriverState::Registered => write!(f, "registered"),
+            DriverState::Deferred => write!(f, "deferred"),
+            DriverState::Ready => write!(f, "ready"),
+            DriverState::Failed(x) => write!(f, "failed: {}", x),
+        }
+    }
+}
+
+impl RegisteredDriver {
+    /// The driver's compatible string.
+    pub fn compatible(&self) -> &'static str {
+        self.descriptor.device_driver.compatible()
+    }
+
+    /// The driver's state.
+    pub fn state(&self) -> DriverState {
+        self.state
+    }
+
+    /// Whether the driver is allowed to fail.
+    pub fn is_optional(&self) -> bool {
+        self.descriptor.optional
+    }
+
+    /// Time spent in the driver's `init()` and post-init callback, summed over all probes.
+    pub fn init_time(&self) -> Duration {
+        self.init_time
+    }
+}
+
+impl DriverManager {
+    /// Register a driver. Must be called during kernel init.
+    pub fn register_driver(&self, descriptor: DeviceDriverDescriptor) -> Result<(), &'static str> {
+        self.inner.write(|inner| {
+            let compatible = descriptor.device_driver.compatible();
+            if inner.iter().any(|x| x.compatible() == compatible) {
+                return Err("Driver already registered");
+            }
+
+            let slot = match inner.drivers.iter_mut().find(|x| x.is_none()) {
+                None => return Err("Storage for drivers exhausted"),
+                Some(x) => x,
+            };
+
+            *slot = Some(RegisteredDriver {
+                descriptor,
+                state: DriverState::Registered,
+                init_time: Duration::ZERO,
+            });
+
+            Ok(())
+        })
+    }
+
+    /// Initialize the registered drivers in dependency order.
+    ///
+    /// Drivers are probed in passes, until a pass does not bring up or fail any driver. Drivers
+    /// whose dependencies are not ready yet, or that defer their probe, are tried again in the next
+    /// pass. Failures are logged.
+    ///
+    /// # Safety
+    ///
+    /// - See [`interface::DeviceDriver::init()`].
+    pub unsafe fn init_drivers(&self) {
+        loop {
+            let mut progress = false;
+
+            for i in 0..MAX_DRIVERS {
+                let driver = match self.inner.read(|inner| inner.drivers[i]) {
+                    Some(x)
+                        if matches!(x.state, DriverState::Registered | DriverState::Deferred) =>
+                    {
+                        x
+                    }
+                    _ => continue,
+                };
+
+                let blocking_state = self
+                    .inner
+                    .read(|inner| inner.blocking_state(driver.descriptor.dependencies));
+
+                // Drivers are probed outside of the lock, because they might need to take it.
+                let (state, init_time) = match blocking_state {
+                    Some(x) => (x, Duration::ZERO),
+                    None => {
+                        let start = time::time_manager().uptime();
+                        let state = driver.probe();
+
+                        (state, time::time_manager().uptime().saturating_sub(start))
+                    }
+                };
+
+                match state {
+                    DriverState::Failed(x) if driver.is_optional() => {
+                        info!("Optional driver not loaded: {}: {}", driver.compatible(), x)
+                    }
+                    DriverState::Failed(x) => {
+                        warn!("Error loading driver: {}: {}", driver.compatible(), x)
+                    }
+                    _ => (),
+                }
+                progress |= state != DriverState::Deferred;
+
+                self.inner.write(|inner| {
+                    if let Some(x) = &mut inner.drivers[i] {
+                        x.state = state;
+                        x.init_time += init_time;
+                    }
+
+                    if state == DriverState::Ready {
+                        inner.init_order[inner.num_ready] = i;
+                        inner.num_ready += 1;
+                    }
+                });
+            }
+
+            if !progress {
+                break;
+            }
+        }
+    }
+
+    /// Let the drivers that are ready register and enable their IRQ handlers. Failures are logged.
+    pub fn register_and_enable_irq_handlers(&self) {
+        self.inner.read(|inner| {
+            for driver in inner.iter().filter(|x| x.state == DriverState::Ready) {
+                if let Err(x) = driver
+                    .descriptor
+                    .device_driver
+                    .register_and_enable_irq_handler()
+                {
+                    warn!(
+                        "Error registering IRQ handler: {}: {}",
+                        driver.compatible(),
+                        x
+                    );
+                }
+            }
+        })
+    }
+
+    /// Shut down the ready drivers in reverse initialization order. Failures are logged.
+    ///
+    /// # Safety
+    ///
+    /// - See [`interface::DeviceDriver::shutdown()`].
+    pub unsafe fn shutdown_drivers(&self) {
+        self.inner.read(|inner| {
+            for driver in inner.ready_drivers().rev() {
+                if let Err(x) = driver.descriptor.device_driver.shutdown() {
+                    warn!("Error shutting down driver: {}: {}", driver.compatible(), x);
+                }
+            }
+        })
+    }
+
+    /// Suspend the ready drivers in reverse initialization order.
+    ///
+    /// If a driver fails to suspend, the drivers that were suspended already are resumed again and
+    /// the error is returned.
+    ///
+    /// # Safety
+    ///
+    /// - See [`interface::DeviceDriver::suspend()`].
+    pub unsafe fn suspend_drivers(&self) -> Result<(), &'static str> {
+        self.inner.read(|inner| {
+            for (num_suspended, driver) in inner.ready_drivers().rev().enumerate() {
+                if let Err(x) = driver.descriptor.device_driver.suspend() {
+                    let suspended = inner.ready_drivers().skip(inner.num_ready - num_suspended);
+
+                    for resumed in suspended {
+                        if let Err(y) = resumed.descriptor.device_driver.resume() {
+                            warn!("Error resuming driver: {}: {}", resumed.compatible(), y);
+                        }
+                    }
+
+                    return Err(x);
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Resume the ready drivers in initialization order. Failures are logged.
+    ///
+    /// # Safety
+    ///
+    /// - See [`interface::DeviceDriver::resume()`].
+    pub unsafe fn resume_drivers(&self) {
+        self.inner.read(|inner| {
+            for driver in inner.ready_drivers() {
+                if let Err(x) = driver.descriptor.device_driver.resume() {
+                    warn!("Error resuming driver: {}: {}", driver.compatible(), x);
+                }
+            }
+        })
+    }
+
+    /// Call `f` for each registered driver, in registration order.
+    pub fn enumerate(&self, mut f: impl FnMut(usize, &RegisteredDriver)) {
+        self.inner.read(|inner| {
+            for (i, driver) in inner.iter().enumerate() {
+                f(i, driver)
+            }
+        })
+    }
+}
+
+/// Return a reference to the global DriverManager.
+pub fn driver_manager() -> &'static DriverManager {
+    &DRIVER_MANAGER
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use test_macros::kernel_test;
+
+    /// Counts calls to `init()` across all test drivers, to record the order of initialization.
+    static INIT_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    /// Counts calls to `suspend()` and `resume()` across all test drivers.
+    static PM_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    struct TestDriver {
+        compatible: &'static str,
+        result: Result<(), &'static str>,
+        suspend_result: Result<(), &'static str>,
+        num_deferrals: AtomicUsize,
+        init_position: AtomicUsize,
+        suspend_position: AtomicUsize,
+        resume_position: AtomicUsize,
+    }
+
+    impl TestDriver {
+        const fn new(
+            compatible: &'static str,
+            result: Result<(), &'static str>,
+            num_deferrals: usize,
+        ) -> Self {
+            Self {
+                compatible,
+                result,
+                suspend_result: Ok(()),
+                num_deferrals: AtomicUsize::new(num_deferrals),
+                init_position: AtomicUsize::new(0),
+                suspend_position: AtomicUsize::new(0),
+                resume_position: AtomicUsize::new(0),
+            }
+        }
+
+        const fn failing_suspend(compatible: &'static str) -> Self {
+            Self {
+                suspend_result: Err("Busy"),
+                ..Self::new(compatible, Ok(()), 0)
+            }
+        }
+    }
+
+    impl interface::DeviceDriver for TestDriver {
+        fn compatible(&self) -> &'static str {
+            self.compatible
+        }
+
+        unsafe fn init(&self) -> Result<(), &'static str> {
+            let position = INIT_COUNTER.fetch_add(1, Ordering::Relaxed) + 1;
+            self.init_position.store(position, Ordering::Relaxed);
+
+            if self.num_deferrals.load(Ordering::Relaxed) > 0 {
+                self.num_deferrals.fetch_sub(1, Ordering::Relaxed);
+                return Err(PROBE_DEFER);
+            }
+
+            self.result
+        }
+
+        unsafe fn suspend(&self) -> Result<(), &'static str> {
+            let position = PM_COUNTER.fetch_add(1, Ordering::Relaxed) + 1;
+            self.suspend_position.store(position, Ordering::Relaxed);
+
+            self.suspend_result
+        }
+
+        unsafe fn resume(&self) -> Result<(), &'static str> {
+            let position = PM_COUNTER.fetch_add(1, Ordering::Relaxed) + 1;
+            self.resume_position.store(position, Ordering::Relaxed);
+
+            Ok(())
+        }
+    }
+
+    static UART: TestDriver = TestDriver::new("uart", Ok(()), 0);
+    static GPIO: TestDriver = TestDriver::new("gpio", Ok(()), 1);
+    static IC: TestDriver = TestDriver::new("ic", Ok(()), 0);
+    static BROKEN: TestDriver = TestDriver::new("broken", Err("Broken"), 0);
+    static NEEDS_BROKEN: TestDriver = TestDriver::new("needs broken", Ok(()), 0);
+    static ORPHAN: TestDriver = TestDriver::new("orphan", Ok(()), 0);
+    static ABSENT: TestDriver = TestDriver::new("absent", Err("No device"), 0);
+
+    fn state_of(manager: &DriverManager, compatible: &str) -> DriverState {
+        let mut state = None;
+        manager.enumerate(|_, x| {
+            if x.compatible() == compatible {
+                state = Some(x.state());
+            }
+        });
+
+        state.unwrap()
+    }
+
+    /// Drivers come up after their dependencies, deferred probes are retried, and failures
+    /// propagate to dependent drivers. Optional drivers fail like any other.
+    #[kernel_test]
+    fn driver_manager_init_order() {
+        static MANAGER: DriverManager = DriverManager::new();
+
+        let descriptors = [
+            DeviceDriverDescriptor::new(&UART, &["gpio", "ic"], None),
+            DeviceDriverDescriptor::new(&GPIO, &["ic"], None),
+            DeviceDriverDescriptor::new(&IC, &[], None),
+            DeviceDriverDescriptor::new(&NEEDS_BROKEN, &["broken"], None),
+            DeviceDriverDescriptor::new(&BROKEN, &[], None),
+            DeviceDriverDescriptor::new(&ORPHAN, &["missing"], None),
+            DeviceDriverDescriptor::new(&ABSENT, &[], None).optional(),
+        ];
+        for descriptor in descriptors {
+            MANAGER.register_driver(descriptor).unwrap();
+        }
+        assert!(MANAGER
+            .register_driver(DeviceDriverDescriptor::new(&IC, &[], None))
+            .is_err());
+
+        unsafe { MANAGER.init_drivers() };
+
+        assert_eq!(state_of(&MANAGER, "ic"), DriverState::Ready);
+        assert_eq!(state_of(&MANAGER, "gpio"), DriverState::Ready);
+        assert_eq!(state_of(&MANAGER, "uart"), DriverState::Ready);
+        assert_eq!(state_of(&MANAGER, "broken"), DriverState::Failed("Broken"));
+        assert_eq!(
+            state_of(&MANAGER, "needs broken"),
+            DriverState::Failed("Dependency failed")
+        );
+        assert_eq!(state_of(&MANAGER, "orphan"), DriverState::Deferred);
+        assert_eq!(
+            state_of(&MANAGER, "absent"),
+            DriverState::Failed("No device")
+        );
+
+        let position = |x: &TestDriver| x.init_position.load(Ordering::Relaxed);
+        assert!(position(&IC) < position(&GPIO));
+        assert!(position(&GPIO) < position(&UART));
+        assert_eq!(position(&NEEDS_BROKEN), 0);
+        assert_eq!(position(&ORPHAN), 0);
+    }
+
+    /// Drivers are suspended in reverse initialization order and resumed in initialization order.
+    /// A failed suspend resumes the drivers that were suspended already.
+    #[kernel_test]
+    fn driver_manager_suspend_resume_order() {
+        static MANAGER: DriverManager = DriverManager::new();
+        static CONSOLE: TestDriver = TestDriver::new("console", Ok(()), 0);
+        static PINMUX: TestDriver = TestDriver::new("pinmux", Ok(()), 0);
+        static INTC: TestDriver = TestDriver::new("intc", Ok(()), 0);
+
+        static ROLLBACK_MANAGER: DriverManager = DriverManager::new();
+        static BUSY: TestDriver = TestDriver::failing_suspend("busy");
+        static USER: TestDriver = TestDriver::new("user", Ok(()), 0);
+
+        MANAGER
+            .register_driver(DeviceDriverDescriptor::new(&CONSOLE, &["pinmux"], None))
+            .unwrap();
+        MANAGER
+            .register_driver(DeviceDriverDescriptor::new(&PINMUX, &["intc"], None))
+            .unwrap();
+        MANAGER
+            .register_driver(DeviceDriverDescriptor::new(&INTC, &[], None))
+            .unwrap();
+
+        unsafe {
+            MANAGER.init_drivers();
+            MANAGER.suspend_drivers().unwrap();
+            MANAGER.resume_drivers();
+        }
+
+        let suspended = |x: &TestDriver| x.suspend_position.load(Ordering::Relaxed);
+        let resumed = |x: &TestDriver| x.resume_position.load(Ordering::Relaxed);
+        assert!(suspended(&CONSOLE) < suspended(&PINMUX));
+        assert!(suspended(&PINMUX) < suspended(&INTC));
+        assert!(suspended(&INTC) < resumed(&INTC));
+        assert!(resumed(&INTC) < resumed(&PINMUX));
+        assert!(resumed(&PINMUX) < resumed(&CONSOLE));
+
+        ROLLBACK_MANAGER
+            .register_driver(DeviceDriverDescriptor::new(&BUSY, &[], None))
+            .unwrap();
+        ROLLBACK_MANAGER
+            .register_driver(DeviceDriverDescriptor::new(&USER, &["busy"], None))
+            .unwrap();
+
+        unsafe {
+            ROLLBACK_MANAGER.init_drivers();
+            assert_eq!(ROLLBACK_MANAGER.suspend_drivers(), Err("Busy"));
+        }
 
-        /// Initialization code that runs after the early print driver init.
-        fn post_early_print_device_driver_init(&self);
+        assert!(suspended(&USER) < suspended(&BUSY));
+        assert!(suspended(&BUSY) < resumed(&USER));
+        assert_eq!(resumed(&BUSY), 0);
     }
 }
@@ -9,6 +9,7 @@
 mod arch_exception;
 
 pub mod asynchronous;
+pub mod fixup;
 
 //--------------------------------------------------------------------------------------------------
 // Architectural Public Reexports
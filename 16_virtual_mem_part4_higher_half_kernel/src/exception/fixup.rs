@@ -0,0 +1,152 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Recoverable synchronous exceptions.
+//!
+//! Instructions that are expected to fault, for example when probing memory that might not be
+//! mapped, are annotated with an entry in the exception table. An entry holds the address of the
+//! instruction and the address of its fixup code. If a synchronous exception is caused by an
+//! annotated instruction, the exception handler does not panic, but resumes execution at the fixup
+//! code, which returns an error to the caller.
+//!
+//! There is no user space yet, so "user" memory in [`copy_from_user()`] and [`copy_to_user()`] is
+//! any memory that is not trusted to be mapped.
+
+#[cfg(target_arch = "aarch64")]
+#[path = "../_arch/aarch64/exception/fixup.rs"]
+mod arch_fixup;
+
+use crate::memory::{Address, Virtual};
+use core::cell::UnsafeCell;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// An exception table entry, as emitted by the arch code.
+#[repr(C)]
+struct ExceptionTableEntry {
+    insn: usize,
+    fixup: usize,
+}
+
+// Symbols from the linker script.
+extern "Rust" {
+    static __ex_table_start: UnsafeCell<()>;
+    static __ex_table_end_exclusive: UnsafeCell<()>;
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn exception_table() -> &'static [ExceptionTableEntry] {
+    unsafe {
+        let start = __ex_table_start.get() as usize;
+        let end_exclusive = __ex_table_end_exclusive.get() as usize;
+        let len = (end_exclusive - start) / core::mem::size_of::<ExceptionTableEntry>();
+
+        core::slice::from_raw_parts(start as *const ExceptionTableEntry, len)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Return the fixup code's address if the instruction at `insn` has an exception table entry.
+pub fn search_exception_table(insn: Address<Virtual>) -> Option<Address<Virtual>> {
+    exception_table()
+        .iter()
+        .find(|entry| entry.insn == insn.as_usize())
+        .map(|entry| Address::new(entry.fixup))
+}
+
+/// Read a 32 bit word from an address that might not be mapped.
+///
+/// Any mapped address is read, including MMIO and memory that is owned by someone else.
+///
+/// # Safety
+///
+/// - Reads from MMIO addresses can have side effects, for example draining a FIFO. If `addr` is
+///   MMIO, the caller must make sure that the read does not interfere with the device's driver.
+pub unsafe fn probe_read(addr: Address<Virtual>) -> Result<u32, &'static str> {
+    if addr.as_usize() % core::mem::size_of::<u32>() != 0 {
+        return Err("Address not 32 bit aligned");
+    }
+
+    arch_fixup::read_u32(addr.as_usize()).ok_or("Address not readable")
+}
+
+/// Fill `dst` from user memory at `src`.
+///
+/// On error, `dst` may be partially filled.
+///
+/// # Safety
+///
+/// - Without a user space, `src` is not checked against any user range. Every mapped address is
+///   read, including MMIO and kernel memory.
+/// - `src` must not be MMIO, where reads can have side effects.
+/// - The caller is responsible for not leaking kernel memory through `dst`.
+pub unsafe fn copy_from_user(dst: &mut [u8], src: Address<Virtual>) -> Result<(), &'static str> {
+    let completed = arch_fixup::copy_bytes(dst.as_mut_ptr() as usize, src.as_usize(), dst.len());
+
+    if !completed {
+        return Err("Source not readable");
+    }
+
+    Ok(())
+}
+
+/// Copy `src` to user memory at `dst`.
+///
+/// On error, `dst` may be partially written.
+///
+/// # Safety
+///
+/// - `dst` must not overlap with memory that is in use by the kernel.
+pub unsafe fn copy_to_user(dst: Address<Virtual>, src: &[u8]) -> Result<(), &'static str> {
+    if !arch_fixup::copy_bytes(dst.as_usize(), src.as_ptr() as usize, src.len()) {
+        return Err("Destination not writable");
+    }
+
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Unmapped address, see the page fault integration test.
+    const UNMAPPED_ADDR: usize = 1024 * 1024 * 1024;
+
+    /// Faulting accesses return an error instead of panicking, and execution continues normally.
+    #[kernel_test]
+    fn faults_are_recovered() {
+        let unmapped = Address::<Virtual>::new(UNMAPPED_ADDR);
+
+        let value: u32 = 0x1234_5678;
+        let value_addr = Address::new(&value as *const _ as usize);
+        let mut buf = [0_u8; 4];
+        let mut dst = [0_u8; 3];
+
+        unsafe {
+            assert!(probe_read(unmapped).is_err());
+            assert_eq!(probe_read(value_addr), Ok(0x1234_5678));
+
+            assert!(copy_from_user(&mut buf, unmapped).is_err());
+            assert!(copy_from_user(&mut buf, value_addr).is_ok());
+
+            assert!(copy_to_user(unmapped, &[1, 2, 3]).is_err());
+            assert!(copy_to_user(Address::new(dst.as_mut_ptr() as usize), &[1, 2, 3]).is_ok());
+        }
+        assert_eq!(u32::from_ne_bytes(buf), 0x1234_5678);
+        assert_eq!(dst, [1, 2, 3]);
+    }
+}
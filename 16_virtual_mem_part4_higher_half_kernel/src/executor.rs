@@ -0,0 +1,550 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Cooperative asynchronous execution.
+//!
+//! A minimal executor for `async` kernel code. There is no heap, so tasks are not owned by the
+//! executor. Instead, they live wherever the spawning code put them (for example, on the stack of a
+//! function that never returns) and are borrowed by the executor for its lifetime.
+//!
+//! Waking a task only sets a bit in a global ready mask. Therefore, wakers can safely be used from
+//! IRQ handlers. When no task is ready, the executor idles the core until the next interrupt.
+//!
+//! Tasks have a priority. Among the ready tasks, the one with the highest priority is polled next.
+//! Tasks of equal priority take turns. A task's priority can temporarily be raised, e.g. by
+//! synchronization primitives that implement priority inheritance.
+//!
+//! Only a single executor may be running at any given time.
+
+use crate::{
+    cpu, exception,
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+type Task<'a> = Pin<&'a mut dyn Future<Output = ()>>;
+
+const NO_TASK: usize = usize::MAX;
+
+/// The number of locks a task can inherit priorities through at the same time.
+const MAX_DONATIONS: usize = 4;
+
+/// A priority that a task inherited through a lock it holds.
+#[derive(Copy, Clone)]
+struct Donation {
+    /// Address of the lock.
+    lock: usize,
+    priority: u8,
+}
+
+type Donations = [Option<Donation>; MAX_DONATIONS];
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The maximum number of tasks an executor can run.
+pub const MAX_TASKS: usize = usize::BITS as usize;
+
+/// The priority of tasks spawned with [`Executor::spawn()`].
+pub const DEFAULT_PRIORITY: u8 = 0;
+
+/// Executes a fixed number of tasks until all of them are finished.
+pub struct Executor<'a, const NUM_TASKS: usize> {
+    tasks: [Option<Task<'a>>; NUM_TASKS],
+}
+
+/// Stores the waker of a task that waits for an event, e.g. an IRQ.
+///
+/// The waiting task registers its waker, and the event source, typically an IRQ handler, calls
+/// [`WakerCell::wake()`].
+pub struct WakerCell {
+    inner: IRQSafeNullLock<Option<Waker>>,
+}
+
+/// Future returned by [`yield_now()`].
+pub struct YieldNow {
+    yielded: bool,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// One bit per task id. A set bit means the task needs to be polled.
+static READY_MASK: AtomicUsize = AtomicUsize::new(0);
+
+static EXECUTOR_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Id of the task that is currently being polled, or `NO_TASK`.
+static CURRENT_TASK: AtomicUsize = AtomicUsize::new(NO_TASK);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const DEFAULT_PRIORITY_INIT: AtomicU8 = AtomicU8::new(DEFAULT_PRIORITY);
+
+/// The priority a task was spawned with.
+static BASE_PRIORITIES: [AtomicU8; MAX_TASKS] = [DEFAULT_PRIORITY_INIT; MAX_TASKS];
+
+/// The priority that is used for scheduling. The maximum of the base priority and the donations.
+static EFFECTIVE_PRIORITIES: [AtomicU8; MAX_TASKS] = [DEFAULT_PRIORITY_INIT; MAX_TASKS];
+
+/// The priorities that each task inherited, per lock.
+static DONATIONS: IRQSafeNullLock<[Donations; MAX_TASKS]> =
+    IRQSafeNullLock::new([[None; MAX_DONATIONS]; MAX_TASKS]);
+
+static TASK_WAKER_VTABLE: RawWakerVTable = RawWakerVTable::new(
+    task_waker_clone,
+    task_waker_wake,
+    task_waker_wake,
+    task_waker_drop,
+);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+unsafe fn task_waker_clone(data: *const ()) -> RawWaker {
+    RawWaker::new(data, &TASK_WAKER_VTABLE)
+}
+
+unsafe fn task_waker_wake(data: *const ()) {
+    READY_MASK.fetch_or(1 << (data as usize), Ordering::Release);
+}
+
+unsafe fn task_waker_drop(_data: *const ()) {}
+
+/// Create the waker for the task with the given id.
+fn task_waker(task_id: usize) -> Waker {
+    let raw_waker = RawWaker::new(task_id as *const (), &TASK_WAKER_VTABLE);
+
+    // Safety: The vtable functions only touch the atomic ready mask.
+    unsafe { Waker::from_raw(raw_waker) }
+}
+
+/// Idle the core until an interrupt arrives, unless a task is ready already.
+///
+/// IRQs are masked while checking the ready mask. Otherwise, an IRQ that wakes a task between the
+/// check and `wfi` would be missed, and the core would sleep although there is work to do. A
+/// pending interrupt ends `wfi` regardless of the mask, and is taken once IRQs are unmasked again.
+fn idle() {
+    exception::asynchronous::exec_with_irq_masked(|| {
+        if READY_MASK.load(Ordering::Acquire) == 0 {
+            cpu::wait_for_interrupt();
+        }
+    });
+}
+
+impl<'a, const NUM_TASKS: usize> Executor<'a, NUM_TASKS> {
+    /// Select the next task to poll from the ready mask.
+    ///
+    /// The search starts right after the previously polled task, so that tasks of equal priority
+    /// take turns.
+    fn next_ready_task(ready: usize, previous_task_id: usize) -> usize {
+        let mut next: Option<(usize, u8)> = None;
+
+        for offset in 1..=NUM_TASKS {
+            let task_id = (previous_task_id + offset) % NUM_TASKS;
+            if ready & (1 << task_id) == 0 {
+                continue;
+            }
+
+            let priority = task_priority(task_id);
+            if next.map_or(true, |(_, p)| priority > p) {
+                next = Some((task_id, priority));
+            }
+        }
+
+        // The caller ensures that at least one of the executor's tasks is ready.
+        next.unwrap().0
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Pin a local future on the stack. The binding is shadowed by a `Pin<&mut _>` to its value.
+///
+/// Stands in for `core::pin::pin!`, which this toolchain does not have yet.
+///
+/// ```ignore
+/// let task = async { /* ... */ };
+/// pin!(task);
+/// executor.spawn(task)?;
+/// ```
+#[macro_export]
+macro_rules! pin {
+    ($($x:ident),+ $(,)?) => {$(
+        let mut $x = $x;
+        // Safety: The value is shadowed and can therefore not be moved anymore.
+        #[allow(unused_mut)]
+        let mut $x = unsafe { core::pin::Pin::new_unchecked(&mut $x) };
+    )+};
+}
+
+impl<'a, const NUM_TASKS: usize> Executor<'a, NUM_TASKS> {
+    /// Create an instance.
+    pub fn new() -> Self {
+        assert!(NUM_TASKS > 0 && NUM_TASKS <= MAX_TASKS);
+
+        Self {
+            tasks: [(); NUM_TASKS].map(|_| None),
+        }
+    }
+
+    /// Add a task with the default priority.
+    ///
+    /// The task is pinned by the caller, typically on its stack with [`pin!`](crate::pin!).
+    pub fn spawn<F: Future<Output = ()> + 'a>(
+        &mut self,
+        task: Pin<&'a mut F>,
+    ) -> Result<(), &'static str> {
+        self.spawn_with_priority(task, DEFAULT_PRIORITY)
+    }
+
+    /// Add each of the pinned tasks with the default priority.
+    pub fn spawn_all<F: Future<Output = ()> + 'a>(
+        &mut self,
+        tasks: Pin<&'a mut [F]>,
+    ) -> Result<(), &'static str> {
+        // Safety: The elements of a pinned slice are pinned as well. None of them is moved.
+        for task in unsafe { tasks.get_unchecked_mut() } {
+            self.spawn(unsafe { Pin::new_unchecked(task) })?;
+        }
+
+        Ok(())
+    }
+
+    /// Add a task with the given priority. Higher values take precedence.
+    pub fn spawn_with_priority<F: Future<Output = ()> + 'a>(
+        &mut self,
+        task: Pin<&'a mut F>,
+        priority: u8,
+    ) -> Result<(), &'static str> {
+        let task_id = match self.tasks.iter().position(|t| t.is_none()) {
+            None => return Err("Executor task slots exhausted"),
+            Some(i) => i,
+        };
+
+        BASE_PRIORITIES[task_id].store(priority, Ordering::Relaxed);
+        EFFECTIVE_PRIORITIES[task_id].store(priority, Ordering::Relaxed);
+        DONATIONS.lock(|donations| donations[task_id] = [None; MAX_DONATIONS]);
+
+        self.tasks[task_id] = Some(task as Task<'a>);
+        READY_MASK.fetch_or(1 << task_id, Ordering::Release);
+
+        Ok(())
+    }
+
+    /// Poll the tasks until all of them are finished.
+    ///
+    /// IRQs must be unmasked for tasks that wait for interrupts to make progress.
+    pub fn run(&mut self) {
+        assert!(
+            !EXECUTOR_RUNNING.swap(true, Ordering::Acquire),
+            "Executor already running"
+        );
+
+        let spawned_mask = usize::MAX >> (MAX_TASKS - NUM_TASKS);
+        let mut task_id = NUM_TASKS - 1;
+
+        while self.tasks.iter().any(Option::is_some) {
+            let ready = READY_MASK.load(Ordering::Acquire) & spawned_mask;
+            if ready == 0 {
+                idle();
+                continue;
+            }
+
+            task_id = Self::next_ready_task(ready, task_id);
+            READY_MASK.fetch_and(!(1 << task_id), Ordering::AcqRel);
+
+            // Wakeups might be left over from tasks that finished already.
+            let slot = &mut self.tasks[task_id];
+            let task = match slot {
+                None => continue,
+                Some(task) => task,
+            };
+
+            let waker = task_waker(task_id);
+            let mut cx = Context::from_waker(&waker);
+
+            CURRENT_TASK.store(task_id, Ordering::Relaxed);
+            let poll = task.as_mut().poll(&mut cx);
+            CURRENT_TASK.store(NO_TASK, Ordering::Relaxed);
+
+            if poll.is_ready() {
+                *slot = None;
+            }
+        }
+
+        EXECUTOR_RUNNING.store(false, Ordering::Release);
+    }
+}
+
+impl<'a, const NUM_TASKS: usize> Default for Executor<'a, NUM_TASKS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WakerCell {
+    /// Create an instance.
+    pub const fn new() -> Self {
+        Self {
+            inner: IRQSafeNullLock::new(None),
+        }
+    }
+
+    /// Store a waker, replacing any previously registered one.
+    pub fn register(&self, waker: &Waker) {
+        self.inner.lock(|inner| match inner {
+            Some(w) if w.will_wake(waker) => (),
+            _ => *inner = Some(waker.clone()),
+        });
+    }
+
+    /// Wake and remove the registered waker, if any.
+    pub fn wake(&self) {
+        if let Some(waker) = self.inner.lock(|inner| inner.take()) {
+            waker.wake();
+        }
+    }
+}
+
+impl Default for WakerCell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.yielded {
+            return Poll::Ready(());
+        }
+
+        self.yielded = true;
+        cx.waker().wake_by_ref();
+
+        Poll::Pending
+    }
+}
+
+/// Return the id of the task that is currently being polled, if any.
+pub fn current_task_id() -> Option<usize> {
+    match CURRENT_TASK.load(Ordering::Relaxed) {
+        NO_TASK => None,
+        task_id => Some(task_id),
+    }
+}
+
+/// Return the priority that is currently used for scheduling the given task.
+pub fn task_priority(task_id: usize) -> u8 {
+    EFFECTIVE_PRIORITIES[task_id].load(Ordering::Relaxed)
+}
+
+/// Raise a task's priority to at least `priority` until [`release_priority()`] is called for the
+/// same `lock`. The lock is identified by its address.
+///
+/// If the task already inherits through too many locks, the donation is added to the last one, so
+/// it may be dropped early.
+pub fn inherit_priority(task_id: usize, lock: usize, priority: u8) {
+    DONATIONS.lock(|donations| {
+        let donations = &mut donations[task_id];
+
+        let slot = match donations
+            .iter()
+            .position(|d| matches!(d, Some(d) if d.lock == lock))
+            .or_else(|| donations.iter().position(|d| d.is_none()))
+        {
+            None => MAX_DONATIONS - 1,
+            Some(i) => i,
+        };
+
+        let donation = donations[slot].get_or_insert(Donation { lock, priority });
+        donation.priority = donation.priority.max(priority);
+
+        EFFECTIVE_PRIORITIES[task_id].fetch_max(priority, Ordering::Relaxed);
+    });
+}
+
+/// Drop the priority a task inherited through `lock`. Priorities inherited through other locks
+/// are kept.
+pub fn release_priority(task_id: usize, lock: usize) {
+    DONATIONS.lock(|donations| {
+        let donations = &mut donations[task_id];
+
+        for slot in donations.iter_mut() {
+            if matches!(slot, Some(d) if d.lock == lock) {
+                *slot = None;
+            }
+        }
+
+        let priority = donations
+            .iter()
+            .flatten()
+            .map(|d| d.priority)
+            .fold(BASE_PRIORITIES[task_id].load(Ordering::Relaxed), u8::max);
+
+        EFFECTIVE_PRIORITIES[task_id].store(priority, Ordering::Relaxed);
+    });
+}
+
+/// Give other tasks a chance to run.
+pub fn yield_now() -> YieldNow {
+    YieldNow { yielded: false }
+}
+
+/// Run a future to completion on the executing core and return its output.
+///
+/// Fails if an executor is running already, e.g. when called from within a task.
+pub fn block_on<F: Future>(future: F) -> Result<F::Output, &'static str> {
+    if EXECUTOR_RUNNING.load(Ordering::Acquire) {
+        return Err("Executor already running");
+    }
+
+    let mut output = None;
+
+    {
+        let task = async {
+            output = Some(future.await);
+        };
+        pin!(task);
+
+        let mut executor = Executor::<1>::new();
+        executor.spawn(task)?;
+        executor.run();
+    }
+
+    Ok(output.unwrap())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+    use test_macros::kernel_test;
+
+    /// Releasing the priority inherited through one lock keeps the ones inherited through others.
+    #[kernel_test]
+    fn priorities_are_inherited_per_lock() {
+        let task_id = MAX_TASKS - 1;
+        let (lock_a, lock_b) = (0x1000, 0x2000);
+        let base = BASE_PRIORITIES[task_id].load(Ordering::Relaxed);
+
+        inherit_priority(task_id, lock_a, base + 3);
+        inherit_priority(task_id, lock_b, base + 2);
+        assert_eq!(task_priority(task_id), base + 3);
+
+        release_priority(task_id, lock_a);
+        assert_eq!(task_priority(task_id), base + 2);
+
+        release_priority(task_id, lock_b);
+        assert_eq!(task_priority(task_id), base);
+    }
+
+    /// block_on() returns the future's output.
+    #[kernel_test]
+    fn block_on_returns_output() {
+        assert_eq!(block_on(async { 42 }), Ok(42));
+    }
+
+    /// block_on() fails inside of a running executor instead of starting another one.
+    #[kernel_test]
+    fn block_on_fails_inside_executor() {
+        let nested = Cell::new(Ok(()));
+
+        let task = async {
+            nested.set(block_on(async {}));
+        };
+        pin!(task);
+
+        let mut executor = Executor::<1>::new();
+        executor.spawn(task).unwrap();
+        executor.run();
+
+        assert!(nested.get().is_err());
+    }
+
+    /// Yielding tasks are polled in turns until all of them are finished.
+    #[kernel_test]
+    fn executor_interleaves_tasks() {
+        let trace = Cell::new(0_u32);
+        let step = |digit: u32| trace.set(trace.get() * 10 + digit);
+
+        let task_a = async {
+            for _ in 0..2 {
+                step(1);
+                yield_now().await;
+            }
+        };
+        let task_b = async {
+            for _ in 0..2 {
+                step(2);
+                yield_now().await;
+            }
+        };
+        pin!(task_a, task_b);
+
+        let mut executor = Executor::<2>::new();
+        executor.spawn(task_a).unwrap();
+        executor.spawn(task_b).unwrap();
+        executor.run();
+
+        assert_eq!(trace.get(), 1212);
+    }
+
+    /// A ready task with higher priority is polled before tasks with lower priority.
+    #[kernel_test]
+    fn executor_prefers_higher_priority() {
+        let trace = Cell::new(0_u32);
+        let step = |digit: u32| trace.set(trace.get() * 10 + digit);
+
+        let low = async {
+            step(1);
+            yield_now().await;
+            step(1);
+        };
+        let high = async {
+            step(2);
+            yield_now().await;
+            step(2);
+        };
+        pin!(low, high);
+
+        let mut executor = Executor::<2>::new();
+        executor.spawn(low).unwrap();
+        executor.spawn_with_priority(high, 1).unwrap();
+        executor.run();
+
+        assert_eq!(trace.get(), 2211);
+    }
+
+    /// Spawning more tasks than there are slots fails.
+    #[kernel_test]
+    fn executor_slots_are_bounded() {
+        let task_a = async {};
+        let task_b = async {};
+        pin!(task_a, task_b);
+
+        let mut executor = Executor::<1>::new();
+        assert!(executor.spawn(task_a).is_ok());
+        assert!(executor.spawn(task_b).is_err());
+    }
+}
@@ -0,0 +1,787 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Flattened device tree.
+//!
+//! A parser for the flattened device tree (FDT) blob that the firmware hands over at boot. Nothing
+//! is allocated: nodes, properties and strings borrow from the blob, and the tree is walked anew
+//! for every lookup.
+//!
+//! # Resources
+//!
+//! - Devicetree Specification, Chapter 5: Flattened Devicetree (DTB) Format. <https://www.devicetree.org/specifications>
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+
+/// Newest layout version that this parser understands. Later versions stay compatible with it, as
+/// long as their `last_comp_version` is not higher.
+const FDT_VERSION: u32 = 17;
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+/// Cell counts that apply if a node does not have `#address-cells` or `#size-cells` properties.
+const DEFAULT_ADDRESS_CELLS: u32 = 2;
+const DEFAULT_SIZE_CELLS: u32 = 1;
+
+/// Addresses and sizes are read into a `u64`, which takes at most two cells. Larger counts in the
+/// blob are rejected.
+const MAX_CELLS: u32 = 2;
+
+/// Tokens of the structure block.
+enum Token<'a> {
+    BeginNode(&'a str),
+    EndNode,
+    Prop(Property<'a>),
+    End,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Size of the header in byte.
+pub const HEADER_SIZE: usize = 40;
+
+/// A validated device tree blob.
+#[derive(Copy, Clone)]
+pub struct Fdt<'a> {
+    blob: &'a [u8],
+    structure: &'a [u8],
+    strings: &'a [u8],
+}
+
+/// A node of the tree.
+#[derive(Copy, Clone)]
+pub struct Node<'a> {
+    fdt: Fdt<'a>,
+    name: &'a str,
+
+    /// Offset into the structure block, right behind the node's name.
+    offset: usize,
+
+    /// The parent's cell counts, which describe the format of this node's `reg`.
+    parent_address_cells: u32,
+    parent_size_cells: u32,
+}
+
+/// A property of a node.
+#[derive(Copy, Clone)]
+pub struct Property<'a> {
+    name: &'a str,
+    value: &'a [u8],
+}
+
+/// Iterator over the properties of a node.
+pub struct PropertyIter<'a> {
+    fdt: Fdt<'a>,
+    offset: usize,
+}
+
+/// Iterator over the children of a node.
+pub struct ChildIter<'a> {
+    fdt: Fdt<'a>,
+    offset: usize,
+    address_cells: u32,
+    size_cells: u32,
+}
+
+/// Iterator over the `(address, size)` pairs of a `reg` property.
+pub struct RegIter<'a> {
+    value: &'a [u8],
+    address_cells: u32,
+    size_cells: u32,
+}
+
+/// Iterator over the strings of a string list property, e.g. `compatible`.
+pub struct StrListIter<'a> {
+    value: &'a [u8],
+}
+
+/// Iterator over the `u32` cells of a property, e.g. `interrupts`.
+pub struct CellIter<'a> {
+    value: &'a [u8],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn read_be32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Read a number that spans up to two cells.
+fn read_cells(data: &[u8], num_cells: u32) -> Option<u64> {
+    match num_cells {
+        0 => Some(0),
+        1 => read_be32(data, 0).map(u64::from),
+        2 => Some((u64::from(read_be32(data, 0)?) << 32) | u64::from(read_be32(data, 4)?)),
+        _ => None,
+    }
+}
+
+/// Read the NUL terminated string at `offset`.
+fn read_str(data: &[u8], offset: usize) -> Option<&str> {
+    let bytes = data.get(offset..)?;
+    let len = bytes.iter().position(|x| *x == 0)?;
+
+    core::str::from_utf8(&bytes[..len]).ok()
+}
+
+const fn align_up_4(value: usize) -> usize {
+    (value + 3) & !3
+}
+
+impl<'a> Fdt<'a> {
+    /// Read the token at `offset`. Returns the token and the offset of the next one.
+    fn next_token(&self, mut offset: usize) -> Option<(Token<'a>, usize)> {
+        loop {
+            let token = read_be32(self.structure, offset)?;
+            offset += 4;
+
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = read_str(self.structure, offset)?;
+
+                    return Some((Token::BeginNode(name), align_up_4(offset + name.len() + 1)));
+                }
+                FDT_END_NODE => return Some((Token::EndNode, offset)),
+                FDT_PROP => {
+                    let len = read_be32(self.structure, offset)? as usize;
+                    let name_offset = read_be32(self.structure, offset + 4)? as usize;
+                    let value_offset = offset + 8;
+
+                    let property = Property {
+                        name: read_str(self.strings, name_offset)?,
+                        value: self
+                            .structure
+                            .get(value_offset..value_offset.checked_add(len)?)?,
+                    };
+
+                    return Some((Token::Prop(property), align_up_4(value_offset + len)));
+                }
+                FDT_NOP => continue,
+                FDT_END => return Some((Token::End, offset)),
+                _ => return None,
+            }
+        }
+    }
+
+    /// Returns the offset behind the end of the node whose contents start at `offset`.
+    fn skip_node(&self, mut offset: usize) -> Option<usize> {
+        let mut depth = 1;
+
+        loop {
+            let (token, next_offset) = self.next_token(offset)?;
+            offset = next_offset;
+
+            match token {
+                Token::BeginNode(_) => depth += 1,
+                Token::EndNode => {
+                    depth -= 1;
+
+                    if depth == 0 {
+                        return Some(offset);
+                    }
+                }
+                Token::Prop(_) => (),
+                Token::End => return None,
+            }
+        }
+    }
+
+    /// Depth-first search below `node`.
+    fn find_below(node: Node<'a>, predicate: &impl Fn(&Node<'a>) -> bool) -> Option<Node<'a>> {
+        for child in node.children() {
+            if predicate(&child) {
+                return Some(child);
+            }
+
+            if let Some(x) = Self::find_below(child, predicate) {
+                return Some(x);
+            }
+        }
+
+        None
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl<'a> Fdt<'a> {
+    /// Validate the header and create an instance.
+    pub fn new(blob: &'a [u8]) -> Result<Self, &'static str> {
+        let header =
+            |index: usize| read_be32(blob, index * 4).ok_or("Device tree header truncated");
+
+        if header(0)? != FDT_MAGIC {
+            return Err("Device tree magic mismatch");
+        }
+
+        let total_size = header(1)? as usize;
+        let structure_offset = header(2)? as usize;
+        let strings_offset = header(3)? as usize;
+        let last_comp_version = header(6)?;
+        let strings_size = header(8)? as usize;
+        let structure_size = header(9)? as usize;
+
+        if last_comp_version > FDT_VERSION {
+            return Err("Device tree version not supported");
+        }
+
+        let blob = blob.get(..total_size).ok_or("Device tree truncated")?;
+        let block = |offset: usize, size: usize| {
+            offset
+                .checked_add(size)
+                .and_then(|end| blob.get(offset..end))
+                .ok_or("Device tree block out of bounds")
+        };
+
+        Ok(Self {
+            blob,
+            structure: block(structure_offset, structure_size)?,
+            strings: block(strings_offset, strings_size)?,
+        })
+    }
+
+    /// Read the total size of a blob from its header, before the rest of the blob is available.
+    pub fn total_size_from_header(header: &[u8]) -> Result<usize, &'static str> {
+        if read_be32(header, 0) != Some(FDT_MAGIC) {
+            return Err("Device tree magic mismatch");
+        }
+
+        read_be32(header, 4)
+            .map(|x| x as usize)
+            .ok_or("Device tree header truncated")
+    }
+
+    /// The total size of the blob in byte.
+    pub fn total_size(&self) -> usize {
+        self.blob.len()
+    }
+
+    /// The root node.
+    pub fn root(&self) -> Option<Node<'a>> {
+        match self.next_token(0)? {
+            (Token::BeginNode(name), offset) => Some(Node {
+                fdt: *self,
+                name,
+                offset,
+                parent_address_cells: DEFAULT_ADDRESS_CELLS,
+                parent_size_cells: DEFAULT_SIZE_CELLS,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Find a node by its absolute path, e.g. `/soc/serial@7e201000`.
+    ///
+    /// Path components without a unit address match regardless of the node's unit address.
+    pub fn find_node(&self, path: &str) -> Option<Node<'a>> {
+        let mut node = self.root()?;
+
+        for component in path.split('/').filter(|x| !x.is_empty()) {
+            node = node.children().find(|x| x.name_matches(component))?;
+        }
+
+        Some(node)
+    }
+
+    /// Find the first node, in depth-first order, that is compatible with `compatible`.
+    pub fn find_compatible(&self, compatible: &str) -> Option<Node<'a>> {
+        Self::find_below(self.root()?, &|x| x.is_compatible(compatible))
+    }
+
+    /// The `/chosen` node, which holds parameters chosen by the firmware, e.g. `bootargs`.
+    pub fn chosen(&self) -> Option<Node<'a>> {
+        self.find_node("/chosen")
+    }
+
+    /// The nodes that describe the physical memory.
+    pub fn memory_nodes(&self) -> impl Iterator<Item = Node<'a>> {
+        self.root()
+            .into_iter()
+            .flat_map(|x| x.children())
+            .filter(|x| x.property_str("device_type") == Some("memory"))
+    }
+}
+
+impl<'a> Node<'a> {
+    /// Returns true if `component` names this node. The unit address is optional.
+    fn name_matches(&self, component: &str) -> bool {
+        if component.contains('@') {
+            return self.name == component;
+        }
+
+        self.name.split('@').next() == Some(component)
+    }
+
+    /// The offset right behind the properties.
+    fn children_offset(&self) -> usize {
+        let mut properties = self.properties();
+        while properties.next().is_some() {}
+
+        properties.offset
+    }
+
+    /// The name, including the unit address.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// All properties.
+    pub fn properties(&self) -> PropertyIter<'a> {
+        PropertyIter {
+            fdt: self.fdt,
+            offset: self.offset,
+        }
+    }
+
+    /// The property called `name`.
+    pub fn property(&self, name: &str) -> Option<Property<'a>> {
+        self.properties().find(|x| x.name == name)
+    }
+
+    /// The value of the string property called `name`.
+    pub fn property_str(&self, name: &str) -> Option<&'a str> {
+        self.property(name)?.as_str()
+    }
+
+    /// The child nodes.
+    pub fn children(&self) -> ChildIter<'a> {
+        ChildIter {
+            fdt: self.fdt,
+            offset: self.children_offset(),
+            address_cells: self.address_cells(),
+            size_cells: self.size_cells(),
+        }
+    }
+
+    /// The number of cells of addresses in the children's `reg`.
+    pub fn address_cells(&self) -> u32 {
+        self.property("#address-cells")
+            .and_then(|x| x.as_u32())
+            .unwrap_or(DEFAULT_ADDRESS_CELLS)
+    }
+
+    /// The number of cells of sizes in the children's `reg`.
+    pub fn size_cells(&self) -> u32 {
+        self.property("#size-cells")
+            .and_then(|x| x.as_u32())
+            .unwrap_or(DEFAULT_SIZE_CELLS)
+    }
+
+    /// The entries of the `compatible` property, most specific first.
+    pub fn compatible(&self) -> StrListIter<'a> {
+        StrListIter {
+            value: self.property("compatible").map_or(&[], |x| x.value),
+        }
+    }
+
+    /// Returns true if any entry of the `compatible` property equals `compatible`.
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.compatible().any(|x| x == compatible)
+    }
+
+    /// The `(address, size)` pairs of the `reg` property, in the address space of the parent.
+    pub fn reg(&self) -> Option<RegIter<'a>> {
+        Some(RegIter {
+            value: self.property("reg")?.value,
+            address_cells: self.parent_address_cells,
+            size_cells: self.parent_size_cells,
+        })
+    }
+
+    /// The cells of the `interrupts` property. Their format is defined by the interrupt parent.
+    pub fn interrupts(&self) -> Option<CellIter<'a>> {
+        Some(self.property("interrupts")?.as_cells())
+    }
+
+    /// Translate an address of a child's `reg` to the address space of this node's parent, using
+    /// this node's `ranges`.
+    pub fn translate_child_address(&self, child_addr: u64) -> Option<u64> {
+        let ranges = self.property("ranges")?.value;
+
+        // An empty property means identity mapping.
+        if ranges.is_empty() {
+            return Some(child_addr);
+        }
+
+        let child_address_cells = self.address_cells();
+        let size_cells = self.size_cells();
+        if [child_address_cells, self.parent_address_cells, size_cells]
+            .iter()
+            .any(|x| *x > MAX_CELLS)
+        {
+            return None;
+        }
+
+        let entry_size =
+            ((child_address_cells + self.parent_address_cells + size_cells) * 4) as usize;
+
+        if entry_size == 0 {
+            return None;
+        }
+
+        for entry in ranges.chunks_exact(entry_size) {
+            let parent_entry = &entry[(child_address_cells * 4) as usize..];
+            let size_entry = &parent_entry[(self.parent_address_cells * 4) as usize..];
+
+            let child_base = read_cells(entry, child_address_cells)?;
+            let parent_base = read_cells(parent_entry, self.parent_address_cells)?;
+            let size = read_cells(size_entry, size_cells)?;
+
+            if child_addr >= child_base && child_addr - child_base < size {
+                return parent_base.checked_add(child_addr - child_base);
+            }
+        }
+
+        None
+    }
+}
+
+impl<'a> Property<'a> {
+    /// The name.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// The raw value.
+    pub fn value(&self) -> &'a [u8] {
+        self.value
+    }
+
+    /// The value as a single cell.
+    pub fn as_u32(&self) -> Option<u32> {
+        if self.value.len() != 4 {
+            return None;
+        }
+
+        read_be32(self.value, 0)
+    }
+
+    /// The value as one or two cells.
+    pub fn as_u64(&self) -> Option<u64> {
+        read_cells(self.value, (self.value.len() / 4) as u32).filter(|_| self.value.len() % 4 == 0)
+    }
+
+    /// The value as a string.
+    pub fn as_str(&self) -> Option<&'a str> {
+        match self.value.split_last() {
+            Some((0, x)) => core::str::from_utf8(x).ok(),
+            _ => None,
+        }
+    }
+
+    /// The value as a list of strings.
+    pub fn as_str_list(&self) -> StrListIter<'a> {
+        StrListIter { value: self.value }
+    }
+
+    /// The value as a list of cells.
+    pub fn as_cells(&self) -> CellIter<'a> {
+        CellIter { value: self.value }
+    }
+}
+
+impl<'a> Iterator for PropertyIter<'a> {
+    type Item = Property<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.fdt.next_token(self.offset)? {
+            (Token::Prop(x), next_offset) => {
+                self.offset = next_offset;
+                Some(x)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<'a> Iterator for ChildIter<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (token, next_offset) = self.fdt.next_token(self.offset)?;
+
+            match token {
+                Token::BeginNode(name) => {
+                    self.offset = self.fdt.skip_node(next_offset)?;
+
+                    return Some(Node {
+                        fdt: self.fdt,
+                        name,
+                        offset: next_offset,
+                        parent_address_cells: self.address_cells,
+                        parent_size_cells: self.size_cells,
+                    });
+                }
+                // Properties after child nodes are not allowed, but harmless.
+                Token::Prop(_) => self.offset = next_offset,
+                Token::EndNode | Token::End => return None,
+            }
+        }
+    }
+}
+
+impl Iterator for RegIter<'_> {
+    type Item = (u64, u64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.address_cells > MAX_CELLS || self.size_cells > MAX_CELLS {
+            return None;
+        }
+
+        let address_size = (self.address_cells * 4) as usize;
+        let entry_size = address_size + (self.size_cells * 4) as usize;
+
+        if entry_size == 0 || self.value.len() < entry_size {
+            return None;
+        }
+
+        let address = read_cells(self.value, self.address_cells)?;
+        let size = read_cells(&self.value[address_size..], self.size_cells)?;
+        self.value = &self.value[entry_size..];
+
+        Some((address, size))
+    }
+}
+
+impl<'a> Iterator for StrListIter<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let s = read_str(self.value, 0)?;
+        self.value = &self.value[s.len() + 1..];
+
+        Some(s)
+    }
+}
+
+impl Iterator for CellIter<'_> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cell = read_be32(self.value, 0)?;
+        self.value = &self.value[4..];
+
+        Some(cell)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Compiled from:
+    ///
+    /// ```text
+    /// / {
+    ///     #address-cells = <1>;
+    ///     #size-cells = <1>;
+    ///     compatible = "raspberrypi,3-model-b", "brcm,bcm2837";
+    ///
+    ///     chosen {
+    ///         bootargs = "console=serial0 loglevel=3";
+    ///     };
+    ///
+    ///     memory@0 {
+    ///         device_type = "memory";
+    ///         reg = <0x0 0x3b400000>;
+    ///     };
+    ///
+    ///     soc {
+    ///         compatible = "simple-bus";
+    ///         #address-cells = <1>;
+    ///         #size-cells = <1>;
+    ///         ranges = <0x7e000000 0x3f000000 0x01000000>, <0x40000000 0x40000000 0x1000>;
+    ///
+    ///         gpio@7e200000 {
+    ///             compatible = "brcm,bcm2835-gpio";
+    ///             reg = <0x7e200000 0xb4>;
+    ///         };
+    ///
+    ///         serial@7e201000 {
+    ///             compatible = "arm,pl011", "arm,primecell";
+    ///             reg = <0x7e201000 0x200>;
+    ///             interrupts = <2 25>;
+    ///         };
+    ///     };
+    /// };
+    /// ```
+    ///
+    /// A NOP token is placed between `chosen` and `memory@0`.
+    #[rustfmt::skip]
+    const TEST_DTB: [u8; 636] = [
+        0xd0, 0x0d, 0xfe, 0xed, 0x00, 0x00, 0x02, 0x7c, 0x00, 0x00, 0x00, 0x38,
+        0x00, 0x00, 0x02, 0x28, 0x00, 0x00, 0x00, 0x28, 0x00, 0x00, 0x00, 0x11,
+        0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x51,
+        0x00, 0x00, 0x01, 0xf0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x03,
+        0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x0f, 0x00, 0x00, 0x00, 0x01,
+        0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x23, 0x00, 0x00, 0x00, 0x1b,
+        0x72, 0x61, 0x73, 0x70, 0x62, 0x65, 0x72, 0x72, 0x79, 0x70, 0x69, 0x2c,
+        0x33, 0x2d, 0x6d, 0x6f, 0x64, 0x65, 0x6c, 0x2d, 0x62, 0x00, 0x62, 0x72,
+        0x63, 0x6d, 0x2c, 0x62, 0x63, 0x6d, 0x32, 0x38, 0x33, 0x37, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x01, 0x63, 0x68, 0x6f, 0x73, 0x65, 0x6e, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x1b, 0x00, 0x00, 0x00, 0x26,
+        0x63, 0x6f, 0x6e, 0x73, 0x6f, 0x6c, 0x65, 0x3d, 0x73, 0x65, 0x72, 0x69,
+        0x61, 0x6c, 0x30, 0x20, 0x6c, 0x6f, 0x67, 0x6c, 0x65, 0x76, 0x65, 0x6c,
+        0x3d, 0x33, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x04,
+        0x00, 0x00, 0x00, 0x01, 0x6d, 0x65, 0x6d, 0x6f, 0x72, 0x79, 0x40, 0x30,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x07,
+        0x00, 0x00, 0x00, 0x2f, 0x6d, 0x65, 0x6d, 0x6f, 0x72, 0x79, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x3b,
+        0x00, 0x00, 0x00, 0x00, 0x3b, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+        0x00, 0x00, 0x00, 0x01, 0x73, 0x6f, 0x63, 0x00, 0x00, 0x00, 0x00, 0x03,
+        0x00, 0x00, 0x00, 0x0b, 0x00, 0x00, 0x00, 0x1b, 0x73, 0x69, 0x6d, 0x70,
+        0x6c, 0x65, 0x2d, 0x62, 0x75, 0x73, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03,
+        0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+        0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x0f,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x18,
+        0x00, 0x00, 0x00, 0x3f, 0x7e, 0x00, 0x00, 0x00, 0x3f, 0x00, 0x00, 0x00,
+        0x01, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x01, 0x67, 0x70, 0x69, 0x6f,
+        0x40, 0x37, 0x65, 0x32, 0x30, 0x30, 0x30, 0x30, 0x30, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x12, 0x00, 0x00, 0x00, 0x1b,
+        0x62, 0x72, 0x63, 0x6d, 0x2c, 0x62, 0x63, 0x6d, 0x32, 0x38, 0x33, 0x35,
+        0x2d, 0x67, 0x70, 0x69, 0x6f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03,
+        0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x3b, 0x7e, 0x20, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0xb4, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01,
+        0x73, 0x65, 0x72, 0x69, 0x61, 0x6c, 0x40, 0x37, 0x65, 0x32, 0x30, 0x31,
+        0x30, 0x30, 0x30, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x18,
+        0x00, 0x00, 0x00, 0x1b, 0x61, 0x72, 0x6d, 0x2c, 0x70, 0x6c, 0x30, 0x31,
+        0x31, 0x00, 0x61, 0x72, 0x6d, 0x2c, 0x70, 0x72, 0x69, 0x6d, 0x65, 0x63,
+        0x65, 0x6c, 0x6c, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x08,
+        0x00, 0x00, 0x00, 0x3b, 0x7e, 0x20, 0x10, 0x00, 0x00, 0x00, 0x02, 0x00,
+        0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x46,
+        0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x19, 0x00, 0x00, 0x00, 0x02,
+        0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x09,
+        0x23, 0x61, 0x64, 0x64, 0x72, 0x65, 0x73, 0x73, 0x2d, 0x63, 0x65, 0x6c,
+        0x6c, 0x73, 0x00, 0x23, 0x73, 0x69, 0x7a, 0x65, 0x2d, 0x63, 0x65, 0x6c,
+        0x6c, 0x73, 0x00, 0x63, 0x6f, 0x6d, 0x70, 0x61, 0x74, 0x69, 0x62, 0x6c,
+        0x65, 0x00, 0x62, 0x6f, 0x6f, 0x74, 0x61, 0x72, 0x67, 0x73, 0x00, 0x64,
+        0x65, 0x76, 0x69, 0x63, 0x65, 0x5f, 0x74, 0x79, 0x70, 0x65, 0x00, 0x72,
+        0x65, 0x67, 0x00, 0x72, 0x61, 0x6e, 0x67, 0x65, 0x73, 0x00, 0x69, 0x6e,
+        0x74, 0x65, 0x72, 0x72, 0x75, 0x70, 0x74, 0x73, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    /// Header validation.
+    #[kernel_test]
+    fn fdt_header_validation() {
+        assert!(Fdt::new(&TEST_DTB).is_ok());
+        assert_eq!(Fdt::new(&TEST_DTB).unwrap().total_size(), TEST_DTB.len());
+        assert_eq!(
+            Fdt::total_size_from_header(&TEST_DTB[..HEADER_SIZE]),
+            Ok(TEST_DTB.len())
+        );
+
+        let mut bad_magic = TEST_DTB;
+        bad_magic[0] = 0;
+        assert!(Fdt::new(&bad_magic).is_err());
+
+        assert!(Fdt::new(&TEST_DTB[..TEST_DTB.len() - 4]).is_err());
+        assert!(Fdt::new(&TEST_DTB[..16]).is_err());
+    }
+
+    /// Lookup of nodes and properties.
+    #[kernel_test]
+    fn fdt_nodes_and_properties() {
+        let fdt = Fdt::new(&TEST_DTB).unwrap();
+
+        let root = fdt.root().unwrap();
+        assert_eq!(root.name(), "");
+        assert!(root.is_compatible("brcm,bcm2837"));
+        assert_eq!(root.children().count(), 3);
+
+        assert_eq!(
+            fdt.chosen().unwrap().property_str("bootargs"),
+            Some("console=serial0 loglevel=3")
+        );
+
+        let serial = fdt.find_node("/soc/serial@7e201000").unwrap();
+        assert_eq!(serial.name(), "serial@7e201000");
+        assert!(fdt.find_node("/soc/serial").is_some());
+        assert!(fdt.find_node("/soc/serial@7e201001").is_none());
+        assert!(fdt.find_node("/nonexistent").is_none());
+
+        let mut compatible = serial.compatible();
+        assert_eq!(compatible.next(), Some("arm,pl011"));
+        assert_eq!(compatible.next(), Some("arm,primecell"));
+        assert_eq!(compatible.next(), None);
+
+        let mut interrupts = serial.interrupts().unwrap();
+        assert_eq!(interrupts.next(), Some(2));
+        assert_eq!(interrupts.next(), Some(25));
+        assert_eq!(interrupts.next(), None);
+
+        let gpio = fdt.find_compatible("brcm,bcm2835-gpio").unwrap();
+        assert_eq!(gpio.name(), "gpio@7e200000");
+        assert_eq!(gpio.property("reg").unwrap().value().len(), 8);
+        assert!(fdt.find_compatible("brcm,bcm2711-gpio").is_none());
+    }
+
+    /// `reg`, `ranges` and memory nodes.
+    #[kernel_test]
+    fn fdt_addresses() {
+        let fdt = Fdt::new(&TEST_DTB).unwrap();
+
+        let mut memory_nodes = fdt.memory_nodes();
+        let mut reg = memory_nodes.next().unwrap().reg().unwrap();
+        assert_eq!(reg.next(), Some((0, 0x3b40_0000)));
+        assert_eq!(reg.next(), None);
+        assert!(memory_nodes.next().is_none());
+
+        let soc = fdt.find_node("/soc").unwrap();
+        let (addr, size) = fdt
+            .find_node("/soc/serial")
+            .unwrap()
+            .reg()
+            .unwrap()
+            .next()
+            .unwrap();
+        assert_eq!((addr, size), (0x7e20_1000, 0x200));
+        assert_eq!(soc.translate_child_address(addr), Some(0x3f20_1000));
+        assert_eq!(soc.translate_child_address(0x4000_0000), Some(0x4000_0000));
+        assert_eq!(soc.translate_child_address(0x7f00_0000), None);
+    }
+
+    /// Cell counts that do not fit into a `u64` are rejected instead of overflowing.
+    #[kernel_test]
+    fn fdt_rejects_large_cell_counts() {
+        // The value of the `#address-cells` property of `soc`.
+        const SOC_ADDRESS_CELLS_OFFSET: usize = 308;
+
+        let mut blob = TEST_DTB;
+        assert_eq!(read_be32(&blob, SOC_ADDRESS_CELLS_OFFSET), Some(1));
+        blob[SOC_ADDRESS_CELLS_OFFSET..SOC_ADDRESS_CELLS_OFFSET + 4].copy_from_slice(&[0xff; 4]);
+
+        let fdt = Fdt::new(&blob).unwrap();
+        let soc = fdt.find_node("/soc").unwrap();
+        assert_eq!(soc.address_cells(), u32::MAX);
+        let mut reg = fdt.find_node("/soc/serial").unwrap().reg().unwrap();
+        assert!(reg.next().is_none());
+        assert_eq!(soc.translate_child_address(0x7e20_1000), None);
+    }
+}
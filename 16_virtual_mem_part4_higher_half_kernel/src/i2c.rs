@@ -0,0 +1,51 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Inter-Integrated Circuit (I2C) bus.
+//!
+//! Devices are addressed with 7 bit addresses. A device that does not acknowledge its address or
+//! data fails the transaction with [`NACK_ERROR`], e.g. when probing for a device that is absent.
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// I2C interfaces.
+pub mod interface {
+    use core::time::Duration;
+
+    /// I2C bus controller functions.
+    pub trait Bus {
+        /// Set the SCL frequency.
+        fn set_clock(&self, hz: u32) -> Result<(), &'static str>;
+
+        /// Set how long a transaction may take before it is aborted with [`super::TIMEOUT_ERROR`].
+        fn set_timeout(&self, timeout: Duration);
+
+        /// Write `bytes` to the device at `addr`.
+        fn write(&self, addr: u8, bytes: &[u8]) -> Result<(), &'static str>;
+
+        /// Read `buf.len()` bytes from the device at `addr`.
+        fn read(&self, addr: u8, buf: &mut [u8]) -> Result<(), &'static str>;
+
+        /// Write `bytes` to the device at `addr`, then read `buf.len()` bytes after a repeated
+        /// start. Typically used to read a device register.
+        fn write_read(&self, addr: u8, bytes: &[u8], buf: &mut [u8]) -> Result<(), &'static str>;
+    }
+}
+
+/// The SCL frequency of standard mode.
+pub const STANDARD_MODE_HZ: u32 = 100_000;
+
+/// The SCL frequency of fast mode.
+pub const FAST_MODE_HZ: u32 = 400_000;
+
+/// The highest 7 bit address.
+pub const MAX_ADDR: u8 = 0x7F;
+
+/// The error of a transaction that was not acknowledged.
+pub const NACK_ERROR: &str = "I2C device did not acknowledge";
+
+/// The error of a transaction that did not finish in time.
+pub const TIMEOUT_ERROR: &str = "I2C transaction timed out";
@@ -47,7 +47,7 @@
 //!
 //! Just like processor architecture code, the `BSP` code's module structure tries to mirror the
 //! `kernel`'s subsystem modules, but there is no reexporting this time. That means whatever is
-//! provided must be called starting from the `bsp` namespace, e.g. `bsp::driver::driver_manager()`.
+//! provided must be called starting from the `bsp` namespace, e.g. `bsp::console::console()`.
 //!
 //! ## Kernel interfaces
 //!
@@ -126,18 +126,29 @@
 #![test_runner(crate::test_runner)]
 
 mod panic_wait;
-mod synchronization;
 
+pub mod backtrace;
 pub mod bsp;
+pub mod cmdline;
 pub mod common;
 pub mod console;
 pub mod cpu;
 pub mod driver;
 pub mod exception;
+pub mod executor;
+pub mod fdt;
+pub mod i2c;
+pub mod log;
 pub mod memory;
 pub mod print;
+pub mod random;
+pub mod shell;
+pub mod spi;
 pub mod state;
+pub mod synchronization;
 pub mod time;
+pub mod usb;
+pub mod watchdog;
 
 //--------------------------------------------------------------------------------------------------
 // Public Code
@@ -179,6 +190,8 @@ unsafe fn kernel_init() -> ! {
     exception::handling_init();
     memory::mmu::post_enable_init();
     bsp::console::qemu_bring_up_console();
+    log::console_ready();
+    memory::stack::register_bsp_stacks().unwrap();
 
     test_main();
 
@@ -0,0 +1,460 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Kernel logging.
+//!
+//! Records are emitted with the `error!`, `warn!`, `info!`, `debug!` and `trace!` macros. Each
+//! record passes two filters:
+//!
+//! 1. A compile-time filter, see [`STATIC_MAX_LEVEL`]. Records rejected here are compiled out.
+//! 2. A runtime filter, see [`set_max_level()`] and [`set_module_level()`].
+//!
+//! Records that pass are stored in an in-memory ring buffer, the kernel log, and printed to the
+//! console. Until the console is ready, records are only stored. They are replayed once the kernel
+//! calls [`console_ready()`].
+
+use crate::{
+    cmdline, console, kernel_param, print, println,
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+    time::{self, interface::TimeManager},
+};
+use core::{
+    fmt,
+    str::FromStr,
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Per-module overrides of [`STATIC_MAX_LEVEL`], matched by module path prefix.
+///
+/// The longest matching prefix wins. For example, `("libkernel::executor", Level::Info)` compiles
+/// out debug and trace records of the executor.
+const STATIC_MODULE_LEVELS: &[(&str, Level)] = &[];
+
+/// The runtime max level after boot.
+const DEFAULT_MAX_LEVEL: Level = Level::Info;
+
+const KERNEL_LOG_SIZE: usize = 16 * 1024;
+
+const MAX_MODULE_FILTERS: usize = 8;
+const MAX_MODULE_PATH_LEN: usize = 64;
+
+/// Runtime override of the max level for a module and its children.
+#[derive(Copy, Clone)]
+struct ModuleFilter {
+    path: [u8; MAX_MODULE_PATH_LEN],
+    path_len: usize,
+    level: Level,
+}
+
+/// Byte ring buffer holding newline-terminated records.
+///
+/// Positions are absolute byte offsets since the buffer was created. When it runs full, the oldest
+/// complete record is dropped.
+struct LogBuffer<const N: usize> {
+    buf: [u8; N],
+
+    /// Position of the oldest stored byte.
+    start: usize,
+
+    /// Position after the newest stored byte.
+    end: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Log levels, from most to least severe.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+/// Records above this level are compiled out, unless overridden per module.
+pub const STATIC_MAX_LEVEL: Level = Level::Trace;
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(DEFAULT_MAX_LEVEL as u8);
+
+static MODULE_FILTERS: IRQSafeNullLock<[Option<ModuleFilter>; MAX_MODULE_FILTERS]> =
+    IRQSafeNullLock::new([None; MAX_MODULE_FILTERS]);
+
+static KERNEL_LOG: IRQSafeNullLock<LogBuffer<KERNEL_LOG_SIZE>> =
+    IRQSafeNullLock::new(LogBuffer::new());
+
+static CONSOLE_IS_READY: AtomicBool = AtomicBool::new(false);
+
+kernel_param! {
+    /// `loglevel=<level>` sets the global runtime max level at boot.
+    static LOG_LEVEL: Level = DEFAULT_MAX_LEVEL, name = "loglevel", on_set = set_max_level;
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Return true if `module_path` is `prefix` or one of its children.
+const fn module_matches(prefix: &[u8], module_path: &[u8]) -> bool {
+    if prefix.len() > module_path.len() {
+        return false;
+    }
+
+    let mut i = 0;
+    while i < prefix.len() {
+        if prefix[i] != module_path[i] {
+            return false;
+        }
+        i += 1;
+    }
+
+    if prefix.len() == module_path.len() {
+        return true;
+    }
+
+    // Only match at a path separator, so that `foo` does not match `foobar`.
+    module_path.len() > prefix.len() + 1
+        && module_path[prefix.len()] == b':'
+        && module_path[prefix.len() + 1] == b':'
+}
+
+impl Level {
+    const fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Level::Error,
+            2 => Level::Warn,
+            3 => Level::Info,
+            4 => Level::Debug,
+            _ => Level::Trace,
+        }
+    }
+
+    /// The tag in front of the timestamp. Info records have none.
+    fn tag(self) -> char {
+        match self {
+            Level::Error => 'E',
+            Level::Warn => 'W',
+            Level::Info => ' ',
+            Level::Debug => 'D',
+            Level::Trace => 'T',
+        }
+    }
+}
+
+impl ModuleFilter {
+    fn path(&self) -> &[u8] {
+        &self.path[..self.path_len]
+    }
+}
+
+impl<const N: usize> LogBuffer<N> {
+    const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            start: 0,
+            end: 0,
+        }
+    }
+
+    fn drop_oldest_record(&mut self) {
+        while self.start != self.end {
+            let c = self.buf[self.start % N];
+            self.start += 1;
+
+            if c == b'\n' {
+                break;
+            }
+        }
+    }
+
+    fn push(&mut self, c: u8) {
+        if self.end - self.start == N {
+            self.drop_oldest_record();
+        }
+
+        self.buf[self.end % N] = c;
+        self.end += 1;
+    }
+
+    /// Copy stored bytes, starting at `pos`, into `out`.
+    ///
+    /// If `pos` was already dropped, reading starts at the oldest stored byte instead. Returns the
+    /// position of the first copied byte and the number of copied bytes.
+    fn read(&self, pos: usize, out: &mut [u8]) -> (usize, usize) {
+        let pos = pos.max(self.start);
+        let num_bytes = out.len().min(self.end.saturating_sub(pos));
+
+        for (i, c) in out[..num_bytes].iter_mut().enumerate() {
+            *c = self.buf[(pos + i) % N];
+        }
+
+        (pos, num_bytes)
+    }
+}
+
+impl<const N: usize> fmt::Write for LogBuffer<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.bytes() {
+            self.push(c);
+        }
+
+        Ok(())
+    }
+}
+
+/// Format a record and pass it to `f`.
+fn with_record(level: Level, args: fmt::Arguments, f: impl FnOnce(fmt::Arguments)) {
+    let timestamp = time::time_manager().uptime();
+    let timestamp_subsec_us = timestamp.subsec_micros();
+
+    f(format_args!(
+        "[{} {:>3}.{:03}{:03}] {}",
+        level.tag(),
+        timestamp.as_secs(),
+        timestamp_subsec_us / 1_000,
+        timestamp_subsec_us % 1_000,
+        args
+    ));
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl FromStr for Level {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err("Unknown log level"),
+        }
+    }
+}
+
+impl cmdline::ParamValue for Level {
+    fn parse_param(value: Option<&str>) -> Result<Self, &'static str> {
+        value.ok_or("Missing value")?.parse()
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// Return true if records of `level` from `module_path` pass the compile-time filter.
+pub const fn static_enabled(level: Level, module_path: &str) -> bool {
+    let mut max_level = STATIC_MAX_LEVEL;
+    let mut best_len = 0;
+
+    let mut i = 0;
+    while i < STATIC_MODULE_LEVELS.len() {
+        let (prefix, prefix_level) = STATIC_MODULE_LEVELS[i];
+
+        if prefix.len() >= best_len && module_matches(prefix.as_bytes(), module_path.as_bytes()) {
+            max_level = prefix_level;
+            best_len = prefix.len();
+        }
+        i += 1;
+    }
+
+    level as u8 <= max_level as u8
+}
+
+/// Return true if records of `level` from `module_path` pass the runtime filter.
+pub fn enabled(level: Level, module_path: &str) -> bool {
+    let module_level = MODULE_FILTERS.lock(|filters| {
+        filters
+            .iter()
+            .flatten()
+            .filter(|f| module_matches(f.path(), module_path.as_bytes()))
+            .max_by_key(|f| f.path_len)
+            .map(|f| f.level)
+    });
+
+    level <= module_level.unwrap_or_else(max_level)
+}
+
+/// The global runtime max level.
+pub fn max_level() -> Level {
+    Level::from_u8(MAX_LEVEL.load(Ordering::Relaxed))
+}
+
+/// Set the global runtime max level.
+pub fn set_max_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Override the runtime max level for a module and its children. `None` removes the override.
+pub fn set_module_level(module_path: &str, level: Option<Level>) -> Result<(), &'static str> {
+    if module_path.len() > MAX_MODULE_PATH_LEN {
+        return Err("Module path too long");
+    }
+
+    MODULE_FILTERS.lock(|filters| {
+        let existing = filters
+            .iter_mut()
+            .find(|slot| matches!(slot, Some(f) if f.path() == module_path.as_bytes()));
+
+        let slot = match (existing, level) {
+            (Some(slot), _) => slot,
+            (None, None) => return Ok(()),
+            (None, Some(_)) => match filters.iter_mut().find(|slot| slot.is_none()) {
+                None => return Err("Storage for module log levels exhausted"),
+                Some(slot) => slot,
+            },
+        };
+
+        *slot = level.map(|level| {
+            let mut path = [0; MAX_MODULE_PATH_LEN];
+            path[..module_path.len()].copy_from_slice(module_path.as_bytes());
+
+            ModuleFilter {
+                path,
+                path_len: module_path.len(),
+                level,
+            }
+        });
+
+        Ok(())
+    })
+}
+
+/// Print the runtime filters.
+pub fn print_levels() {
+    println!("  {:<30} {}", "(default)", max_level());
+
+    MODULE_FILTERS.lock(|filters| {
+        for f in filters.iter().flatten() {
+            let path = core::str::from_utf8(f.path()).unwrap_or_default();
+            println!("  {:<30} {}", path, f.level);
+        }
+    });
+}
+
+/// Store a record in the kernel log and print it, if the console is ready.
+///
+/// Not intended to be called directly. Use the logging macros instead.
+#[doc(hidden)]
+pub fn _log(level: Level, args: fmt::Arguments) {
+    with_record(level, args, |record| {
+        KERNEL_LOG.lock(|log| fmt::Write::write_fmt(log, record).unwrap_or(()));
+
+        if CONSOLE_IS_READY.load(Ordering::Acquire) {
+            print::_print(record);
+        }
+    });
+}
+
+/// Mark the console as ready and replay the records stored so far.
+pub fn console_ready() {
+    if !CONSOLE_IS_READY.swap(true, Ordering::AcqRel) {
+        print_kernel_log();
+    }
+}
+
+/// Print the contents of the kernel log.
+pub fn print_kernel_log() {
+    use console::interface::Write;
+
+    let console = crate::bsp::console::console();
+    let mut chunk = [0_u8; 64];
+    let mut pos = 0;
+
+    // Copy the log out in chunks, so that IRQs are not masked for the whole print.
+    loop {
+        let (chunk_pos, num_bytes) = KERNEL_LOG.lock(|log| log.read(pos, &mut chunk));
+        if num_bytes == 0 {
+            break;
+        }
+
+        // Records are expected to be ASCII.
+        for c in &chunk[..num_bytes] {
+            console.write_char(*c as char);
+        }
+        pos = chunk_pos + num_bytes;
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Module filters match whole path components, and the longest match wins.
+    #[kernel_test]
+    fn log_level_filtering() {
+        assert!(module_matches(b"libkernel", b"libkernel::time"));
+        assert!(module_matches(b"libkernel::time", b"libkernel::time"));
+        assert!(!module_matches(b"libkernel::time", b"libkernel::timer"));
+        assert!(!module_matches(b"libkernel::time", b"libkernel"));
+
+        assert!(enabled(Level::Warn, "libkernel::time"));
+        assert!(!enabled(Level::Debug, "libkernel::time"));
+
+        set_module_level("libkernel", Some(Level::Trace)).unwrap();
+        set_module_level("libkernel::time", Some(Level::Error)).unwrap();
+        assert!(enabled(Level::Trace, "libkernel::memory"));
+        assert!(!enabled(Level::Warn, "libkernel::time::arch_time"));
+        assert!(enabled(Level::Error, "libkernel::time::arch_time"));
+
+        set_module_level("libkernel", None).unwrap();
+        set_module_level("libkernel::time", None).unwrap();
+        assert!(!enabled(Level::Debug, "libkernel::memory"));
+
+        set_max_level(Level::Error);
+        assert!(!enabled(Level::Warn, "libkernel::memory"));
+        set_max_level(DEFAULT_MAX_LEVEL);
+    }
+
+    /// When full, the oldest complete records are dropped.
+    #[kernel_test]
+    fn log_buffer_wraparound() {
+        use fmt::Write;
+
+        let mut log = LogBuffer::<16>::new();
+        for record in ["aaaa\n", "bbbb\n", "cccc\n", "dddd\n"] {
+            log.write_str(record).unwrap();
+        }
+
+        let mut out = [0_u8; 32];
+        let (pos, num_bytes) = log.read(0, &mut out);
+        assert_eq!(pos, 5);
+        assert_eq!(&out[..num_bytes], b"bbbb\ncccc\ndddd\n");
+
+        // Reading continues where the previous read stopped.
+        let (pos, num_bytes) = log.read(12, &mut out[..2]);
+        assert_eq!(pos, 12);
+        assert_eq!(&out[..num_bytes], b"cc");
+    }
+}
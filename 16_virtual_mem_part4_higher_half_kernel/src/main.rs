@@ -11,7 +11,10 @@
 #![no_main]
 #![no_std]
 
-use libkernel::{bsp, cpu, driver, exception, info, memory, state, time, warn};
+use libkernel::{
+    bsp, cmdline, cpu, driver, exception, executor, info, memory, pin, random, shell, state, time,
+    usb, warn,
+};
 
 /// Early init code.
 ///
@@ -23,41 +26,48 @@ use libkernel::{bsp, cpu, driver, exception, info, memory, state, time, warn};
 /// - Printing will not work until the respective driver's MMIO is remapped.
 #[no_mangle]
 unsafe fn kernel_init() -> ! {
-    use driver::interface::DriverManager;
-
     exception::handling_init();
+
+    // Also parses the device tree, which may relocate the drivers' MMIO.
     memory::mmu::post_enable_init();
 
-    // Add the mapping records for the precomputed entries first, so that they appear on the top of
-    // the list.
-    bsp::memory::mmu::kernel_add_mapping_records_for_precomputed();
-
-    // Bring up the drivers needed for printing first.
-    for i in bsp::driver::driver_manager()
-        .early_print_device_drivers()
-        .iter()
-    {
-        // Any encountered errors cannot be printed yet, obviously, so just safely park the CPU.
-        i.init().unwrap_or_else(|_| cpu::wait_forever());
+    cmdline::init(bsp::device_tree::bootargs().unwrap_or_else(cmdline::builtin_cmdline));
+
+    // Stack overflows can only be told apart from other faults for registered stacks.
+    if let Err(x) = memory::stack::register_bsp_stacks() {
+        warn!("Error registering kernel stacks: {}", x);
     }
-    bsp::driver::driver_manager().post_early_print_device_driver_init();
-    // Printing available from here on.
-
-    // Now bring up the remaining drivers.
-    for i in bsp::driver::driver_manager()
-        .non_early_print_device_drivers()
-        .iter()
-    {
-        if let Err(x) = i.init() {
-            panic!("Error loading driver: {}: {}", i.compatible(), x);
-        }
+
+    if let Err(x) = bsp::driver::init() {
+        panic!("Error registering drivers: {}", x);
+    }
+
+    // Bring up the drivers in dependency order. Printing is available once the UART is ready.
+    driver::driver_manager().init_drivers();
+
+    // Seeding draws from the hardware RNG, if there is one.
+    if let Err(x) = random::init() {
+        warn!("{}", x);
+    }
+
+    // Keyboards that are found become a source of console input.
+    if let Err(x) = usb::init() {
+        warn!("USB: {}", x);
+    }
+
+    // All mappings are in place now. None of them may be both writable and executable.
+    if let Err(x) = memory::mmu::kernel_audit_write_xor_execute() {
+        warn!("{}", x);
     }
 
     // Let device drivers register and enable their handlers with the interrupt controller.
-    for i in bsp::driver::driver_manager().all_device_drivers() {
-        if let Err(msg) = i.register_and_enable_irq_handler() {
-            warn!("Error registering IRQ handler: {}", msg);
-        }
+    driver::driver_manager().register_and_enable_irq_handlers();
+    if let Err(msg) = time::register_and_enable_irq_handler() {
+        warn!("Error registering IRQ handler: {}", msg);
+    }
+
+    if let Err(msg) = shell::register_builtin_commands() {
+        warn!("Error registering shell commands: {}", msg);
     }
 
     // Unmask interrupts on the boot CPU core.
@@ -72,12 +82,22 @@ unsafe fn kernel_init() -> ! {
 
 /// The main function running after the early init.
 fn kernel_main() -> ! {
-    use driver::interface::DriverManager;
     use exception::asynchronous::interface::IRQManager;
+    use time::interface::TimeManager;
 
     info!("{}", libkernel::version());
     info!("Booting on: {}", bsp::board_name());
 
+    match bsp::device_tree::device_tree() {
+        None => info!("Device tree: None, using built-in defaults"),
+        Some(x) => info!("Device tree: {} bytes", x.total_size()),
+    }
+    info!("Command line: {}", cmdline::cmdline());
+    info!(
+        "DRAM: {} MiB",
+        bsp::memory::mmu::phys_direct_map_region().size() / (1024 * 1024)
+    );
+
     info!("MMU online:");
     memory::mmu::kernel_print_mappings();
 
@@ -87,23 +107,40 @@ fn kernel_main() -> ! {
     info!("Exception handling state:");
     exception::asynchronous::print_state();
 
+    let timer = match time::clock_source() {
+        time::ClockSource::Arch => "Architectural",
+        time::ClockSource::Board => "Board",
+    };
     info!(
-        "Architectural timer resolution: {} ns",
+        "{} timer resolution: {} ns",
+        timer,
         time::time_manager().resolution().as_nanos()
     );
 
-    info!("Drivers loaded:");
-    for (i, driver) in bsp::driver::driver_manager()
-        .all_device_drivers()
-        .iter()
-        .enumerate()
-    {
-        info!("      {}. {}", i + 1, driver.compatible());
-    }
+    info!("Drivers:");
+    driver::driver_manager().enumerate(|i, driver| {
+        info!(
+            "      {}. {} [{}, {} us]",
+            i + 1,
+            driver.compatible(),
+            driver.state(),
+            driver.init_time().as_micros()
+        );
+    });
 
     info!("Registered IRQ handlers:");
     bsp::exception::asynchronous::irq_manager().print_handler();
 
-    info!("Echoing input now");
+    info!("Kernel shell ready. Type 'help' for a list of commands.");
+
+    let shell = shell::run();
+    let keyboards = usb::hid::run();
+    pin!(shell, keyboards);
+
+    let mut executor = executor::Executor::<2>::new();
+    executor.spawn(shell).unwrap();
+    executor.spawn(keyboards).unwrap();
+    executor.run();
+
     cpu::wait_forever();
 }
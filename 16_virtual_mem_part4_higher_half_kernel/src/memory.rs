@@ -4,7 +4,10 @@
 
 //! Memory Management.
 
+pub mod cache;
+pub mod dma;
 pub mod mmu;
+pub mod stack;
 
 use crate::{bsp, common};
 use core::{
@@ -83,6 +86,39 @@ impl<ATYPE: AddressType> Address<ATYPE> {
     }
 }
 
+impl Address<Physical> {
+    /// Translate to the corresponding address in the kernel's direct map of DRAM.
+    ///
+    /// The direct map is in place after `mmu::post_enable_init()`.
+    pub fn phys_to_virt(self) -> Result<Address<Virtual>, &'static str> {
+        let phys_direct_map_region = bsp::memory::mmu::phys_direct_map_region();
+
+        if !phys_direct_map_region.contains(self) {
+            return Err("Physical address is not in the direct map");
+        }
+
+        let offset = self.value - phys_direct_map_region.start_addr().value;
+        Ok(bsp::memory::mmu::virt_direct_map_region().start_addr() + offset)
+    }
+}
+
+impl Address<Virtual> {
+    /// Translate an address in the kernel's direct map of DRAM to the physical address.
+    ///
+    /// Other virtual addresses are rejected, use `mmu::try_kernel_virt_addr_to_phys_addr()` for
+    /// those.
+    pub fn virt_to_phys(self) -> Result<Address<Physical>, &'static str> {
+        let virt_direct_map_region = bsp::memory::mmu::virt_direct_map_region();
+
+        if !virt_direct_map_region.contains(self) {
+            return Err("Virtual address is not in the direct map");
+        }
+
+        let offset = self.value - virt_direct_map_region.start_addr().value;
+        Ok(bsp::memory::mmu::phys_direct_map_region().start_addr() + offset)
+    }
+}
+
 impl<ATYPE: AddressType> Add<usize> for Address<ATYPE> {
     type Output = Self;
 
@@ -164,4 +200,28 @@ mod tests {
 
         assert_eq!(addr.offset_into_page(), 100);
     }
+
+    /// The direct map translates in both directions, and maps the same memory as the kernel image.
+    #[kernel_test]
+    fn direct_map_sanity() {
+        static DATA: u64 = 0x1234_5678_9abc_def0;
+
+        let virt_addr = Address::<Virtual>::new(&DATA as *const u64 as usize);
+        let phys_addr = mmu::try_kernel_virt_addr_to_phys_addr(virt_addr).unwrap();
+        let direct_virt_addr = phys_addr.phys_to_virt().unwrap();
+
+        assert_ne!(direct_virt_addr, virt_addr);
+        assert_eq!(direct_virt_addr.virt_to_phys(), Ok(phys_addr));
+        assert_eq!(
+            unsafe { core::ptr::read_volatile(direct_virt_addr.as_usize() as *const u64) },
+            DATA
+        );
+
+        assert!(virt_addr.virt_to_phys().is_err());
+        assert!(bsp::memory::mmu::phys_direct_map_region()
+            .end_exclusive_page_addr()
+            .into_inner()
+            .phys_to_virt()
+            .is_err());
+    }
 }
@@ -0,0 +1,67 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Cache maintenance.
+//!
+//! Needed whenever memory is shared with agents that do not snoop the CPU's caches, e.g. DMA
+//! masters, or when instructions are written as data.
+
+#[cfg(target_arch = "aarch64")]
+#[path = "../_arch/aarch64/memory/cache.rs"]
+mod arch_cache;
+
+//--------------------------------------------------------------------------------------------------
+// Architectural Public Reexports
+//--------------------------------------------------------------------------------------------------
+pub use arch_cache::{
+    clean_and_invalidate_range, clean_range, data_cache_line_size, invalidate_range,
+    sync_instruction_cache,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{Address, Virtual};
+    use test_macros::kernel_test;
+
+    /// The line size read from `CTR_EL0` is sane.
+    #[kernel_test]
+    fn data_cache_line_size_is_sane() {
+        let line_size = data_cache_line_size();
+
+        assert!(line_size.is_power_of_two());
+        assert!((16..=2048).contains(&line_size));
+    }
+
+    /// Invalidating a range keeps dirty data next to it, which shares the range's edge lines.
+    ///
+    /// QEMU does not model caches, so the data survives there either way. On hardware, it only
+    /// survives because the edge lines are cleaned before they are discarded.
+    #[kernel_test]
+    fn invalidate_range_keeps_neighbouring_data() {
+        let line_size = data_cache_line_size();
+        let mut data = [0xa5_u8; 3 * 2048];
+
+        // Two whole lines, with one neighbouring byte each in the edge lines.
+        let start =
+            crate::common::align_up(data.as_ptr() as usize, line_size) - data.as_ptr() as usize;
+        let first = start;
+        let last = start + 2 * line_size - 1;
+        let range_addr = Address::<Virtual>::new(&data[first + 1] as *const _ as usize);
+
+        clean_range(Address::new(data.as_ptr() as usize), data.len());
+        unsafe {
+            core::ptr::write_volatile(&mut data[first], 0x5a);
+            core::ptr::write_volatile(&mut data[last], 0x5a);
+            invalidate_range(range_addr, last - first - 1);
+
+            assert_eq!(core::ptr::read_volatile(&data[first]), 0x5a);
+            assert_eq!(core::ptr::read_volatile(&data[last]), 0x5a);
+        }
+    }
+}
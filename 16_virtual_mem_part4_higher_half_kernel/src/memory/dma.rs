@@ -0,0 +1,276 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! DMA buffers.
+//!
+//! A [`DmaBuffer`] is physically contiguous, page aligned memory that is accessed by the CPU
+//! through the direct map. DMA masters do not snoop the CPU's caches, so the buffer is handed over
+//! explicitly: [`DmaBuffer::give_to_device()`] and [`DeviceOwnedDmaBuffer::take_from_device()`] do
+//! the cache maintenance for the direction of the transfer. While the device owns the buffer, the
+//! CPU cannot access its contents.
+//!
+//! Small structures that are handed over often, like DMA control blocks, are better off in a
+//! buffer from [`DmaBuffer::new_coherent()`]. It is mapped non-cacheable, so that no cache
+//! maintenance is needed.
+
+use crate::{
+    bsp, common,
+    memory::{
+        cache,
+        mmu::{self, MemoryRegion},
+        Address, Physical, Virtual,
+    },
+    warn,
+};
+use core::num::NonZeroUsize;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The direction of a DMA transfer.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DmaDirection {
+    /// The device reads the buffer.
+    ToDevice,
+
+    /// The device writes the buffer.
+    FromDevice,
+
+    /// The device reads and writes the buffer.
+    Bidirectional,
+}
+
+/// A buffer that is owned by the CPU.
+pub struct DmaBuffer {
+    phys_region: MemoryRegion<Physical>,
+    virt_start_addr: Address<Virtual>,
+    size: usize,
+    is_coherent: bool,
+}
+
+/// A buffer that is owned by a device.
+pub struct DeviceOwnedDmaBuffer {
+    buffer: DmaBuffer,
+    direction: DmaDirection,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Return the frames of a buffer to the pool they were allocated from.
+fn free_frames(phys_region: MemoryRegion<Physical>, is_coherent: bool) -> Result<(), &'static str> {
+    match is_coherent {
+        false => mmu::kernel_free_dma_frames(phys_region),
+        true => mmu::kernel_free_dma_coherent_frames(phys_region),
+    }
+}
+
+impl DmaBuffer {
+    fn alloc(size: usize, alignment: usize, is_coherent: bool) -> Result<Self, &'static str> {
+        let page_size = bsp::memory::mmu::KernelGranule::SIZE;
+
+        if !alignment.is_power_of_two() {
+            return Err("Alignment must be a power of two");
+        }
+
+        let num_pages = match NonZeroUsize::new(common::align_up(size, page_size) / page_size) {
+            None => return Err("Requested 0 bytes"),
+            Some(x) => x,
+        };
+
+        let alignment = alignment.max(page_size);
+        let phys_region = match is_coherent {
+            false => mmu::kernel_alloc_dma_frames(num_pages, alignment)?,
+            true => mmu::kernel_alloc_dma_coherent_frames(num_pages, alignment)?,
+        };
+        let virt_start_addr = match phys_region.start_addr().phys_to_virt() {
+            Ok(x) => x,
+            Err(x) => {
+                free_frames(phys_region, is_coherent)?;
+                return Err(x);
+            }
+        };
+
+        unsafe {
+            core::ptr::write_bytes(virt_start_addr.as_usize() as *mut u8, 0, phys_region.size())
+        };
+
+        Ok(Self {
+            phys_region,
+            virt_start_addr,
+            size,
+            is_coherent,
+        })
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl DmaBuffer {
+    /// Allocate a zeroed buffer of `size` bytes, starting at a multiple of `alignment`.
+    ///
+    /// The buffer always spans whole pages, so it never shares a cache line with other data.
+    pub fn new(size: usize, alignment: usize) -> Result<Self, &'static str> {
+        Self::alloc(size, alignment, false)
+    }
+
+    /// Like [`DmaBuffer::new()`], but the buffer is mapped non-cacheable. Handing it over does not
+    /// need cache maintenance, but every CPU access goes to memory.
+    ///
+    /// The non-cacheable frames are a small pool, meant for control structures.
+    pub fn new_coherent(size: usize, alignment: usize) -> Result<Self, &'static str> {
+        Self::alloc(size, alignment, true)
+    }
+
+    /// The size in bytes, as requested.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The physical start address.
+    pub fn phys_start_addr(&self) -> Address<Physical> {
+        self.phys_region.start_addr()
+    }
+
+    /// The contents.
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(self.virt_start_addr.as_usize() as *const u8, self.size)
+        }
+    }
+
+    /// The contents, for writing.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe {
+            core::slice::from_raw_parts_mut(self.virt_start_addr.as_usize() as *mut u8, self.size)
+        }
+    }
+
+    /// Hand the buffer over to a device for a transfer in the given direction.
+    pub fn give_to_device(self, direction: DmaDirection) -> DeviceOwnedDmaBuffer {
+        match direction {
+            _ if self.is_coherent => (),
+            DmaDirection::ToDevice | DmaDirection::Bidirectional => {
+                cache::clean_range(self.virt_start_addr, self.phys_region.size())
+            }
+            // Dirty lines must not be evicted on top of the data the device writes.
+            DmaDirection::FromDevice => {
+                cache::clean_and_invalidate_range(self.virt_start_addr, self.phys_region.size())
+            }
+        }
+
+        DeviceOwnedDmaBuffer {
+            buffer: self,
+            direction,
+        }
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        if let Err(x) = free_frames(self.phys_region, self.is_coherent) {
+            warn!("Failed to free DMA buffer: {}", x);
+        }
+    }
+}
+
+impl DeviceOwnedDmaBuffer {
+    /// The size in bytes.
+    pub fn size(&self) -> usize {
+        self.buffer.size()
+    }
+
+    /// The physical start address, which is handed to the device.
+    pub fn phys_start_addr(&self) -> Address<Physical> {
+        self.buffer.phys_start_addr()
+    }
+
+    /// The direction of the transfer.
+    pub fn direction(&self) -> DmaDirection {
+        self.direction
+    }
+
+    /// Take the buffer back from the device, once the transfer has finished.
+    pub fn take_from_device(self) -> DmaBuffer {
+        match self.direction {
+            _ if self.buffer.is_coherent => (),
+            DmaDirection::ToDevice => (),
+            // Lines might have been fetched speculatively while the device was writing.
+            DmaDirection::FromDevice | DmaDirection::Bidirectional => unsafe {
+                cache::invalidate_range(self.buffer.virt_start_addr, self.buffer.phys_region.size())
+            },
+        }
+
+        self.buffer
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Buffers are zeroed, aligned and disjoint, and keep their contents across a hand over.
+    #[kernel_test]
+    fn dma_buffer_sanity() {
+        let page_size = bsp::memory::mmu::KernelGranule::SIZE;
+
+        let mut a = DmaBuffer::new(100, 1).unwrap();
+        let b = DmaBuffer::new(page_size + 1, 2 * page_size).unwrap();
+
+        assert_eq!(a.size(), 100);
+        assert!(a.as_slice().iter().all(|x| *x == 0));
+        assert!(a.phys_start_addr().is_page_aligned());
+        assert!(common::is_aligned(
+            b.phys_start_addr().as_usize(),
+            2 * page_size
+        ));
+        assert!(
+            a.phys_start_addr().as_usize() + page_size <= b.phys_start_addr().as_usize()
+                || b.phys_start_addr().as_usize() + 2 * page_size <= a.phys_start_addr().as_usize()
+        );
+
+        a.as_mut_slice()[99] = 42;
+        let a = a.give_to_device(DmaDirection::ToDevice);
+        assert_eq!(a.direction(), DmaDirection::ToDevice);
+        let a = a.take_from_device();
+        assert_eq!(a.as_slice()[99], 42);
+
+        assert!(DmaBuffer::new(0, 1).is_err());
+        assert!(DmaBuffer::new(1, 3).is_err());
+    }
+
+    /// Coherent buffers are mapped non-cacheable, and streaming buffers are not.
+    #[kernel_test]
+    fn dma_coherent_buffer_is_non_cacheable() {
+        use crate::memory::mmu::MemAttributes;
+
+        let mem_attributes = |buffer: &DmaBuffer| {
+            mmu::try_kernel_page_attributes(mmu::PageAddress::from(buffer.virt_start_addr))
+                .unwrap()
+                .mem_attributes
+        };
+
+        let mut coherent = DmaBuffer::new_coherent(64, 32).unwrap();
+        let streaming = DmaBuffer::new(64, 32).unwrap();
+
+        assert_eq!(mem_attributes(&coherent), MemAttributes::NonCacheable);
+        assert_eq!(mem_attributes(&streaming), MemAttributes::CacheableDRAM);
+        assert!(coherent.as_slice().iter().all(|x| *x == 0));
+
+        coherent.as_mut_slice()[63] = 42;
+        let coherent = coherent
+            .give_to_device(DmaDirection::Bidirectional)
+            .take_from_device();
+        assert_eq!(coherent.as_slice()[63], 42);
+    }
+}
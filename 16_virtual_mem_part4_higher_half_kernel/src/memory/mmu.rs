@@ -14,13 +14,14 @@ mod translation_table;
 mod types;
 
 use crate::{
-    bsp,
-    memory::{Address, Physical, Virtual},
+    bsp, info,
+    memory::{cache, Address, Physical, Virtual},
     synchronization::{self, interface::Mutex},
     warn,
 };
 use core::{fmt, num::NonZeroUsize};
 
+pub use arch_mmu::Lvl2Granule;
 pub use types::*;
 
 //--------------------------------------------------------------------------------------------------
@@ -88,15 +89,159 @@ use translation_table::interface::TranslationTable;
 
 /// Query the BSP for the reserved virtual addresses for MMIO remapping and initialize the kernel's
 /// MMIO VA allocator with it.
-fn kernel_init_mmio_va_allocator() {
+fn kernel_init_mmio_va_allocator() -> Result<(), &'static str> {
     let region = bsp::memory::mmu::virt_mmio_remap_region();
 
-    alloc::kernel_mmio_va_allocator().lock(|allocator| allocator.initialize(region));
+    alloc::kernel_mmio_va_allocator().lock(|allocator| allocator.initialize(region))
+}
+
+/// Query the BSP for the frames reserved for translation tables that are allocated at runtime, and
+/// initialize the kernel's frame allocator with them.
+fn kernel_init_table_frame_allocator() -> Result<(), &'static str> {
+    let region = bsp::memory::mmu::phys_table_frames_region();
+
+    alloc::kernel_table_frame_allocator().lock(|allocator| allocator.initialize(region))
+}
+
+/// Query the BSP for the frames reserved for DMA buffers, and initialize the kernel's DMA frame
+/// allocator with them.
+fn kernel_init_dma_frame_allocator() -> Result<(), &'static str> {
+    let region = bsp::memory::mmu::phys_dma_frames_region();
+
+    alloc::kernel_dma_frame_allocator().lock(|allocator| allocator.initialize(region))
+}
+
+/// Remap the frames reserved for non-cacheable DMA memory, and initialize the kernel's allocator
+/// for them.
+///
+/// The precomputed tables map the frames as part of the kernel's data segment, which is cacheable.
+/// Lines that were fetched through that mapping are discarded once the frames are non-cacheable.
+fn kernel_init_dma_coherent_frames() -> Result<(), &'static str> {
+    let virt_region = bsp::memory::mmu::virt_dma_coherent_frames_region();
+    let phys_region = bsp::memory::mmu::phys_dma_coherent_frames_region();
+
+    let attr = AttributeFields {
+        mem_attributes: MemAttributes::NonCacheable,
+        acc_perms: AccessPermissions::ReadWrite,
+        execute_never: true,
+    };
+
+    // Break before make, since the cacheability changes. Nothing uses the frames yet.
+    unsafe {
+        bsp::memory::mmu::kernel_translation_tables().write(|t| t.unmap_at(&virt_region))?;
+        kernel_map_at_unchecked("DMA coherent frames", &virt_region, &phys_region, &attr)?;
+    }
+    cache::clean_and_invalidate_range(virt_region.start_addr(), virt_region.size());
+
+    alloc::kernel_dma_coherent_frame_allocator().lock(|allocator| allocator.initialize(phys_region))
+}
+
+/// Map all of DRAM into the kernel's direct map.
+///
+/// The direct map aliases the kernel binary, among others. It is never executable, and the kernel's
+/// code and read-only data are mapped read-only. The non-cacheable DMA frames stay non-cacheable.
+fn kernel_init_direct_map() -> Result<(), &'static str> {
+    let phys_region = bsp::memory::mmu::phys_direct_map_region();
+    let virt_start_page_addr = bsp::memory::mmu::virt_direct_map_region().start_page_addr();
+    let phys_read_only_region = bsp::memory::mmu::phys_kernel_read_only_region();
+    let phys_dma_coherent_region = bsp::memory::mmu::phys_dma_coherent_frames_region();
+
+    let attr = AttributeFields {
+        mem_attributes: MemAttributes::CacheableDRAM,
+        acc_perms: AccessPermissions::ReadWrite,
+        execute_never: true,
+    };
+    let read_only_attr = AttributeFields {
+        acc_perms: AccessPermissions::ReadOnly,
+        ..attr
+    };
+    let non_cacheable_attr = AttributeFields {
+        mem_attributes: MemAttributes::NonCacheable,
+        ..attr
+    };
+
+    // The kernel binary is part of DRAM. Clamping only keeps the parts in order.
+    let start = phys_region.start_addr().as_usize();
+    let end_exclusive = phys_region
+        .end_exclusive_page_addr()
+        .into_inner()
+        .as_usize();
+    let read_only_start = phys_read_only_region
+        .start_addr()
+        .as_usize()
+        .clamp(start, end_exclusive);
+    let read_only_end_exclusive = phys_read_only_region
+        .end_exclusive_page_addr()
+        .into_inner()
+        .as_usize()
+        .clamp(read_only_start, end_exclusive);
+    let dma_coherent_start = phys_dma_coherent_region
+        .start_addr()
+        .as_usize()
+        .clamp(read_only_end_exclusive, end_exclusive);
+    let dma_coherent_end_exclusive = phys_dma_coherent_region
+        .end_exclusive_page_addr()
+        .into_inner()
+        .as_usize()
+        .clamp(dma_coherent_start, end_exclusive);
+
+    let parts = [
+        ("Direct map of DRAM", start, read_only_start, &attr),
+        (
+            "Direct map of the kernel's code and read-only data",
+            read_only_start,
+            read_only_end_exclusive,
+            &read_only_attr,
+        ),
+        (
+            "Direct map of DRAM",
+            read_only_end_exclusive,
+            dma_coherent_start,
+            &attr,
+        ),
+        (
+            "Direct map of the DMA coherent frames",
+            dma_coherent_start,
+            dma_coherent_end_exclusive,
+            &non_cacheable_attr,
+        ),
+        (
+            "Direct map of DRAM",
+            dma_coherent_end_exclusive,
+            end_exclusive,
+            &attr,
+        ),
+    ];
+
+    for (name, part_start, part_end_exclusive, attr) in parts {
+        if part_start == part_end_exclusive {
+            continue;
+        }
+
+        let phys_part = MemoryRegion::new(
+            PageAddress::from(part_start),
+            PageAddress::from(part_end_exclusive),
+        );
+        let virt_part_start = virt_start_page_addr
+            .checked_offset(((part_start - start) / bsp::memory::mmu::KernelGranule::SIZE) as isize)
+            .ok_or("Direct map out of bounds")?;
+        let virt_part = MemoryRegion::new(
+            virt_part_start,
+            virt_part_start
+                .checked_offset(phys_part.num_pages() as isize)
+                .ok_or("Direct map out of bounds")?,
+        );
+
+        unsafe { kernel_map_at_unchecked(name, &virt_part, &phys_part, attr)? };
+    }
+
+    Ok(())
 }
 
 /// Map a region in the kernel's translation tables.
 ///
-/// No input checks done, input is passed through to the architectural implementation.
+/// No input checks done besides enforcing W^X, input is passed through to the architectural
+/// implementation.
 ///
 /// # Safety
 ///
@@ -108,6 +253,10 @@ unsafe fn kernel_map_at_unchecked(
     phys_region: &MemoryRegion<Physical>,
     attr: &AttributeFields,
 ) -> Result<(), &'static str> {
+    if attr.is_writable_and_executable() {
+        return Err("Refusing to map memory as both writable and executable");
+    }
+
     bsp::memory::mmu::kernel_translation_tables()
         .write(|tables| tables.map_at(virt_region, phys_region, attr))?;
 
@@ -116,16 +265,6 @@ unsafe fn kernel_map_at_unchecked(
     Ok(())
 }
 
-/// Try to translate a kernel virtual address to a physical address.
-///
-/// Will only succeed if there exists a valid mapping for the input address.
-fn try_kernel_virt_addr_to_phys_addr(
-    virt_addr: Address<Virtual>,
-) -> Result<Address<Physical>, &'static str> {
-    bsp::memory::mmu::kernel_translation_tables()
-        .read(|tables| tables.try_virt_addr_to_phys_addr(virt_addr))
-}
-
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
@@ -214,7 +353,7 @@ pub unsafe fn kernel_map_mmio(
         let virt_region =
             alloc::kernel_mmio_va_allocator().lock(|allocator| allocator.alloc(num_pages))?;
 
-        kernel_map_at_unchecked(
+        let result = kernel_map_at_unchecked(
             name,
             &virt_region,
             &phys_region,
@@ -223,7 +362,13 @@ pub unsafe fn kernel_map_mmio(
                 acc_perms: AccessPermissions::ReadWrite,
                 execute_never: true,
             },
-        )?;
+        );
+
+        // Give the virtual pages back if they could not be used.
+        if let Err(x) = result {
+            alloc::kernel_mmio_va_allocator().lock(|allocator| allocator.free(virt_region))?;
+            return Err(x);
+        }
 
         virt_region.start_addr()
     };
@@ -231,6 +376,99 @@ pub unsafe fn kernel_map_mmio(
     Ok(virt_addr + offset_into_start_page)
 }
 
+/// Allocate physically contiguous frames for a DMA buffer, starting at a multiple of `alignment`.
+pub fn kernel_alloc_dma_frames(
+    num_pages: NonZeroUsize,
+    alignment: usize,
+) -> Result<MemoryRegion<Physical>, &'static str> {
+    alloc::kernel_dma_frame_allocator()
+        .lock(|allocator| allocator.alloc_aligned(num_pages, alignment))
+}
+
+/// Return frames that were allocated with `kernel_alloc_dma_frames()`.
+pub fn kernel_free_dma_frames(phys_region: MemoryRegion<Physical>) -> Result<(), &'static str> {
+    alloc::kernel_dma_frame_allocator().lock(|allocator| allocator.free(phys_region))
+}
+
+/// Allocate physically contiguous, non-cacheable frames for DMA memory, starting at a multiple of
+/// `alignment`.
+pub fn kernel_alloc_dma_coherent_frames(
+    num_pages: NonZeroUsize,
+    alignment: usize,
+) -> Result<MemoryRegion<Physical>, &'static str> {
+    alloc::kernel_dma_coherent_frame_allocator()
+        .lock(|allocator| allocator.alloc_aligned(num_pages, alignment))
+}
+
+/// Return frames that were allocated with `kernel_alloc_dma_coherent_frames()`.
+pub fn kernel_free_dma_coherent_frames(
+    phys_region: MemoryRegion<Physical>,
+) -> Result<(), &'static str> {
+    alloc::kernel_dma_coherent_frame_allocator().lock(|allocator| allocator.free(phys_region))
+}
+
+/// Copy physical memory into a buffer, before the direct map is in place.
+///
+/// The pages are mapped read-only at their direct map addresses for the duration of the copy. They
+/// must lie inside the region that the direct map will cover.
+///
+/// # Safety
+///
+/// - The physical memory must not be written concurrently.
+pub unsafe fn kernel_copy_from_phys_early(
+    phys_addr: Address<Physical>,
+    buf: &mut [u8],
+) -> Result<(), &'static str> {
+    if buf.is_empty() {
+        return Ok(());
+    }
+
+    let phys_direct_map_region = bsp::memory::mmu::phys_direct_map_region();
+    let phys_end_exclusive = phys_addr
+        .as_usize()
+        .checked_add(buf.len())
+        .ok_or("Physical range overflows")?;
+    if !phys_direct_map_region.contains(phys_addr)
+        || phys_end_exclusive
+            > phys_direct_map_region
+                .end_exclusive_page_addr()
+                .into_inner()
+                .as_usize()
+    {
+        return Err("Physical range is outside of the direct map");
+    }
+
+    let phys_region = MemoryRegion::new(
+        PageAddress::from(phys_addr.align_down_page()),
+        PageAddress::from(Address::<Physical>::new(phys_end_exclusive).align_up_page()),
+    );
+    let virt_start_page_addr = PageAddress::from(phys_region.start_addr().phys_to_virt()?);
+    let virt_region = MemoryRegion::new(
+        virt_start_page_addr,
+        virt_start_page_addr
+            .checked_offset(phys_region.num_pages() as isize)
+            .ok_or("Direct map out of bounds")?,
+    );
+
+    let attr = AttributeFields {
+        mem_attributes: MemAttributes::CacheableDRAM,
+        acc_perms: AccessPermissions::ReadOnly,
+        execute_never: true,
+    };
+
+    let tables = bsp::memory::mmu::kernel_translation_tables();
+    tables.write(|t| t.map_at(&virt_region, &phys_region, &attr))?;
+
+    let virt_addr = phys_addr.phys_to_virt()?;
+    core::ptr::copy_nonoverlapping(
+        virt_addr.as_usize() as *const u8,
+        buf.as_mut_ptr(),
+        buf.len(),
+    );
+
+    tables.write(|t| t.unmap_at(&virt_region))
+}
+
 /// Try to translate a kernel virtual page address to a physical page address.
 ///
 /// Will only succeed if there exists a valid mapping for the input page.
@@ -241,6 +479,35 @@ pub fn try_kernel_virt_page_addr_to_phys_page_addr(
         .read(|tables| tables.try_virt_page_addr_to_phys_page_addr(virt_page_addr))
 }
 
+/// Try to get the size of the memory that is mapped by the descriptor of a kernel page.
+///
+/// Will only succeed if there exists a valid mapping for the input page.
+pub fn try_kernel_descriptor_size(
+    virt_page_addr: PageAddress<Virtual>,
+) -> Result<usize, &'static str> {
+    bsp::memory::mmu::kernel_translation_tables()
+        .read(|tables| tables.try_descriptor_size(virt_page_addr))
+}
+
+/// Try to translate a kernel virtual address to a physical address.
+///
+/// Will only succeed if there exists a valid mapping for the input address.
+pub fn try_kernel_virt_addr_to_phys_addr(
+    virt_addr: Address<Virtual>,
+) -> Result<Address<Physical>, &'static str> {
+    bsp::memory::mmu::kernel_translation_tables()
+        .read(|tables| tables.try_virt_addr_to_phys_addr(virt_addr))
+}
+
+/// Try to find a kernel virtual address that maps the given physical address.
+///
+/// Only mappings that appear in the mapping record are searched.
+pub fn try_kernel_phys_addr_to_virt_addr(
+    phys_addr: Address<Physical>,
+) -> Result<Address<Virtual>, &'static str> {
+    mapping_record::kernel_find_virt_addr(phys_addr).ok_or("Physical address not mapped")
+}
+
 /// Try to get the attributes of a kernel page.
 ///
 /// Will only succeed if there exists a valid mapping for the input page.
@@ -251,6 +518,67 @@ pub fn try_kernel_page_attributes(
         .read(|tables| tables.try_page_attributes(virt_page_addr))
 }
 
+/// Check all valid kernel page descriptors for mappings that are both writable and executable.
+///
+/// Every contiguous range of offending pages is reported. Blocks and the windows of missing tables
+/// are checked as a whole.
+pub fn kernel_audit_write_xor_execute() -> Result<(), &'static str> {
+    let virt_start_addr = usize::MAX - bsp::memory::mmu::KernelVirtAddrSpace::SIZE + 1;
+    let mut num_violations = 0;
+
+    bsp::memory::mmu::kernel_translation_tables().read(|tables| {
+        let mut violation_start = None;
+        let mut next_virt_addr = Some(virt_start_addr);
+
+        while let Some(virt_addr) = next_virt_addr {
+            let virt_page_addr = PageAddress::<Virtual>::from(virt_addr);
+
+            let (violates, size) = match tables.try_page_attributes(virt_page_addr) {
+                Err(_) => (false, tables.try_unmapped_size(virt_page_addr).unwrap()),
+                Ok(attr) => {
+                    let descriptor_size = tables.try_descriptor_size(virt_page_addr).unwrap();
+
+                    (
+                        attr.is_writable_and_executable(),
+                        descriptor_size - (virt_addr % descriptor_size),
+                    )
+                }
+            };
+
+            match (violates, violation_start) {
+                (true, None) => {
+                    violation_start = Some(virt_page_addr);
+                    num_violations += 1;
+                }
+                (false, Some(start)) => {
+                    warn!(
+                        "W^X violation: {} - {}",
+                        start.into_inner(),
+                        virt_page_addr.into_inner()
+                    );
+                    violation_start = None;
+                }
+                _ => (),
+            }
+
+            next_virt_addr = virt_addr.checked_add(size);
+        }
+
+        if let Some(start) = violation_start {
+            warn!(
+                "W^X violation: {} - end of address space",
+                start.into_inner()
+            );
+        }
+    });
+
+    if num_violations != 0 {
+        return Err("Kernel mappings that are both writable and executable found");
+    }
+
+    Ok(())
+}
+
 /// Enable the MMU and data + instruction caching.
 ///
 /// # Safety
@@ -264,11 +592,43 @@ pub unsafe fn enable_mmu_and_caching(
 }
 
 /// Finish initialization of the MMU subsystem.
+///
+/// The kernel's translation tables take over the precomputed tables first. The direct map comes
+/// last, because it allocates its tables from the table frames and is bounded by the device tree.
 pub fn post_enable_init() {
-    kernel_init_mmio_va_allocator();
+    if let Err(x) = bsp::memory::mmu::kernel_init_translation_tables() {
+        panic!("Error adopting the precomputed translation tables: {}", x);
+    }
+
+    // Add the mapping records for the precomputed entries first, so that they appear on the top of
+    // the list.
+    bsp::memory::mmu::kernel_add_mapping_records_for_precomputed();
+
+    if let Err(x) = kernel_init_mmio_va_allocator()
+        .and_then(|_| kernel_init_table_frame_allocator())
+        .and_then(|_| kernel_init_dma_frame_allocator())
+    {
+        panic!("Error initializing the page allocators: {}", x);
+    }
+
+    if let Err(x) = kernel_init_dma_coherent_frames() {
+        panic!("Error remapping the DMA coherent frames: {}", x);
+    }
+
+    // The direct map covers the DRAM that the device tree describes.
+    if let Err(x) = unsafe { bsp::device_tree::init() } {
+        warn!("Error parsing the device tree: {}", x);
+    }
+
+    if let Err(x) = kernel_init_direct_map() {
+        panic!("Error mapping the direct map: {}", x);
+    }
 }
 
 /// Human-readable print of all recorded kernel mappings.
 pub fn kernel_print_mappings() {
-    mapping_record::kernel_print()
+    mapping_record::kernel_print();
+
+    let stats = alloc::kernel_mmio_va_allocator().lock(|allocator| allocator.stats());
+    info!("      MMIO VA allocator: {}", stats);
 }
@@ -6,19 +6,46 @@
 
 use super::MemoryRegion;
 use crate::{
-    memory::{AddressType, Virtual},
+    bsp, common,
+    memory::{AddressType, Physical, Virtual},
     synchronization::IRQSafeNullLock,
-    warn,
 };
-use core::num::NonZeroUsize;
+use core::{fmt, num::NonZeroUsize};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Maximum number of pages an allocator can manage. Covers the 8 MiB MMIO remap window with 4 KiB
+/// pages.
+const MAX_PAGES: usize = 2048;
+
+const BITS_PER_WORD: usize = u64::BITS as usize;
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
-/// A page allocator that can be lazyily initialized.
+/// A page range allocator that can be lazily initialized.
+///
+/// Every page of the pool has a bit that tells whether it is allocated, so the bookkeeping cannot
+/// run out of space when ranges are freed. Free ranges are the runs of clear bits, which makes
+/// adjacent free ranges coalesce on their own.
 pub struct PageAllocator<ATYPE: AddressType> {
     pool: Option<MemoryRegion<ATYPE>>,
+
+    /// One bit per page of the pool, set if the page is allocated.
+    allocated: [u64; MAX_PAGES / BITS_PER_WORD],
+}
+
+/// Usage and fragmentation statistics of a [`PageAllocator`].
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AllocatorStats {
+    pub total_pages: usize,
+    pub free_pages: usize,
+    pub num_free_ranges: usize,
+    pub largest_free_range_pages: usize,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -28,6 +55,83 @@ pub struct PageAllocator<ATYPE: AddressType> {
 static KERNEL_MMIO_VA_ALLOCATOR: IRQSafeNullLock<PageAllocator<Virtual>> =
     IRQSafeNullLock::new(PageAllocator::new());
 
+static KERNEL_TABLE_FRAME_ALLOCATOR: IRQSafeNullLock<PageAllocator<Physical>> =
+    IRQSafeNullLock::new(PageAllocator::new());
+
+static KERNEL_DMA_FRAME_ALLOCATOR: IRQSafeNullLock<PageAllocator<Physical>> =
+    IRQSafeNullLock::new(PageAllocator::new());
+
+static KERNEL_DMA_COHERENT_FRAME_ALLOCATOR: IRQSafeNullLock<PageAllocator<Physical>> =
+    IRQSafeNullLock::new(PageAllocator::new());
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl<ATYPE: AddressType> PageAllocator<ATYPE> {
+    fn num_pages(&self) -> usize {
+        self.pool.map_or(0, |x| x.num_pages())
+    }
+
+    fn is_allocated(&self, index: usize) -> bool {
+        self.allocated[index / BITS_PER_WORD] & (1 << (index % BITS_PER_WORD)) != 0
+    }
+
+    fn set_allocated(&mut self, first: usize, num_pages: usize, allocated: bool) {
+        for index in first..(first + num_pages) {
+            let word = &mut self.allocated[index / BITS_PER_WORD];
+            let bit = 1 << (index % BITS_PER_WORD);
+
+            match allocated {
+                true => *word |= bit,
+                false => *word &= !bit,
+            }
+        }
+    }
+
+    /// The pool's pages from `first` on, up to `end_exclusive`.
+    fn region(&self, first: usize, end_exclusive: usize) -> MemoryRegion<ATYPE> {
+        let start_page_addr = self.pool.unwrap().start_page_addr();
+
+        MemoryRegion::new(
+            start_page_addr.checked_offset(first as isize).unwrap(),
+            start_page_addr
+                .checked_offset(end_exclusive as isize)
+                .unwrap(),
+        )
+    }
+
+    /// The runs of free pages, as `(first, num_pages)`.
+    fn free_ranges(&self) -> impl Iterator<Item = (usize, usize)> + Clone + '_ {
+        let num_pages = self.num_pages();
+        let mut index = 0;
+
+        core::iter::from_fn(move || {
+            while index < num_pages && self.is_allocated(index) {
+                index += 1;
+            }
+
+            let first = index;
+            while index < num_pages && !self.is_allocated(index) {
+                index += 1;
+            }
+
+            (index > first).then(|| (first, index - first))
+        })
+    }
+
+    /// The number of pages that must be skipped at the start of `region` to reach `alignment`.
+    fn num_pages_to_alignment(region: &MemoryRegion<ATYPE>, alignment: usize) -> usize {
+        let misalignment = region.start_addr().as_usize() & (alignment - 1);
+
+        if misalignment == 0 {
+            return 0;
+        }
+
+        (alignment - misalignment) >> bsp::memory::mmu::KernelGranule::SHIFT
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
@@ -37,34 +141,285 @@ pub fn kernel_mmio_va_allocator() -> &'static IRQSafeNullLock<PageAllocator<Virt
     &KERNEL_MMIO_VA_ALLOCATOR
 }
 
+/// Return a reference to the allocator of physical frames for translation tables.
+pub fn kernel_table_frame_allocator() -> &'static IRQSafeNullLock<PageAllocator<Physical>> {
+    &KERNEL_TABLE_FRAME_ALLOCATOR
+}
+
+/// Return a reference to the allocator of physical frames for DMA buffers.
+pub fn kernel_dma_frame_allocator() -> &'static IRQSafeNullLock<PageAllocator<Physical>> {
+    &KERNEL_DMA_FRAME_ALLOCATOR
+}
+
+/// Return a reference to the allocator of non-cacheable physical frames for DMA memory.
+pub fn kernel_dma_coherent_frame_allocator() -> &'static IRQSafeNullLock<PageAllocator<Physical>> {
+    &KERNEL_DMA_COHERENT_FRAME_ALLOCATOR
+}
+
 impl<ATYPE: AddressType> PageAllocator<ATYPE> {
     /// Create an instance.
     pub const fn new() -> Self {
-        Self { pool: None }
+        Self {
+            pool: None,
+            allocated: [0; MAX_PAGES / BITS_PER_WORD],
+        }
     }
 
     /// Initialize the allocator.
-    pub fn initialize(&mut self, pool: MemoryRegion<ATYPE>) {
+    ///
+    /// Fails if the pool has more pages than the allocator can track.
+    pub fn initialize(&mut self, pool: MemoryRegion<ATYPE>) -> Result<(), &'static str> {
         if self.pool.is_some() {
-            warn!("Already initialized");
-            return;
+            return Err("Page allocator already initialized");
+        }
+
+        if pool.num_pages() > MAX_PAGES {
+            return Err("Page allocator pool too large");
         }
 
         self.pool = Some(pool);
+
+        Ok(())
     }
 
     /// Allocate a number of pages.
     pub fn alloc(
         &mut self,
         num_requested_pages: NonZeroUsize,
+    ) -> Result<MemoryRegion<ATYPE>, &'static str> {
+        self.alloc_aligned(num_requested_pages, bsp::memory::mmu::KernelGranule::SIZE)
+    }
+
+    /// Allocate a number of pages, starting at an address that is a multiple of `alignment`.
+    ///
+    /// Useful for large mappings that shall be backed by blocks. The lowest address that fits is
+    /// used.
+    pub fn alloc_aligned(
+        &mut self,
+        num_requested_pages: NonZeroUsize,
+        alignment: usize,
     ) -> Result<MemoryRegion<ATYPE>, &'static str> {
         if self.pool.is_none() {
             return Err("Allocator not initialized");
         }
 
-        self.pool
-            .as_mut()
-            .unwrap()
-            .take_first_n_pages(num_requested_pages)
+        if !alignment.is_power_of_two()
+            || !common::is_aligned(alignment, bsp::memory::mmu::KernelGranule::SIZE)
+        {
+            return Err("Alignment must be a power of two multiple of the page size");
+        }
+
+        let first = self
+            .free_ranges()
+            .find_map(|(range_first, len)| {
+                let range = self.region(range_first, range_first + len);
+                let first = range_first + Self::num_pages_to_alignment(&range, alignment);
+
+                match first.checked_add(num_requested_pages.get()) {
+                    Some(x) if x <= range_first + len => Some(first),
+                    _ => None,
+                }
+            })
+            .ok_or("Page allocator exhausted: No free range is large enough")?;
+
+        self.set_allocated(first, num_requested_pages.get(), true);
+
+        Ok(self.region(first, first + num_requested_pages.get()))
+    }
+
+    /// Return a previously allocated region to the allocator.
+    pub fn free(&mut self, region: MemoryRegion<ATYPE>) -> Result<(), &'static str> {
+        let pool = match self.pool {
+            None => return Err("Allocator not initialized"),
+            Some(x) => x,
+        };
+
+        if region.num_pages() == 0 {
+            return Ok(());
+        }
+
+        if region.start_page_addr() < pool.start_page_addr()
+            || region.end_exclusive_page_addr() > pool.end_exclusive_page_addr()
+        {
+            return Err("Freed region is not part of the allocator's pool");
+        }
+
+        let first = (region.start_addr().as_usize() - pool.start_addr().as_usize())
+            >> bsp::memory::mmu::KernelGranule::SHIFT;
+
+        if (first..(first + region.num_pages())).any(|i| !self.is_allocated(i)) {
+            return Err("Freed region is already free");
+        }
+
+        self.set_allocated(first, region.num_pages(), false);
+
+        Ok(())
+    }
+
+    /// Return usage and fragmentation statistics.
+    pub fn stats(&self) -> AllocatorStats {
+        let free_ranges = self.free_ranges();
+
+        AllocatorStats {
+            total_pages: self.num_pages(),
+            free_pages: free_ranges.clone().map(|(_, len)| len).sum(),
+            num_free_ranges: free_ranges.clone().count(),
+            largest_free_range_pages: free_ranges.map(|(_, len)| len).max().unwrap_or(0),
+        }
+    }
+}
+
+impl AllocatorStats {
+    /// The share of free pages that are not part of the largest free range, in percent.
+    ///
+    /// Zero means that all free pages can be handed out in a single allocation.
+    pub fn fragmentation_percent(&self) -> usize {
+        if self.free_pages == 0 {
+            return 0;
+        }
+
+        100 - (self.largest_free_range_pages * 100) / self.free_pages
+    }
+}
+
+impl fmt::Display for AllocatorStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{} pages free in {} range(s), largest {} pages, {}% fragmentation",
+            self.free_pages,
+            self.total_pages,
+            self.num_free_ranges,
+            self.largest_free_range_pages,
+            self.fragmentation_percent()
+        )
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{mmu::PageAddress, Physical};
+    use test_macros::kernel_test;
+
+    fn pages(n: usize) -> NonZeroUsize {
+        NonZeroUsize::new(n).unwrap()
+    }
+
+    fn test_allocator(num_pages: usize) -> PageAllocator<Physical> {
+        let start = PageAddress::from(bsp::memory::mmu::KernelGranule::SIZE * 8);
+        let mut allocator = PageAllocator::new();
+
+        allocator
+            .initialize(MemoryRegion::new(
+                start,
+                start.checked_offset(num_pages as isize).unwrap(),
+            ))
+            .unwrap();
+
+        allocator
+    }
+
+    /// Freed regions are coalesced with their neighbors.
+    #[kernel_test]
+    fn alloc_free_coalesce() {
+        let mut allocator = test_allocator(16);
+
+        let a = allocator.alloc(pages(4)).unwrap();
+        let b = allocator.alloc(pages(4)).unwrap();
+        let c = allocator.alloc(pages(4)).unwrap();
+        assert_eq!(a.end_exclusive_page_addr(), b.start_page_addr());
+        assert_eq!(allocator.stats().free_pages, 4);
+
+        assert_eq!(allocator.free(a), Ok(()));
+        assert_eq!(allocator.free(c), Ok(()));
+        assert_eq!(allocator.stats().num_free_ranges, 2);
+        assert!(allocator.stats().fragmentation_percent() > 0);
+        assert!(allocator.free(c).is_err());
+
+        assert_eq!(allocator.free(b), Ok(()));
+        let stats = allocator.stats();
+        assert_eq!(stats.num_free_ranges, 1);
+        assert_eq!(stats.free_pages, 16);
+        assert_eq!(stats.fragmentation_percent(), 0);
+    }
+
+    /// Aligned allocations skip pages, which stay available.
+    #[kernel_test]
+    fn alloc_aligned() {
+        let mut allocator = test_allocator(32);
+        let alignment = bsp::memory::mmu::KernelGranule::SIZE * 16;
+
+        allocator.alloc(pages(1)).unwrap();
+        let aligned = allocator.alloc_aligned(pages(2), alignment).unwrap();
+        assert!(common::is_aligned(
+            aligned.start_addr().as_usize(),
+            alignment
+        ));
+        assert_eq!(allocator.stats().free_pages, 29);
+
+        let skipped = allocator.alloc(pages(1)).unwrap();
+        assert!(skipped.start_page_addr() < aligned.start_page_addr());
+
+        assert!(allocator.alloc_aligned(pages(1), 3).is_err());
+    }
+
+    /// Any number of disjoint free ranges can be tracked, so frees never fail for lack of space.
+    #[kernel_test]
+    fn free_many_disjoint_ranges() {
+        let mut allocator = test_allocator(64);
+
+        let mut all = [allocator.alloc(pages(1)).unwrap(); 64];
+        for region in all.iter_mut().skip(1) {
+            *region = allocator.alloc(pages(1)).unwrap();
+        }
+        assert_eq!(allocator.stats().free_pages, 0);
+
+        for region in all.iter().step_by(2) {
+            assert_eq!(allocator.free(*region), Ok(()));
+        }
+        assert_eq!(allocator.stats().num_free_ranges, 32);
+        assert_eq!(allocator.stats().largest_free_range_pages, 1);
+
+        for region in all.iter().skip(1).step_by(2) {
+            assert_eq!(allocator.free(*region), Ok(()));
+        }
+        assert_eq!(allocator.stats().num_free_ranges, 1);
+        assert_eq!(allocator.stats().free_pages, 64);
+    }
+
+    /// Running out of pages is reported.
+    #[kernel_test]
+    fn alloc_exhausted() {
+        let mut allocator = test_allocator(4);
+
+        assert!(allocator.alloc(pages(5)).is_err());
+        let all = allocator.alloc(pages(4)).unwrap();
+        assert_eq!(allocator.stats().num_free_ranges, 0);
+        assert_eq!(
+            allocator.alloc(pages(1)),
+            Err("Page allocator exhausted: No free range is large enough")
+        );
+
+        assert_eq!(allocator.free(all), Ok(()));
+        assert_eq!(allocator.stats().free_pages, 4);
+    }
+
+    /// A pool with more pages than the allocator can track is rejected, not truncated.
+    #[kernel_test]
+    fn initialize_rejects_oversized_pool() {
+        let start = PageAddress::from(bsp::memory::mmu::KernelGranule::SIZE * 8);
+        let pool = |num_pages: usize| {
+            MemoryRegion::new(start, start.checked_offset(num_pages as isize).unwrap())
+        };
+
+        let mut allocator = PageAllocator::<Physical>::new();
+        assert!(allocator.initialize(pool(MAX_PAGES + 1)).is_err());
+        assert_eq!(allocator.initialize(pool(MAX_PAGES)), Ok(()));
+        assert!(allocator.initialize(pool(1)).is_err());
     }
 }
@@ -6,7 +6,7 @@
 
 use super::{
     AccessPermissions, Address, AttributeFields, MMIODescriptor, MemAttributes, MemoryRegion,
-    Physical, Virtual,
+    PageAddress, Physical, Virtual,
 };
 use crate::{bsp, info, synchronization, synchronization::InitStateLock, warn};
 
@@ -106,6 +106,20 @@ impl MappingRecord {
             })
     }
 
+    fn find_virt_addr(&self, phys_addr: Address<Physical>) -> Option<Address<Virtual>> {
+        self.inner.iter().flatten().find_map(|x| {
+            let offset = phys_addr
+                .as_usize()
+                .checked_sub(x.phys_start_addr.as_usize())?;
+
+            if offset >= x.num_pages * bsp::memory::mmu::KernelGranule::SIZE {
+                return None;
+            }
+
+            Some(x.virt_start_addr + offset)
+        })
+    }
+
     pub fn add(
         &mut self,
         name: &'static str,
@@ -127,13 +141,14 @@ impl MappingRecord {
     pub fn print(&self) {
         const KIB_RSHIFT: u32 = 10; // log2(1024).
         const MIB_RSHIFT: u32 = 20; // log2(1024 * 1024).
+        const GIB_RSHIFT: u32 = 30; // log2(1024 * 1024 * 1024).
 
-        info!("      -------------------------------------------------------------------------------------------------------------------------------------------");
+        info!("      ---------------------------------------------------------------------------------------------------------------------------------------------------");
         info!(
-            "      {:^44}     {:^30}   {:^7}   {:^9}   {:^35}",
-            "Virtual", "Physical", "Size", "Attr", "Entity"
+            "      {:^44}     {:^30}   {:^7}   {:^9}   {:^5}   {:^35}",
+            "Virtual", "Physical", "Size", "Attr", "Desc", "Entity"
         );
-        info!("      -------------------------------------------------------------------------------------------------------------------------------------------");
+        info!("      ---------------------------------------------------------------------------------------------------------------------------------------------------");
 
         for i in self.inner.iter().flatten() {
             let size = i.num_pages * bsp::memory::mmu::KernelGranule::SIZE;
@@ -142,7 +157,9 @@ impl MappingRecord {
             let phys_start = i.phys_start_addr;
             let phys_end_inclusive = phys_start + (size - 1);
 
-            let (size, unit) = if (size >> MIB_RSHIFT) > 0 {
+            let (size, unit) = if (size >> GIB_RSHIFT) > 0 {
+                (size >> GIB_RSHIFT, "GiB")
+            } else if (size >> MIB_RSHIFT) > 0 {
                 (size >> MIB_RSHIFT, "MiB")
             } else if (size >> KIB_RSHIFT) > 0 {
                 (size >> KIB_RSHIFT, "KiB")
@@ -152,6 +169,7 @@ impl MappingRecord {
 
             let attr = match i.attribute_fields.mem_attributes {
                 MemAttributes::CacheableDRAM => "C",
+                MemAttributes::NonCacheable => "NC",
                 MemAttributes::Device => "Dev",
             };
 
@@ -166,9 +184,15 @@ impl MappingRecord {
                 "X"
             };
 
+            let desc = match super::try_kernel_descriptor_size(PageAddress::from(virt_start)) {
+                Ok(desc_size) if desc_size > bsp::memory::mmu::KernelGranule::SIZE => "Block",
+                Ok(_) => "Page",
+                Err(_) => "-",
+            };
+
             info!(
                 "      {}..{} --> {}..{} | \
-                        {: >3} {} | {: <3} {} {: <2} | {}",
+                        {: >3} {} | {: <3} {} {: <2} | {: <5} | {}",
                 virt_start,
                 virt_end_inclusive,
                 phys_start,
@@ -178,20 +202,21 @@ impl MappingRecord {
                 attr,
                 acc_p,
                 xn,
+                desc,
                 i.users[0].unwrap()
             );
 
             for k in i.users[1..].iter() {
                 if let Some(additional_user) = *k {
                     info!(
-                        "                                                                                                            | {}",
+                        "                                                                                                                    | {}",
                         additional_user
                     );
                 }
             }
         }
 
-        info!("      -------------------------------------------------------------------------------------------------------------------------------------------");
+        info!("      ---------------------------------------------------------------------------------------------------------------------------------------------------");
     }
 }
 
@@ -227,6 +252,11 @@ pub fn kernel_find_and_insert_mmio_duplicate(
     })
 }
 
+/// Find the virtual address of a physical address in the recorded kernel mappings.
+pub fn kernel_find_virt_addr(phys_addr: Address<Physical>) -> Option<Address<Virtual>> {
+    KERNEL_MAPPING_RECORD.read(|mr| mr.find_virt_addr(phys_addr))
+}
+
 /// Human-readable print of all recorded kernel mappings.
 pub fn kernel_print() {
     KERNEL_MAPPING_RECORD.read(|mr| mr.print());
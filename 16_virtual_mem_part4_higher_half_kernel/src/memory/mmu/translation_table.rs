@@ -15,7 +15,7 @@ use crate::memory::{Address, Physical, Virtual};
 // Architectural Public Reexports
 //--------------------------------------------------------------------------------------------------
 #[cfg(target_arch = "aarch64")]
-pub use arch_translation_table::FixedSizeTranslationTable;
+pub use arch_translation_table::DynamicTranslationTable;
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -39,6 +39,9 @@ pub mod interface {
 
         /// Map the given virtual memory region to the given physical memory region.
         ///
+        /// Where the alignment of both regions allows it, the implementation may use descriptors
+        /// that map more than one page at once, for example block descriptors.
+        ///
         /// # Safety
         ///
         /// - Using wrong attributes can cause multiple issues of different nature in the system.
@@ -53,6 +56,35 @@ pub mod interface {
             attr: &AttributeFields,
         ) -> Result<(), &'static str>;
 
+        /// Change the attributes of the given, already mapped, virtual memory region.
+        ///
+        /// Descriptors that map more than one page, but only partly overlap with the region, are
+        /// split up first.
+        ///
+        /// # Safety
+        ///
+        /// - Same as `map_at()`.
+        /// - Changing the memory attributes of pages that are in use might require
+        ///   break-before-make, which is not done for individual pages.
+        unsafe fn protect_at(
+            &mut self,
+            virt_region: &MemoryRegion<Virtual>,
+            attr: &AttributeFields,
+        ) -> Result<(), &'static str>;
+
+        /// Remove the mappings of the given, already mapped, virtual memory region.
+        ///
+        /// Descriptors that map more than one page, but only partly overlap with the region, are
+        /// split up first. Implementations may release translation tables that become empty.
+        ///
+        /// # Safety
+        ///
+        /// - The unmapped memory must not be accessed anymore.
+        unsafe fn unmap_at(
+            &mut self,
+            virt_region: &MemoryRegion<Virtual>,
+        ) -> Result<(), &'static str>;
+
         /// Try to translate a virtual page address to a physical page address.
         ///
         /// Will only succeed if there exists a valid mapping for the input page.
@@ -69,6 +101,25 @@ pub mod interface {
             virt_page_addr: PageAddress<Virtual>,
         ) -> Result<AttributeFields, &'static str>;
 
+        /// Try to get the size of the memory that is mapped by the descriptor of a page. That is,
+        /// the page size, or the block size if the page is part of a block.
+        ///
+        /// Will only succeed if there exists a valid mapping for the input page.
+        fn try_descriptor_size(
+            &self,
+            virt_page_addr: PageAddress<Virtual>,
+        ) -> Result<usize, &'static str>;
+
+        /// Try to get the size of the unmapped memory that starts at a page. If a table on the way
+        /// to the page is missing, that is the rest of the memory the table would cover. Otherwise,
+        /// it is the page size.
+        ///
+        /// Will only succeed if the input page is not mapped.
+        fn try_unmapped_size(
+            &self,
+            virt_page_addr: PageAddress<Virtual>,
+        ) -> Result<usize, &'static str>;
+
         /// Try to translate a virtual address to a physical address.
         ///
         /// Will only succeed if there exists a valid mapping for the input address.
@@ -95,8 +146,7 @@ mod tests {
     /// TranslationTable構造体の実装の健全性確認
     #[kernel_test]
     fn translationtable_implementation_sanity() {
-        // This will occupy a lot of space on the stack.
-        let mut tables = MinSizeTranslationTable::new_for_runtime();
+        let mut tables = MinSizeTranslationTable::new();
 
         assert!(tables.init().is_ok());
 
@@ -137,5 +187,12 @@ mod tests {
         let virt_addr = virt_start_page_addr.into_inner() + 0x100;
         let phys_addr = phys_start_page_addr.into_inner() + 0x100;
         assert_eq!(tables.try_virt_addr_to_phys_addr(virt_addr), Ok(phys_addr));
+
+        // Mappings that are both writable and executable are rejected.
+        let wx_attr = AttributeFields {
+            execute_never: false,
+            ..attr
+        };
+        unsafe { assert!(tables.map_at(&virt_region, &phys_region, &wx_attr).is_err()) };
     }
 }
@@ -32,6 +32,7 @@ pub struct MemoryRegion<ATYPE: AddressType> {
 #[derive(Copy, Clone, Debug, PartialOrd, PartialEq)]
 pub enum MemAttributes {
     CacheableDRAM,
+    NonCacheable,
     Device,
 }
 
@@ -270,6 +271,17 @@ impl From<MMIODescriptor> for MemoryRegion<Physical> {
     }
 }
 
+//------------------------------------------------------------------------------
+// AttributeFields
+//------------------------------------------------------------------------------
+
+impl AttributeFields {
+    /// Return true if the attributes allow both writing and executing, which violates W^X.
+    pub fn is_writable_and_executable(&self) -> bool {
+        self.acc_perms == AccessPermissions::ReadWrite && !self.execute_never
+    }
+}
+
 //------------------------------------------------------------------------------
 // MMIODescriptor
 //------------------------------------------------------------------------------
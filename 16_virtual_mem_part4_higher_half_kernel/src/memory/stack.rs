@@ -0,0 +1,181 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Kernel stacks.
+//!
+//! Every kernel stack has an unmapped guard page right below it, so that an overflow causes a page
+//! fault instead of silently corrupting the memory below the stack. Stacks are registered here, so
+//! that the exception handler can tell a stack overflow apart from other faults, and report which
+//! stack overflowed.
+
+use crate::{
+    bsp,
+    memory::{
+        mmu::{self, MemoryRegion},
+        Address, Virtual,
+    },
+    synchronization::{interface::ReadWriteEx, InitStateLock},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Maximum number of registered kernel stacks.
+const MAX_KERNEL_STACKS: usize = 8;
+
+struct KernelStackRegistry {
+    stacks: [Option<KernelStack>; MAX_KERNEL_STACKS],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A kernel stack.
+#[derive(Copy, Clone)]
+pub struct KernelStack {
+    name: &'static str,
+    region: MemoryRegion<Virtual>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static KERNEL_STACKS: InitStateLock<KernelStackRegistry> =
+    InitStateLock::new(KernelStackRegistry::new());
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl KernelStackRegistry {
+    const fn new() -> Self {
+        Self {
+            stacks: [None; MAX_KERNEL_STACKS],
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &KernelStack> {
+        self.stacks.iter().flatten()
+    }
+
+    fn add(&mut self, stack: KernelStack) -> Result<(), &'static str> {
+        if self
+            .iter()
+            .any(|other| other.region.overlaps(&stack.guard_page()))
+        {
+            return Err("Guard page overlaps with another stack");
+        }
+
+        let slot = match self.stacks.iter_mut().find(|slot| slot.is_none()) {
+            None => return Err("Storage for kernel stacks exhausted"),
+            Some(slot) => slot,
+        };
+        *slot = Some(stack);
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl KernelStack {
+    /// Create an instance.
+    pub const fn new(name: &'static str, region: MemoryRegion<Virtual>) -> Self {
+        Self { name, region }
+    }
+
+    /// The stack's name.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The stack's pages.
+    pub fn region(&self) -> &MemoryRegion<Virtual> {
+        &self.region
+    }
+
+    /// The guard page below the stack.
+    pub fn guard_page(&self) -> MemoryRegion<Virtual> {
+        let end_exclusive = self.region.start_page_addr();
+
+        MemoryRegion::new(end_exclusive.checked_offset(-1).unwrap(), end_exclusive)
+    }
+}
+
+/// Register a kernel stack.
+///
+/// Only possible during kernel init. The stack's guard page must be unmapped.
+pub fn register(stack: KernelStack) -> Result<(), &'static str> {
+    if mmu::try_kernel_virt_page_addr_to_phys_page_addr(stack.guard_page().start_page_addr())
+        .is_ok()
+    {
+        return Err("Guard page is mapped");
+    }
+
+    KERNEL_STACKS.write(|registry| registry.add(stack))
+}
+
+/// Register the stacks that are set up by the BSP.
+///
+/// Only possible during kernel init.
+pub fn register_bsp_stacks() -> Result<(), &'static str> {
+    register(KernelStack::new(
+        "boot-core",
+        bsp::memory::mmu::virt_boot_core_stack_region(),
+    ))?;
+    register(KernelStack::new(
+        "exception",
+        bsp::memory::mmu::virt_exception_stack_region(),
+    ))
+}
+
+/// Return the stack that contains `addr`.
+pub fn find(addr: Address<Virtual>) -> Option<KernelStack> {
+    KERNEL_STACKS.read(|registry| {
+        registry
+            .iter()
+            .find(|stack| stack.region.contains(addr))
+            .copied()
+    })
+}
+
+/// Return the stack whose guard page contains `addr`.
+pub fn find_by_guard_page(addr: Address<Virtual>) -> Option<KernelStack> {
+    KERNEL_STACKS.read(|registry| {
+        registry
+            .iter()
+            .find(|stack| stack.guard_page().contains(addr))
+            .copied()
+    })
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// The stack in use is registered, and its guard page is found.
+    #[kernel_test]
+    fn boot_core_stack_is_registered() {
+        let local = 0_u64;
+        let addr = Address::<Virtual>::new(&local as *const _ as usize);
+
+        let stack = find(addr).unwrap();
+        assert_eq!(stack.name(), "boot-core");
+        assert!(find_by_guard_page(addr).is_none());
+
+        let guard_addr = Address::new(stack.region().start_addr().as_usize() - 8);
+        assert!(find(guard_addr).is_none());
+        assert_eq!(find_by_guard_page(guard_addr).unwrap().name(), "boot-core");
+    }
+}
@@ -2,11 +2,21 @@
 //
 // Copyright (c) 2018-2022 Andre Richter <andre.o.richter@gmail.com>
 
-//! A panic handler that infinitely waits.
+//! A panic handler that infinitely waits, or reboots after a delay.
 
-use crate::{bsp, cpu, exception};
+use crate::{backtrace::Backtrace, bsp, cpu, exception, kernel_param};
 use core::{fmt, panic::PanicInfo};
 
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+kernel_param! {
+    /// `panic=<seconds>` reboots that many seconds after a panic. 0 waits forever, a negative
+    /// value reboots right away.
+    static PANIC_TIMEOUT: i64 = 0, name = "panic";
+}
+
 //--------------------------------------------------------------------------------------------------
 // Private Code
 //--------------------------------------------------------------------------------------------------
@@ -25,7 +35,21 @@ fn _panic_print(args: fmt::Arguments) {
 fn _panic_exit() -> ! {
     #[cfg(not(feature = "test_build"))]
     {
-        cpu::wait_forever()
+        use crate::{time, time::interface::TimeManager};
+        use core::time::Duration;
+
+        let timeout = PANIC_TIMEOUT.get();
+        if timeout == 0 {
+            cpu::wait_forever()
+        }
+
+        if timeout > 0 {
+            _panic_print(format_args_nl!("\nRebooting in {} seconds", timeout));
+            time::time_manager().spin_for(Duration::from_secs(timeout as u64));
+        }
+
+        // The drivers are not shut down, as one of them might have caused the panic.
+        bsp::cpu::reset()
     }
 
     #[cfg(feature = "test_build")]
@@ -54,5 +78,7 @@ fn panic(info: &PanicInfo) -> ! {
         panic_println!("\nKernel panic!");
     }
 
+    _panic_print(format_args!("\n{}", Backtrace::current()));
+
     _panic_exit()
 }
@@ -37,70 +37,46 @@ macro_rules! println {
     })
 }
 
-/// Prints an info, with a newline.
+/// Emits a log record at the given level, with a newline.
+///
+/// See [`crate::log`] for how records are filtered.
 #[macro_export]
-macro_rules! info {
-    ($string:expr) => ({
-        #[allow(unused_imports)]
-        use crate::time::interface::TimeManager;
-
-        let timestamp = $crate::time::time_manager().uptime();
-        let timestamp_subsec_us = timestamp.subsec_micros();
-
-        $crate::print::_print(format_args_nl!(
-            concat!("[  {:>3}.{:03}{:03}] ", $string),
-            timestamp.as_secs(),
-            timestamp_subsec_us / 1_000,
-            timestamp_subsec_us % 1_000
-        ));
-    });
-    ($format_string:expr, $($arg:tt)*) => ({
-        #[allow(unused_imports)]
-        use crate::time::interface::TimeManager;
-
-        let timestamp = $crate::time::time_manager().uptime();
-        let timestamp_subsec_us = timestamp.subsec_micros();
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => ({
+        const IS_STATIC_ENABLED: bool = $crate::log::static_enabled($level, module_path!());
 
-        $crate::print::_print(format_args_nl!(
-            concat!("[  {:>3}.{:03}{:03}] ", $format_string),
-            timestamp.as_secs(),
-            timestamp_subsec_us / 1_000,
-            timestamp_subsec_us % 1_000,
-            $($arg)*
-        ));
+        if IS_STATIC_ENABLED && $crate::log::enabled($level, module_path!()) {
+            $crate::log::_log($level, format_args_nl!($($arg)*));
+        }
     })
 }
 
-/// Prints a warning, with a newline.
+/// Emits an error, with a newline.
 #[macro_export]
-macro_rules! warn {
-    ($string:expr) => ({
-        #[allow(unused_imports)]
-        use crate::time::interface::TimeManager;
+macro_rules! error {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Error, $($arg)*));
+}
 
-        let timestamp = $crate::time::time_manager().uptime();
-        let timestamp_subsec_us = timestamp.subsec_micros();
+/// Emits a warning, with a newline.
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Warn, $($arg)*));
+}
 
-        $crate::print::_print(format_args_nl!(
-            concat!("[W {:>3}.{:03}{:03}] ", $string),
-            timestamp.as_secs(),
-            timestamp_subsec_us / 1_000,
-            timestamp_subsec_us % 1_000
-        ));
-    });
-    ($format_string:expr, $($arg:tt)*) => ({
-        #[allow(unused_imports)]
-        use crate::time::interface::TimeManager;
+/// Emits an info message, with a newline.
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Info, $($arg)*));
+}
 
-        let timestamp = $crate::time::time_manager().uptime();
-        let timestamp_subsec_us = timestamp.subsec_micros();
+/// Emits a debug message, with a newline.
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Debug, $($arg)*));
+}
 
-        $crate::print::_print(format_args_nl!(
-            concat!("[W {:>3}.{:03}{:03}] ", $format_string),
-            timestamp.as_secs(),
-            timestamp_subsec_us / 1_000,
-            timestamp_subsec_us % 1_000,
-            $($arg)*
-        ));
-    })
+/// Emits a trace message, with a newline.
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Trace, $($arg)*));
 }
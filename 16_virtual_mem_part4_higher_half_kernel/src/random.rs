@@ -0,0 +1,331 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Random numbers.
+//!
+//! Entropy is collected in a pool, from a hardware RNG if the BSP registered one, and from the
+//! jitter of the architectural counter. The pool seeds a ChaCha20 based CSPRNG, which is reseeded
+//! after every [`RESEED_INTERVAL`] bytes of output. After each request, the generator replaces its
+//! key with fresh output, so that earlier output cannot be reconstructed from its state.
+
+#[cfg(target_arch = "aarch64")]
+#[path = "_arch/aarch64/random.rs"]
+mod arch_random;
+
+use crate::synchronization::{
+    interface::{Mutex, ReadWriteEx},
+    IRQSafeNullLock, InitStateLock,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Words read from the hardware RNG per reseed.
+const HW_WORDS_PER_SEED: usize = 8;
+
+/// Counter samples taken per reseed.
+const JITTER_SAMPLES_PER_SEED: usize = 64;
+
+/// Bytes of output after which the generator is reseeded.
+const RESEED_INTERVAL: usize = 1 << 20;
+
+/// "expand 32-byte k"
+const CHACHA_CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+
+const CHACHA_NONCE: [u32; 3] = [0; 3];
+
+/// Bytes per ChaCha20 block.
+const BLOCK_SIZE: usize = 64;
+
+/// Input is XORed into the first half of the state, which is then stirred with the ChaCha20 block
+/// function. Seeds are taken from the first half as well, so the second half is never revealed.
+struct EntropyPool {
+    state: [u32; 16],
+}
+
+struct ChaChaRng {
+    key: [u32; 8],
+    is_seeded: bool,
+    bytes_since_reseed: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Random number interfaces.
+pub mod interface {
+    /// A source of hardware entropy.
+    pub trait EntropySource {
+        /// Read a word of entropy, or `None` if the source did not deliver one in time.
+        fn read_entropy(&self) -> Option<u32>;
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static HW_ENTROPY_SOURCE: InitStateLock<Option<&'static (dyn interface::EntropySource + Sync)>> =
+    InitStateLock::new(None);
+
+static POOL: IRQSafeNullLock<EntropyPool> = IRQSafeNullLock::new(EntropyPool::new());
+
+static RNG: IRQSafeNullLock<ChaChaRng> = IRQSafeNullLock::new(ChaChaRng::new());
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn quarter_round(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(16);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(12);
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(8);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(7);
+}
+
+/// The 20 ChaCha rounds, followed by adding the input.
+fn chacha20(input: &[u32; 16]) -> [u32; 16] {
+    let mut x = *input;
+
+    for _ in 0..10 {
+        // Column rounds.
+        quarter_round(&mut x, 0, 4, 8, 12);
+        quarter_round(&mut x, 1, 5, 9, 13);
+        quarter_round(&mut x, 2, 6, 10, 14);
+        quarter_round(&mut x, 3, 7, 11, 15);
+
+        // Diagonal rounds.
+        quarter_round(&mut x, 0, 5, 10, 15);
+        quarter_round(&mut x, 1, 6, 11, 12);
+        quarter_round(&mut x, 2, 7, 8, 13);
+        quarter_round(&mut x, 3, 4, 9, 14);
+    }
+
+    for (x, i) in x.iter_mut().zip(input) {
+        *x = x.wrapping_add(*i);
+    }
+
+    x
+}
+
+/// The ChaCha20 block function of RFC 8439.
+fn chacha20_block(key: &[u32; 8], counter: u32, nonce: &[u32; 3]) -> [u32; 16] {
+    let mut state = [0; 16];
+
+    state[..4].copy_from_slice(&CHACHA_CONSTANTS);
+    state[4..12].copy_from_slice(key);
+    state[12] = counter;
+    state[13..].copy_from_slice(nonce);
+
+    chacha20(&state)
+}
+
+impl EntropyPool {
+    const fn new() -> Self {
+        Self { state: [0; 16] }
+    }
+
+    fn mix(&mut self, words: &[u32]) {
+        for chunk in words.chunks(8) {
+            for (state, word) in self.state.iter_mut().zip(chunk) {
+                *state ^= *word;
+            }
+
+            self.state = chacha20(&self.state);
+        }
+    }
+
+    /// Mix in fresh entropy. Returns whether the hardware RNG contributed to it.
+    fn collect(&mut self) -> bool {
+        let mut is_hw_used = false;
+
+        if let Some(source) = HW_ENTROPY_SOURCE.read(|x| *x) {
+            for _ in 0..HW_WORDS_PER_SEED {
+                match source.read_entropy() {
+                    None => break,
+                    Some(word) => {
+                        self.mix(&[word]);
+                        is_hw_used = true;
+                    }
+                }
+            }
+        }
+
+        // Mixing takes long enough for the counter to advance by a varying amount in between.
+        for _ in 0..JITTER_SAMPLES_PER_SEED {
+            let sample = arch_random::jitter_sample();
+
+            self.mix(&[sample as u32, (sample >> 32) as u32]);
+        }
+
+        is_hw_used
+    }
+
+    /// Take a seed. The pool is stirred afterwards, so that the seed cannot be recovered from it.
+    fn extract(&mut self) -> [u32; 8] {
+        let mut seed = [0; 8];
+
+        self.state = chacha20(&self.state);
+        seed.copy_from_slice(&self.state[..8]);
+        self.state = chacha20(&self.state);
+
+        seed
+    }
+}
+
+impl ChaChaRng {
+    const fn new() -> Self {
+        Self {
+            key: [0; 8],
+            is_seeded: false,
+            bytes_since_reseed: 0,
+        }
+    }
+
+    /// Replace the key with a seed from the pool. Returns whether hardware entropy went into it.
+    fn reseed(&mut self) -> bool {
+        let key = self.key;
+        let (seed, is_hw_used) = POOL.lock(|pool| {
+            // Carry over the entropy of earlier seeds.
+            pool.mix(&key);
+            let is_hw_used = pool.collect();
+
+            (pool.extract(), is_hw_used)
+        });
+
+        self.key = seed;
+        self.is_seeded = true;
+        self.bytes_since_reseed = 0;
+
+        is_hw_used
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        if !self.is_seeded || self.bytes_since_reseed >= RESEED_INTERVAL {
+            self.reseed();
+        }
+
+        // Block 0 becomes the next key, the following blocks are output.
+        let next_key = chacha20_block(&self.key, 0, &CHACHA_NONCE);
+
+        for (i, chunk) in dest.chunks_mut(BLOCK_SIZE).enumerate() {
+            let block = chacha20_block(&self.key, i as u32 + 1, &CHACHA_NONCE);
+            let bytes = block.iter().flat_map(|word| word.to_le_bytes());
+
+            for (dest, byte) in chunk.iter_mut().zip(bytes) {
+                *dest = byte;
+            }
+        }
+
+        self.key.copy_from_slice(&next_key[..8]);
+        self.bytes_since_reseed += dest.len();
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Register a hardware RNG with the entropy pool. Must be called during kernel init.
+pub fn register_hw_entropy_source(source: &'static (dyn interface::EntropySource + Sync)) {
+    HW_ENTROPY_SOURCE.write(|x| *x = Some(source));
+}
+
+/// Whether a hardware RNG was registered.
+pub fn is_hw_entropy_available() -> bool {
+    HW_ENTROPY_SOURCE.read(|x| x.is_some())
+}
+
+/// Seed the generator.
+///
+/// Returns an error if there was no hardware entropy. The generator is seeded from the counter
+/// jitter alone then, and can still be used.
+pub fn init() -> Result<(), &'static str> {
+    match RNG.lock(|rng| rng.reseed()) {
+        true => Ok(()),
+        false => Err("No hardware entropy, random numbers are seeded from timer jitter only"),
+    }
+}
+
+/// Fill `dest` with random bytes.
+pub fn fill_bytes(dest: &mut [u8]) {
+    RNG.lock(|rng| rng.fill_bytes(dest))
+}
+
+/// Return a random `u64`.
+pub fn next_u64() -> u64 {
+    let mut bytes = [0; 8];
+    fill_bytes(&mut bytes);
+
+    u64::from_le_bytes(bytes)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// The block function matches the test vector of RFC 8439, section 2.3.2.
+    #[kernel_test]
+    fn chacha20_block_test_vector() {
+        let key = [
+            0x0302_0100,
+            0x0706_0504,
+            0x0b0a_0908,
+            0x0f0e_0d0c,
+            0x1312_1110,
+            0x1716_1514,
+            0x1b1a_1918,
+            0x1f1e_1d1c,
+        ];
+        let nonce = [0x0900_0000, 0x4a00_0000, 0x0000_0000];
+
+        let expected = [
+            0xe4e7_f110,
+            0x1559_3bd1,
+            0x1fdd_0f50,
+            0xc471_20a3,
+            0xc7f4_d1c7,
+            0x0368_c033,
+            0x9aaa_2204,
+            0x4e6c_d4c3,
+            0x4664_82d2,
+            0x09aa_9f07,
+            0x05d7_c214,
+            0xa202_8bd9,
+            0xd19c_12b5,
+            0xb94e_16de,
+            0xe883_d0cb,
+            0x4e3c_50a2,
+        ];
+
+        assert_eq!(chacha20_block(&key, 1, &nonce), expected);
+    }
+
+    /// Without a hardware RNG, seeding is reported as degraded but random numbers are available.
+    #[kernel_test]
+    fn random_without_hw_entropy() {
+        assert!(!is_hw_entropy_available());
+        assert!(init().is_err());
+
+        let mut a = [0u8; 100];
+        let mut b = [0u8; 100];
+        fill_bytes(&mut a);
+        fill_bytes(&mut b);
+
+        assert_ne!(a, b);
+        assert_ne!(a, [0; 100]);
+        assert_ne!(next_u64(), next_u64());
+    }
+}
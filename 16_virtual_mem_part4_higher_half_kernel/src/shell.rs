@@ -0,0 +1,250 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Interactive kernel shell.
+//!
+//! Commands are kept in a registry. Any part of the kernel can add its own commands with
+//! [`register_command()`] during kernel init. After that, the registry is read-only. A set of
+//! built-in commands is added with [`register_builtin_commands()`].
+
+mod commands;
+mod line_editor;
+
+use crate::{
+    bsp, console, println,
+    synchronization::{interface::ReadWriteEx, InitStateLock},
+};
+use line_editor::LineEditor;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Maximum number of registered commands.
+const MAX_COMMANDS: usize = 24;
+
+/// Maximum number of arguments passed to a command. Additional ones are dropped.
+const MAX_ARGS: usize = 8;
+
+const PROMPT: &str = "kernel> ";
+
+struct CommandRegistry {
+    commands: [Option<Command>; MAX_COMMANDS],
+}
+
+/// Snapshot of the registered command names.
+struct CommandNames {
+    names: [&'static str; MAX_COMMANDS],
+    len: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A shell command.
+#[derive(Copy, Clone)]
+pub struct Command {
+    /// The name the command is invoked with.
+    pub name: &'static str,
+
+    /// Usage and a short description, printed by `help`.
+    pub help: &'static str,
+
+    /// Executes the command. Receives the arguments, without the command's name.
+    pub handler: fn(args: &[&str]) -> Result<(), &'static str>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static COMMAND_REGISTRY: InitStateLock<CommandRegistry> =
+    InitStateLock::new(CommandRegistry::new());
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl CommandRegistry {
+    const fn new() -> Self {
+        Self {
+            commands: [None; MAX_COMMANDS],
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Command> {
+        self.commands.iter().flatten()
+    }
+
+    fn find(&self, name: &str) -> Option<Command> {
+        self.iter().find(|cmd| cmd.name == name).copied()
+    }
+
+    fn add(&mut self, command: Command) -> Result<(), &'static str> {
+        if self.find(command.name).is_some() {
+            return Err("Command name already registered");
+        }
+
+        let slot = match self.commands.iter_mut().find(|slot| slot.is_none()) {
+            None => return Err("Storage for shell commands exhausted"),
+            Some(slot) => slot,
+        };
+        *slot = Some(command);
+
+        Ok(())
+    }
+}
+
+impl CommandNames {
+    fn new() -> Self {
+        let mut names = [""; MAX_COMMANDS];
+
+        let len = COMMAND_REGISTRY.read(|registry| {
+            for (name, command) in names.iter_mut().zip(registry.iter()) {
+                *name = command.name;
+            }
+
+            registry.iter().count()
+        });
+
+        Self { names, len }
+    }
+
+    fn as_slice(&self) -> &[&'static str] {
+        &self.names[..self.len]
+    }
+}
+
+/// Split a line into words, separated by whitespace.
+fn split_args<'a>(line: &'a str, args: &mut [&'a str; MAX_ARGS]) -> usize {
+    let mut num_args = 0;
+
+    for (arg, word) in args.iter_mut().zip(line.split_whitespace()) {
+        *arg = word;
+        num_args += 1;
+    }
+
+    num_args
+}
+
+/// Execute a single command line.
+fn execute(line: &str) {
+    let mut args = [""; MAX_ARGS];
+    let num_args = split_args(line, &mut args);
+
+    if num_args == 0 {
+        return;
+    }
+
+    let command = match COMMAND_REGISTRY.read(|registry| registry.find(args[0])) {
+        None => {
+            println!("{}: command not found. Try 'help'.", args[0]);
+            return;
+        }
+        Some(command) => command,
+    };
+
+    if let Err(msg) = (command.handler)(&args[1..num_args]) {
+        println!("{}: {}", command.name, msg);
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Add a command to the shell.
+///
+/// Only possible during kernel init.
+pub fn register_command(command: Command) -> Result<(), &'static str> {
+    COMMAND_REGISTRY.write(|registry| registry.add(command))
+}
+
+/// Add the built-in commands to the shell.
+pub fn register_builtin_commands() -> Result<(), &'static str> {
+    for command in commands::BUILTIN_COMMANDS {
+        register_command(*command)?;
+    }
+
+    Ok(())
+}
+
+/// Print all registered commands and their help texts.
+pub fn print_commands() {
+    COMMAND_REGISTRY.read(|registry| {
+        for command in registry.iter() {
+            println!("  {:<10} {}", command.name, command.help);
+        }
+    });
+}
+
+/// Run the shell on the BSP's console. Does not return.
+pub async fn run() {
+    let console = bsp::console::console();
+    let mut editor = LineEditor::new(PROMPT);
+
+    // The registry does not change after kernel init, so the completion candidates can be cached.
+    let names = CommandNames::new();
+
+    loop {
+        editor.print_prompt(console);
+
+        while !editor.feed(console::read_char(console).await, console, names.as_slice()) {}
+
+        execute(editor.line());
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    fn nop(_args: &[&str]) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    /// Words are split on any whitespace, and surplus ones are dropped.
+    #[kernel_test]
+    fn split_args_works() {
+        let mut args = [""; MAX_ARGS];
+
+        assert_eq!(split_args("  peek\t0x1000   4 ", &mut args), 3);
+        assert_eq!(args[..3], ["peek", "0x1000", "4"]);
+
+        assert_eq!(split_args("a b c d e f g h i j", &mut args), MAX_ARGS);
+        assert_eq!(args[MAX_ARGS - 1], "h");
+    }
+
+    /// Names are unique, and the registry's capacity is bounded.
+    #[kernel_test]
+    fn command_registry_add() {
+        let mut registry = CommandRegistry::new();
+        let cmd = Command {
+            name: "nop",
+            help: "",
+            handler: nop,
+        };
+
+        assert!(registry.add(cmd).is_ok());
+        assert!(registry.add(cmd).is_err());
+        assert!(registry.find("nop").is_some());
+        assert!(registry.find("no").is_none());
+
+        const NAMES: [&str; MAX_COMMANDS] = [
+            "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o", "p", "q",
+            "r", "s", "t", "u", "v", "w", "x",
+        ];
+        let mut registry = CommandRegistry::new();
+        for name in NAMES {
+            assert!(registry.add(Command { name, ..cmd }).is_ok());
+        }
+        assert!(registry.add(cmd).is_err());
+    }
+}
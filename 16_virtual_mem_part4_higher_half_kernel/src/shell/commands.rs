@@ -0,0 +1,376 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Built-in shell commands.
+
+use super::Command;
+use crate::{
+    bsp, cpu, driver,
+    exception::{
+        asynchronous::{self, interface::IRQManager},
+        fixup,
+    },
+    log,
+    memory::{
+        mmu::{self, AccessPermissions, PageAddress},
+        Address, Physical, Virtual,
+    },
+    println, time,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Size of the words accessed by `peek` and `poke`.
+const WORD_SIZE: usize = core::mem::size_of::<u32>();
+
+/// Upper limit for the word count of `peek`.
+const MAX_PEEK_WORDS: usize = 64;
+
+/// A mapped word, as accessed by `peek` and `poke`.
+struct Word {
+    virt_addr: Address<Virtual>,
+    phys_addr: Address<Physical>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The built-in commands.
+pub const BUILTIN_COMMANDS: &[Command] = &[
+    Command {
+        name: "help",
+        help: "List all commands",
+        handler: help,
+    },
+    Command {
+        name: "mappings",
+        help: "Print the kernel's memory mappings",
+        handler: mappings,
+    },
+    Command {
+        name: "irqs",
+        help: "Print the registered IRQ handlers",
+        handler: irqs,
+    },
+    Command {
+        name: "drivers",
+        help: "List the loaded device drivers",
+        handler: drivers,
+    },
+    Command {
+        name: "uptime",
+        help: "Print the time since power-on",
+        handler: uptime,
+    },
+    Command {
+        name: "peek",
+        help: "[-p] <addr> [count] - Read 32 bit words, -p for a physical address",
+        handler: peek,
+    },
+    Command {
+        name: "poke",
+        help: "[-p] <addr> <value> - Write a 32 bit word, -p for a physical address",
+        handler: poke,
+    },
+    Command {
+        name: "dmesg",
+        help: "Print the kernel log",
+        handler: dmesg,
+    },
+    Command {
+        name: "loglevel",
+        help: "[[module] <level|default>] - Show or set the runtime log levels",
+        handler: loglevel,
+    },
+    Command {
+        name: "suspend",
+        help: "<ms> - Suspend the device drivers and idle for the given time",
+        handler: suspend,
+    },
+    Command {
+        name: "watchdog",
+        help: "[<seconds>|pet|stop] - Show, arm, pet or stop the watchdog",
+        handler: watchdog,
+    },
+    Command {
+        name: "reboot",
+        help: "Reboot the system",
+        handler: reboot,
+    },
+    Command {
+        name: "poweroff",
+        help: "Power the system off",
+        handler: poweroff,
+    },
+];
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Parse an unsigned number. Accepts decimal and, with a `0x` prefix, hexadecimal. Underscores can
+/// be used as separators.
+fn parse_number(s: &str) -> Result<usize, &'static str> {
+    let (digits, radix) = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => (hex, 16),
+        None => (s, 10),
+    };
+
+    let mut value: usize = 0;
+    let mut num_digits = 0;
+    for c in digits.chars().filter(|c| *c != '_') {
+        let digit = c.to_digit(radix).ok_or("Invalid number")?;
+
+        value = value
+            .checked_mul(radix as usize)
+            .and_then(|v| v.checked_add(digit as usize))
+            .ok_or("Number too large")?;
+        num_digits += 1;
+    }
+
+    if num_digits == 0 {
+        return Err("Invalid number");
+    }
+
+    Ok(value)
+}
+
+/// Parse the `[-p] <addr>` prefix of the arguments of `peek` and `poke`.
+///
+/// Returns the word to access and the remaining arguments.
+fn parse_address<'a, 'b>(args: &'a [&'b str]) -> Result<(Word, &'a [&'b str]), &'static str> {
+    let (is_physical, args) = match args {
+        ["-p", rest @ ..] => (true, rest),
+        _ => (false, args),
+    };
+
+    let (addr, rest) = match args {
+        [addr, rest @ ..] => (parse_number(addr)?, rest),
+        [] => return Err("Missing address"),
+    };
+
+    if addr % WORD_SIZE != 0 {
+        return Err("Address not 32 bit aligned");
+    }
+
+    // Only touch addresses that are known to be mapped, so that typos do not end in a fault.
+    let (virt_addr, phys_addr) = if is_physical {
+        let phys_addr = Address::<Physical>::new(addr);
+
+        (
+            mmu::try_kernel_phys_addr_to_virt_addr(phys_addr)?,
+            phys_addr,
+        )
+    } else {
+        let virt_addr = Address::<Virtual>::new(addr);
+
+        (
+            virt_addr,
+            mmu::try_kernel_virt_addr_to_phys_addr(virt_addr)?,
+        )
+    };
+
+    Ok((
+        Word {
+            virt_addr,
+            phys_addr,
+        },
+        rest,
+    ))
+}
+
+fn help(_args: &[&str]) -> Result<(), &'static str> {
+    super::print_commands();
+
+    Ok(())
+}
+
+fn mappings(_args: &[&str]) -> Result<(), &'static str> {
+    mmu::kernel_print_mappings();
+
+    Ok(())
+}
+
+fn irqs(_args: &[&str]) -> Result<(), &'static str> {
+    bsp::exception::asynchronous::irq_manager().print_handler();
+
+    Ok(())
+}
+
+fn drivers(_args: &[&str]) -> Result<(), &'static str> {
+    driver::driver_manager().enumerate(|i, driver| {
+        println!(
+            "  {}. {} [{}, {} us]",
+            i + 1,
+            driver.compatible(),
+            driver.state(),
+            driver.init_time().as_micros()
+        );
+    });
+
+    Ok(())
+}
+
+fn uptime(_args: &[&str]) -> Result<(), &'static str> {
+    use time::interface::TimeManager;
+
+    let uptime = time::time_manager().uptime();
+    println!("{}.{:06} s", uptime.as_secs(), uptime.subsec_micros());
+
+    Ok(())
+}
+
+fn peek(args: &[&str]) -> Result<(), &'static str> {
+    let (word, args) = parse_address(args)?;
+    let count = match args {
+        [] => 1,
+        [count] => parse_number(count)?,
+        _ => return Err("Too many arguments"),
+    };
+
+    if count == 0 || count > MAX_PEEK_WORDS {
+        return Err("Count must be between 1 and 64");
+    }
+
+    for i in 0..count {
+        let virt_addr = word.virt_addr + i * WORD_SIZE;
+
+        // Reads may cross into the next page, which could be unmapped. Side effects of reading
+        // MMIO are up to the user, just like the writes of `poke`.
+        let value = unsafe { fixup::probe_read(virt_addr)? };
+        let phys_addr = if i == 0 {
+            word.phys_addr
+        } else {
+            mmu::try_kernel_virt_addr_to_phys_addr(virt_addr)?
+        };
+
+        println!("  {} ({}): {:#010x}", virt_addr, phys_addr, value);
+    }
+
+    Ok(())
+}
+
+fn poke(args: &[&str]) -> Result<(), &'static str> {
+    let (word, args) = parse_address(args)?;
+    let value = match args {
+        [value] => parse_number(value)?,
+        [] => return Err("Missing value"),
+        _ => return Err("Too many arguments"),
+    };
+
+    if value > u32::MAX as usize {
+        return Err("Value does not fit into 32 bits");
+    }
+
+    let page_attributes =
+        mmu::try_kernel_page_attributes(PageAddress::from(word.virt_addr.align_down_page()))?;
+    if page_attributes.acc_perms != AccessPermissions::ReadWrite {
+        return Err("Address is mapped read-only");
+    }
+
+    unsafe { core::ptr::write_volatile(word.virt_addr.as_usize() as *mut u32, value as u32) };
+    println!(
+        "  {} ({}) <- {:#010x}",
+        word.virt_addr, word.phys_addr, value
+    );
+
+    Ok(())
+}
+
+fn dmesg(_args: &[&str]) -> Result<(), &'static str> {
+    log::print_kernel_log();
+
+    Ok(())
+}
+
+fn loglevel(args: &[&str]) -> Result<(), &'static str> {
+    match args {
+        [] => log::print_levels(),
+        ["default"] => log::set_max_level(log::Level::Info),
+        [level] => log::set_max_level(level.parse()?),
+        [module, "default"] => log::set_module_level(module, None)?,
+        [module, level] => log::set_module_level(module, Some(level.parse()?))?,
+        _ => return Err("Too many arguments"),
+    }
+
+    Ok(())
+}
+
+fn suspend(args: &[&str]) -> Result<(), &'static str> {
+    use core::time::Duration;
+    use time::interface::TimeManager;
+
+    let millis = match args {
+        [x] => parse_number(x)?,
+        _ => return Err("Expected the idle time in milliseconds"),
+    };
+
+    // IRQs stay masked while the drivers are suspended, so that no handler sees a suspended device.
+    // The core idles until the timer wakes it up at the deadline.
+    asynchronous::exec_with_irq_masked(|| {
+        unsafe { driver::driver_manager().suspend_drivers()? };
+        time::idle_until(time::time_manager().uptime() + Duration::from_millis(millis as u64));
+        unsafe { driver::driver_manager().resume_drivers() };
+
+        Ok(())
+    })
+}
+
+fn watchdog(args: &[&str]) -> Result<(), &'static str> {
+    use crate::watchdog::interface::Watchdog;
+    use core::time::Duration;
+
+    let watchdog = bsp::driver::watchdog();
+
+    match args {
+        [] => (),
+        ["pet"] => watchdog.pet(),
+        ["stop"] => watchdog.stop(),
+        [secs] => watchdog.start(Duration::from_secs(parse_number(secs)? as u64))?,
+        _ => return Err("Too many arguments"),
+    }
+
+    match watchdog.time_left() {
+        None => println!("Watchdog stopped"),
+        Some(x) => println!("Reset in {}.{:03} s", x.as_secs(), x.subsec_millis()),
+    }
+
+    Ok(())
+}
+
+fn reboot(_args: &[&str]) -> Result<(), &'static str> {
+    println!("Rebooting");
+    cpu::reboot()
+}
+
+fn poweroff(_args: &[&str]) -> Result<(), &'static str> {
+    println!("Powering off");
+    cpu::power_off()
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Decimal and hexadecimal numbers are parsed, garbage is rejected.
+    #[kernel_test]
+    fn parse_number_works() {
+        assert_eq!(parse_number("42"), Ok(42));
+        assert_eq!(parse_number("0x3F20_0000"), Ok(0x3F20_0000));
+        assert_eq!(parse_number("0XfF"), Ok(0xff));
+
+        assert!(parse_number("").is_err());
+        assert!(parse_number("0x").is_err());
+        assert!(parse_number("12a").is_err());
+        assert!(parse_number("0x1_0000_0000_0000_0000").is_err());
+    }
+}
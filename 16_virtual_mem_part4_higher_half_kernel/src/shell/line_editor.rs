@@ -0,0 +1,367 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Line editor with history and tab completion.
+
+use crate::console;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Maximum length of a line. Additional characters are dropped.
+const LINE_LEN: usize = 80;
+
+/// Number of remembered lines.
+const HISTORY_LEN: usize = 8;
+
+/// Progress in parsing an ANSI escape sequence.
+#[derive(Copy, Clone, PartialEq)]
+enum EscapeState {
+    None,
+    Escape,
+    ControlSequence,
+}
+
+#[derive(Copy, Clone)]
+struct Line {
+    buf: [u8; LINE_LEN],
+    len: usize,
+}
+
+struct History {
+    lines: [Line; HISTORY_LEN],
+
+    /// Number of valid entries.
+    len: usize,
+
+    /// Index the next entry will be stored at.
+    next: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Turns single input characters into complete lines.
+///
+/// Supports backspace, walking the history with the up and down arrow keys and completing the first
+/// word with the tab key.
+pub struct LineEditor {
+    prompt: &'static str,
+    line: Line,
+    history: History,
+
+    /// How far the user has walked back into the history. `0` is the line being edited.
+    history_pos: usize,
+
+    escape: EscapeState,
+
+    /// The line was finished by the last character. It is cleared by the next one.
+    is_complete: bool,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Line {
+    const fn new() -> Self {
+        Self {
+            buf: [0; LINE_LEN],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        // Only printable ASCII is ever stored, so this cannot fail.
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or_default()
+    }
+
+    fn push(&mut self, c: char) -> bool {
+        if !c.is_ascii() || c.is_ascii_control() || self.len == LINE_LEN {
+            return false;
+        }
+
+        self.buf[self.len] = c as u8;
+        self.len += 1;
+
+        true
+    }
+
+    fn pop(&mut self) -> bool {
+        if self.len == 0 {
+            return false;
+        }
+
+        self.len -= 1;
+
+        true
+    }
+}
+
+impl History {
+    const fn new() -> Self {
+        Self {
+            lines: [Line::new(); HISTORY_LEN],
+            len: 0,
+            next: 0,
+        }
+    }
+
+    /// Return the `n`-th most recent entry, starting at `1`.
+    fn get(&self, n: usize) -> Option<&Line> {
+        if n == 0 || n > self.len {
+            return None;
+        }
+
+        Some(&self.lines[(self.next + HISTORY_LEN - n) % HISTORY_LEN])
+    }
+
+    fn push(&mut self, line: &Line) {
+        if line.len == 0 || self.get(1).map(|l| l.as_str()) == Some(line.as_str()) {
+            return;
+        }
+
+        self.lines[self.next] = *line;
+        self.next = (self.next + 1) % HISTORY_LEN;
+        self.len = HISTORY_LEN.min(self.len + 1);
+    }
+}
+
+impl LineEditor {
+    /// Erase the line on the terminal and print it again.
+    fn redraw(&self, out: &dyn console::interface::Write) {
+        out.write_char('\r');
+        out.write_fmt(format_args!("\x1b[K{}{}", self.prompt, self.line.as_str()))
+            .unwrap_or(());
+    }
+
+    fn history_step(&mut self, older: bool, out: &dyn console::interface::Write) {
+        let pos = if older {
+            self.history_pos + 1
+        } else {
+            match self.history_pos.checked_sub(1) {
+                None => return,
+                Some(pos) => pos,
+            }
+        };
+
+        self.line = match self.history.get(pos) {
+            Some(line) => *line,
+            None if pos == 0 => Line::new(),
+            None => return,
+        };
+        self.history_pos = pos;
+
+        self.redraw(out);
+    }
+
+    fn complete(&mut self, out: &dyn console::interface::Write, names: &[&str]) {
+        let prefix = self.line.as_str();
+
+        // Only the command name is completed.
+        if prefix.contains(' ') {
+            return;
+        }
+
+        let mut candidates = names.iter().filter(|name| name.starts_with(prefix));
+        let first = match candidates.next() {
+            None => return,
+            Some(name) => *name,
+        };
+
+        // Shorten the first candidate to the prefix it has in common with all others.
+        let mut common_len = first.len();
+        let mut num_candidates = 1;
+        for name in candidates.clone() {
+            common_len = first
+                .bytes()
+                .zip(name.bytes())
+                .take(common_len)
+                .take_while(|(a, b)| a == b)
+                .count();
+            num_candidates += 1;
+        }
+
+        if common_len > prefix.len() {
+            for c in first[prefix.len()..common_len].chars() {
+                if self.line.push(c) {
+                    out.write_char(c);
+                }
+            }
+        } else if num_candidates > 1 {
+            out.write_char('\n');
+            out.write_fmt(format_args!("{}", first)).unwrap_or(());
+            for name in candidates {
+                out.write_fmt(format_args!("  {}", name)).unwrap_or(());
+            }
+            out.write_char('\n');
+            self.redraw(out);
+            return;
+        }
+
+        if num_candidates == 1 && self.line.push(' ') {
+            out.write_char(' ');
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl LineEditor {
+    /// Create an instance.
+    pub const fn new(prompt: &'static str) -> Self {
+        Self {
+            prompt,
+            line: Line::new(),
+            history: History::new(),
+            history_pos: 0,
+            escape: EscapeState::None,
+            is_complete: false,
+        }
+    }
+
+    /// Print the prompt.
+    pub fn print_prompt(&self, out: &dyn console::interface::Write) {
+        out.write_fmt(format_args!("{}", self.prompt)).unwrap_or(());
+    }
+
+    /// Process a single input character and echo its effect to `out`.
+    ///
+    /// `names` are the candidates for tab completion. Returns true if the character finished a
+    /// line. The line can then be retrieved with [`LineEditor::line()`] until the next call.
+    pub fn feed(&mut self, c: char, out: &dyn console::interface::Write, names: &[&str]) -> bool {
+        if self.is_complete {
+            self.is_complete = false;
+            self.line = Line::new();
+        }
+
+        match (self.escape, c) {
+            (EscapeState::None, '\x1b') => self.escape = EscapeState::Escape,
+            (EscapeState::Escape, '[') => self.escape = EscapeState::ControlSequence,
+            (EscapeState::Escape, _) => self.escape = EscapeState::None,
+            (EscapeState::ControlSequence, c) => {
+                match c {
+                    'A' => self.history_step(true, out),
+                    'B' => self.history_step(false, out),
+                    // Parameters and intermediate bytes, the sequence continues.
+                    '\x20'..='\x3f' => return false,
+                    _ => (),
+                }
+                self.escape = EscapeState::None;
+            }
+            (EscapeState::None, '\n') => {
+                out.write_char('\n');
+                self.history.push(&self.line);
+                self.history_pos = 0;
+                self.is_complete = true;
+            }
+            (EscapeState::None, '\x08' | '\x7f') => {
+                if self.line.pop() {
+                    for c in ['\x08', ' ', '\x08'] {
+                        out.write_char(c);
+                    }
+                }
+            }
+            (EscapeState::None, '\t') => self.complete(out, names),
+            (EscapeState::None, c) => {
+                if self.line.push(c) {
+                    out.write_char(c);
+                }
+            }
+        }
+
+        self.is_complete
+    }
+
+    /// The current line.
+    pub fn line(&self) -> &str {
+        self.line.as_str()
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt;
+    use test_macros::kernel_test;
+
+    struct NullConsole;
+
+    impl console::interface::Write for NullConsole {
+        fn write_char(&self, _c: char) {}
+
+        fn write_fmt(&self, _args: fmt::Arguments) -> fmt::Result {
+            Ok(())
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn feed_str(editor: &mut LineEditor, input: &str, names: &[&str]) -> bool {
+        input
+            .chars()
+            .fold(false, |_, c| editor.feed(c, &NullConsole, names))
+    }
+
+    /// Backspace removes characters, but not beyond the start of the line.
+    #[kernel_test]
+    fn line_editor_backspace() {
+        let mut editor = LineEditor::new("> ");
+
+        assert!(feed_str(&mut editor, "ab\x08\x08\x08cd\x7fe\n", &[]));
+        assert_eq!(editor.line(), "ce");
+    }
+
+    /// Up and down walk the history, and consecutive duplicates are stored once.
+    #[kernel_test]
+    fn line_editor_history() {
+        let mut editor = LineEditor::new("> ");
+
+        feed_str(&mut editor, "one\n", &[]);
+        feed_str(&mut editor, "two\n", &[]);
+        feed_str(&mut editor, "two\n", &[]);
+
+        assert!(!feed_str(&mut editor, "\x1b[A\x1b[A", &[]));
+        assert_eq!(editor.line(), "one");
+
+        // There is nothing older.
+        feed_str(&mut editor, "\x1b[A", &[]);
+        assert_eq!(editor.line(), "one");
+
+        feed_str(&mut editor, "\x1b[B", &[]);
+        assert_eq!(editor.line(), "two");
+
+        feed_str(&mut editor, "\x1b[B", &[]);
+        assert_eq!(editor.line(), "");
+    }
+
+    /// Tab completes unique names and extends ambiguous ones to their common prefix.
+    #[kernel_test]
+    fn line_editor_tab_completion() {
+        let names = ["peek", "poke", "help"];
+        let mut editor = LineEditor::new("> ");
+
+        feed_str(&mut editor, "h\t", &names);
+        assert_eq!(editor.line(), "help ");
+
+        let mut editor = LineEditor::new("> ");
+        feed_str(&mut editor, "p\t", &names);
+        assert_eq!(editor.line(), "p");
+
+        feed_str(&mut editor, "e\t", &names);
+        assert_eq!(editor.line(), "peek ");
+
+        // Arguments are not completed.
+        feed_str(&mut editor, "p\t", &names);
+        assert_eq!(editor.line(), "peek p");
+    }
+}
@@ -0,0 +1,88 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Serial Peripheral Interface (SPI) bus.
+//!
+//! A device is selected with its chip select line for the duration of a transaction. Every byte
+//! that is sent clocks in a byte from the device, so reading means sending zeros.
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// SPI interfaces.
+pub mod interface {
+    use core::time::Duration;
+
+    /// SPI bus controller functions.
+    pub trait Bus {
+        /// Set the SCLK frequency. It is rounded down to what the controller supports.
+        fn set_clock(&self, hz: u32) -> Result<(), &'static str>;
+
+        /// Set the clock polarity and phase.
+        fn set_mode(&self, mode: super::Mode);
+
+        /// Set how long a transaction may take before it is aborted with [`super::TIMEOUT_ERROR`].
+        fn set_timeout(&self, timeout: Duration);
+
+        /// Send `bytes` to the device at `chip_select`, discarding what it sends back.
+        fn write(&self, chip_select: usize, bytes: &[u8]) -> Result<(), &'static str>;
+
+        /// Receive `buf.len()` bytes from the device at `chip_select`.
+        fn read(&self, chip_select: usize, buf: &mut [u8]) -> Result<(), &'static str>;
+
+        /// Send `bytes`, then receive `buf.len()` bytes, without deselecting the device in
+        /// between. Typically used to send a command and read its response.
+        fn write_read(
+            &self,
+            chip_select: usize,
+            bytes: &[u8],
+            buf: &mut [u8],
+        ) -> Result<(), &'static str>;
+
+        /// Send `bytes` and receive into `buf` at the same time. The shorter one is padded with
+        /// zeros or truncated, respectively.
+        fn transfer(
+            &self,
+            chip_select: usize,
+            bytes: &[u8],
+            buf: &mut [u8],
+        ) -> Result<(), &'static str>;
+    }
+}
+
+/// Clock polarity and phase.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Mode {
+    /// The clock idles low, data is sampled on the rising edge.
+    Mode0,
+
+    /// The clock idles low, data is sampled on the falling edge.
+    Mode1,
+
+    /// The clock idles high, data is sampled on the falling edge.
+    Mode2,
+
+    /// The clock idles high, data is sampled on the rising edge.
+    Mode3,
+}
+
+/// The error of a transaction that did not finish in time.
+pub const TIMEOUT_ERROR: &str = "SPI transaction timed out";
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Mode {
+    /// Whether the clock idles high.
+    pub fn cpol(self) -> bool {
+        matches!(self, Mode::Mode2 | Mode::Mode3)
+    }
+
+    /// Whether data is sampled on the second clock edge.
+    pub fn cpha(self) -> bool {
+        matches!(self, Mode::Mode1 | Mode::Mode3)
+    }
+}
@@ -9,9 +9,27 @@
 //!   - <https://doc.rust-lang.org/book/ch16-04-extensible-concurrency-sync-and-send.html>
 //!   - <https://stackoverflow.com/questions/59428096/understanding-the-send-trait>
 //!   - <https://doc.rust-lang.org/std/cell/index.html>
+//!
+//! # Sleeping primitives
+//!
+//! Besides the locks in this file, which are safe for use in any context, there are primitives
+//! that let contending tasks sleep instead. They build upon the [`executor`](crate::executor), so
+//! their blocking operations are `async`. Operations that never sleep are safe in IRQ context.
+
+mod channel;
+mod condvar;
+mod semaphore;
+mod sleeping_mutex;
+mod wait_queue;
 
 use core::cell::UnsafeCell;
 
+pub use channel::*;
+pub use condvar::*;
+pub use semaphore::*;
+pub use sleeping_mutex::*;
+pub use wait_queue::*;
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
@@ -147,8 +165,22 @@ impl<T> interface::ReadWriteEx for InitStateLock<T> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        executor::{self, yield_now, Executor},
+        pin,
+    };
+    use core::{cell::Cell, future::Future, pin::Pin};
     use test_macros::kernel_test;
 
+    /// Run tasks of equal priority to completion.
+    fn run_all<F: Future<Output = ()>, const N: usize>(tasks: [F; N]) {
+        pin!(tasks);
+
+        let mut executor = Executor::<N>::new();
+        executor.spawn_all(tasks).unwrap();
+        executor.run();
+    }
+
     /// InitStateLock must be transparent.
     #[kernel_test]
     fn init_state_lock_is_transparent() {
@@ -156,4 +188,248 @@ mod tests {
 
         assert_eq!(size_of::<InitStateLock<u64>>(), size_of::<u64>());
     }
+
+    /// A semaphore never hands out more permits than it has, even with many contending tasks.
+    #[kernel_test]
+    fn semaphore_limits_concurrency() {
+        let semaphore = Semaphore::new(2);
+        let (in_flight, max_in_flight) = (Cell::new(0), Cell::new(0));
+        let (semaphore, in_flight, max_in_flight) = (&semaphore, &in_flight, &max_in_flight);
+
+        let tasks = [(); 8].map(|_| async move {
+            for _ in 0..50 {
+                semaphore.acquire().await;
+                in_flight.set(in_flight.get() + 1);
+                max_in_flight.set(max_in_flight.get().max(in_flight.get()));
+
+                yield_now().await;
+
+                in_flight.set(in_flight.get() - 1);
+                semaphore.release();
+            }
+        });
+        run_all(tasks);
+
+        assert_eq!(max_in_flight.get(), 2);
+        assert_eq!(semaphore.available_permits(), 2);
+    }
+
+    /// A sleeping mutex keeps read-modify-write sequences intact across yields.
+    #[kernel_test]
+    fn sleeping_mutex_serializes_access() {
+        let mutex = SleepingMutex::new(0_u32);
+        let mutex = &mutex;
+
+        let tasks = [(); 8].map(|_| async move {
+            for _ in 0..100 {
+                let mut guard = mutex.lock().await;
+                let value = *guard;
+
+                yield_now().await;
+                *guard = value + 1;
+            }
+        });
+        run_all(tasks);
+
+        assert!(!mutex.is_locked());
+        assert_eq!(interface::Mutex::lock(mutex, |data| *data), 800);
+    }
+
+    /// The owner of a contended sleeping mutex inherits the waiter's priority.
+    ///
+    /// Without inheritance, the medium priority task would starve the owner, and thereby the high
+    /// priority waiter, until it gives up.
+    #[kernel_test]
+    fn sleeping_mutex_priority_inheritance() {
+        let mutex = SleepingMutex::new(());
+        let started = Semaphore::new(0);
+        let high_done = Cell::new(false);
+        let owner_priority = Cell::new((0, 0));
+
+        let low = async {
+            let guard = mutex.lock().await;
+            let task_id = executor::current_task_id().unwrap();
+
+            started.release();
+            started.release();
+            yield_now().await;
+
+            let boosted = executor::task_priority(task_id);
+            drop(guard);
+            owner_priority.set((boosted, executor::task_priority(task_id)));
+        };
+        let medium = async {
+            started.acquire().await;
+
+            for _ in 0..1000 {
+                if high_done.get() {
+                    break;
+                }
+                yield_now().await;
+            }
+        };
+        let high = async {
+            started.acquire().await;
+            let _guard = mutex.lock().await;
+            high_done.set(true);
+        };
+
+        pin!(low, medium, high);
+
+        let mut executor = Executor::<3>::new();
+        executor.spawn(low).unwrap();
+        executor.spawn_with_priority(medium, 1).unwrap();
+        executor.spawn_with_priority(high, 2).unwrap();
+        executor.run();
+
+        assert!(high_done.get());
+        assert_eq!(owner_priority.get(), (2, 0));
+    }
+
+    /// A waiter that is woken, but dropped before it acts on the wakeup, passes it on to the next
+    /// waiter.
+    #[kernel_test]
+    fn wait_queue_passes_on_wakeup_of_dropped_waiter() {
+        use core::{
+            sync::atomic::{AtomicUsize, Ordering},
+            task::{Context, RawWaker, RawWakerVTable, Waker},
+        };
+
+        // The waker's data is the index of its counter.
+        static WAKES: [AtomicUsize; 2] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake, drop);
+
+        unsafe fn clone(data: *const ()) -> RawWaker {
+            RawWaker::new(data, &VTABLE)
+        }
+        unsafe fn wake(data: *const ()) {
+            WAKES[data as usize].fetch_add(1, Ordering::Relaxed);
+        }
+        unsafe fn drop(_data: *const ()) {}
+
+        let never: fn() -> bool = || false;
+        let queue = WaitQueue::new();
+        let mut first = queue.wait_until(never);
+        let mut second = queue.wait_until(never);
+
+        for (i, waiter) in [&mut first, &mut second].into_iter().enumerate() {
+            let waker = unsafe { Waker::from_raw(RawWaker::new(i as *const (), &VTABLE)) };
+
+            // Safety: The futures are not moved while they are enqueued.
+            let waiter = unsafe { Pin::new_unchecked(waiter) };
+            assert!(waiter.poll(&mut Context::from_waker(&waker)).is_pending());
+        }
+
+        assert!(queue.wake_one());
+        assert_eq!(WAKES[0].load(Ordering::Relaxed), 1);
+        assert_eq!(WAKES[1].load(Ordering::Relaxed), 0);
+
+        core::mem::drop(first);
+        assert_eq!(WAKES[1].load(Ordering::Relaxed), 1);
+        assert!(queue.is_empty());
+    }
+
+    /// Producers and a consumer hand over items through a condition variable.
+    #[kernel_test]
+    fn condvar_producer_consumer() {
+        let queued = SleepingMutex::new(0_u32);
+        let condvar = Condvar::new();
+        let consumed = Cell::new(0);
+        let (queued, condvar, consumed) = (&queued, &condvar, &consumed);
+
+        let producers = [(); 4].map(|_| async move {
+            for _ in 0..50 {
+                *queued.lock().await += 1;
+                condvar.notify_one();
+                yield_now().await;
+            }
+        });
+        let consumer = async move {
+            while consumed.get() < 200 {
+                let mut guard = condvar.wait_while(queued.lock().await, |q| *q == 0).await;
+
+                consumed.set(consumed.get() + *guard);
+                *guard = 0;
+            }
+        };
+
+        pin!(producers, consumer);
+
+        let mut executor = Executor::<5>::new();
+        executor.spawn(consumer).unwrap();
+        executor.spawn_all(producers).unwrap();
+        executor.run();
+
+        assert_eq!(consumed.get(), 200);
+    }
+
+    /// A condition variable can wait on data behind an `IRQSafeNullLock`.
+    #[kernel_test]
+    fn condvar_wait_until_irq_safe_lock() {
+        let flag = IRQSafeNullLock::new(None);
+        let condvar = Condvar::new();
+
+        let waiter = async {
+            let value = condvar.wait_until(&flag, |f| f.take()).await;
+            assert_eq!(value, 42);
+        };
+        let notifier = async {
+            yield_now().await;
+            interface::Mutex::lock(&flag, |f| *f = Some(42));
+            condvar.notify_all();
+        };
+
+        pin!(waiter, notifier);
+
+        let mut executor = Executor::<2>::new();
+        executor.spawn(waiter).unwrap();
+        executor.spawn(notifier).unwrap();
+        executor.run();
+    }
+
+    /// Items of many producers arrive completely through a small channel.
+    #[kernel_test]
+    fn channel_many_producers() {
+        let channel: Channel<u32, 4> = Channel::new();
+        let receiver = channel.receiver().unwrap();
+        let sum = Cell::new(0);
+        let (channel, receiver, sum) = (&channel, &receiver, &sum);
+
+        assert!(channel.receiver().is_err());
+
+        let producers = [(); 4].map(|_| async move {
+            let sender = channel.sender();
+
+            for i in 1..=100 {
+                sender.send(i).await;
+            }
+        });
+        let consumer = async move {
+            for _ in 0..400 {
+                sum.set(sum.get() + receiver.recv().await);
+            }
+        };
+
+        pin!(producers, consumer);
+
+        let mut executor = Executor::<5>::new();
+        executor.spawn_all(producers).unwrap();
+        executor.spawn(consumer).unwrap();
+        executor.run();
+
+        assert_eq!(sum.get(), 4 * 5050);
+        assert!(channel.is_empty());
+    }
+
+    /// Non-blocking sends fail on a full channel instead of sleeping.
+    #[kernel_test]
+    fn channel_try_send_on_full_channel() {
+        let channel: Channel<u8, 2> = Channel::new();
+        let sender = channel.sender();
+
+        assert_eq!(sender.try_send(1), Ok(()));
+        assert_eq!(sender.try_send(2), Ok(()));
+        assert_eq!(sender.try_send(3), Err(3));
+        assert_eq!(channel.receiver().unwrap().try_recv(), Some(1));
+    }
 }
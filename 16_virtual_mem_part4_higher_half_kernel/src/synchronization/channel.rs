@@ -0,0 +1,220 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Bounded multi-producer, single-consumer channel.
+
+use super::{interface::Mutex, IRQSafeNullLock, WaitQueue};
+use core::{
+    mem::MaybeUninit,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Fixed-capacity FIFO ring buffer.
+struct RingBuffer<T, const N: usize> {
+    /// Slots `[head, head + len)`, modulo `N`, are initialized.
+    buf: MaybeUninit<[T; N]>,
+    head: usize,
+    len: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A bounded channel with capacity `N`.
+///
+/// Any number of [`Sender`]s can exist, but only a single [`Receiver`]. Sending with
+/// [`Sender::try_send()`] never sleeps and is therefore safe in IRQ context.
+pub struct Channel<T, const N: usize> {
+    buffer: IRQSafeNullLock<RingBuffer<T, N>>,
+    receiver_taken: AtomicBool,
+
+    /// Tasks waiting for free space.
+    senders: WaitQueue,
+
+    /// The receiver, if it is waiting for an item.
+    receiver: WaitQueue,
+}
+
+/// The sending half of a [`Channel`].
+pub struct Sender<'a, T, const N: usize> {
+    channel: &'a Channel<T, N>,
+}
+
+/// The receiving half of a [`Channel`].
+pub struct Receiver<'a, T, const N: usize> {
+    channel: &'a Channel<T, N>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl<T, const N: usize> RingBuffer<T, N> {
+    const fn new() -> Self {
+        Self {
+            buf: MaybeUninit::uninit(),
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn slot(&mut self, index: usize) -> *mut T {
+        unsafe { (self.buf.as_mut_ptr() as *mut T).add(index % N) }
+    }
+
+    fn push(&mut self, item: T) -> Result<(), T> {
+        if self.len == N {
+            return Err(item);
+        }
+
+        let slot = self.slot(self.head + self.len);
+        unsafe { slot.write(item) };
+        self.len += 1;
+
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let item = unsafe { self.slot(self.head).read() };
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+
+        Some(item)
+    }
+}
+
+impl<T, const N: usize> Drop for RingBuffer<T, N> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+impl<T, const N: usize> Channel<T, N> {
+    fn try_send(&self, item: T) -> Result<(), T> {
+        self.buffer.lock(|buffer| buffer.push(item))?;
+        self.receiver.wake_one();
+
+        Ok(())
+    }
+
+    fn try_recv(&self) -> Option<T> {
+        let item = self.buffer.lock(|buffer| buffer.pop())?;
+        self.senders.wake_one();
+
+        Some(item)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl<T, const N: usize> Channel<T, N> {
+    /// Create an instance.
+    pub const fn new() -> Self {
+        assert!(N > 0);
+
+        Self {
+            buffer: IRQSafeNullLock::new(RingBuffer::new()),
+            receiver_taken: AtomicBool::new(false),
+            senders: WaitQueue::new(),
+            receiver: WaitQueue::new(),
+        }
+    }
+
+    /// Return a new sending half.
+    pub fn sender(&self) -> Sender<'_, T, N> {
+        Sender { channel: self }
+    }
+
+    /// Return the receiving half. Succeeds only once.
+    pub fn receiver(&self) -> Result<Receiver<'_, T, N>, &'static str> {
+        if self.receiver_taken.swap(true, Ordering::AcqRel) {
+            return Err("Channel receiver already taken");
+        }
+
+        Ok(Receiver { channel: self })
+    }
+
+    /// The number of queued items.
+    pub fn len(&self) -> usize {
+        self.buffer.lock(|buffer| buffer.len)
+    }
+
+    /// Return true if no items are queued.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T, const N: usize> Default for Channel<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Sender<'_, T, N> {
+    /// Send an item if there is space. Otherwise, hand it back.
+    ///
+    /// Safe to call from IRQ context.
+    pub fn try_send(&self, item: T) -> Result<(), T> {
+        self.channel.try_send(item)
+    }
+
+    /// Send an item, sleeping until there is space.
+    pub async fn send(&self, item: T) {
+        let mut item = Some(item);
+
+        self.channel
+            .senders
+            .wait_until(|| match item.take().map(|i| self.channel.try_send(i)) {
+                Some(Err(i)) => {
+                    item = Some(i);
+                    false
+                }
+                _ => true,
+            })
+            .await
+    }
+}
+
+impl<T, const N: usize> Clone for Sender<'_, T, N> {
+    fn clone(&self) -> Self {
+        Self {
+            channel: self.channel,
+        }
+    }
+}
+
+impl<T, const N: usize> Receiver<'_, T, N> {
+    /// Receive an item if one is queued.
+    pub fn try_recv(&self) -> Option<T> {
+        self.channel.try_recv()
+    }
+
+    /// Receive an item, sleeping until one is queued.
+    pub async fn recv(&self) -> T {
+        let mut item = None;
+
+        self.channel
+            .receiver
+            .wait_until(|| {
+                item = self.channel.try_recv();
+                item.is_some()
+            })
+            .await;
+
+        // The wait only finishes once an item was received.
+        item.unwrap()
+    }
+}
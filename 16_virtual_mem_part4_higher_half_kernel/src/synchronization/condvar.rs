@@ -0,0 +1,115 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Condition variable.
+
+use super::{interface, SleepingMutexGuard, WaitQueue};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A condition variable.
+///
+/// Lets tasks sleep until another task or an IRQ handler signals that shared state has changed.
+/// Waiters must be prepared for spurious wakeups and check their condition in a loop. Both
+/// [`Condvar::wait_while()`] and [`Condvar::wait_until()`] do this already.
+pub struct Condvar {
+    /// Incremented on every notification.
+    generation: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Condvar {
+    /// Create an instance.
+    pub const fn new() -> Self {
+        Self {
+            generation: AtomicUsize::new(0),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Unlock the mutex, sleep until notified and lock the mutex again.
+    pub async fn wait<'a, T>(&self, guard: SleepingMutexGuard<'a, T>) -> SleepingMutexGuard<'a, T>
+    where
+        T: ?Sized,
+    {
+        let mutex = guard.mutex();
+        let generation = self.generation.load(Ordering::Acquire);
+
+        drop(guard);
+        self.waiters
+            .wait_until(|| self.generation.load(Ordering::Acquire) != generation)
+            .await;
+
+        mutex.lock().await
+    }
+
+    /// Sleep while `condition` returns true for the data protected by the guard's mutex.
+    pub async fn wait_while<'a, T, F>(
+        &self,
+        mut guard: SleepingMutexGuard<'a, T>,
+        mut condition: F,
+    ) -> SleepingMutexGuard<'a, T>
+    where
+        T: ?Sized,
+        F: FnMut(&mut T) -> bool,
+    {
+        while condition(&mut guard) {
+            guard = self.wait(guard).await;
+        }
+
+        guard
+    }
+
+    /// Sleep until `condition` returns true for data that is protected by any
+    /// [`interface::Mutex`], for example an `IRQSafeNullLock` shared with an IRQ handler.
+    ///
+    /// Returns the condition's output once it is satisfied. The mutex is only locked while the
+    /// condition is evaluated.
+    pub async fn wait_until<M, F, R>(&self, mutex: &M, mut condition: F) -> R
+    where
+        M: interface::Mutex,
+        F: FnMut(&mut M::Data) -> Option<R>,
+    {
+        let mut output = None;
+
+        self.waiters
+            .wait_until(|| {
+                output = mutex.lock(|data| condition(data));
+                output.is_some()
+            })
+            .await;
+
+        // The wait only finishes once the condition produced an output.
+        output.unwrap()
+    }
+
+    /// Wake the longest waiting task.
+    ///
+    /// Safe to call from IRQ context.
+    pub fn notify_one(&self) {
+        self.generation.fetch_add(1, Ordering::Release);
+        self.waiters.wake_one();
+    }
+
+    /// Wake all waiting tasks.
+    ///
+    /// Safe to call from IRQ context.
+    pub fn notify_all(&self) {
+        self.generation.fetch_add(1, Ordering::Release);
+        self.waiters.wake_all();
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,204 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Sleeping mutex with priority inheritance.
+
+use super::{interface, WaitQueue};
+use crate::executor;
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// `owner` value of an unlocked mutex.
+const UNLOCKED: usize = usize::MAX;
+
+/// `owner` value of a mutex that was locked outside of any executor task.
+const NO_TASK_OWNER: usize = usize::MAX - 1;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A mutex that lets contending tasks sleep instead of spinning.
+///
+/// While a task waits for the mutex, the owning task inherits the waiter's priority if it is
+/// higher. This prevents tasks of intermediate priority from starving the owner, and thereby the
+/// waiter. The inherited priority is dropped when the owner unlocks this mutex. Priorities that it
+/// inherited through other mutexes it still holds are kept.
+pub struct SleepingMutex<T>
+where
+    T: ?Sized,
+{
+    /// Task id of the owner, or `UNLOCKED`.
+    owner: AtomicUsize,
+    waiters: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+/// Grants access to the data of a locked [`SleepingMutex`]. Unlocks when dropped.
+pub struct SleepingMutexGuard<'a, T>
+where
+    T: ?Sized,
+{
+    mutex: &'a SleepingMutex<T>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl<T> SleepingMutex<T>
+where
+    T: ?Sized,
+{
+    /// Identifies the mutex for priority inheritance.
+    fn lock_id(&self) -> usize {
+        self as *const Self as *const () as usize
+    }
+
+    /// A woken waiter that is dropped before it took the mutex passes the wakeup on to the next
+    /// waiter. See [`WaitQueue`].
+    fn unlock(&self) {
+        let owner = self.owner.swap(UNLOCKED, Ordering::Release);
+
+        if owner < executor::MAX_TASKS {
+            executor::release_priority(owner, self.lock_id());
+        }
+
+        self.waiters.wake_one();
+    }
+
+    /// Lend the current task's priority to the owner.
+    fn donate_priority(&self) {
+        let owner = self.owner.load(Ordering::Relaxed);
+
+        if let Some(current) = executor::current_task_id() {
+            if owner < executor::MAX_TASKS {
+                executor::inherit_priority(owner, self.lock_id(), executor::task_priority(current));
+            }
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+unsafe impl<T> Send for SleepingMutex<T> where T: ?Sized + Send {}
+unsafe impl<T> Sync for SleepingMutex<T> where T: ?Sized + Send {}
+
+impl<T> SleepingMutex<T> {
+    /// Create an instance.
+    pub const fn new(data: T) -> Self {
+        Self {
+            owner: AtomicUsize::new(UNLOCKED),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+impl<T> SleepingMutex<T>
+where
+    T: ?Sized,
+{
+    /// Lock the mutex if it is free.
+    pub fn try_lock(&self) -> Option<SleepingMutexGuard<'_, T>> {
+        let owner = executor::current_task_id().unwrap_or(NO_TASK_OWNER);
+
+        self.owner
+            .compare_exchange(UNLOCKED, owner, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| SleepingMutexGuard { mutex: self })
+    }
+
+    /// Lock the mutex, sleeping until it is free.
+    pub async fn lock(&self) -> SleepingMutexGuard<'_, T> {
+        let mut guard = None;
+
+        self.waiters
+            .wait_until(|| {
+                guard = self.try_lock();
+                if guard.is_none() {
+                    self.donate_priority();
+                }
+
+                guard.is_some()
+            })
+            .await;
+
+        // The wait only finishes once the guard was taken.
+        guard.unwrap()
+    }
+
+    /// Return true if the mutex is currently locked.
+    pub fn is_locked(&self) -> bool {
+        self.owner.load(Ordering::Relaxed) != UNLOCKED
+    }
+}
+
+impl<'a, T> SleepingMutexGuard<'a, T>
+where
+    T: ?Sized,
+{
+    /// Return the mutex this guard belongs to.
+    pub fn mutex(&self) -> &'a SleepingMutex<T> {
+        self.mutex
+    }
+}
+
+impl<T> Deref for SleepingMutexGuard<'_, T>
+where
+    T: ?Sized,
+{
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for SleepingMutexGuard<'_, T>
+where
+    T: ?Sized,
+{
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for SleepingMutexGuard<'_, T>
+where
+    T: ?Sized,
+{
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+/// Closure-based locking for synchronous code.
+///
+/// Sleeping is not possible outside of a task, and on a single core, spinning would never see the
+/// owner unlock. Hence, this panics if the mutex is contended.
+impl<T> interface::Mutex for SleepingMutex<T> {
+    type Data = T;
+
+    fn lock<R>(&self, f: impl FnOnce(&mut Self::Data) -> R) -> R {
+        let mut guard = self
+            .try_lock()
+            .expect("SleepingMutex contended in synchronous lock");
+
+        f(&mut guard)
+    }
+}
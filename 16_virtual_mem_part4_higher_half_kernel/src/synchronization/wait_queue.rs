@@ -0,0 +1,255 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Wait queue.
+
+use super::{interface::Mutex, IRQSafeNullLock};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const WAIT_QUEUE_CAPACITY: usize = 16;
+
+struct Waiter {
+    /// Address of the pinned [`WaitUntil`] future that owns the entry.
+    owner: usize,
+
+    /// Monotonically increasing, used to wake waiters in FIFO order.
+    ticket: usize,
+
+    waker: Waker,
+}
+
+struct WaitQueueInner {
+    waiters: [Option<Waiter>; WAIT_QUEUE_CAPACITY],
+    next_ticket: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A queue of tasks waiting for a condition to become true.
+///
+/// The condition is checked with IRQs masked, and a waiter that finds it false is enqueued
+/// atomically with the check. This way, an IRQ handler that makes the condition true and then calls
+/// [`WaitQueue::wake_one()`] or [`WaitQueue::wake_all()`] cannot slip in between.
+///
+/// A waiter that was woken, but is dropped before it polled its condition again, passes the wakeup
+/// on to the next waiter. Otherwise, [`WaitQueue::wake_one()`] could wake nobody that still waits.
+///
+/// If the queue is full, additional waiters poll their condition continuously instead.
+pub struct WaitQueue {
+    inner: IRQSafeNullLock<WaitQueueInner>,
+}
+
+/// Future returned by [`WaitQueue::wait_until()`].
+pub struct WaitUntil<'a, F> {
+    queue: &'a WaitQueue,
+    condition: F,
+    is_enqueued: bool,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl WaitQueueInner {
+    /// Remove the entry of `owner`. Returns false if there was none.
+    fn remove(&mut self, owner: usize) -> bool {
+        let mut removed = false;
+
+        for slot in self.waiters.iter_mut() {
+            if matches!(slot, Some(w) if w.owner == owner) {
+                *slot = None;
+                removed = true;
+            }
+        }
+
+        removed
+    }
+
+    /// Insert or update the entry of `owner`. Returns false if the queue is full.
+    fn enqueue(&mut self, owner: usize, waker: &Waker) -> bool {
+        if let Some(w) = self.waiters.iter_mut().flatten().find(|w| w.owner == owner) {
+            if !w.waker.will_wake(waker) {
+                w.waker = waker.clone();
+            }
+
+            return true;
+        }
+
+        let slot = match self.waiters.iter_mut().find(|slot| slot.is_none()) {
+            None => return false,
+            Some(slot) => slot,
+        };
+
+        *slot = Some(Waiter {
+            owner,
+            ticket: self.next_ticket,
+            waker: waker.clone(),
+        });
+        self.next_ticket = self.next_ticket.wrapping_add(1);
+
+        true
+    }
+
+    /// Remove and return the waker of the longest waiting entry.
+    fn dequeue(&mut self) -> Option<Waker> {
+        let next_ticket = self.next_ticket;
+
+        // Age relative to the next ticket, so that the order survives ticket wraparound.
+        let oldest = self
+            .waiters
+            .iter_mut()
+            .filter(|slot| slot.is_some())
+            .max_by_key(|slot| match slot {
+                Some(w) => next_ticket.wrapping_sub(w.ticket),
+                None => 0,
+            })?;
+
+        oldest.take().map(|w| w.waker)
+    }
+}
+
+impl<F> WaitUntil<'_, F> {
+    /// The future is pinned while enqueued, so its address identifies it.
+    fn owner(&self) -> usize {
+        self as *const _ as usize
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl WaitQueue {
+    /// Create an instance.
+    pub const fn new() -> Self {
+        const NO_WAITER: Option<Waiter> = None;
+
+        Self {
+            inner: IRQSafeNullLock::new(WaitQueueInner {
+                waiters: [NO_WAITER; WAIT_QUEUE_CAPACITY],
+                next_ticket: 0,
+            }),
+        }
+    }
+
+    /// Wait asynchronously until `condition` returns true.
+    ///
+    /// The condition is evaluated with IRQs masked, so it should be short. It is evaluated again on
+    /// every wakeup, which makes spurious wakeups harmless.
+    pub fn wait_until<F>(&self, condition: F) -> WaitUntil<'_, F>
+    where
+        F: FnMut() -> bool,
+    {
+        WaitUntil {
+            queue: self,
+            condition,
+            is_enqueued: false,
+        }
+    }
+
+    /// Wake the longest waiting task. Returns false if there was none.
+    ///
+    /// Safe to call from IRQ context.
+    pub fn wake_one(&self) -> bool {
+        match self.inner.lock(|inner| inner.dequeue()) {
+            None => false,
+            Some(waker) => {
+                waker.wake();
+                true
+            }
+        }
+    }
+
+    /// Wake all waiting tasks and return how many there were.
+    ///
+    /// Safe to call from IRQ context.
+    pub fn wake_all(&self) -> usize {
+        let mut num_woken = 0;
+
+        while self.wake_one() {
+            num_woken += 1;
+        }
+
+        num_woken
+    }
+
+    /// Return the number of enqueued waiters.
+    pub fn len(&self) -> usize {
+        self.inner
+            .lock(|inner| inner.waiters.iter().flatten().count())
+    }
+
+    /// Return true if no task is waiting.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F> Future for WaitUntil<'_, F>
+where
+    F: FnMut() -> bool,
+{
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // Safety: Neither the condition nor the queue reference are moved out.
+        let this = unsafe { self.get_unchecked_mut() };
+        let owner = this.owner();
+        let queue = this.queue;
+        let condition = &mut this.condition;
+
+        // Checking the condition and enqueueing happen under the same lock, so no wakeup can be
+        // missed in between.
+        let (is_ready, is_enqueued) = queue.inner.lock(|inner| {
+            if condition() {
+                inner.remove(owner);
+                return (true, false);
+            }
+
+            (false, inner.enqueue(owner, cx.waker()))
+        });
+        this.is_enqueued = is_enqueued;
+
+        if is_ready {
+            return Poll::Ready(());
+        }
+
+        // Without a free slot, fall back to being polled again right away.
+        if !is_enqueued {
+            cx.waker().wake_by_ref();
+        }
+
+        Poll::Pending
+    }
+}
+
+impl<F> Drop for WaitUntil<'_, F> {
+    fn drop(&mut self) {
+        if !self.is_enqueued {
+            return;
+        }
+
+        // A missing entry means that the waiter was dequeued by a wakeup it did not act on.
+        let owner = self.owner();
+        if !self.queue.inner.lock(|inner| inner.remove(owner)) {
+            self.queue.wake_one();
+        }
+    }
+}
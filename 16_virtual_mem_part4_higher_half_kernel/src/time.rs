@@ -3,15 +3,68 @@
 // Copyright (c) 2020-2022 Andre Richter <andre.o.richter@gmail.com>
 
 //! Timer primitives.
+//!
+//! The time manager and the event source are the architectural timer's by default. A board can
+//! offer its own timer with [`register_board_timer()`], which is then used if the command line says
+//! `clocksource=board`.
 
 #[cfg(target_arch = "aarch64")]
 #[path = "_arch/aarch64/time.rs"]
 mod arch_time;
 
+use crate::{
+    cmdline, kernel_param,
+    synchronization::{
+        interface::{Mutex, ReadWriteEx},
+        IRQSafeNullLock, InitStateLock,
+    },
+};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
 //--------------------------------------------------------------------------------------------------
 // Architectural Public Reexports
 //--------------------------------------------------------------------------------------------------
-pub use arch_time::time_manager;
+pub use arch_time::{idle_until, register_and_enable_irq_handler};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const MAX_SLEEPERS: usize = 16;
+
+struct Sleeper {
+    /// Address of the pinned [`Sleep`] future that owns the entry.
+    owner: usize,
+    deadline: Duration,
+    waker: Waker,
+}
+
+type SleeperTable = [Option<Sleeper>; MAX_SLEEPERS];
+
+/// The difference between the kernel's uptime and the board timer's uptime.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct UptimeOffset {
+    delta: Duration,
+    board_is_behind: bool,
+}
+
+/// A board timer that serves as both time manager and event source.
+#[derive(Copy, Clone)]
+struct BoardTimer {
+    time_manager: &'static (dyn interface::TimeManager + Sync),
+    event_source: &'static (dyn interface::EventSource + Sync),
+
+    /// Keeps the uptime continuous when switching from the architectural timer.
+    offset: UptimeOffset,
+}
+
+/// Forwards to the board timer, if one is used, or to the architectural timer.
+struct SelectedTimer;
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -34,4 +87,359 @@ pub mod interface {
         /// Spin for a given duration.
         fn spin_for(&self, duration: Duration);
     }
+
+    /// A one-shot timer that raises an IRQ at a given point in time.
+    ///
+    /// The IRQ handler of the implementor is expected to call
+    /// [`wake_expired_sleepers()`](super::wake_expired_sleepers).
+    pub trait EventSource {
+        /// Raise an IRQ once the uptime reaches `deadline`. Replaces a previously set deadline.
+        ///
+        /// A deadline in the past raises the IRQ immediately.
+        fn set_event(&self, deadline: Duration);
+
+        /// Cancel a previously set deadline.
+        fn clear_event(&self);
+    }
+}
+
+/// The timers to choose from with `clocksource=`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ClockSource {
+    /// The CPU architecture's timer.
+    Arch,
+
+    /// The timer registered with [`register_board_timer()`].
+    Board,
+}
+
+/// Future returned by [`sleep_until()`] and [`sleep_for()`].
+pub struct Sleep {
+    deadline: Duration,
+    is_registered: bool,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+const NO_SLEEPER: Option<Sleeper> = None;
+
+static SLEEPERS: IRQSafeNullLock<SleeperTable> = IRQSafeNullLock::new([NO_SLEEPER; MAX_SLEEPERS]);
+
+static BOARD_TIMER: InitStateLock<Option<BoardTimer>> = InitStateLock::new(None);
+
+static SELECTED_TIMER: SelectedTimer = SelectedTimer;
+
+kernel_param! {
+    /// `clocksource=<arch|board>` selects the timer.
+    static CLOCKSOURCE: ClockSource = ClockSource::Arch, name = "clocksource";
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Sleep {
+    /// The future is pinned while registered, so its address identifies it.
+    fn owner(&self) -> usize {
+        self as *const _ as usize
+    }
+}
+
+impl UptimeOffset {
+    /// The offset that maps `board_uptime` onto `kernel_uptime`.
+    fn between(kernel_uptime: Duration, board_uptime: Duration) -> Self {
+        match kernel_uptime.checked_sub(board_uptime) {
+            Some(delta) => Self {
+                delta,
+                board_is_behind: true,
+            },
+            None => Self {
+                delta: board_uptime - kernel_uptime,
+                board_is_behind: false,
+            },
+        }
+    }
+
+    fn to_kernel(self, board_uptime: Duration) -> Duration {
+        match self.board_is_behind {
+            true => board_uptime + self.delta,
+            false => board_uptime.saturating_sub(self.delta),
+        }
+    }
+
+    fn to_board(self, kernel_uptime: Duration) -> Duration {
+        match self.board_is_behind {
+            true => kernel_uptime.saturating_sub(self.delta),
+            false => kernel_uptime + self.delta,
+        }
+    }
+}
+
+impl SelectedTimer {
+    fn board_timer(&self) -> Option<BoardTimer> {
+        BOARD_TIMER.read(|x| *x)
+    }
+
+    fn time_manager(&self) -> &'static dyn interface::TimeManager {
+        match self.board_timer() {
+            None => arch_time::time_manager(),
+            Some(timer) => timer.time_manager,
+        }
+    }
+}
+
+/// Program the event source with the earliest deadline of all sleepers.
+fn rearm(table: &SleeperTable) {
+    use interface::EventSource;
+
+    match table.iter().flatten().map(|s| s.deadline).min() {
+        None => timer_event_source().clear_event(),
+        Some(deadline) => timer_event_source().set_event(deadline),
+    }
+}
+
+/// Remove the entry of the given owner, if any, and rearm the event source.
+fn remove_sleeper(owner: usize) {
+    SLEEPERS.lock(|table| {
+        for slot in table.iter_mut() {
+            if matches!(slot, Some(s) if s.owner == owner) {
+                *slot = None;
+            }
+        }
+
+        rearm(table);
+    });
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Return a reference to the time manager.
+pub fn time_manager() -> &'static impl interface::TimeManager {
+    &SELECTED_TIMER
+}
+
+/// Return a reference to the one-shot event source used for sleeping.
+pub fn timer_event_source() -> &'static impl interface::EventSource {
+    &SELECTED_TIMER
+}
+
+/// Offer a board timer as an alternative to the architectural one.
+///
+/// The timer is used from here on if it was selected on the command line. The uptime continues
+/// from the architectural timer's value, and pending sleepers are moved to the board timer. Must be
+/// called during kernel init.
+pub fn register_board_timer(
+    time_manager: &'static (dyn interface::TimeManager + Sync),
+    event_source: &'static (dyn interface::EventSource + Sync),
+) {
+    use interface::{EventSource, TimeManager};
+
+    if CLOCKSOURCE.get() != ClockSource::Board {
+        return;
+    }
+
+    SLEEPERS.lock(|table| {
+        let offset =
+            UptimeOffset::between(arch_time::time_manager().uptime(), time_manager.uptime());
+
+        arch_time::timer_event_source().clear_event();
+        BOARD_TIMER.write(|x| {
+            *x = Some(BoardTimer {
+                time_manager,
+                event_source,
+                offset,
+            })
+        });
+
+        rearm(table);
+    });
+}
+
+/// The timer that is in use.
+pub fn clock_source() -> ClockSource {
+    match BOARD_TIMER.read(|x| x.is_some()) {
+        false => ClockSource::Arch,
+        true => ClockSource::Board,
+    }
+}
+
+/// Wait asynchronously until the uptime reaches `deadline`.
+pub fn sleep_until(deadline: Duration) -> Sleep {
+    Sleep {
+        deadline,
+        is_registered: false,
+    }
+}
+
+/// Wait asynchronously for a given duration.
+pub fn sleep_for(duration: Duration) -> Sleep {
+    use interface::TimeManager;
+
+    sleep_until(time_manager().uptime() + duration)
+}
+
+/// Wake all sleepers whose deadline has passed and rearm the event source.
+///
+/// Called from the IRQ handler of the event source.
+pub fn wake_expired_sleepers() {
+    use interface::TimeManager;
+
+    let now = time_manager().uptime();
+
+    SLEEPERS.lock(|table| {
+        for slot in table.iter_mut() {
+            if matches!(slot, Some(s) if s.deadline <= now) {
+                if let Some(s) = slot.take() {
+                    s.waker.wake();
+                }
+            }
+        }
+
+        rearm(table);
+    });
+}
+
+impl interface::TimeManager for SelectedTimer {
+    fn resolution(&self) -> Duration {
+        self.time_manager().resolution()
+    }
+
+    fn uptime(&self) -> Duration {
+        match self.board_timer() {
+            None => arch_time::time_manager().uptime(),
+            Some(timer) => timer.offset.to_kernel(timer.time_manager.uptime()),
+        }
+    }
+
+    fn spin_for(&self, duration: Duration) {
+        self.time_manager().spin_for(duration)
+    }
+}
+
+impl interface::EventSource for SelectedTimer {
+    fn set_event(&self, deadline: Duration) {
+        match self.board_timer() {
+            None => arch_time::timer_event_source().set_event(deadline),
+            Some(timer) => timer
+                .event_source
+                .set_event(timer.offset.to_board(deadline)),
+        }
+    }
+
+    fn clear_event(&self) {
+        match self.board_timer() {
+            None => arch_time::timer_event_source().clear_event(),
+            Some(timer) => timer.event_source.clear_event(),
+        }
+    }
+}
+
+impl cmdline::ParamValue for ClockSource {
+    fn parse_param(value: Option<&str>) -> Result<Self, &'static str> {
+        match value {
+            Some("arch") => Ok(ClockSource::Arch),
+            Some("board") => Ok(ClockSource::Board),
+            _ => Err("Expected arch or board"),
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        use interface::TimeManager;
+
+        let owner = self.owner();
+
+        if time_manager().uptime() >= self.deadline {
+            if self.is_registered {
+                self.is_registered = false;
+                remove_sleeper(owner);
+            }
+
+            return Poll::Ready(());
+        }
+
+        let deadline = self.deadline;
+        let is_registered = SLEEPERS.lock(|table| {
+            // The IRQ handler removes the entry when waking, so it might be gone already.
+            let slot = match table
+                .iter()
+                .position(|slot| matches!(slot, Some(s) if s.owner == owner))
+            {
+                None => table.iter().position(Option::is_none),
+                existing => existing,
+            };
+
+            let i = match slot {
+                None => return false,
+                Some(i) => i,
+            };
+
+            table[i] = Some(Sleeper {
+                owner,
+                deadline,
+                waker: cx.waker().clone(),
+            });
+            rearm(table);
+
+            true
+        });
+
+        // Without a free slot, fall back to being polled again right away.
+        if !is_registered {
+            cx.waker().wake_by_ref();
+        }
+        self.is_registered = is_registered;
+
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if self.is_registered {
+            remove_sleeper(self.owner());
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// The uptime offset converts between the board's and the kernel's uptime in both directions.
+    #[kernel_test]
+    fn uptime_offset_converts_both_ways() {
+        let behind = UptimeOffset::between(Duration::from_secs(5), Duration::from_secs(2));
+        assert_eq!(
+            behind.to_kernel(Duration::from_secs(3)),
+            Duration::from_secs(6)
+        );
+        assert_eq!(
+            behind.to_board(Duration::from_secs(6)),
+            Duration::from_secs(3)
+        );
+        assert_eq!(behind.to_board(Duration::from_secs(1)), Duration::ZERO);
+
+        let ahead = UptimeOffset::between(Duration::from_secs(2), Duration::from_secs(5));
+        assert_eq!(
+            ahead.to_kernel(Duration::from_secs(6)),
+            Duration::from_secs(3)
+        );
+        assert_eq!(
+            ahead.to_board(Duration::from_secs(3)),
+            Duration::from_secs(6)
+        );
+    }
 }
@@ -0,0 +1,626 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! USB host support.
+//!
+//! The BSP registers a host controller, which executes control and interrupt transfers for devices
+//! behind its root port. [`init()`] enumerates the bus once: every device gets an address and its
+//! first configuration, hubs have their ports powered and enumerated in turn, and HID boot
+//! keyboards are handed to the [`hid`] driver. Devices that are plugged in later go unnoticed.
+//!
+//! There is no heap, so descriptors are read into fixed size buffers on the stack, and devices are
+//! only remembered by the driver that claims them.
+
+pub mod hid;
+mod hub;
+
+use crate::{
+    info,
+    synchronization::{interface::ReadWriteEx, InitStateLock},
+    time,
+};
+use core::{fmt, time::Duration};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const DESCRIPTOR_TYPE_DEVICE: u8 = 1;
+const DESCRIPTOR_TYPE_CONFIGURATION: u8 = 2;
+const DESCRIPTOR_TYPE_INTERFACE: u8 = 4;
+const DESCRIPTOR_TYPE_ENDPOINT: u8 = 5;
+
+const REQUEST_SET_ADDRESS: u8 = 5;
+const REQUEST_GET_DESCRIPTOR: u8 = 6;
+const REQUEST_SET_CONFIGURATION: u8 = 9;
+
+/// Standard request, device to host, addressed to the device.
+const REQUEST_TYPE_STANDARD_IN: u8 = 0x80;
+
+/// Standard request, host to device, addressed to the device.
+const REQUEST_TYPE_STANDARD_OUT: u8 = 0x00;
+
+const CLASS_HID: u8 = 3;
+const CLASS_HUB: u8 = 9;
+
+const ENDPOINT_TYPE_INTERRUPT: u8 = 3;
+
+/// Addresses 1 to 127 can be assigned.
+const MAX_ADDRESS: u8 = 127;
+
+/// Long enough for the configuration of a keyboard or a hub.
+const MAX_CONFIGURATION_SIZE: usize = 256;
+
+/// The time a device may take to switch to a new address. USB 2.0, section 9.2.6.3.
+const SET_ADDRESS_RECOVERY: Duration = Duration::from_millis(2);
+
+/// Assigns addresses while walking the bus.
+struct Enumerator {
+    host_controller: &'static (dyn interface::HostController + Sync),
+    next_address: u8,
+}
+
+/// The fields of an interface descriptor that drivers are matched against.
+#[derive(Copy, Clone)]
+struct InterfaceDescriptor {
+    number: u8,
+    class: u8,
+    subclass: u8,
+    protocol: u8,
+}
+
+/// Walks the descriptors of a configuration.
+struct Descriptors<'a> {
+    remaining: &'a [u8],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// USB interfaces.
+pub mod interface {
+    use super::{Device, Endpoint, SetupPacket, Speed};
+
+    /// A host controller with a single root port.
+    pub trait HostController {
+        /// Reset the device on the root port and return its speed.
+        fn reset_root_port(&self) -> Result<Speed, &'static str>;
+
+        /// Execute a control transfer on the default endpoint of `device`.
+        ///
+        /// `data` must hold at least `setup.length` bytes. Returns the number of bytes that were
+        /// transferred in the data stage.
+        fn control_transfer(
+            &self,
+            device: &Device,
+            setup: &SetupPacket,
+            data: &mut [u8],
+        ) -> Result<usize, &'static str>;
+
+        /// Execute a single interrupt transfer of up to `data.len()` bytes, and update the
+        /// endpoint's data toggle.
+        ///
+        /// Returns [`NAK_ERROR`](super::NAK_ERROR) if the endpoint had nothing to transfer.
+        fn interrupt_transfer(
+            &self,
+            endpoint: &mut Endpoint,
+            data: &mut [u8],
+        ) -> Result<usize, &'static str>;
+    }
+}
+
+/// The endpoint had nothing to transfer.
+pub const NAK_ERROR: &str = "USB endpoint has no data";
+
+/// The endpoint rejected the request.
+pub const STALL_ERROR: &str = "USB endpoint stalled";
+
+/// The speed of a device.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Speed {
+    /// 1.5 Mbit/s.
+    Low,
+
+    /// 12 Mbit/s.
+    Full,
+
+    /// 480 Mbit/s.
+    High,
+}
+
+/// The hub that translates between a high speed bus and a low or full speed device.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TransactionTranslator {
+    /// The address of the high speed hub.
+    pub hub_address: u8,
+
+    /// The hub's port that the device is attached to, or that leads to it.
+    pub port: u8,
+}
+
+/// An addressed device.
+#[derive(Copy, Clone, Debug)]
+pub struct Device {
+    /// The bus address, or 0 before it was assigned.
+    pub address: u8,
+
+    /// The speed of the device.
+    pub speed: Speed,
+
+    /// The maximum packet size of the default endpoint.
+    pub max_packet_size_0: u16,
+
+    /// Set for low and full speed devices behind a high speed hub.
+    pub transaction_translator: Option<TransactionTranslator>,
+}
+
+/// The direction of a transfer, as seen from the host.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Direction {
+    /// Host to device.
+    Out,
+
+    /// Device to host.
+    In,
+}
+
+/// An interrupt endpoint.
+#[derive(Copy, Clone, Debug)]
+pub struct Endpoint {
+    /// The device the endpoint belongs to.
+    pub device: Device,
+
+    /// The endpoint number.
+    pub number: u8,
+
+    /// The direction of the endpoint.
+    pub direction: Direction,
+
+    /// The maximum packet size.
+    pub max_packet_size: u16,
+
+    /// How often the endpoint wants to be polled.
+    pub interval: Duration,
+
+    /// Whether the next data packet is a DATA1 packet. Maintained by the host controller.
+    pub data_toggle: bool,
+}
+
+/// The packet that starts a control transfer.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SetupPacket {
+    /// Direction, type and recipient of the request.
+    pub request_type: u8,
+
+    /// The request.
+    pub request: u8,
+
+    /// Request specific.
+    pub value: u16,
+
+    /// Request specific, usually an interface or port number.
+    pub index: u16,
+
+    /// The number of bytes in the data stage.
+    pub length: u16,
+}
+
+/// The fields of a device descriptor that the enumeration uses.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DeviceDescriptor {
+    /// The device class, or 0 if it is given per interface.
+    pub class: u8,
+
+    /// The maximum packet size of the default endpoint.
+    pub max_packet_size_0: u8,
+
+    /// The vendor ID.
+    pub vendor_id: u16,
+
+    /// The product ID.
+    pub product_id: u16,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static HOST_CONTROLLER: InitStateLock<Option<&'static (dyn interface::HostController + Sync)>> =
+    InitStateLock::new(None);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+impl<'a> Iterator for Descriptors<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        let len = *self.remaining.first()? as usize;
+
+        // A truncated or malformed descriptor ends the walk.
+        if len < 2 || len > self.remaining.len() {
+            return None;
+        }
+
+        let (descriptor, remaining) = self.remaining.split_at(len);
+        self.remaining = remaining;
+
+        Some(descriptor)
+    }
+}
+
+impl Endpoint {
+    /// Parse an endpoint descriptor. Returns `None` if it is not an interrupt endpoint.
+    fn from_descriptor(device: Device, descriptor: &[u8]) -> Option<Self> {
+        if descriptor.len() < 7 || descriptor[3] & 0b11 != ENDPOINT_TYPE_INTERRUPT {
+            return None;
+        }
+
+        let address = descriptor[2];
+        let interval = descriptor[6].max(1) as u32;
+
+        // High speed devices give the interval as an exponent of microframes.
+        let interval = match device.speed {
+            Speed::High => Duration::from_micros(125 << (interval.min(16) - 1)),
+            Speed::Low | Speed::Full => Duration::from_millis(interval as u64),
+        };
+
+        Some(Self {
+            device,
+            number: address & 0xF,
+            direction: match address & 0x80 {
+                0 => Direction::Out,
+                _ => Direction::In,
+            },
+            max_packet_size: read_u16(descriptor, 4) & 0x7FF,
+            interval,
+            data_toggle: false,
+        })
+    }
+}
+
+impl InterfaceDescriptor {
+    fn from_descriptor(descriptor: &[u8]) -> Option<Self> {
+        if descriptor.len() < 9 {
+            return None;
+        }
+
+        Some(Self {
+            number: descriptor[2],
+            class: descriptor[5],
+            subclass: descriptor[6],
+            protocol: descriptor[7],
+        })
+    }
+}
+
+impl Enumerator {
+    fn allocate_address(&mut self) -> Result<u8, &'static str> {
+        if self.next_address > MAX_ADDRESS {
+            return Err("No USB addresses left");
+        }
+
+        let address = self.next_address;
+        self.next_address += 1;
+
+        Ok(address)
+    }
+
+    /// Address and configure the device that was just reset, and hand it to a driver.
+    ///
+    /// `depth` is the number of hubs between the device and the root port.
+    fn enumerate(
+        &mut self,
+        speed: Speed,
+        transaction_translator: Option<TransactionTranslator>,
+        depth: usize,
+    ) -> Result<(), &'static str> {
+        use time::interface::TimeManager;
+
+        let hc = self.host_controller;
+        let mut device = Device {
+            address: 0,
+            speed,
+            // Every device accepts packets of 8 bytes, which is enough to learn the real size.
+            max_packet_size_0: 8,
+            transaction_translator,
+        };
+
+        let mut buf = [0; 18];
+        hc.control_transfer(
+            &device,
+            &SetupPacket::get_descriptor(DESCRIPTOR_TYPE_DEVICE, 8),
+            &mut buf[..8],
+        )?;
+        device.max_packet_size_0 = match buf[7] {
+            x @ (8 | 16 | 32 | 64) => x as u16,
+            _ => return Err("Invalid USB default endpoint packet size"),
+        };
+
+        let address = self.allocate_address()?;
+        hc.control_transfer(&device, &SetupPacket::set_address(address), &mut [])?;
+        time::time_manager().spin_for(SET_ADDRESS_RECOVERY);
+        device.address = address;
+
+        let len = hc.control_transfer(
+            &device,
+            &SetupPacket::get_descriptor(DESCRIPTOR_TYPE_DEVICE, 18),
+            &mut buf,
+        )?;
+        let descriptor = DeviceDescriptor::parse(&buf[..len])?;
+
+        let mut config = [0; MAX_CONFIGURATION_SIZE];
+        hc.control_transfer(
+            &device,
+            &SetupPacket::get_descriptor(DESCRIPTOR_TYPE_CONFIGURATION, 9),
+            &mut config[..9],
+        )?;
+        let total_len = (read_u16(&config, 2) as usize).min(MAX_CONFIGURATION_SIZE);
+        let len = hc.control_transfer(
+            &device,
+            &SetupPacket::get_descriptor(DESCRIPTOR_TYPE_CONFIGURATION, total_len as u16),
+            &mut config[..total_len],
+        )?;
+        let config = &config[..len];
+        if config.len() < 9 {
+            return Err("Truncated USB configuration descriptor");
+        }
+
+        hc.control_transfer(&device, &SetupPacket::set_configuration(config[5]), &mut [])?;
+
+        info!(
+            "USB device {}: {:04x}:{:04x}, {} speed",
+            address, descriptor.vendor_id, descriptor.product_id, speed
+        );
+
+        if descriptor.class == CLASS_HUB {
+            return hub::enumerate_ports(self, device, depth);
+        }
+
+        self.attach_interface_drivers(device, config)
+    }
+
+    /// Hand the interfaces of a configuration to the drivers that support them.
+    fn attach_interface_drivers(&self, device: Device, config: &[u8]) -> Result<(), &'static str> {
+        let mut interface: Option<InterfaceDescriptor> = None;
+
+        for descriptor in (Descriptors { remaining: config }) {
+            match descriptor[1] {
+                DESCRIPTOR_TYPE_INTERFACE => {
+                    interface = InterfaceDescriptor::from_descriptor(descriptor);
+                }
+                DESCRIPTOR_TYPE_ENDPOINT => {
+                    let iface = match interface {
+                        Some(x) if hid::is_boot_keyboard(x.class, x.subclass, x.protocol) => x,
+                        _ => continue,
+                    };
+
+                    let endpoint = match Endpoint::from_descriptor(device, descriptor) {
+                        Some(x) if x.direction == Direction::In => x,
+                        _ => continue,
+                    };
+
+                    hid::attach_keyboard(self.host_controller, iface.number, endpoint)?;
+
+                    // The interface is claimed.
+                    interface = None;
+                }
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Speed::Low => write!(f, "low"),
+            Speed::Full => write!(f, "full"),
+            Speed::High => write!(f, "high"),
+        }
+    }
+}
+
+impl SetupPacket {
+    /// The size of the packet on the wire.
+    pub const SIZE: usize = 8;
+
+    /// Create an instance.
+    pub const fn new(request_type: u8, request: u8, value: u16, index: u16, length: u16) -> Self {
+        Self {
+            request_type,
+            request,
+            value,
+            index,
+            length,
+        }
+    }
+
+    /// Read the first `length` bytes of the device's descriptor of the given type.
+    pub const fn get_descriptor(descriptor_type: u8, length: u16) -> Self {
+        Self::new(
+            REQUEST_TYPE_STANDARD_IN,
+            REQUEST_GET_DESCRIPTOR,
+            (descriptor_type as u16) << 8,
+            0,
+            length,
+        )
+    }
+
+    /// Assign a bus address.
+    pub const fn set_address(address: u8) -> Self {
+        Self::new(
+            REQUEST_TYPE_STANDARD_OUT,
+            REQUEST_SET_ADDRESS,
+            address as u16,
+            0,
+            0,
+        )
+    }
+
+    /// Select a configuration.
+    pub const fn set_configuration(value: u8) -> Self {
+        Self::new(
+            REQUEST_TYPE_STANDARD_OUT,
+            REQUEST_SET_CONFIGURATION,
+            value as u16,
+            0,
+            0,
+        )
+    }
+
+    /// The direction of the data stage.
+    pub fn direction(&self) -> Direction {
+        match self.request_type & 0x80 {
+            0 => Direction::Out,
+            _ => Direction::In,
+        }
+    }
+
+    /// The packet as it is sent on the wire.
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let value = self.value.to_le_bytes();
+        let index = self.index.to_le_bytes();
+        let length = self.length.to_le_bytes();
+
+        [
+            self.request_type,
+            self.request,
+            value[0],
+            value[1],
+            index[0],
+            index[1],
+            length[0],
+            length[1],
+        ]
+    }
+}
+
+impl DeviceDescriptor {
+    /// Parse a device descriptor.
+    pub fn parse(bytes: &[u8]) -> Result<Self, &'static str> {
+        if bytes.len() < 18 || bytes[1] != DESCRIPTOR_TYPE_DEVICE {
+            return Err("Invalid USB device descriptor");
+        }
+
+        Ok(Self {
+            class: bytes[4],
+            max_packet_size_0: bytes[7],
+            vendor_id: read_u16(bytes, 8),
+            product_id: read_u16(bytes, 10),
+        })
+    }
+}
+
+/// Register the host controller. Called by the BSP.
+pub fn register_host_controller(host_controller: &'static (dyn interface::HostController + Sync)) {
+    HOST_CONTROLLER.write(|x| *x = Some(host_controller));
+}
+
+/// Enumerate the devices on the bus.
+///
+/// Boards without a host controller driver are not an error. Returns an error if the device on the
+/// root port could not be enumerated. Errors behind hubs are only logged.
+pub fn init() -> Result<(), &'static str> {
+    let host_controller = match HOST_CONTROLLER.read(|x| *x) {
+        None => {
+            info!("USB: No host controller");
+            return Ok(());
+        }
+        Some(x) => x,
+    };
+
+    let speed = host_controller.reset_root_port()?;
+
+    let mut enumerator = Enumerator {
+        host_controller,
+        next_address: 1,
+    };
+    enumerator.enumerate(speed, None, 0)?;
+
+    hid::register_console_input();
+
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Setup packets are sent in little endian.
+    #[kernel_test]
+    fn setup_packet_layout() {
+        let setup = SetupPacket::get_descriptor(DESCRIPTOR_TYPE_CONFIGURATION, 0x109);
+
+        assert_eq!(setup.direction(), Direction::In);
+        assert_eq!(setup.to_bytes(), [0x80, 6, 0, 2, 0, 0, 0x09, 0x01]);
+        assert_eq!(SetupPacket::set_address(5).direction(), Direction::Out);
+    }
+
+    /// The interrupt endpoint of a keyboard's configuration is found, and its interval converted.
+    #[kernel_test]
+    fn configuration_walk() {
+        #[rustfmt::skip]
+        let config = [
+            9, DESCRIPTOR_TYPE_CONFIGURATION, 34, 0, 1, 1, 0, 0xA0, 50,
+            9, DESCRIPTOR_TYPE_INTERFACE, 0, 0, 1, CLASS_HID, 1, 1, 0,
+            9, 0x21, 0x11, 0x01, 0, 1, 0x22, 63, 0,
+            7, DESCRIPTOR_TYPE_ENDPOINT, 0x81, 0x03, 8, 0, 10,
+        ];
+        let device = Device {
+            address: 1,
+            speed: Speed::Full,
+            max_packet_size_0: 8,
+            transaction_translator: None,
+        };
+
+        let descriptors = Descriptors { remaining: &config };
+        assert_eq!(descriptors.count(), 4);
+
+        let interface = Descriptors { remaining: &config }
+            .find(|x| x[1] == DESCRIPTOR_TYPE_INTERFACE)
+            .and_then(InterfaceDescriptor::from_descriptor)
+            .unwrap();
+        assert!(hid::is_boot_keyboard(
+            interface.class,
+            interface.subclass,
+            interface.protocol
+        ));
+
+        let endpoint = Descriptors { remaining: &config }
+            .find(|x| x[1] == DESCRIPTOR_TYPE_ENDPOINT)
+            .and_then(|x| Endpoint::from_descriptor(device, x))
+            .unwrap();
+        assert_eq!(endpoint.number, 1);
+        assert_eq!(endpoint.direction, Direction::In);
+        assert_eq!(endpoint.max_packet_size, 8);
+        assert_eq!(endpoint.interval, Duration::from_millis(10));
+    }
+
+    /// A descriptor that claims to be longer than the remaining bytes ends the walk.
+    #[kernel_test]
+    fn configuration_walk_stops_at_truncation() {
+        #[rustfmt::skip]
+        let config = [9, DESCRIPTOR_TYPE_CONFIGURATION, 9, 0, 0, 1, 0, 0, 0, 7, 5, 0x81];
+
+        assert_eq!(Descriptors { remaining: &config }.count(), 1);
+    }
+}
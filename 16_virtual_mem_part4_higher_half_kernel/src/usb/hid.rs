@@ -0,0 +1,444 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! HID boot protocol keyboards.
+//!
+//! Keyboards are switched to the boot protocol, whose reports have a fixed layout: a byte of
+//! modifier bits, a reserved byte, and the usage IDs of up to six keys that are held down. A key
+//! that is in a report but not in the one before was pressed. Its character, for a US layout, is
+//! queued for the console, which reads from the queue alongside its own input.
+//!
+//! Keys do not repeat while they are held down.
+
+use super::{interface::HostController, Endpoint, SetupPacket};
+use crate::{
+    console, executor,
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+    time, warn,
+};
+use core::{
+    task::{Context, Poll},
+    time::Duration,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const SUBCLASS_BOOT: u8 = 1;
+const PROTOCOL_KEYBOARD: u8 = 1;
+
+const REQUEST_SET_IDLE: u8 = 0x0A;
+const REQUEST_SET_PROTOCOL: u8 = 0x0B;
+
+/// Class request, host to device, addressed to an interface.
+const REQUEST_TYPE_INTERFACE_OUT: u8 = 0x21;
+
+/// The `SET_PROTOCOL` value for the boot protocol.
+const BOOT_PROTOCOL: u16 = 0;
+
+const REPORT_SIZE: usize = 8;
+
+const MAX_KEYBOARDS: usize = 4;
+
+/// Characters that were typed but not read yet. Further characters are dropped.
+const INPUT_QUEUE_SIZE: usize = 32;
+
+/// Keyboards are not polled more often than this, whatever their endpoints ask for.
+const MIN_POLL_INTERVAL: Duration = Duration::from_millis(8);
+
+const MODIFIERS_CTRL: u8 = 0x11;
+const MODIFIERS_SHIFT: u8 = 0x22;
+
+/// Reported in all key slots when more keys are held down than fit into a report.
+const USAGE_ERROR_ROLL_OVER: u8 = 0x01;
+
+const USAGE_A: u8 = 0x04;
+const USAGE_Z: u8 = 0x1D;
+const USAGE_SLASH: u8 = 0x38;
+const USAGE_CAPS_LOCK: u8 = 0x39;
+const USAGE_KEYPAD_SLASH: u8 = 0x54;
+const USAGE_KEYPAD_DOT: u8 = 0x63;
+
+/// Characters for the usages from `USAGE_A` to `USAGE_SLASH`.
+const KEYS: &[u8; 53] = b"abcdefghijklmnopqrstuvwxyz1234567890\n\x1b\x08\t -=[]\\#;'`,./";
+
+/// Characters for the usages from `USAGE_A` to `USAGE_SLASH`, with shift held down.
+const SHIFTED_KEYS: &[u8; 53] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ!@#$%^&*()\n\x1b\x08\t _+{}|~:\"~<>?";
+
+/// Characters for the usages from `USAGE_KEYPAD_SLASH` to `USAGE_KEYPAD_DOT`.
+const KEYPAD_KEYS: &[u8; 16] = b"/*-+\n1234567890.";
+
+#[derive(Copy, Clone)]
+struct KeyboardState {
+    previous: [u8; REPORT_SIZE],
+    caps_lock: bool,
+}
+
+#[derive(Copy, Clone)]
+struct Keyboard {
+    host_controller: &'static (dyn HostController + Sync),
+    endpoint: Endpoint,
+    state: KeyboardState,
+}
+
+struct InputQueue {
+    chars: [char; INPUT_QUEUE_SIZE],
+    head: usize,
+    len: usize,
+}
+
+/// The console input source that delivers the keyboards' characters.
+struct KeyboardInput;
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static KEYBOARDS: IRQSafeNullLock<[Option<Keyboard>; MAX_KEYBOARDS]> =
+    IRQSafeNullLock::new([None; MAX_KEYBOARDS]);
+
+static INPUT_QUEUE: IRQSafeNullLock<InputQueue> = IRQSafeNullLock::new(InputQueue::new());
+
+static INPUT_WAKER: executor::WakerCell = executor::WakerCell::new();
+
+static KEYBOARD_INPUT: KeyboardInput = KeyboardInput;
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// The character that a key produces, if any.
+fn usage_to_char(usage: u8, modifiers: u8, caps_lock: bool) -> Option<char> {
+    let is_shifted = modifiers & MODIFIERS_SHIFT != 0;
+
+    let c = match usage {
+        USAGE_A..=USAGE_SLASH => {
+            let is_letter = usage <= USAGE_Z;
+            let index = (usage - USAGE_A) as usize;
+
+            match is_shifted ^ (caps_lock && is_letter) {
+                false => KEYS[index],
+                true => SHIFTED_KEYS[index],
+            }
+        }
+        USAGE_KEYPAD_SLASH..=USAGE_KEYPAD_DOT => KEYPAD_KEYS[(usage - USAGE_KEYPAD_SLASH) as usize],
+        _ => return None,
+    };
+
+    // Ctrl turns letters into control characters, e.g. Ctrl-C into ETX.
+    if modifiers & MODIFIERS_CTRL != 0 && c.is_ascii_alphabetic() {
+        return Some((c.to_ascii_lowercase() - b'a' + 1) as char);
+    }
+
+    Some(c as char)
+}
+
+impl KeyboardState {
+    const fn new() -> Self {
+        Self {
+            previous: [0; REPORT_SIZE],
+            caps_lock: false,
+        }
+    }
+
+    /// Call `f` with the characters of the keys that were pressed since the previous report.
+    fn process_report(&mut self, report: &[u8; REPORT_SIZE], mut f: impl FnMut(char)) {
+        let modifiers = report[0];
+        let keys = &report[2..];
+
+        // The keys that are held down are unknown, so none of them can be told to be new.
+        if keys.contains(&USAGE_ERROR_ROLL_OVER) {
+            return;
+        }
+
+        for &usage in keys {
+            if usage == 0 || self.previous[2..].contains(&usage) {
+                continue;
+            }
+
+            if usage == USAGE_CAPS_LOCK {
+                self.caps_lock = !self.caps_lock;
+                continue;
+            }
+
+            if let Some(c) = usage_to_char(usage, modifiers, self.caps_lock) {
+                f(c);
+            }
+        }
+
+        self.previous = *report;
+    }
+}
+
+impl InputQueue {
+    const fn new() -> Self {
+        Self {
+            chars: ['\0'; INPUT_QUEUE_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, c: char) {
+        if self.len == INPUT_QUEUE_SIZE {
+            return;
+        }
+
+        self.chars[(self.head + self.len) % INPUT_QUEUE_SIZE] = c;
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<char> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let c = self.chars[self.head];
+        self.head = (self.head + 1) % INPUT_QUEUE_SIZE;
+        self.len -= 1;
+
+        Some(c)
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+impl Keyboard {
+    /// Fetch a report, if there is a new one, and queue the characters of newly pressed keys.
+    fn poll(&mut self) -> Result<(), &'static str> {
+        let mut report = [0; REPORT_SIZE];
+
+        match self
+            .host_controller
+            .interrupt_transfer(&mut self.endpoint, &mut report)
+        {
+            Err(super::NAK_ERROR) => return Ok(()),
+            Err(x) => return Err(x),
+            // Boot keyboards always send full reports.
+            Ok(len) if len < REPORT_SIZE => return Ok(()),
+            Ok(_) => (),
+        }
+
+        let mut is_input_queued = false;
+        INPUT_QUEUE.lock(|queue| {
+            self.state.process_report(&report, |c| {
+                queue.push(c);
+                is_input_queued = true;
+            })
+        });
+
+        if is_input_queued {
+            INPUT_WAKER.wake();
+        }
+
+        Ok(())
+    }
+}
+
+/// Poll every keyboard once. Keyboards that fail are dropped.
+///
+/// Returns the time until the next poll is due, or `None` if there are no keyboards.
+fn poll_keyboards() -> Option<Duration> {
+    KEYBOARDS.lock(|keyboards| {
+        let mut interval: Option<Duration> = None;
+
+        for slot in keyboards.iter_mut() {
+            let keyboard = match slot {
+                None => continue,
+                Some(x) => x,
+            };
+
+            if let Err(x) = keyboard.poll() {
+                warn!(
+                    "USB keyboard {} disabled: {}",
+                    keyboard.endpoint.device.address, x
+                );
+                *slot = None;
+                continue;
+            }
+
+            let keyboard_interval = keyboard.endpoint.interval.max(MIN_POLL_INTERVAL);
+            interval = Some(interval.map_or(keyboard_interval, |x| x.min(keyboard_interval)));
+        }
+
+        interval
+    })
+}
+
+impl console::interface::Read for KeyboardInput {
+    fn read_char(&self) -> char {
+        use time::interface::TimeManager;
+
+        loop {
+            if let Some(c) = INPUT_QUEUE.lock(|queue| queue.pop()) {
+                return c;
+            }
+
+            let interval = poll_keyboards().unwrap_or(MIN_POLL_INTERVAL);
+            time::time_manager().spin_for(interval);
+        }
+    }
+
+    fn poll_read_char(&self, cx: &mut Context) -> Poll<char> {
+        // Register before checking the queue, so that a character that is queued right afterwards
+        // will wake the reader.
+        INPUT_WAKER.register(cx.waker());
+
+        match INPUT_QUEUE.lock(|queue| queue.pop()) {
+            Some(c) => Poll::Ready(c),
+            None => Poll::Pending,
+        }
+    }
+
+    fn clear_rx(&self) {
+        INPUT_QUEUE.lock(|queue| queue.clear());
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Whether an interface with the given class codes is a keyboard that supports the boot protocol.
+pub(super) fn is_boot_keyboard(class: u8, subclass: u8, protocol: u8) -> bool {
+    class == super::CLASS_HID && subclass == SUBCLASS_BOOT && protocol == PROTOCOL_KEYBOARD
+}
+
+/// Switch a keyboard interface to the boot protocol and start taking its input.
+pub(super) fn attach_keyboard(
+    host_controller: &'static (dyn HostController + Sync),
+    interface: u8,
+    endpoint: Endpoint,
+) -> Result<(), &'static str> {
+    let device = endpoint.device;
+
+    let set_protocol = SetupPacket::new(
+        REQUEST_TYPE_INTERFACE_OUT,
+        REQUEST_SET_PROTOCOL,
+        BOOT_PROTOCOL,
+        interface as u16,
+        0,
+    );
+    host_controller.control_transfer(&device, &set_protocol, &mut [])?;
+
+    // Only report changes. The request is optional for keyboards, so a stall is fine.
+    let set_idle = SetupPacket::new(
+        REQUEST_TYPE_INTERFACE_OUT,
+        REQUEST_SET_IDLE,
+        0,
+        interface as u16,
+        0,
+    );
+    match host_controller.control_transfer(&device, &set_idle, &mut []) {
+        Ok(_) | Err(super::STALL_ERROR) => (),
+        Err(x) => return Err(x),
+    }
+
+    let keyboard = Keyboard {
+        host_controller,
+        endpoint,
+        state: KeyboardState::new(),
+    };
+
+    KEYBOARDS.lock(
+        |keyboards| match keyboards.iter_mut().find(|x| x.is_none()) {
+            None => Err("Too many USB keyboards"),
+            Some(slot) => {
+                *slot = Some(keyboard);
+                Ok(())
+            }
+        },
+    )
+}
+
+/// Make the console read from the keyboards, if there are any.
+pub(super) fn register_console_input() {
+    if KEYBOARDS.lock(|keyboards| keyboards.iter().any(Option::is_some)) {
+        console::register_input_source(&KEYBOARD_INPUT);
+    }
+}
+
+/// Poll the keyboards at the intervals their endpoints ask for.
+///
+/// Returns once there are no keyboards, which is right away if none were found.
+pub async fn run() {
+    while let Some(interval) = poll_keyboards() {
+        time::sleep_for(interval).await;
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    const USAGE_1: u8 = 0x1E;
+    const USAGE_ENTER: u8 = 0x28;
+
+    fn report(modifiers: u8, keys: &[u8]) -> [u8; REPORT_SIZE] {
+        let mut report = [0; REPORT_SIZE];
+        report[0] = modifiers;
+        report[2..2 + keys.len()].copy_from_slice(keys);
+
+        report
+    }
+
+    /// Pressed keys produce characters according to the modifiers.
+    #[kernel_test]
+    fn usage_translation() {
+        assert_eq!(usage_to_char(USAGE_A, 0, false), Some('a'));
+        assert_eq!(usage_to_char(USAGE_A, 0x02, false), Some('A'));
+        assert_eq!(usage_to_char(USAGE_A, 0, true), Some('A'));
+        assert_eq!(usage_to_char(USAGE_A, 0x20, true), Some('a'));
+        assert_eq!(usage_to_char(USAGE_1, 0, true), Some('1'));
+        assert_eq!(usage_to_char(USAGE_1, 0x20, false), Some('!'));
+        assert_eq!(usage_to_char(USAGE_SLASH, 0x02, false), Some('?'));
+        assert_eq!(usage_to_char(USAGE_ENTER, 0, false), Some('\n'));
+        assert_eq!(usage_to_char(0x06, 0x01, false), Some('\x03'));
+        assert_eq!(usage_to_char(USAGE_KEYPAD_DOT, 0, false), Some('.'));
+        assert_eq!(usage_to_char(USAGE_CAPS_LOCK, 0, false), None);
+    }
+
+    /// Only keys that were not held down before produce characters.
+    #[kernel_test]
+    fn report_new_presses_only() {
+        let mut state = KeyboardState::new();
+        let mut typed = InputQueue::new();
+
+        state.process_report(&report(0, &[USAGE_A]), |c| typed.push(c));
+        state.process_report(&report(0, &[USAGE_A, USAGE_Z]), |c| typed.push(c));
+        state.process_report(&report(0, &[USAGE_Z]), |c| typed.push(c));
+        state.process_report(&report(0, &[USAGE_Z, USAGE_A]), |c| typed.push(c));
+
+        assert_eq!(typed.pop(), Some('a'));
+        assert_eq!(typed.pop(), Some('z'));
+        assert_eq!(typed.pop(), Some('a'));
+        assert_eq!(typed.pop(), None);
+    }
+
+    /// Caps lock toggles on each press, and rollover reports are ignored.
+    #[kernel_test]
+    fn report_caps_lock_and_rollover() {
+        let mut state = KeyboardState::new();
+        let mut typed = InputQueue::new();
+
+        state.process_report(&report(0, &[USAGE_CAPS_LOCK]), |c| typed.push(c));
+        state.process_report(&report(0, &[]), |c| typed.push(c));
+        state.process_report(&report(0, &[USAGE_A]), |c| typed.push(c));
+        state.process_report(&report(0, &[USAGE_ERROR_ROLL_OVER; 6]), |c| typed.push(c));
+        state.process_report(&report(0, &[USAGE_A, USAGE_Z]), |c| typed.push(c));
+
+        assert!(state.caps_lock);
+        assert_eq!(typed.pop(), Some('A'));
+        assert_eq!(typed.pop(), Some('Z'));
+        assert_eq!(typed.pop(), None);
+    }
+}
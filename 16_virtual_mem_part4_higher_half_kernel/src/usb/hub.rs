@@ -0,0 +1,233 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! USB hubs.
+//!
+//! All ports are powered, then reset and enumerated one after the other. Port status changes are
+//! not watched afterwards.
+
+use super::{Device, Enumerator, SetupPacket, Speed, TransactionTranslator};
+use crate::{time, warn};
+use core::time::Duration;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const DESCRIPTOR_TYPE_HUB: u8 = 0x29;
+
+const REQUEST_GET_STATUS: u8 = 0;
+const REQUEST_CLEAR_FEATURE: u8 = 1;
+const REQUEST_SET_FEATURE: u8 = 3;
+const REQUEST_GET_DESCRIPTOR: u8 = 6;
+
+/// Class request, device to host, addressed to the hub.
+const REQUEST_TYPE_HUB_IN: u8 = 0xA0;
+
+/// Class request, device to host, addressed to a port.
+const REQUEST_TYPE_PORT_IN: u8 = 0xA3;
+
+/// Class request, host to device, addressed to a port.
+const REQUEST_TYPE_PORT_OUT: u8 = 0x23;
+
+const FEATURE_PORT_RESET: u16 = 4;
+const FEATURE_PORT_POWER: u16 = 8;
+const FEATURE_C_PORT_CONNECTION: u16 = 16;
+const FEATURE_C_PORT_RESET: u16 = 20;
+
+const PORT_STATUS_CONNECTION: u16 = 1 << 0;
+const PORT_STATUS_ENABLE: u16 = 1 << 1;
+const PORT_STATUS_LOW_SPEED: u16 = 1 << 9;
+const PORT_STATUS_HIGH_SPEED: u16 = 1 << 10;
+
+const PORT_CHANGE_RESET: u16 = 1 << 4;
+
+/// USB 2.0 allows five hubs between the root port and a device.
+const MAX_HUB_DEPTH: usize = 5;
+
+/// How often the port status is read while waiting for a reset to finish.
+const RESET_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+const RESET_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The time a device may take to recover from a reset. USB 2.0, section 7.1.7.3.
+const RESET_RECOVERY: Duration = Duration::from_millis(10);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn set_port_feature(
+    enumerator: &Enumerator,
+    hub: &Device,
+    port: u8,
+    feature: u16,
+) -> Result<(), &'static str> {
+    let setup = SetupPacket::new(
+        REQUEST_TYPE_PORT_OUT,
+        REQUEST_SET_FEATURE,
+        feature,
+        port as u16,
+        0,
+    );
+
+    enumerator
+        .host_controller
+        .control_transfer(hub, &setup, &mut [])
+        .map(|_| ())
+}
+
+fn clear_port_feature(
+    enumerator: &Enumerator,
+    hub: &Device,
+    port: u8,
+    feature: u16,
+) -> Result<(), &'static str> {
+    let setup = SetupPacket::new(
+        REQUEST_TYPE_PORT_OUT,
+        REQUEST_CLEAR_FEATURE,
+        feature,
+        port as u16,
+        0,
+    );
+
+    enumerator
+        .host_controller
+        .control_transfer(hub, &setup, &mut [])
+        .map(|_| ())
+}
+
+/// Return the port's status and change bits.
+fn port_status(
+    enumerator: &Enumerator,
+    hub: &Device,
+    port: u8,
+) -> Result<(u16, u16), &'static str> {
+    let setup = SetupPacket::new(REQUEST_TYPE_PORT_IN, REQUEST_GET_STATUS, 0, port as u16, 4);
+    let mut buf = [0; 4];
+
+    if enumerator
+        .host_controller
+        .control_transfer(hub, &setup, &mut buf)?
+        < buf.len()
+    {
+        return Err("Truncated USB hub port status");
+    }
+
+    Ok((
+        u16::from_le_bytes([buf[0], buf[1]]),
+        u16::from_le_bytes([buf[2], buf[3]]),
+    ))
+}
+
+/// Reset the port and enumerate the device attached to it, if any.
+fn enumerate_port(
+    enumerator: &mut Enumerator,
+    hub: &Device,
+    port: u8,
+    depth: usize,
+) -> Result<(), &'static str> {
+    use time::interface::TimeManager;
+
+    let (status, _) = port_status(enumerator, hub, port)?;
+    if status & PORT_STATUS_CONNECTION == 0 {
+        return Ok(());
+    }
+
+    clear_port_feature(enumerator, hub, port, FEATURE_C_PORT_CONNECTION)?;
+    set_port_feature(enumerator, hub, port, FEATURE_PORT_RESET)?;
+
+    let deadline = time::time_manager().uptime() + RESET_TIMEOUT;
+    let status = loop {
+        time::time_manager().spin_for(RESET_POLL_INTERVAL);
+
+        let (status, change) = port_status(enumerator, hub, port)?;
+        if change & PORT_CHANGE_RESET != 0 {
+            break status;
+        }
+
+        if time::time_manager().uptime() >= deadline {
+            return Err("Timeout resetting the port");
+        }
+    };
+    clear_port_feature(enumerator, hub, port, FEATURE_C_PORT_RESET)?;
+
+    if status & PORT_STATUS_ENABLE == 0 {
+        return Err("Port was not enabled by the reset");
+    }
+
+    let speed = if status & PORT_STATUS_LOW_SPEED != 0 {
+        Speed::Low
+    } else if status & PORT_STATUS_HIGH_SPEED != 0 {
+        Speed::High
+    } else {
+        Speed::Full
+    };
+
+    // Low and full speed devices are reached through the first high speed hub on their way.
+    let transaction_translator = match (hub.speed, speed) {
+        (Speed::High, Speed::Low | Speed::Full) => Some(TransactionTranslator {
+            hub_address: hub.address,
+            port,
+        }),
+        _ => hub.transaction_translator,
+    };
+
+    time::time_manager().spin_for(RESET_RECOVERY);
+
+    enumerator.enumerate(speed, transaction_translator, depth + 1)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Power the ports of a configured hub and enumerate the devices attached to them.
+///
+/// `depth` is the number of hubs between this hub and the root port. Errors on a port are logged
+/// and do not keep the other ports from being enumerated.
+pub(super) fn enumerate_ports(
+    enumerator: &mut Enumerator,
+    hub: Device,
+    depth: usize,
+) -> Result<(), &'static str> {
+    use time::interface::TimeManager;
+
+    if depth >= MAX_HUB_DEPTH {
+        return Err("USB hubs are nested too deeply");
+    }
+
+    let setup = SetupPacket::new(
+        REQUEST_TYPE_HUB_IN,
+        REQUEST_GET_DESCRIPTOR,
+        (DESCRIPTOR_TYPE_HUB as u16) << 8,
+        0,
+        7,
+    );
+    let mut descriptor = [0; 7];
+    if enumerator
+        .host_controller
+        .control_transfer(&hub, &setup, &mut descriptor)?
+        < descriptor.len()
+    {
+        return Err("Truncated USB hub descriptor");
+    }
+
+    let num_ports = descriptor[2];
+    // Given in units of 2 ms.
+    let power_on_to_power_good = Duration::from_millis(descriptor[5] as u64 * 2);
+
+    for port in 1..=num_ports {
+        set_port_feature(enumerator, &hub, port, FEATURE_PORT_POWER)?;
+    }
+    time::time_manager().spin_for(power_on_to_power_good);
+
+    for port in 1..=num_ports {
+        if let Err(x) = enumerate_port(enumerator, &hub, port, depth) {
+            warn!("USB hub {}, port {}: {}", hub.address, port, x);
+        }
+    }
+
+    Ok(())
+}
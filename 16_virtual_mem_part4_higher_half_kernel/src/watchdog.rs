@@ -0,0 +1,32 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Watchdog.
+//!
+//! Once armed, a watchdog resets the board unless it is pet within its timeout.
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Watchdog interfaces.
+pub mod interface {
+    use core::time::Duration;
+
+    /// Watchdog functions.
+    pub trait Watchdog {
+        /// Arm the watchdog. The board is reset unless [`Watchdog::pet()`] is called within
+        /// `timeout`.
+        fn start(&self, timeout: Duration) -> Result<(), &'static str>;
+
+        /// Restart the timeout of an armed watchdog.
+        fn pet(&self);
+
+        /// Disarm the watchdog.
+        fn stop(&self);
+
+        /// The time until the board is reset, or `None` if the watchdog is not armed.
+        fn time_left(&self) -> Option<Duration>;
+    }
+}
@@ -11,16 +11,21 @@
 #![test_runner(libkernel::test_runner)]
 
 use core::time::Duration;
-use libkernel::{bsp, cpu, exception, memory, time, time::interface::TimeManager};
+use libkernel::{
+    bsp, cpu, driver, exception, executor, memory, time, time::interface::TimeManager,
+};
 use test_macros::kernel_test;
 
 #[no_mangle]
 unsafe fn kernel_init() -> ! {
     exception::handling_init();
     memory::mmu::post_enable_init();
-    bsp::console::qemu_bring_up_console();
 
-    // Depending on CPU arch, some timer bring-up code could go here. Not needed for the RPi.
+    // The interrupt controller comes up with the drivers.
+    bsp::driver::init().unwrap_or_else(|_| cpu::qemu_exit_failure());
+    driver::driver_manager().init_drivers();
+    time::register_and_enable_irq_handler().unwrap_or_else(|_| cpu::qemu_exit_failure());
+    exception::asynchronous::local_irq_unmask();
 
     test_main();
 
@@ -48,3 +53,19 @@ fn spin_accuracy_check_1_second() {
 
     assert_eq!((t2 - t1).as_secs(), 1)
 }
+
+/// Sleeping until a deadline that already passed must not wait for the timer IRQ.
+#[kernel_test]
+fn sleep_with_elapsed_deadline_is_ready() {
+    executor::block_on(time::sleep_until(Duration::ZERO)).unwrap();
+}
+
+/// Sleeping is ended by the architectural timer's IRQ.
+#[kernel_test]
+fn sleep_is_woken_by_timer_irq() {
+    let t1 = time::time_manager().uptime();
+    executor::block_on(time::sleep_for(Duration::from_millis(10))).unwrap();
+    let t2 = time::time_manager().uptime();
+
+    assert!(t2 - t1 >= Duration::from_millis(10))
+}
@@ -0,0 +1,35 @@
+# frozen_string_literal: true
+
+# SPDX-License-Identifier: MIT OR Apache-2.0
+#
+# Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+require 'expect'
+
+TIMEOUT_SECS = 3
+
+# Error class for when expect times out.
+class ExpectTimeoutError < StandardError
+    def initialize
+        super('Timeout while expecting string')
+    end
+end
+
+# Verify that the overflow is reported as such, and not as some other fault.
+class StackOverflowReported
+    def name
+        'Boot-core stack overflow reported'
+    end
+
+    def run(qemu_out, _qemu_in)
+        expected = 'Kernel stack overflow! Stack: boot-core'
+        raise ExpectTimeoutError if qemu_out.expect(expected, TIMEOUT_SECS).nil?
+    end
+end
+
+##--------------------------------------------------------------------------------------------------
+## Test registration
+##--------------------------------------------------------------------------------------------------
+def subtest_collection
+    [StackOverflowReported.new]
+end
@@ -0,0 +1,54 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! A kernel stack overflow must hit the guard page and be reported from the exception stack.
+//!
+//! Any panic exits QEMU with success, so `04_exception_stack_overflow.rb` checks that the panic
+//! reports the overflow.
+
+#![feature(format_args_nl)]
+#![no_main]
+#![no_std]
+
+/// Overwrites libkernel's `panic_wait::_panic_exit()` so that it returns a "success" code.
+///
+/// In this test, reaching the panic is a success, because it is called from the synchronous
+/// exception handler, which is what this test wants to achieve.
+///
+/// It also means that this integration test can not use any other code that calls panic!() directly
+/// or indirectly.
+mod panic_exit_success;
+
+use libkernel::{bsp, cpu, exception, memory, println};
+
+/// Recurse until the stack overflows. The volatile accesses keep the frames from being optimized
+/// away.
+#[inline(never)]
+fn recurse(depth: u64) -> u64 {
+    let mut frame = [depth; 64];
+    unsafe { core::ptr::write_volatile(&mut frame[63], depth) };
+
+    recurse(depth + 1) + unsafe { core::ptr::read_volatile(&frame[0]) }
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+    memory::mmu::post_enable_init();
+    bsp::console::qemu_bring_up_console();
+
+    // Without the registered stacks, the overflow would be reported as a plain data abort.
+    if memory::stack::register_bsp_stacks().is_err() {
+        cpu::qemu_exit_failure()
+    }
+
+    // This line will be printed as the test header.
+    println!("Testing kernel stack overflow detection");
+
+    println!("Overflowing the boot-core stack...");
+    recurse(0);
+
+    // If execution reaches here, the stack did not overflow into the guard page.
+    cpu::qemu_exit_failure()
+}
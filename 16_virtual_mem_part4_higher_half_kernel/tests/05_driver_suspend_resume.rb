@@ -0,0 +1,47 @@
+# frozen_string_literal: true
+
+# SPDX-License-Identifier: MIT OR Apache-2.0
+#
+# Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+require 'expect'
+
+TIMEOUT_SECS = 3
+
+# Error class for when expect times out.
+class ExpectTimeoutError < StandardError
+    def initialize
+        super('Timeout while expecting string')
+    end
+end
+
+# Verify the kernel wakes up from the suspended state.
+class SuspendResume
+    def name
+        'Suspend and resume'
+    end
+
+    def run(qemu_out, _qemu_in)
+        raise ExpectTimeoutError if qemu_out.expect('Suspending', TIMEOUT_SECS).nil?
+        raise ExpectTimeoutError if qemu_out.expect('Resumed', TIMEOUT_SECS).nil?
+    end
+end
+
+# Verify sending and receiving works after the resume. Depends on test 1 being run first.
+class TxRxAfterResume
+    def name
+        'Transmit and Receive after resume'
+    end
+
+    def run(qemu_out, qemu_in)
+        qemu_in.write_nonblock('AB')
+        raise ExpectTimeoutError if qemu_out.expect('OK', TIMEOUT_SECS).nil?
+    end
+end
+
+##--------------------------------------------------------------------------------------------------
+## Test registration
+##--------------------------------------------------------------------------------------------------
+def subtest_collection
+    [SuspendResume.new, TxRxAfterResume.new]
+end
@@ -0,0 +1,60 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Driver suspend and resume tests.
+//!
+//! The I/O test harness checks that the console transmits and receives after the drivers were
+//! suspended and resumed.
+
+#![feature(format_args_nl)]
+#![no_main]
+#![no_std]
+
+use core::time::Duration;
+use libkernel::{bsp, console, cpu, driver, exception, memory, print, time};
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    use bsp::console::console;
+    use console::interface::*;
+    use time::interface::TimeManager;
+
+    exception::handling_init();
+    memory::mmu::post_enable_init();
+
+    // Bring up all drivers, so that the console is resumed together with its dependencies.
+    bsp::driver::init().unwrap_or_else(|_| cpu::qemu_exit_failure());
+    driver::driver_manager().init_drivers();
+
+    // All drivers come up, except for optional ones, which might not find their hardware.
+    driver::driver_manager().enumerate(|_, x| {
+        if !x.is_optional() && (x.state() != driver::DriverState::Ready) {
+            cpu::qemu_exit_failure()
+        }
+    });
+
+    print!("Suspending");
+
+    let deadline = time::time_manager().uptime() + Duration::from_millis(100);
+    exception::asynchronous::exec_with_irq_masked(|| {
+        driver::driver_manager()
+            .suspend_drivers()
+            .unwrap_or_else(|_| cpu::qemu_exit_failure());
+        time::idle_until(deadline);
+        driver::driver_manager().resume_drivers();
+    });
+
+    if time::time_manager().uptime() < deadline {
+        cpu::qemu_exit_failure()
+    }
+
+    // Handshake
+    print!("Resumed");
+    assert_eq!(console().read_char(), 'A');
+    assert_eq!(console().read_char(), 'B');
+    print!("OK");
+
+    // The QEMU process running this test will be closed by the I/O test harness.
+    cpu::wait_forever();
+}
@@ -0,0 +1,72 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Board timer sanity tests.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+use core::time::Duration;
+use libkernel::{
+    bsp, cmdline, cpu, driver, exception, executor, memory, time, time::interface::TimeManager,
+};
+use test_macros::kernel_test;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+    memory::mmu::post_enable_init();
+
+    // The board timer is picked up while the drivers come up.
+    cmdline::init("clocksource=board");
+    bsp::driver::init().unwrap_or_else(|_| cpu::qemu_exit_failure());
+    driver::driver_manager().init_drivers();
+    driver::driver_manager().register_and_enable_irq_handlers();
+    exception::asynchronous::local_irq_unmask();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// The board timer is in use.
+#[kernel_test]
+fn board_timer_is_selected() {
+    assert_eq!(time::clock_source(), time::ClockSource::Board)
+}
+
+/// Simple check that the timer is running.
+#[kernel_test]
+fn timer_is_counting() {
+    assert!(time::time_manager().uptime().as_nanos() > 0)
+}
+
+/// The system timer counts microseconds.
+#[kernel_test]
+fn timer_resolution_is_one_microsecond() {
+    assert_eq!(time::time_manager().resolution(), Duration::from_micros(1))
+}
+
+/// Sanity check spin_for() implementation.
+#[kernel_test]
+fn spin_accuracy_check_1_second() {
+    let t1 = time::time_manager().uptime();
+    time::time_manager().spin_for(Duration::from_secs(1));
+    let t2 = time::time_manager().uptime();
+
+    assert_eq!((t2 - t1).as_secs(), 1)
+}
+
+/// Sleeping is ended by the timer IRQ.
+#[kernel_test]
+fn sleep_is_woken_by_timer_irq() {
+    let t1 = time::time_manager().uptime();
+    executor::block_on(time::sleep_for(Duration::from_millis(10))).unwrap();
+    let t2 = time::time_manager().uptime();
+
+    assert!(t2 - t1 >= Duration::from_millis(10))
+}
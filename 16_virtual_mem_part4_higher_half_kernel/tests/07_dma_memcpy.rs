@@ -0,0 +1,124 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! DMA memory to memory copy tests.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+use libkernel::{
+    bsp, cpu, driver, exception, executor,
+    memory::{
+        self,
+        dma::{DmaBuffer, DmaDirection},
+    },
+};
+use test_macros::kernel_test;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+    memory::mmu::post_enable_init();
+
+    // Completion is signaled with an IRQ.
+    bsp::driver::init().unwrap_or_else(|_| cpu::qemu_exit_failure());
+    driver::driver_manager().init_drivers();
+    driver::driver_manager().register_and_enable_irq_handlers();
+    exception::asynchronous::local_irq_unmask();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// A buffer of `size` bytes with a recognizable pattern.
+fn pattern_buffer(size: usize) -> DmaBuffer {
+    let mut buffer = DmaBuffer::new(size, 1).unwrap();
+
+    for (i, x) in buffer.as_mut_slice().iter_mut().enumerate() {
+        *x = (i % 251) as u8;
+    }
+
+    buffer
+}
+
+/// A copy that spans several control blocks arrives intact.
+#[kernel_test]
+fn dma_copy_memory_to_memory() {
+    let size = 3 * 64 * 1024 + 123;
+
+    let src = pattern_buffer(size).give_to_device(DmaDirection::ToDevice);
+    let dest = DmaBuffer::new(size, 1)
+        .unwrap()
+        .give_to_device(DmaDirection::FromDevice);
+
+    // `block_on()` polls the transfer to completion.
+    executor::block_on(unsafe { bsp::driver::dma().copy(&dest, &src) })
+        .unwrap()
+        .unwrap();
+
+    let src = src.take_from_device();
+    let dest = dest.take_from_device();
+    assert_eq!(dest.as_slice(), src.as_slice());
+}
+
+/// A chain gathers segments in the order they were pushed.
+#[kernel_test]
+fn dma_scatter_gather() {
+    let half = 1000;
+
+    let src = pattern_buffer(2 * half).give_to_device(DmaDirection::ToDevice);
+    let dest = DmaBuffer::new(2 * half, 1)
+        .unwrap()
+        .give_to_device(DmaDirection::FromDevice);
+
+    let mut channel = bsp::driver::dma().allocate_channel().unwrap();
+    let mut chain = bsp::driver::dma().new_chain(2).unwrap();
+    chain.push_copy(&dest, 0, &src, half, half).unwrap();
+    chain.push_copy(&dest, half, &src, 0, half).unwrap();
+    assert_eq!(chain.len(), 2);
+
+    executor::block_on(unsafe { channel.start(chain) }.unwrap())
+        .unwrap()
+        .unwrap();
+
+    let src = src.take_from_device();
+    let dest = dest.take_from_device();
+    assert_eq!(dest.as_slice()[..half], src.as_slice()[half..]);
+    assert_eq!(dest.as_slice()[half..], src.as_slice()[..half]);
+}
+
+/// Segments outside a buffer, or against the buffer's direction, are rejected.
+#[kernel_test]
+fn dma_rejects_invalid_segments() {
+    let src = DmaBuffer::new(100, 1)
+        .unwrap()
+        .give_to_device(DmaDirection::ToDevice);
+    let dest = DmaBuffer::new(100, 1)
+        .unwrap()
+        .give_to_device(DmaDirection::FromDevice);
+
+    let mut chain = bsp::driver::dma().new_chain(1).unwrap();
+    assert!(chain.push_copy(&dest, 0, &src, 50, 51).is_err());
+    assert!(chain.push_copy(&src, 0, &dest, 0, 100).is_err());
+    assert!(chain.push_copy(&dest, 0, &src, 0, 0).is_err());
+    assert!(chain.is_empty());
+
+    chain.push_copy(&dest, 0, &src, 0, 100).unwrap();
+    assert!(chain.push_copy(&dest, 0, &src, 0, 100).is_err());
+}
+
+/// Channels are returned to the controller when dropped.
+#[kernel_test]
+fn dma_channels_are_freed() {
+    let a = bsp::driver::dma().allocate_channel().unwrap();
+    let index = a.index();
+    drop(a);
+
+    let b = bsp::driver::dma().allocate_channel().unwrap();
+    assert_eq!(b.index(), index);
+}
@@ -0,0 +1,54 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2022 Andre Richter <andre.o.richter@gmail.com>
+
+//! Driver registration tests.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+use libkernel::{bsp, cpu, driver, exception, memory};
+use test_macros::kernel_test;
+
+#[cfg(feature = "bsp_rpi3")]
+const INTERRUPT_CONTROLLER_COMPATIBLE: &str = "BCM Interrupt Controller";
+
+#[cfg(feature = "bsp_rpi4")]
+const INTERRUPT_CONTROLLER_COMPATIBLE: &str = "GICv2 (ARM Generic Interrupt Controller v2)";
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+    memory::mmu::post_enable_init();
+    bsp::console::qemu_bring_up_console();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// The driver manager has room for all of the BSP's drivers, and the interrupt controller comes
+/// first.
+#[kernel_test]
+fn bsp_drivers_register() {
+    assert_eq!(bsp::driver::init(), Ok(()));
+
+    let mut num_drivers = 0;
+    driver::driver_manager().enumerate(|i, x| {
+        if i == 0 {
+            assert_eq!(x.compatible(), INTERRUPT_CONTROLLER_COMPATIBLE);
+        }
+        assert_eq!(x.state(), driver::DriverState::Registered);
+
+        num_drivers += 1;
+    });
+
+    #[cfg(feature = "bsp_rpi3")]
+    assert_eq!(num_drivers, 10);
+
+    #[cfg(feature = "bsp_rpi4")]
+    assert_eq!(num_drivers, 8);
+}
@@ -56,11 +56,14 @@ module Arch
 # Arch::ARMv8
 #---------------------------------------------------------------------------------------------------
 module ARMv8
+# Descriptors store addresses in bits [47:12], regardless of the granule.
+ADDR_FIELD_SHIFT = Granule4KiB::SHIFT
+
 # ARMv8 Table Descriptor.
 class Stage1TableDescriptor < BitField
     module NextLevelTableAddr
-        OFFSET = 16
-        NUMBITS = 32
+        OFFSET = 12
+        NUMBITS = 36
     end
 
     module Type
@@ -84,7 +87,7 @@ class Stage1TableDescriptor < BitField
     attr_bitfield(:valid, Valid::OFFSET, Valid::NUMBITS)
 
     def next_level_table_addr=(addr)
-        addr = addr >> Granule64KiB::SHIFT
+        addr = addr >> ADDR_FIELD_SHIFT
 
         self.__next_level_table_addr = addr
     end
@@ -111,8 +114,8 @@ class Stage1PageDescriptor < BitField
     end
 
     module OutputAddr
-        OFFSET = 16
-        NUMBITS = 32
+        OFFSET = 12
+        NUMBITS = 36
     end
 
     module AF
@@ -170,7 +173,7 @@ class Stage1PageDescriptor < BitField
     attr_bitfield(:valid, Valid::OFFSET, Valid::NUMBITS)
 
     def output_addr=(addr)
-        addr = addr >> Granule64KiB::SHIFT
+        addr = addr >> ADDR_FIELD_SHIFT
 
         self.__output_addr = addr
     end
@@ -178,23 +181,29 @@ class Stage1PageDescriptor < BitField
     private :__output_addr=
 end
 
-# Translation table representing the structure defined in translation_table.rs.
+# Translation tables representing the structure defined in translation_table.rs.
+#
+# Tables are only created for the parts of the address space that the kernel binary occupies. They
+# are placed in the precomputed table frames, with the root table in the first frame.
 class TranslationTable
     module MAIR
         NORMAL = 1
     end
 
     def initialize
-        do_sanity_checks
+        @num_table_entries = BSP.kernel_granule::SIZE / 8
+        @lvl2_granule_shift = BSP.kernel_granule::SHIFT + Math.log2(@num_table_entries).to_i
 
-        num_lvl2_tables = BSP.kernel_virt_addr_space_size >> Granule512MiB::SHIFT
+        do_sanity_checks
 
-        @lvl3 = new_lvl3(num_lvl2_tables, BSP.phys_addr_of_kernel_tables)
+        @num_lvl2_descriptors = BSP.kernel_virt_addr_space_size >> @lvl2_granule_shift
 
-        @lvl2_phys_start_addr = @lvl3.phys_start_addr + @lvl3.size_in_byte
-        @lvl2 = new_lvl2(num_lvl2_tables, @lvl2_phys_start_addr)
+        @frames = []
+        @root = new_table(Stage1TableDescriptor)
 
-        populate_lvl2_entries
+        # Indexed by the lvl1 and the global lvl2 index, respectively.
+        @lvl2_tables = {}
+        @lvl3_tables = {}
     end
 
     def map_at(virt_region, phys_region, attributes)
@@ -209,59 +218,84 @@ class TranslationTable
         end
     end
 
+    # Unused frames are filled with zeros.
     def to_binary
-        data = @lvl3.flatten.map(&:to_i) + @lvl2.map(&:to_i)
+        data = @frames.flatten.map(&:to_i)
+        data += [0] * ((BSP.num_precomputed_table_frames * @num_table_entries) - data.size)
         data.pack('Q<*') # "Q" == uint64_t, "<" == little endian
     end
 
     def phys_tables_base_addr_binary
-        [@lvl2_phys_start_addr].pack('Q<*') # "Q" == uint64_t, "<" == little endian
+        [phys_tables_base_addr].pack('Q<*') # "Q" == uint64_t, "<" == little endian
     end
 
+    # The lookup starts at the lvl1 if the address space is larger than a single lvl2 table.
     def phys_tables_base_addr
-        @lvl2_phys_start_addr
+        @root.phys_start_addr
     end
 
     private
 
     def do_sanity_checks
-        raise unless BSP.kernel_granule::SIZE == Granule64KiB::SIZE
-        raise unless (BSP.kernel_virt_addr_space_size % Granule512MiB::SIZE).zero?
+        lvl2_granule_size = 2**@lvl2_granule_shift
+
+        raise unless (BSP.kernel_virt_addr_space_size % lvl2_granule_size).zero?
+        raise unless BSP.kernel_virt_addr_space_size <= lvl2_granule_size * (@num_table_entries**2)
+    end
+
+    def uses_lvl1?
+        @num_lvl2_descriptors > @num_table_entries
     end
 
-    def new_lvl3(num_lvl2_tables, start_addr)
-        CArray.new(start_addr, num_lvl2_tables) do
-            temp = CArray.new(start_addr, 8192) do
-                Stage1PageDescriptor.new
-            end
-            start_addr += temp.size_in_byte
+    def new_table(descriptor_class)
+        raise 'Out of precomputed table frames' if @frames.size >= BSP.num_precomputed_table_frames
 
-            temp
+        start_addr = BSP.phys_addr_of_precomputed_table_frames +
+                     (@frames.size * BSP.kernel_granule::SIZE)
+        table = CArray.new(start_addr, @num_table_entries) do
+            descriptor_class.new
         end
+        @frames << table
+
+        table
+    end
+
+    def link_table(descriptor, table)
+        descriptor.next_level_table_addr = table.phys_start_addr
+        descriptor.type = Stage1TableDescriptor::Type::TABLE
+        descriptor.valid = Stage1TableDescriptor::Valid::TRUE
     end
 
-    def new_lvl2(num_lvl2_tables, start_addr)
-        CArray.new(start_addr, num_lvl2_tables) do
-            Stage1TableDescriptor.new
+    # Returns the lvl2 descriptor for a global lvl2 index. The lvl2 table is created if needed.
+    def lvl2_descriptor(lvl2_index)
+        return @root[lvl2_index] unless uses_lvl1?
+
+        lvl1_index = lvl2_index / @num_table_entries
+        unless @lvl2_tables.key?(lvl1_index)
+            @lvl2_tables[lvl1_index] = new_table(Stage1TableDescriptor)
+            link_table(@root[lvl1_index], @lvl2_tables[lvl1_index])
         end
+
+        @lvl2_tables[lvl1_index][lvl2_index % @num_table_entries]
     end
 
-    def populate_lvl2_entries
-        @lvl2.each_with_index do |descriptor, i|
-            descriptor.next_level_table_addr = @lvl3[i].phys_start_addr
-            descriptor.type = Stage1TableDescriptor::Type::TABLE
-            descriptor.valid = Stage1TableDescriptor::Valid::TRUE
+    def lvl3_table(lvl2_index)
+        unless @lvl3_tables.key?(lvl2_index)
+            @lvl3_tables[lvl2_index] = new_table(Stage1PageDescriptor)
+            link_table(lvl2_descriptor(lvl2_index), @lvl3_tables[lvl2_index])
         end
+
+        @lvl3_tables[lvl2_index]
     end
 
     def lvl2_lvl3_index_from(addr)
         # kernel_virt_start_addrが0でなくなったので仮想memory空間のkernel領域内におけるindexを計算するためにkernel_virt_start_addrを引き算するようにした
         addr -= BSP.kernel_virt_start_addr
 
-        lvl2_index = addr >> Granule512MiB::SHIFT
-        lvl3_index = (addr & Granule512MiB::MASK) >> Granule64KiB::SHIFT
+        lvl2_index = addr >> @lvl2_granule_shift
+        lvl3_index = (addr & ((2**@lvl2_granule_shift) - 1)) >> BSP.kernel_granule::SHIFT
 
-        raise unless lvl2_index < @lvl2.size
+        raise unless lvl2_index < @num_lvl2_descriptors
 
         [lvl2_index, lvl3_index]
     end
@@ -269,7 +303,7 @@ class TranslationTable
     def page_descriptor_from(virt_addr)
         lvl2_index, lvl3_index = lvl2_lvl3_index_from(virt_addr)
 
-        @lvl3[lvl2_index][lvl3_index]
+        lvl3_table(lvl2_index)[lvl3_index]
     end
 
     # rubocop:disable Metrics/MethodLength
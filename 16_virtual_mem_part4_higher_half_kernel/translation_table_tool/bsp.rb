@@ -7,29 +7,32 @@
 # Raspberry Pi 3 + 4
 class RaspberryPi
     # kernel_virt_start_addrが0でなくなったのでメンバとして追加した
-    attr_reader :kernel_granule, :kernel_virt_addr_space_size, :kernel_virt_start_addr
+    attr_reader :kernel_granule, :kernel_virt_addr_space_size, :kernel_virt_start_addr,
+                :num_precomputed_table_frames
 
     MEMORY_SRC = File.read('src/bsp/raspberrypi/memory.rs').split("\n")
 
     def initialize
-        @kernel_granule = Granule64KiB
+        @kernel_granule = granule_from_size(KERNEL_ELF.symbol_value('__kernel_granule_size'))
 
         @kernel_virt_addr_space_size = KERNEL_ELF.symbol_value('__kernel_virt_addr_space_size')
         # KERNEL_ELFからkernel_virt_start_addrを読み込む
         @kernel_virt_start_addr = KERNEL_ELF.symbol_value('__kernel_virt_start_addr')
 
-        @virt_addr_of_kernel_tables = KERNEL_ELF.symbol_value('KERNEL_TABLES')
+        @virt_addr_of_precomputed_table_frames = KERNEL_ELF.symbol_value('PRECOMPUTED_TABLE_FRAMES')
+        @num_precomputed_table_frames =
+            KERNEL_ELF.symbol_size('PRECOMPUTED_TABLE_FRAMES') / @kernel_granule::SIZE
         @virt_addr_of_phys_kernel_tables_base_addr = KERNEL_ELF.symbol_value(
             'PHYS_KERNEL_TABLES_BASE_ADDR'
         )
     end
 
-    def phys_addr_of_kernel_tables
-        KERNEL_ELF.virt_to_phys(@virt_addr_of_kernel_tables)
+    def phys_addr_of_precomputed_table_frames
+        KERNEL_ELF.virt_to_phys(@virt_addr_of_precomputed_table_frames)
     end
 
-    def kernel_tables_offset_in_file
-        KERNEL_ELF.virt_addr_to_file_offset(@virt_addr_of_kernel_tables)
+    def precomputed_table_frames_offset_in_file
+        KERNEL_ELF.virt_addr_to_file_offset(@virt_addr_of_precomputed_table_frames)
     end
 
     def phys_kernel_tables_base_addr_offset_in_file
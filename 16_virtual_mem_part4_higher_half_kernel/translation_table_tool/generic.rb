@@ -4,15 +4,25 @@
 #
 # Copyright (c) 2021-2022 Andre Richter <andre.o.richter@gmail.com>
 
+module Granule4KiB
+    SIZE = 4 * 1024
+    SHIFT = Math.log2(SIZE).to_i
+end
+
+module Granule16KiB
+    SIZE = 16 * 1024
+    SHIFT = Math.log2(SIZE).to_i
+end
+
 module Granule64KiB
     SIZE = 64 * 1024
     SHIFT = Math.log2(SIZE).to_i
 end
 
-module Granule512MiB
-    SIZE = 512 * 1024 * 1024
-    SHIFT = Math.log2(SIZE).to_i
-    MASK = SIZE - 1
+# Look up the granule module for a size in byte.
+def granule_from_size(size)
+    [Granule4KiB, Granule16KiB, Granule64KiB].find { |g| g::SIZE == size } ||
+        raise("Unsupported translation granule: #{size}")
 end
 
 # Monkey-patch Integer with some helper functions.
@@ -113,7 +123,7 @@ class MappingDescriptor
         name = @name.ljust(self.class.max_section_name_length)
         virt_start = @virt_region.first.to_hex_underscore(with_leading_zeros: true)
         phys_start = @phys_region.first.to_hex_underscore(with_leading_zeros: true)
-        size = ((@virt_region.size * 65_536) / 1024).to_s.rjust(3)
+        size = ((@virt_region.size * BSP.kernel_granule::SIZE) / 1024).to_s.rjust(3)
 
         "#{name} | #{virt_start} | #{phys_start} | #{size} KiB | #{@attributes}"
     end
@@ -161,10 +171,11 @@ end
 
 def kernel_patch_tables(kernel_elf_path)
     print 'Patching'.rjust(12).green.bold
-    print ' Kernel table struct at ELF file offset '
-    puts BSP.kernel_tables_offset_in_file.to_hex_underscore
+    print ' Precomputed table frames at ELF file offset '
+    puts BSP.precomputed_table_frames_offset_in_file.to_hex_underscore
 
-    File.binwrite(kernel_elf_path, TRANSLATION_TABLES.to_binary, BSP.kernel_tables_offset_in_file)
+    File.binwrite(kernel_elf_path, TRANSLATION_TABLES.to_binary,
+                  BSP.precomputed_table_frames_offset_in_file)
 end
 
 def kernel_patch_base_addr(kernel_elf_path)
@@ -17,6 +17,10 @@ class KernelELF
         @symtab_section.symbol_by_name(symbol_name).header.st_value
     end
 
+    def symbol_size(symbol_name)
+        @symtab_section.symbol_by_name(symbol_name).header.st_size
+    end
+
     def segment_containing_virt_addr(virt_addr)
         @elf.each_segments do |segment|
             return segment if segment.vma_in?(virt_addr)